@@ -0,0 +1,345 @@
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+struct Property {
+    name: String,
+    values: Vec<String>,
+}
+
+struct Block {
+    name: String,
+    min_state: u64,
+    default_state: u64,
+    properties: Vec<Property>,
+    hardness: f64,
+    replaceable: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PropertyKind {
+    Bool,
+    Int,
+    Str,
+}
+
+impl PropertyKind {
+    fn of(values: &[String]) -> PropertyKind {
+        if values
+            .iter()
+            .all(|value| value == "true" || value == "false")
+        {
+            PropertyKind::Bool
+        } else if values.iter().all(|value| value.parse::<u8>().is_ok()) {
+            PropertyKind::Int
+        } else {
+            PropertyKind::Str
+        }
+    }
+}
+
+/// Every block in the report needs an entry in the materials,
+/// so new blocks can't silently end up with made up values
+fn load_blocks(path: &Path, materials_path: &Path) -> Vec<Block> {
+    let data = fs::read_to_string(path).unwrap();
+    let report: BTreeMap<String, Value> = serde_json::from_str(&data).unwrap();
+    let data = fs::read_to_string(materials_path).unwrap();
+    let mut materials: BTreeMap<String, Value> = serde_json::from_str(&data).unwrap();
+    let mut blocks = Vec::new();
+    for (name, block) in report {
+        let material = materials
+            .remove(&name)
+            .unwrap_or_else(|| panic!("{} is missing from {}", name, materials_path.display()));
+        let hardness = material["hardness"]
+            .as_f64()
+            .unwrap_or_else(|| panic!("{} has no hardness", name));
+        let replaceable = material["replaceable"].as_bool().unwrap_or(false);
+        let properties: Vec<Property> = match block["properties"].as_object() {
+            Some(properties) => properties
+                .iter()
+                .map(|(name, values)| Property {
+                    name: name.clone(),
+                    values: values
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|value| value.as_str().unwrap().to_string())
+                        .collect(),
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        let states = block["states"].as_array().unwrap();
+        let min_state = states
+            .iter()
+            .map(|state| state["id"].as_u64().unwrap())
+            .min()
+            .unwrap();
+        let mut default_state = min_state;
+
+        // Property values are derived from the offset of a state to the block's first state,
+        // so make sure the report enumerates states in that order.
+        for state in states {
+            let id = state["id"].as_u64().unwrap();
+            if state["default"].as_bool() == Some(true) {
+                default_state = id;
+            }
+            let mut offset = id - min_state;
+            for property in properties.iter().rev() {
+                let len = property.values.len() as u64;
+                let expected = &property.values[(offset % len) as usize];
+                let actual = state["properties"][&property.name].as_str().unwrap();
+                assert_eq!(
+                    expected, actual,
+                    "Unexpected state order for {} at {}",
+                    name, id
+                );
+                offset /= len;
+            }
+        }
+
+        blocks.push(Block {
+            name,
+            min_state,
+            default_state,
+            properties,
+            hardness,
+            replaceable,
+        });
+    }
+    if let Some(name) = materials.keys().next() {
+        panic!("{} in {} is not a block", name, materials_path.display());
+    }
+    blocks.sort_by_key(|block| block.min_state);
+    blocks
+}
+
+fn generate_blocks(blocks: &[Block]) -> String {
+    let mut out = String::new();
+    writeln!(out, "pub static BLOCKS: [Block; {}] = [", blocks.len()).unwrap();
+    for block in blocks {
+        let state_count: u64 = block
+            .properties
+            .iter()
+            .map(|property| property.values.len() as u64)
+            .product();
+        writeln!(out, "    Block {{").unwrap();
+        writeln!(out, "        name: {:?},", block.name).unwrap();
+        writeln!(out, "        min_state: {},", block.min_state).unwrap();
+        writeln!(
+            out,
+            "        max_state: {},",
+            block.min_state + state_count - 1
+        )
+        .unwrap();
+        writeln!(out, "        default_state: {},", block.default_state).unwrap();
+        writeln!(out, "        hardness: {:?},", block.hardness).unwrap();
+        writeln!(out, "        replaceable: {},", block.replaceable).unwrap();
+        writeln!(out, "        properties: &[").unwrap();
+        for property in &block.properties {
+            writeln!(
+                out,
+                "            Property {{ name: {:?}, values: &{:?} }},",
+                property.name, property.values
+            )
+            .unwrap();
+        }
+        writeln!(out, "        ],").unwrap();
+        writeln!(out, "    }},").unwrap();
+    }
+    writeln!(out, "];").unwrap();
+
+    // One accessor per property name, typed by the values it can take
+    let mut kinds: BTreeMap<&str, PropertyKind> = BTreeMap::new();
+    for property in blocks.iter().flat_map(|block| &block.properties) {
+        let kind = PropertyKind::of(&property.values);
+        let entry = kinds.entry(&property.name).or_insert(kind);
+        if *entry != kind {
+            *entry = PropertyKind::Str;
+        }
+    }
+    writeln!(out).unwrap();
+    writeln!(out, "impl BlockState {{").unwrap();
+    for (name, kind) in kinds {
+        let (return_type, conversion) = match kind {
+            PropertyKind::Bool => ("bool", ".map(|value| value == \"true\")"),
+            PropertyKind::Int => ("u8", ".map(|value| value.parse().unwrap())"),
+            PropertyKind::Str => ("&'static str", ""),
+        };
+        let method = match name {
+            "type" => "r#type",
+            name => name,
+        };
+        writeln!(
+            out,
+            "    pub fn {}(self) -> Option<{}> {{",
+            method, return_type
+        )
+        .unwrap();
+        writeln!(out, "        self.get_property({:?}){}", name, conversion).unwrap();
+        writeln!(out, "    }}").unwrap();
+    }
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+/// Converts a resource path like `ambient.underwater.enter` or `oak_planks` to a type or variant name
+fn camel_case(name: &str) -> String {
+    name.split(['_', '.', '/'])
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+fn generate_registries(path: &Path, added_path: &Path) -> String {
+    let data = fs::read_to_string(path).unwrap();
+    let report: BTreeMap<String, Value> = serde_json::from_str(&data).unwrap();
+    let data = fs::read_to_string(added_path).unwrap();
+    let added_in_1_15: BTreeMap<String, Vec<String>> = serde_json::from_str(&data).unwrap();
+    let mut out = String::new();
+    for (registry, contents) in report {
+        let type_name = camel_case(registry.trim_start_matches("minecraft:"));
+        let mut entries: Vec<(String, i64)> = contents["entries"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .map(|(name, entry)| (name.clone(), entry["protocol_id"].as_i64().unwrap()))
+            .collect();
+        entries.sort_by_key(|(_, id)| *id);
+        // The ids of the entries that 1.14.4 doesn't have, the report may not have all of them
+        let added: Vec<String> = entries
+            .iter()
+            .filter(|(name, _)| {
+                added_in_1_15
+                    .get(&registry)
+                    .is_some_and(|added| added.contains(name))
+            })
+            .map(|(_, id)| id.to_string())
+            .collect();
+        let added = format!("&[{}]", added.join(", "));
+        let variants: Vec<(String, &str, i64)> = entries
+            .iter()
+            .map(|(name, id)| {
+                let path = name.trim_start_matches("minecraft:");
+                (camel_case(path), name.as_str(), *id)
+            })
+            .collect();
+
+        writeln!(out, "/// The `{}` registry", registry).unwrap();
+        writeln!(out, "#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]").unwrap();
+        writeln!(out, "pub enum {} {{", type_name).unwrap();
+        for (variant, _, id) in &variants {
+            writeln!(out, "    {} = {},", variant, id).unwrap();
+        }
+        writeln!(out, "}}").unwrap();
+        writeln!(out).unwrap();
+
+        writeln!(out, "impl {} {{", type_name).unwrap();
+        writeln!(out, "    pub fn id(self) -> i32 {{").unwrap();
+        writeln!(out, "        self as i32").unwrap();
+        writeln!(out, "    }}").unwrap();
+        writeln!(out).unwrap();
+        writeln!(
+            out,
+            "    pub fn from_id(id: i32) -> Option<{}> {{",
+            type_name
+        )
+        .unwrap();
+        writeln!(out, "        match id {{").unwrap();
+        for (variant, _, id) in &variants {
+            writeln!(
+                out,
+                "            {} => Some({}::{}),",
+                id, type_name, variant
+            )
+            .unwrap();
+        }
+        writeln!(out, "            _ => None,").unwrap();
+        writeln!(out, "        }}").unwrap();
+        writeln!(out, "    }}").unwrap();
+        writeln!(out).unwrap();
+        writeln!(out, "    pub fn name(self) -> &'static str {{").unwrap();
+        writeln!(out, "        match self {{").unwrap();
+        for (variant, name, _) in &variants {
+            writeln!(out, "            {}::{} => {:?},", type_name, variant, name).unwrap();
+        }
+        writeln!(out, "        }}").unwrap();
+        writeln!(out, "    }}").unwrap();
+        writeln!(out).unwrap();
+        writeln!(
+            out,
+            "    pub fn from_name(name: &str) -> Option<{}> {{",
+            type_name
+        )
+        .unwrap();
+        writeln!(out, "        match name {{").unwrap();
+        for (variant, name, _) in &variants {
+            writeln!(
+                out,
+                "            {:?} => Some({}::{}),",
+                name, type_name, variant
+            )
+            .unwrap();
+        }
+        writeln!(out, "            _ => None,").unwrap();
+        writeln!(out, "        }}").unwrap();
+        writeln!(out, "    }}").unwrap();
+        writeln!(out).unwrap();
+        writeln!(
+            out,
+            "    pub fn protocol_id(self, protocol: ProtocolVersion) -> Option<i32> {{"
+        )
+        .unwrap();
+        writeln!(out, "        protocol_id(self.id(), {}, protocol)", added).unwrap();
+        writeln!(out, "    }}").unwrap();
+        writeln!(out).unwrap();
+        writeln!(
+            out,
+            "    pub fn from_protocol_id(id: i32, protocol: ProtocolVersion) -> Option<{}> {{",
+            type_name
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "        {}::from_id(latest_id(id, {}, protocol))",
+            type_name, added
+        )
+        .unwrap();
+        writeln!(out, "    }}").unwrap();
+        writeln!(out, "}}").unwrap();
+        writeln!(out).unwrap();
+    }
+    out
+}
+
+fn main() {
+    let blocks_report = Path::new("res/blocks.json");
+    let block_materials = Path::new("res/block_materials.json");
+    let registries_report = Path::new("res/registries.json");
+    let registries_added = Path::new("res/registries_added_1_15.json");
+    println!("cargo:rerun-if-changed={}", blocks_report.display());
+    println!("cargo:rerun-if-changed={}", block_materials.display());
+    println!("cargo:rerun-if-changed={}", registries_report.display());
+    println!("cargo:rerun-if-changed={}", registries_added.display());
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let blocks = load_blocks(blocks_report, block_materials);
+    fs::write(
+        Path::new(&out_dir).join("blocks.rs"),
+        generate_blocks(&blocks),
+    )
+    .unwrap();
+    fs::write(
+        Path::new(&out_dir).join("registries.rs"),
+        generate_registries(registries_report, registries_added),
+    )
+    .unwrap();
+}
@@ -0,0 +1,346 @@
+//! `#[derive(Packet)]` for the packet structs in packets.rs.
+//!
+//! ```ignore
+//! #[derive(Packet)]
+//! #[packet(id = 0x01, state = Login, bound = Client)]
+//! pub struct C01EcryptionRequest {
+//!     pub server_id: String,
+//!     pub public_key: ByteArray,
+//!     pub verify_token: ByteArray,
+//! }
+//! ```
+//!
+//! Clientbound packets get an `encode` and serverbound packets a fallible `decode` method.
+//! Fields are read and written by the name of their type alias (`VarInt`, `Chat`, `Position`...).
+//! `Vec<T>` is prefixed with its length as a VarInt, or the type given with `#[packet(length = Short)]`,
+//! and `Option<T>` is prefixed with a Boolean. Any other type has to implement `PacketField`.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{
+    parse_macro_input, Attribute, Data, DeriveInput, Error, Expr, Fields, GenericArgument, Ident,
+    PathArguments, Result, Token, Type,
+};
+
+struct Setting {
+    key: Ident,
+    value: Expr,
+}
+
+impl Parse for Setting {
+    fn parse(input: ParseStream) -> Result<Setting> {
+        let key = input.parse()?;
+        input.parse::<Token![=]>()?;
+        let value = input.parse()?;
+        Ok(Setting { key, value })
+    }
+}
+
+fn settings(attrs: &[Attribute]) -> Result<Vec<Setting>> {
+    let mut settings = Vec::new();
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("packet")) {
+        let parsed = attr.parse_args_with(Punctuated::<Setting, Token![,]>::parse_terminated)?;
+        settings.extend(parsed);
+    }
+    Ok(settings)
+}
+
+fn expr_ident(expr: &Expr) -> Result<&Ident> {
+    match expr {
+        Expr::Path(path) if path.path.get_ident().is_some() => Ok(path.path.get_ident().unwrap()),
+        _ => Err(Error::new_spanned(expr, "expected an identifier")),
+    }
+}
+
+enum Bound {
+    Client,
+    Server,
+}
+
+/// Codec methods for a type alias and whether the writer takes a reference
+fn primitive(name: &str) -> Option<(&'static str, &'static str, bool)> {
+    Some(match name {
+        "Boolean" => ("read_bool", "write_bool", false),
+        "Byte" => ("read_byte", "write_byte", false),
+        "UnsignedByte" => ("read_ubyte", "write_ubyte", false),
+        "Short" => ("read_short", "write_short", false),
+        "UnsignedShort" => ("read_ushort", "write_ushort", false),
+        "Int" => ("read_int", "write_int", false),
+        "Long" => ("read_long", "write_long", false),
+        "Float" => ("read_float", "write_float", false),
+        "Double" => ("read_double", "write_double", false),
+        "String" | "Chat" => ("read_string", "write_string", false),
+        "Identifier" => ("read_identifier", "write_identifier", false),
+        "Angle" => ("read_angle", "write_angle", false),
+        "VarInt" => ("read_varint", "write_varint", false),
+        "VarLong" => ("read_varlong", "write_varlong", false),
+        "UUID" => ("read_uuid", "write_uuid", false),
+        "Position" => ("read_position", "write_position", true),
+        "Slot" => ("read_slot", "write_slot", true),
+        "RemainingBytes" => ("read_remaining_bytes", "write_remaining_bytes", false),
+        _ => return None,
+    })
+}
+
+enum FieldType<'a> {
+    Primitive(&'static str, &'static str, bool),
+    ByteArray,
+    Vec(&'a Type),
+    Option(&'a Type),
+    Other(&'a Type),
+}
+
+fn generic_argument(args: &PathArguments) -> Option<&Type> {
+    match args {
+        PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn field_type(ty: &Type) -> FieldType<'_> {
+    if let Type::Path(path) = ty {
+        if let Some(segment) = path.path.segments.last() {
+            let name = segment.ident.to_string();
+            if let Some((reader, writer, by_ref)) = primitive(&name) {
+                return FieldType::Primitive(reader, writer, by_ref);
+            }
+            match (name.as_str(), generic_argument(&segment.arguments)) {
+                ("ByteArray", _) => return FieldType::ByteArray,
+                ("Vec", Some(inner)) => return FieldType::Vec(inner),
+                ("Option", Some(inner)) => return FieldType::Option(inner),
+                _ => {}
+            }
+        }
+    }
+    FieldType::Other(ty)
+}
+
+fn length_codec(length: &Option<Ident>) -> Result<(Ident, Ident, TokenStream2)> {
+    let (reader, writer, ty) = match length.as_ref().map(|ident| ident.to_string()) {
+        None => ("read_varint", "write_varint", quote!(i32)),
+        Some(name) => match name.as_str() {
+            "VarInt" => ("read_varint", "write_varint", quote!(i32)),
+            "Byte" => ("read_byte", "write_byte", quote!(i8)),
+            "UnsignedByte" => ("read_ubyte", "write_ubyte", quote!(u8)),
+            "Short" => ("read_short", "write_short", quote!(i16)),
+            "Int" => ("read_int", "write_int", quote!(i32)),
+            _ => {
+                return Err(Error::new_spanned(
+                    length,
+                    "expected VarInt, Byte, UnsignedByte, Short or Int",
+                ))
+            }
+        },
+    };
+    Ok((
+        Ident::new(reader, Span::call_site()),
+        Ident::new(writer, Span::call_site()),
+        ty,
+    ))
+}
+
+/// Writes the value of `expr`, which is owned
+fn encode_value(ty: &Type, expr: TokenStream2, length: &Option<Ident>) -> Result<TokenStream2> {
+    Ok(match field_type(ty) {
+        FieldType::Primitive(_, writer, by_ref) => {
+            let writer = Ident::new(writer, Span::call_site());
+            if by_ref {
+                quote!(encoder.#writer(&#expr);)
+            } else {
+                quote!(encoder.#writer(#expr);)
+            }
+        }
+        FieldType::ByteArray => {
+            let (_, writer, length_ty) = length_codec(length)?;
+            quote! {
+                let mut bytes = #expr;
+                encoder.#writer(bytes.len() as #length_ty);
+                encoder.write_bytes(&mut bytes);
+            }
+        }
+        FieldType::Vec(inner) => {
+            let (_, writer, length_ty) = length_codec(length)?;
+            let element = encode_value(inner, quote!(element), &None)?;
+            quote! {
+                let elements = #expr;
+                encoder.#writer(elements.len() as #length_ty);
+                for element in elements {
+                    #element
+                }
+            }
+        }
+        FieldType::Option(inner) => {
+            let value = encode_value(inner, quote!(value), length)?;
+            quote! {
+                match #expr {
+                    Some(value) => {
+                        encoder.write_bool(true);
+                        #value
+                    }
+                    None => encoder.write_bool(false),
+                }
+            }
+        }
+        FieldType::Other(ty) => {
+            quote!(<#ty as crate::packets::PacketField>::write(#expr, &mut encoder);)
+        }
+    })
+}
+
+fn decode_value(ty: &Type, length: &Option<Ident>) -> Result<TokenStream2> {
+    Ok(match field_type(ty) {
+        FieldType::Primitive(reader, _, _) => {
+            let reader = Ident::new(reader, Span::call_site());
+            quote!(decoder.#reader()?)
+        }
+        FieldType::ByteArray => {
+            let (reader, _, _) = length_codec(length)?;
+            quote!({
+                let length = decoder.#reader()? as i64;
+                let length = decoder.check_length(length)?;
+                decoder.read_bytes(length)?
+            })
+        }
+        FieldType::Vec(inner) => {
+            let (reader, _, _) = length_codec(length)?;
+            let element = decode_value(inner, &None)?;
+            quote!({
+                let length = decoder.#reader()? as i64;
+                let length = decoder.check_length(length)?;
+                let mut elements = Vec::with_capacity(length);
+                for _ in 0..length {
+                    elements.push(#element);
+                }
+                elements
+            })
+        }
+        FieldType::Option(inner) => {
+            let value = decode_value(inner, length)?;
+            quote!(if decoder.read_bool()? { Some(#value) } else { None })
+        }
+        FieldType::Other(ty) => quote!(<#ty as crate::packets::PacketField>::read(&mut decoder)?),
+    })
+}
+
+fn derive(input: DeriveInput) -> Result<TokenStream2> {
+    let mut id = None;
+    let mut state = None;
+    let mut bound = None;
+    for setting in settings(&input.attrs)? {
+        match setting.key.to_string().as_str() {
+            "id" => id = Some(setting.value),
+            "state" => {
+                let ident = expr_ident(&setting.value)?;
+                let variant = match ident.to_string().as_str() {
+                    "Handshaking" => "HANDSHAKING",
+                    "Status" => "STATUS",
+                    "Login" => "LOGIN",
+                    "Play" => "PLAY",
+                    _ => {
+                        return Err(Error::new_spanned(
+                            ident,
+                            "expected Handshaking, Status, Login or Play",
+                        ))
+                    }
+                };
+                state = Some(Ident::new(variant, ident.span()));
+            }
+            "bound" => {
+                let ident = expr_ident(&setting.value)?;
+                bound = Some(match ident.to_string().as_str() {
+                    "Client" => Bound::Client,
+                    "Server" => Bound::Server,
+                    _ => return Err(Error::new_spanned(ident, "expected Client or Server")),
+                });
+            }
+            _ => return Err(Error::new_spanned(setting.key, "unknown packet setting")),
+        }
+    }
+    let missing = |name| Error::new(Span::call_site(), format!("missing packet {}", name));
+    let id = id.ok_or_else(|| missing("id"))?;
+    let state = state.ok_or_else(|| missing("state"))?;
+    let bound = bound.ok_or_else(|| missing("bound"))?;
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => fields.named.iter().collect(),
+            Fields::Unit => Vec::new(),
+            Fields::Unnamed(_) => {
+                return Err(Error::new_spanned(&input, "packet fields must be named"))
+            }
+        },
+        _ => return Err(Error::new_spanned(&input, "packets must be structs")),
+    };
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let mut names = Vec::new();
+    let mut encoders = Vec::new();
+    let mut decoders = Vec::new();
+    for field in fields {
+        let mut length = None;
+        for setting in settings(&field.attrs)? {
+            match setting.key.to_string().as_str() {
+                "length" => length = Some(expr_ident(&setting.value)?.clone()),
+                _ => return Err(Error::new_spanned(setting.key, "unknown field setting")),
+            }
+        }
+        let field_name = field.ident.as_ref().unwrap();
+        encoders.push(encode_value(&field.ty, quote!(self.#field_name), &length)?);
+        decoders.push(decode_value(&field.ty, &length)?);
+        names.push(field_name);
+    }
+
+    let codec = match bound {
+        Bound::Client => quote! {
+            pub fn encode(self) -> crate::packets::PacketEncoder {
+                let mut encoder = crate::packets::PacketEncoder::new(#id);
+                #({ #encoders })*
+                encoder
+            }
+        },
+        Bound::Server if names.is_empty() => quote! {
+            pub fn decode(
+                _decoder: crate::packets::PacketDecoder,
+            ) -> crate::packets::ProtocolResult<Self> {
+                Ok(#name {})
+            }
+        },
+        Bound::Server => quote! {
+            pub fn decode(
+                mut decoder: crate::packets::PacketDecoder,
+            ) -> crate::packets::ProtocolResult<Self> {
+                Ok(#name {
+                    #(#names: #decoders,)*
+                })
+            }
+        },
+    };
+    Ok(quote! {
+        impl #impl_generics crate::packets::Packet for #name #ty_generics #where_clause {
+            const ID: i32 = #id;
+            const STATE: crate::packets::NetworkState = crate::packets::NetworkState::#state;
+        }
+
+        impl #impl_generics #name #ty_generics #where_clause {
+            #codec
+        }
+    })
+}
+
+#[proc_macro_derive(Packet, attributes(packet))]
+pub fn derive_packet(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match derive(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
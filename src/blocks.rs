@@ -0,0 +1,227 @@
+pub struct Property {
+    pub name: &'static str,
+    pub values: &'static [&'static str],
+}
+
+pub struct Block {
+    pub name: &'static str,
+    pub min_state: u32,
+    pub max_state: u32,
+    pub default_state: u32,
+    /// Sorted by name, the last property changes fastest between consecutive state ids
+    pub properties: &'static [Property],
+    /// Negative for unbreakable blocks
+    pub hardness: f32,
+    /// Whether placing a block into this one replaces it
+    pub replaceable: bool,
+}
+
+// Generated by build.rs from res/blocks.json and res/block_materials.json
+include!(concat!(env!("OUT_DIR"), "/blocks.rs"));
+
+impl Block {
+    pub fn from_name(name: &str) -> Option<&'static Block> {
+        BLOCKS.iter().find(|block| block.name == name)
+    }
+
+    pub fn default_state(&self) -> BlockState {
+        BlockState(self.default_state)
+    }
+
+    /// The least amount of ticks a player needs to break this block. Enchantments and effects
+    /// aren't tracked, so this assumes a golden tool with Efficiency V and Haste II.
+    /// Returns None if the block can't be broken, and 0 if it can break as soon as digging starts.
+    pub fn min_break_ticks(&self) -> Option<u32> {
+        // 12 for gold, 5² + 1 more for Efficiency V and 40% more for Haste II
+        const FASTEST_SPEED: f32 = (12.0 + 26.0) * 1.4;
+        let hardness = self.hardness;
+        if hardness < 0.0 || self.replaceable {
+            return None;
+        }
+        if hardness == 0.0 {
+            return Some(0);
+        }
+        let progress_per_tick = FASTEST_SPEED / hardness / 30.0;
+        // The client sends the finish once the progress reaches 1, but vanilla allows for 30% of lag.
+        // Clients that break the block in the first tick only send the start.
+        Some(((0.7 / progress_per_tick).ceil() as u32).saturating_sub(1))
+    }
+}
+
+/// A global palette block state id
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BlockState(pub u32);
+
+impl BlockState {
+    pub const AIR: BlockState = BlockState(0);
+
+    pub fn id(self) -> u32 {
+        self.0
+    }
+
+    pub fn block(self) -> Option<&'static Block> {
+        let index = match BLOCKS.binary_search_by_key(&self.0, |block| block.min_state) {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+        let block = &BLOCKS[index];
+        if self.0 <= block.max_state {
+            Some(block)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_replaceable(self) -> bool {
+        self.block().is_some_and(|block| block.replaceable)
+    }
+
+    /// The index of every property value of this state, in the order of `Block::properties`
+    fn value_indices(self, block: &Block) -> Vec<usize> {
+        let mut offset = (self.0 - block.min_state) as usize;
+        let mut indices = vec![0; block.properties.len()];
+        for (i, property) in block.properties.iter().enumerate().rev() {
+            indices[i] = offset % property.values.len();
+            offset /= property.values.len();
+        }
+        indices
+    }
+
+    pub fn get_property(self, name: &str) -> Option<&'static str> {
+        let block = self.block()?;
+        let indices = self.value_indices(block);
+        block
+            .properties
+            .iter()
+            .zip(indices)
+            .find(|(property, _)| property.name == name)
+            .map(|(property, index)| property.values[index])
+    }
+
+    /// Returns the same block with a property changed, or None if the block doesn't have it
+    pub fn with_property(self, name: &str, value: &str) -> Option<BlockState> {
+        let block = self.block()?;
+        let mut indices = self.value_indices(block);
+        let property = block
+            .properties
+            .iter()
+            .position(|property| property.name == name)?;
+        indices[property] = block.properties[property]
+            .values
+            .iter()
+            .position(|v| *v == value)?;
+
+        let mut offset = 0;
+        for (property, index) in block.properties.iter().zip(indices) {
+            offset = offset * property.values.len() + index;
+        }
+        Some(BlockState(block.min_state + offset as u32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STAIRS: Block = Block {
+        name: "minecraft:oak_stairs",
+        min_state: 1000,
+        max_state: 1079,
+        default_state: 1011,
+        properties: &[
+            Property {
+                name: "facing",
+                values: &["north", "south", "west", "east"],
+            },
+            Property {
+                name: "half",
+                values: &["top", "bottom"],
+            },
+            Property {
+                name: "shape",
+                values: &[
+                    "straight",
+                    "inner_left",
+                    "inner_right",
+                    "outer_left",
+                    "outer_right",
+                ],
+            },
+            Property {
+                name: "waterlogged",
+                values: &["true", "false"],
+            },
+        ],
+        hardness: 2.0,
+        replaceable: false,
+    };
+
+    #[test]
+    fn break_ticks() {
+        let ticks = |name| Block::from_name(name).unwrap().min_break_ticks();
+        assert_eq!(ticks("minecraft:bedrock"), None);
+        assert_eq!(ticks("minecraft:water"), None);
+        assert_eq!(ticks("minecraft:oak_sapling"), Some(0));
+        // Stone breaks instantly with Efficiency V and Haste II, so clients only send the start
+        assert_eq!(ticks("minecraft:stone"), Some(0));
+        assert_eq!(ticks("minecraft:iron_ore"), Some(1));
+    }
+
+    #[test]
+    fn value_indices() {
+        // The last property changes fastest
+        assert_eq!(BlockState(1000).value_indices(&STAIRS), [0, 0, 0, 0]);
+        assert_eq!(BlockState(1001).value_indices(&STAIRS), [0, 0, 0, 1]);
+        assert_eq!(BlockState(1002).value_indices(&STAIRS), [0, 0, 1, 0]);
+        assert_eq!(BlockState(1011).value_indices(&STAIRS), [0, 1, 0, 1]);
+        assert_eq!(BlockState(1020).value_indices(&STAIRS), [1, 0, 0, 0]);
+        assert_eq!(BlockState(1079).value_indices(&STAIRS), [3, 1, 4, 1]);
+    }
+
+    #[test]
+    fn properties() {
+        let dispenser = Block::from_name("minecraft:dispenser").unwrap();
+        let state = dispenser.default_state();
+        assert_eq!(state.get_property("facing"), Some("north"));
+        assert_eq!(state.get_property("triggered"), Some("false"));
+        assert_eq!(state.get_property("axis"), None);
+
+        let state = state.with_property("facing", "down").unwrap();
+        assert_eq!(state, BlockState(dispenser.max_state));
+        let state = state.with_property("triggered", "true").unwrap();
+        assert_eq!(state, BlockState(dispenser.max_state - 1));
+        assert_eq!(state.get_property("facing"), Some("down"));
+        assert_eq!(state.with_property("facing", "sideways"), None);
+        assert_eq!(state.with_property("axis", "x"), None);
+        assert_eq!(BlockState(u32::MAX).with_property("facing", "down"), None);
+    }
+
+    #[test]
+    fn property_round_trips() {
+        for block in BLOCKS.iter() {
+            for id in block.min_state..=block.max_state {
+                let state = BlockState(id);
+                assert_eq!(state.block().unwrap().name, block.name);
+                for property in block.properties {
+                    let value = state.get_property(property.name).unwrap();
+                    assert_eq!(state.with_property(property.name, value), Some(state));
+                    for new_value in property.values {
+                        let changed = state.with_property(property.name, new_value).unwrap();
+                        assert_eq!(changed.block().unwrap().name, block.name);
+                        assert_eq!(changed.get_property(property.name), Some(*new_value));
+                        // Every other property keeps its value
+                        for other in block.properties {
+                            if other.name != property.name {
+                                assert_eq!(
+                                    changed.get_property(other.name),
+                                    state.get_property(other.name)
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
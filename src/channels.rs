@@ -0,0 +1,777 @@
+//! Plugin messages carry custom data between the server and clients, mods or proxies over named
+//! channels. Both sides tell each other which channels they listen on with `minecraft:register`
+//! and `minecraft:unregister`, and only get messages on those.
+
+use crate::packets::is_valid_identifier;
+use std::convert::TryInto;
+use std::rc::Rc;
+
+pub const REGISTER: &str = "minecraft:register";
+pub const UNREGISTER: &str = "minecraft:unregister";
+pub const BRAND: &str = "minecraft:brand";
+/// What BungeeCord and compatible proxies listen on, plugins can also use the legacy name
+pub const BUNGEECORD: &str = "bungeecord:main";
+const LEGACY_BUNGEECORD: &str = "BungeeCord";
+/// Sent to clients on join, shown in the debug screen
+pub const SERVER_BRAND: &str = "RustMcServer";
+/// How many channels a client can listen on, so registering can't use up memory
+pub const MAX_CLIENT_CHANNELS: usize = 128;
+/// The largest plugin message clients can send
+pub const MAX_SERVERBOUND_SIZE: usize = 32767;
+/// The largest plugin message clients accept
+pub const MAX_CLIENTBOUND_SIZE: usize = 1048576;
+
+/// The name a channel is sent as, or None if it isn't a valid channel
+pub fn channel_name(channel: &str) -> Option<&str> {
+    if channel == LEGACY_BUNGEECORD {
+        Some(BUNGEECORD)
+    } else if channel.contains(':') && is_valid_identifier(channel) {
+        Some(channel)
+    } else {
+        None
+    }
+}
+
+/// Everyone can send on the `minecraft` channels, other channels have to be registered first
+pub fn needs_registration(channel: &str) -> bool {
+    !channel.starts_with("minecraft:")
+}
+
+/// The channels of a register or unregister message, which are separated by null bytes
+pub fn decode_channel_list(data: &[u8]) -> Vec<String> {
+    data.split(|byte| *byte == 0)
+        .filter_map(|channel| std::str::from_utf8(channel).ok())
+        .filter(|channel| channel_name(channel) == Some(channel))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Adds the channels of a register message to the channels a client listens on.
+/// Returns false if that would be more than `MAX_CLIENT_CHANNELS`, the rest are ignored then.
+pub fn register_channels(channels: &mut Vec<String>, data: &[u8]) -> bool {
+    for channel in decode_channel_list(data) {
+        if channels.contains(&channel) {
+            continue;
+        }
+        if channels.len() >= MAX_CLIENT_CHANNELS {
+            return false;
+        }
+        channels.push(channel);
+    }
+    true
+}
+
+pub fn unregister_channels(channels: &mut Vec<String>, data: &[u8]) {
+    let unregistered = decode_channel_list(data);
+    channels.retain(|channel| !unregistered.contains(channel));
+}
+
+pub fn encode_channel_list(channels: &[&str]) -> Vec<u8> {
+    channels.join("\0").into_bytes()
+}
+
+/// A brand is sent as a string prefixed with its length as a VarInt
+pub fn decode_brand(data: &[u8]) -> Option<String> {
+    let mut length = 0usize;
+    for (i, byte) in data.iter().enumerate().take(5) {
+        length |= ((byte & 0x7F) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            let brand = data.get(i + 1..i + 1 + length)?;
+            return String::from_utf8(brand.to_vec()).ok();
+        }
+    }
+    None
+}
+
+pub fn encode_brand(brand: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut length = brand.len();
+    loop {
+        let byte = (length & 0x7F) as u8;
+        length >>= 7;
+        if length == 0 {
+            data.push(byte);
+            break;
+        }
+        data.push(byte | 0x80);
+    }
+    data.extend_from_slice(brand.as_bytes());
+    data
+}
+
+pub type ChannelHandler<S> = Rc<dyn Fn(&mut S, u32, &[u8])>;
+
+struct Listener<S> {
+    channel: String,
+    plugin: String,
+    handler: ChannelHandler<S>,
+}
+
+/// The channels plugins listen on, the handlers get the id of the client and the data
+pub struct ChannelRegistry<S> {
+    listeners: Vec<Listener<S>>,
+}
+
+impl<S> ChannelRegistry<S> {
+    pub fn new() -> ChannelRegistry<S> {
+        ChannelRegistry {
+            listeners: Vec::new(),
+        }
+    }
+
+    /// Adds a handler for a channel, returns whether nobody listened on the channel before
+    pub fn listen<F>(&mut self, plugin: &str, channel: &str, handler: F) -> bool
+    where
+        F: Fn(&mut S, u32, &[u8]) + 'static,
+    {
+        let new = !self.is_registered(channel);
+        self.listeners.push(Listener {
+            channel: channel.to_string(),
+            plugin: plugin.to_string(),
+            handler: Rc::new(handler),
+        });
+        new
+    }
+
+    pub fn is_registered(&self, channel: &str) -> bool {
+        self.listeners
+            .iter()
+            .any(|listener| listener.channel == channel)
+    }
+
+    /// Every channel that's listened on, once each
+    pub fn channels(&self) -> Vec<&str> {
+        let mut channels: Vec<&str> = Vec::new();
+        for listener in &self.listeners {
+            if !channels.contains(&listener.channel.as_str()) {
+                channels.push(&listener.channel);
+            }
+        }
+        channels
+    }
+
+    /// The handlers of a channel, cloned so they can be called with the server
+    pub fn handlers(&self, channel: &str) -> Vec<ChannelHandler<S>> {
+        self.listeners
+            .iter()
+            .filter(|listener| listener.channel == channel)
+            .map(|listener| listener.handler.clone())
+            .collect()
+    }
+
+    /// Removes a plugin's handlers, returns the channels nobody listens on anymore
+    pub fn unregister_plugin(&mut self, plugin: &str) -> Vec<String> {
+        let channels: Vec<String> = self
+            .listeners
+            .iter()
+            .filter(|listener| listener.plugin == plugin)
+            .map(|listener| listener.channel.clone())
+            .collect();
+        self.listeners.retain(|listener| listener.plugin != plugin);
+        let mut unused: Vec<String> = Vec::new();
+        for channel in channels {
+            if !self.is_registered(&channel) && !unused.contains(&channel) {
+                unused.push(channel);
+            }
+        }
+        unused
+    }
+}
+
+/// Writes Java's `DataOutput` types, which is what BungeeCord reads and writes its messages with
+struct DataWriter {
+    data: Vec<u8>,
+    /// Set when a value didn't fit into its length prefix, the message can't be sent then
+    too_long: bool,
+}
+
+impl DataWriter {
+    fn new(subchannel: &str) -> DataWriter {
+        let mut writer = DataWriter {
+            data: Vec::new(),
+            too_long: false,
+        };
+        writer.write_utf(subchannel);
+        writer
+    }
+
+    /// Writes Java's modified UTF-8, which encodes null as two bytes and characters outside
+    /// the BMP as two three byte surrogates, prefixed with its length as an unsigned short
+    fn write_utf(&mut self, string: &str) {
+        let mut bytes = Vec::with_capacity(string.len());
+        for unit in string.encode_utf16() {
+            match unit {
+                0x01..=0x7F => bytes.push(unit as u8),
+                0x00 | 0x80..=0x7FF => {
+                    bytes.push(0xC0 | (unit >> 6) as u8);
+                    bytes.push(0x80 | (unit & 0x3F) as u8);
+                }
+                _ => {
+                    bytes.push(0xE0 | (unit >> 12) as u8);
+                    bytes.push(0x80 | ((unit >> 6) & 0x3F) as u8);
+                    bytes.push(0x80 | (unit & 0x3F) as u8);
+                }
+            }
+        }
+        if bytes.len() > u16::MAX as usize {
+            self.too_long = true;
+            return;
+        }
+        self.data
+            .extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+        self.data.extend_from_slice(&bytes);
+    }
+
+    /// Writes bytes prefixed with their length as a short, which BungeeCord reads as signed
+    fn write_short_bytes(&mut self, bytes: &[u8]) {
+        if bytes.len() > i16::MAX as usize {
+            self.too_long = true;
+            return;
+        }
+        self.data
+            .extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+        self.data.extend_from_slice(bytes);
+    }
+
+    fn finish(self) -> Option<Vec<u8>> {
+        if self.too_long {
+            None
+        } else {
+            Some(self.data)
+        }
+    }
+}
+
+/// Reads the `DataInput` types written by BungeeCord
+struct DataReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> DataReader<'a> {
+    fn take(&mut self, bytes: usize) -> Option<&'a [u8]> {
+        if bytes > self.data.len() {
+            return None;
+        }
+        let (taken, rest) = self.data.split_at(bytes);
+        self.data = rest;
+        Some(taken)
+    }
+
+    fn read_ushort(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn read_int(&mut self) -> Option<i32> {
+        Some(i32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// Reads Java's modified UTF-8, see `DataWriter::write_utf`
+    fn read_utf(&mut self) -> Option<String> {
+        let length = self.read_ushort()? as usize;
+        let bytes = self.take(length)?;
+        let mut units = Vec::with_capacity(length);
+        let mut i = 0;
+        while i < bytes.len() {
+            let byte = bytes[i] as u16;
+            let (unit, size) = match byte {
+                0x01..=0x7F => (byte, 1),
+                0xC0..=0xDF => ((byte & 0x1F) << 6 | continuation(bytes, i + 1)?, 2),
+                0xE0..=0xEF => {
+                    let unit = (byte & 0x0F) << 12
+                        | continuation(bytes, i + 1)? << 6
+                        | continuation(bytes, i + 2)?;
+                    (unit, 3)
+                }
+                _ => return None,
+            };
+            units.push(unit);
+            i += size;
+        }
+        String::from_utf16(&units).ok()
+    }
+
+    fn read_short_bytes(&mut self) -> Option<Vec<u8>> {
+        let length = self.read_ushort()? as usize;
+        Some(self.take(length)?.to_vec())
+    }
+
+    /// BungeeCord joins lists with ", "
+    fn read_list(&mut self) -> Option<Vec<String>> {
+        let list = self.read_utf()?;
+        Some(if list.is_empty() {
+            Vec::new()
+        } else {
+            list.split(", ").map(str::to_string).collect()
+        })
+    }
+}
+
+/// The bits of a byte after the first one of a modified UTF-8 character
+fn continuation(bytes: &[u8], i: usize) -> Option<u16> {
+    match bytes.get(i) {
+        Some(byte) if byte & 0xC0 == 0x80 => Some((byte & 0x3F) as u16),
+        _ => None,
+    }
+}
+
+/// A request to the proxy on the BungeeCord channel. `server` can be `ALL` for every server
+/// where it makes sense, and `player` can be `ALL` for messages.
+pub enum BungeeRequest {
+    /// Sends the player the message is sent through to another server
+    Connect {
+        server: String,
+    },
+    ConnectOther {
+        player: String,
+        server: String,
+    },
+    /// The address of the player the message is sent through
+    Ip,
+    IpOther {
+        player: String,
+    },
+    PlayerCount {
+        server: String,
+    },
+    PlayerList {
+        server: String,
+    },
+    GetServers,
+    Message {
+        player: String,
+        message: String,
+    },
+    /// A message that's a JSON chat component
+    MessageRaw {
+        player: String,
+        json: String,
+    },
+    /// The name of this server on the proxy
+    GetServer,
+    /// Sends data to plugins on another server, `ONLINE` only sends to servers with players
+    Forward {
+        server: String,
+        subchannel: String,
+        data: Vec<u8>,
+    },
+    ForwardToPlayer {
+        player: String,
+        subchannel: String,
+        data: Vec<u8>,
+    },
+    /// The UUID of the player the message is sent through
+    Uuid,
+    UuidOther {
+        player: String,
+    },
+    ServerIp {
+        server: String,
+    },
+    KickPlayer {
+        player: String,
+        reason: String,
+    },
+}
+
+impl BungeeRequest {
+    /// Builds a request from its subchannel and its arguments in the order BungeeCord reads them,
+    /// for plugins that aren't written in Rust. `data` is what `Forward` and `ForwardToPlayer`
+    /// send. Returns None if the subchannel doesn't exist or the arguments don't match it.
+    pub fn from_parts(subchannel: &str, args: &[String], data: Vec<u8>) -> Option<BungeeRequest> {
+        let count = match subchannel {
+            "IP" | "GetServers" | "GetServer" | "UUID" => 0,
+            "Connect" | "IPOther" | "PlayerCount" | "PlayerList" | "UUIDOther" | "ServerIP" => 1,
+            _ => 2,
+        };
+        if args.len() != count {
+            return None;
+        }
+        let mut args = args.iter().cloned();
+        let mut arg = || args.next().unwrap();
+        Some(match subchannel {
+            "Connect" => BungeeRequest::Connect { server: arg() },
+            "ConnectOther" => BungeeRequest::ConnectOther {
+                player: arg(),
+                server: arg(),
+            },
+            "IP" => BungeeRequest::Ip,
+            "IPOther" => BungeeRequest::IpOther { player: arg() },
+            "PlayerCount" => BungeeRequest::PlayerCount { server: arg() },
+            "PlayerList" => BungeeRequest::PlayerList { server: arg() },
+            "GetServers" => BungeeRequest::GetServers,
+            "Message" => BungeeRequest::Message {
+                player: arg(),
+                message: arg(),
+            },
+            "MessageRaw" => BungeeRequest::MessageRaw {
+                player: arg(),
+                json: arg(),
+            },
+            "GetServer" => BungeeRequest::GetServer,
+            "Forward" => BungeeRequest::Forward {
+                server: arg(),
+                subchannel: arg(),
+                data,
+            },
+            "ForwardToPlayer" => BungeeRequest::ForwardToPlayer {
+                player: arg(),
+                subchannel: arg(),
+                data,
+            },
+            "UUID" => BungeeRequest::Uuid,
+            "UUIDOther" => BungeeRequest::UuidOther { player: arg() },
+            "ServerIP" => BungeeRequest::ServerIp { server: arg() },
+            "KickPlayer" => BungeeRequest::KickPlayer {
+                player: arg(),
+                reason: arg(),
+            },
+            _ => return None,
+        })
+    }
+
+    /// Returns None if a string or the forwarded data is too long to be sent
+    pub fn encode(&self) -> Option<Vec<u8>> {
+        let mut writer;
+        match self {
+            BungeeRequest::Connect { server } => {
+                writer = DataWriter::new("Connect");
+                writer.write_utf(server);
+            }
+            BungeeRequest::ConnectOther { player, server } => {
+                writer = DataWriter::new("ConnectOther");
+                writer.write_utf(player);
+                writer.write_utf(server);
+            }
+            BungeeRequest::Ip => writer = DataWriter::new("IP"),
+            BungeeRequest::IpOther { player } => {
+                writer = DataWriter::new("IPOther");
+                writer.write_utf(player);
+            }
+            BungeeRequest::PlayerCount { server } => {
+                writer = DataWriter::new("PlayerCount");
+                writer.write_utf(server);
+            }
+            BungeeRequest::PlayerList { server } => {
+                writer = DataWriter::new("PlayerList");
+                writer.write_utf(server);
+            }
+            BungeeRequest::GetServers => writer = DataWriter::new("GetServers"),
+            BungeeRequest::Message { player, message } => {
+                writer = DataWriter::new("Message");
+                writer.write_utf(player);
+                writer.write_utf(message);
+            }
+            BungeeRequest::MessageRaw { player, json } => {
+                writer = DataWriter::new("MessageRaw");
+                writer.write_utf(player);
+                writer.write_utf(json);
+            }
+            BungeeRequest::GetServer => writer = DataWriter::new("GetServer"),
+            BungeeRequest::Forward {
+                server,
+                subchannel,
+                data,
+            } => {
+                writer = DataWriter::new("Forward");
+                writer.write_utf(server);
+                writer.write_utf(subchannel);
+                writer.write_short_bytes(data);
+            }
+            BungeeRequest::ForwardToPlayer {
+                player,
+                subchannel,
+                data,
+            } => {
+                writer = DataWriter::new("ForwardToPlayer");
+                writer.write_utf(player);
+                writer.write_utf(subchannel);
+                writer.write_short_bytes(data);
+            }
+            BungeeRequest::Uuid => writer = DataWriter::new("UUID"),
+            BungeeRequest::UuidOther { player } => {
+                writer = DataWriter::new("UUIDOther");
+                writer.write_utf(player);
+            }
+            BungeeRequest::ServerIp { server } => {
+                writer = DataWriter::new("ServerIP");
+                writer.write_utf(server);
+            }
+            BungeeRequest::KickPlayer { player, reason } => {
+                writer = DataWriter::new("KickPlayer");
+                writer.write_utf(player);
+                writer.write_utf(reason);
+            }
+        }
+        writer.finish()
+    }
+}
+
+/// The proxy's answer to a `BungeeRequest`, or data forwarded from another server
+#[derive(Debug, PartialEq)]
+pub enum BungeeResponse {
+    Ip {
+        ip: String,
+        port: i32,
+    },
+    IpOther {
+        player: String,
+        ip: String,
+        port: i32,
+    },
+    PlayerCount {
+        server: String,
+        count: i32,
+    },
+    PlayerList {
+        server: String,
+        players: Vec<String>,
+    },
+    GetServers {
+        servers: Vec<String>,
+    },
+    GetServer {
+        server: String,
+    },
+    Uuid {
+        uuid: String,
+    },
+    UuidOther {
+        player: String,
+        uuid: String,
+    },
+    ServerIp {
+        server: String,
+        ip: String,
+        port: u16,
+    },
+    /// Sent with `Forward` or `ForwardToPlayer`, under the subchannel the sender chose
+    Forwarded {
+        subchannel: String,
+        data: Vec<u8>,
+    },
+}
+
+impl BungeeResponse {
+    /// Returns None if the message is malformed
+    pub fn decode(data: &[u8]) -> Option<BungeeResponse> {
+        let mut reader = DataReader { data };
+        let subchannel = reader.read_utf()?;
+        Some(match subchannel.as_str() {
+            "IP" => BungeeResponse::Ip {
+                ip: reader.read_utf()?,
+                port: reader.read_int()?,
+            },
+            "IPOther" => BungeeResponse::IpOther {
+                player: reader.read_utf()?,
+                ip: reader.read_utf()?,
+                port: reader.read_int()?,
+            },
+            "PlayerCount" => BungeeResponse::PlayerCount {
+                server: reader.read_utf()?,
+                count: reader.read_int()?,
+            },
+            "PlayerList" => BungeeResponse::PlayerList {
+                server: reader.read_utf()?,
+                players: reader.read_list()?,
+            },
+            "GetServers" => BungeeResponse::GetServers {
+                servers: reader.read_list()?,
+            },
+            "GetServer" => BungeeResponse::GetServer {
+                server: reader.read_utf()?,
+            },
+            "UUID" => BungeeResponse::Uuid {
+                uuid: reader.read_utf()?,
+            },
+            "UUIDOther" => BungeeResponse::UuidOther {
+                player: reader.read_utf()?,
+                uuid: reader.read_utf()?,
+            },
+            "ServerIP" => BungeeResponse::ServerIp {
+                server: reader.read_utf()?,
+                ip: reader.read_utf()?,
+                port: reader.read_ushort()?,
+            },
+            _ => BungeeResponse::Forwarded {
+                subchannel,
+                data: reader.read_short_bytes()?,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(string: &str) -> String {
+        let mut writer = DataWriter::new(string);
+        writer.write_short_bytes(&[1, 2, 3]);
+        let data = writer.finish().unwrap();
+        let mut reader = DataReader { data: &data };
+        let read = reader.read_utf().unwrap();
+        assert_eq!(reader.read_short_bytes().unwrap(), [1, 2, 3]);
+        assert!(reader.data.is_empty());
+        read
+    }
+
+    #[test]
+    fn modified_utf8() {
+        for string in &["", "Connect", "§aGrün ✓", "日本語", "a\0b", "🙂 emoji"] {
+            assert_eq!(round_trip(string), *string);
+        }
+        // Null takes two bytes and characters outside the BMP are written as surrogates
+        let data = DataWriter::new("\0🙂").finish().unwrap();
+        assert_eq!(data, [0, 8, 0xC0, 0x80, 0xED, 0xA0, 0xBD, 0xED, 0xB9, 0x82]);
+    }
+
+    #[test]
+    fn invalid_modified_utf8() {
+        for data in &[
+            // Plain UTF-8 outside the BMP
+            &[0, 4, 0xF0, 0x9F, 0x99, 0x82][..],
+            // A missing continuation byte
+            &[0, 2, 0xE6, 0x97],
+            // Longer than the message
+            &[0, 3, b'a'],
+            // A lone surrogate
+            &[0, 3, 0xED, 0xA0, 0xBD],
+        ] {
+            assert_eq!(DataReader { data }.read_utf(), None);
+        }
+    }
+
+    #[test]
+    fn too_long() {
+        let mut writer = DataWriter::new(&"a".repeat(u16::MAX as usize));
+        assert!(writer.finish().is_some());
+        // Each of these takes three bytes
+        writer = DataWriter::new(&"✓".repeat(u16::MAX as usize / 3 + 1));
+        assert!(writer.finish().is_none());
+        let forward = BungeeRequest::Forward {
+            server: "ALL".to_string(),
+            subchannel: "test".to_string(),
+            data: vec![0; i16::MAX as usize + 1],
+        };
+        assert!(forward.encode().is_none());
+    }
+
+    #[test]
+    fn bungee_requests() {
+        let connect = BungeeRequest::Connect {
+            server: "lobby".to_string(),
+        };
+        assert_eq!(connect.encode().unwrap(), b"\0\x07Connect\0\x05lobby");
+        let forward = BungeeRequest::Forward {
+            server: "ALL".to_string(),
+            subchannel: "test".to_string(),
+            data: vec![1, 2],
+        };
+        let data = forward.encode().unwrap();
+        assert_eq!(data, b"\0\x07Forward\0\x03ALL\0\x04test\0\x02\x01\x02");
+        // The other servers get the data without the target server
+        assert_eq!(
+            BungeeResponse::decode(&data[14..]),
+            Some(BungeeResponse::Forwarded {
+                subchannel: "test".to_string(),
+                data: vec![1, 2],
+            })
+        );
+    }
+
+    #[test]
+    fn bungee_requests_from_parts() {
+        let args =
+            |args: &[&str]| -> Vec<String> { args.iter().map(|arg| arg.to_string()).collect() };
+        let forward = BungeeRequest::from_parts("Forward", &args(&["ALL", "test"]), vec![1, 2]);
+        assert_eq!(
+            forward.unwrap().encode().unwrap(),
+            b"\0\x07Forward\0\x03ALL\0\x04test\0\x02\x01\x02"
+        );
+        let uuid = BungeeRequest::from_parts("UUID", &[], Vec::new());
+        assert_eq!(uuid.unwrap().encode().unwrap(), b"\0\x04UUID");
+        let connect = BungeeRequest::from_parts("Connect", &args(&["lobby"]), Vec::new());
+        assert_eq!(
+            connect.unwrap().encode().unwrap(),
+            b"\0\x07Connect\0\x05lobby"
+        );
+        assert!(BungeeRequest::from_parts("Connect", &[], Vec::new()).is_none());
+        assert!(BungeeRequest::from_parts("IP", &args(&["Steve"]), Vec::new()).is_none());
+        assert!(BungeeRequest::from_parts("Teleport", &args(&["a", "b"]), Vec::new()).is_none());
+    }
+
+    #[test]
+    fn bungee_responses() {
+        let data = b"\0\x0bPlayerCount\0\x03ALL\0\0\0\x05";
+        assert_eq!(
+            BungeeResponse::decode(data),
+            Some(BungeeResponse::PlayerCount {
+                server: "ALL".to_string(),
+                count: 5,
+            })
+        );
+        let data = b"\0\x0aPlayerList\0\x03hub\0\x0bAlex, Steve";
+        assert_eq!(
+            BungeeResponse::decode(data),
+            Some(BungeeResponse::PlayerList {
+                server: "hub".to_string(),
+                players: vec!["Alex".to_string(), "Steve".to_string()],
+            })
+        );
+        let data = b"\0\x0aGetServers\0\0";
+        assert_eq!(
+            BungeeResponse::decode(data),
+            Some(BungeeResponse::GetServers {
+                servers: Vec::new()
+            })
+        );
+        assert_eq!(BungeeResponse::decode(b"\0\x02IP\0\x03"), None);
+    }
+
+    #[test]
+    fn channel_names() {
+        assert_eq!(channel_name("BungeeCord"), Some(BUNGEECORD));
+        assert_eq!(channel_name("my_plugin:data"), Some("my_plugin:data"));
+        assert_eq!(channel_name("data"), None);
+        assert_eq!(channel_name("My:Data"), None);
+    }
+
+    #[test]
+    fn register_and_unregister() {
+        let mut channels = Vec::new();
+        assert!(register_channels(
+            &mut channels,
+            b"a:one\0Invalid\0a:two\0a:one"
+        ));
+        assert_eq!(channels, ["a:one", "a:two"]);
+        unregister_channels(&mut channels, b"a:one\0a:three");
+        assert_eq!(channels, ["a:two"]);
+    }
+
+    #[test]
+    fn register_too_many_channels() {
+        let names: Vec<String> = (0..MAX_CLIENT_CHANNELS + 10)
+            .map(|i| format!("test:channel_{}", i))
+            .collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let mut channels = Vec::new();
+        assert!(!register_channels(
+            &mut channels,
+            &encode_channel_list(&names)
+        ));
+        assert_eq!(channels.len(), MAX_CLIENT_CHANNELS);
+        // Registering a channel again doesn't count
+        assert!(register_channels(&mut channels, b"test:channel_0"));
+        unregister_channels(&mut channels, b"test:channel_0");
+        assert!(register_channels(&mut channels, b"test:channel_200"));
+        assert!(!register_channels(&mut channels, b"test:channel_201"));
+    }
+
+    #[test]
+    fn brand() {
+        assert_eq!(decode_brand(&encode_brand("vanilla")).unwrap(), "vanilla");
+        let long = "x".repeat(300);
+        assert_eq!(decode_brand(&encode_brand(&long)).unwrap(), long);
+        assert_eq!(decode_brand(&[5, b'a']), None);
+    }
+}
@@ -1,3 +1,8 @@
+use log::error;
+use serde::{Deserialize, Deserializer, Serialize};
+
+#[derive(Copy, Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ChatColor {
     Black,
     DarkBlue,
@@ -15,9 +20,61 @@ pub enum ChatColor {
     LightPurple,
     Yellow,
     White,
+    /// Resets the color inherited from the parent
+    Reset,
+    #[default]
     NoColor,
 }
 
+impl ChatColor {
+    /// Colors in the order of their legacy codes 0-9 and a-f
+    const LEGACY: [ChatColor; 16] = [
+        ChatColor::Black,
+        ChatColor::DarkBlue,
+        ChatColor::DarkGreen,
+        ChatColor::DarkAqua,
+        ChatColor::DarkRed,
+        ChatColor::DarkPurple,
+        ChatColor::Gold,
+        ChatColor::Gray,
+        ChatColor::DarkGray,
+        ChatColor::Blue,
+        ChatColor::Green,
+        ChatColor::Aqua,
+        ChatColor::Red,
+        ChatColor::LightPurple,
+        ChatColor::Yellow,
+        ChatColor::White,
+    ];
+
+    pub fn from_legacy_code(code: char) -> Option<ChatColor> {
+        let index = code.to_digit(16)?;
+        Some(ChatColor::LEGACY[index as usize])
+    }
+
+    pub fn legacy_code(self) -> Option<char> {
+        match self {
+            ChatColor::Reset => Some('r'),
+            ChatColor::NoColor => None,
+            color => {
+                let index = ChatColor::LEGACY.iter().position(|c| *c == color)?;
+                std::char::from_digit(index as u32, 16)
+            }
+        }
+    }
+
+    /// Looks up a color by the name used in JSON, e.g. `dark_red`
+    pub fn from_name(name: &str) -> Option<ChatColor> {
+        ChatColor::LEGACY
+            .iter()
+            .chain(&[ChatColor::Reset])
+            .find(|color| ChatComponent::color_to_str(**color) == name)
+            .copied()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ClickEventType {
     OpenUrl,
     RunCommand,
@@ -25,40 +82,287 @@ pub enum ClickEventType {
     ChangePage,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum HoverEventType {
     ShowText,
+    /// The value is the item as SNBT
     ShowItem,
+    /// The value is the entity's name, type and UUID as SNBT
     ShowEntity,
-    ChangePage,
 }
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ClickEvent {
+    pub action: ClickEventType,
+    pub value: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HoverEvent {
+    pub action: HoverEventType,
+    #[serde(deserialize_with = "deserialize_component")]
+    pub value: Box<ChatComponent>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Score {
+    /// A player name, UUID or selector
+    pub name: String,
+    pub objective: String,
+    /// Shown instead of the actual score if set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+}
+
+/// What a component displays, only one of these is allowed per component
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ChatContent {
+    Text {
+        text: String,
+    },
+    Translate {
+        translate: String,
+        #[serde(
+            default,
+            skip_serializing_if = "Vec::is_empty",
+            deserialize_with = "deserialize_components"
+        )]
+        with: Vec<ChatComponent>,
+    },
+    Score {
+        score: Score,
+    },
+    Selector {
+        selector: String,
+    },
+    Keybind {
+        keybind: String,
+    },
+}
+
+/// A component of the JSON chat format, children inherit the style of their parent.
+/// Style fields that aren't set are inherited, so `Some(false)` is different from `None`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct ChatComponent {
-    text: String,
-    color: ChatColor,
-    obfuscated: bool,
-    bold: bool,
-    strikethrough: bool,
-    underline: bool,
-    italic: bool,
-    reset: bool,
-    extra: Vec<ChatComponent>,
+    #[serde(flatten)]
+    pub content: ChatContent,
+    #[serde(default, skip_serializing_if = "is_no_color")]
+    pub color: ChatColor,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub obfuscated: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bold: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strikethrough: Option<bool>,
+    #[serde(
+        rename = "underlined",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub underline: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub italic: Option<bool>,
+    /// Text inserted into the chat box when the component is shift clicked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub insertion: Option<String>,
+    #[serde(
+        rename = "clickEvent",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub click_event: Option<ClickEvent>,
+    #[serde(
+        rename = "hoverEvent",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub hover_event: Option<HoverEvent>,
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "deserialize_components"
+    )]
+    pub extra: Vec<ChatComponent>,
+}
+
+fn is_no_color(color: &ChatColor) -> bool {
+    *color == ChatColor::NoColor
+}
+
+/// Plain strings are allowed wherever a component is expected
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ComponentOrText {
+    Text(String),
+    Component(ChatComponent),
+}
+
+impl From<ComponentOrText> for ChatComponent {
+    fn from(value: ComponentOrText) -> ChatComponent {
+        match value {
+            ComponentOrText::Text(text) => ChatComponent::new(text),
+            ComponentOrText::Component(component) => component,
+        }
+    }
+}
+
+fn deserialize_component<'de, D>(deserializer: D) -> Result<Box<ChatComponent>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let component = ComponentOrText::deserialize(deserializer)?;
+    Ok(Box::new(component.into()))
+}
+
+fn deserialize_components<'de, D>(deserializer: D) -> Result<Vec<ChatComponent>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let components = Vec::<ComponentOrText>::deserialize(deserializer)?;
+    Ok(components.into_iter().map(ChatComponent::from).collect())
 }
 
 impl ChatComponent {
-    pub fn new(text: String) -> Self {
+    fn with_content(content: ChatContent) -> Self {
         ChatComponent {
-            text,
+            content,
             color: ChatColor::NoColor,
-            obfuscated: false,
-            bold: false,
-            strikethrough: false,
-            underline: false,
-            italic: false,
-            reset: false,
+            obfuscated: None,
+            bold: None,
+            strikethrough: None,
+            underline: None,
+            italic: None,
+            insertion: None,
+            click_event: None,
+            hover_event: None,
             extra: Vec::new(),
         }
     }
 
+    pub fn new(text: String) -> Self {
+        ChatComponent::with_content(ChatContent::Text { text })
+    }
+
+    pub fn translate(key: &str, with: Vec<ChatComponent>) -> Self {
+        ChatComponent::with_content(ChatContent::Translate {
+            translate: key.to_string(),
+            with,
+        })
+    }
+
+    pub fn score(name: &str, objective: &str) -> Self {
+        ChatComponent::with_content(ChatContent::Score {
+            score: Score {
+                name: name.to_string(),
+                objective: objective.to_string(),
+                value: None,
+            },
+        })
+    }
+
+    pub fn selector(selector: &str) -> Self {
+        ChatComponent::with_content(ChatContent::Selector {
+            selector: selector.to_string(),
+        })
+    }
+
+    /// Shows the key bound to a control, e.g. `key.inventory`
+    pub fn keybind(keybind: &str) -> Self {
+        ChatComponent::with_content(ChatContent::Keybind {
+            keybind: keybind.to_string(),
+        })
+    }
+
+    pub fn color(mut self, color: ChatColor) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn bold(mut self, bold: bool) -> Self {
+        self.bold = Some(bold);
+        self
+    }
+
+    pub fn italic(mut self, italic: bool) -> Self {
+        self.italic = Some(italic);
+        self
+    }
+
+    pub fn underline(mut self, underline: bool) -> Self {
+        self.underline = Some(underline);
+        self
+    }
+
+    pub fn strikethrough(mut self, strikethrough: bool) -> Self {
+        self.strikethrough = Some(strikethrough);
+        self
+    }
+
+    pub fn obfuscated(mut self, obfuscated: bool) -> Self {
+        self.obfuscated = Some(obfuscated);
+        self
+    }
+
+    pub fn insertion(mut self, insertion: &str) -> Self {
+        self.insertion = Some(insertion.to_string());
+        self
+    }
+
+    pub fn click_event(mut self, action: ClickEventType, value: &str) -> Self {
+        self.click_event = Some(ClickEvent {
+            action,
+            value: value.to_string(),
+        });
+        self
+    }
+
+    pub fn hover_event(mut self, action: HoverEventType, value: ChatComponent) -> Self {
+        self.hover_event = Some(HoverEvent {
+            action,
+            value: Box::new(value),
+        });
+        self
+    }
+
+    pub fn append(mut self, child: ChatComponent) -> Self {
+        self.extra.push(child);
+        self
+    }
+
+    /// The JSON representation used by the `Chat` type in packets
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|err| {
+            // Send the text at least rather than nothing
+            error!("Couldn't serialize a chat component: {}", err);
+            serde_json::json!({ "text": self.to_legacy() }).to_string()
+        })
+    }
+
+    /// Parses a component from JSON, which can also be a plain string or
+    /// an array of components where the rest are children of the first
+    pub fn from_json(json: &str) -> serde_json::Result<ChatComponent> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Json {
+            Single(ComponentOrText),
+            List(Vec<ComponentOrText>),
+        }
+        match serde_json::from_str(json)? {
+            Json::Single(component) => Ok(component.into()),
+            Json::List(components) => {
+                let mut components = components.into_iter().map(ChatComponent::from);
+                let mut first = match components.next() {
+                    Some(first) => first,
+                    None => ChatComponent::new(String::new()),
+                };
+                first.extra.extend(components);
+                Ok(first)
+            }
+        }
+    }
+
     pub fn color_to_str(cc: ChatColor) -> &'static str {
         match cc {
             ChatColor::Black => "black",
@@ -77,7 +381,778 @@ impl ChatComponent {
             ChatColor::LightPurple => "light_purple",
             ChatColor::Yellow => "yellow",
             ChatColor::White => "white",
+            ChatColor::Reset => "reset",
             ChatColor::NoColor => "",
         }
     }
 }
+
+/// The style a piece of text ends up with after inheriting from its parents
+#[derive(Clone, Default, PartialEq)]
+struct Style {
+    color: ChatColor,
+    obfuscated: bool,
+    bold: bool,
+    strikethrough: bool,
+    underline: bool,
+    italic: bool,
+    insertion: Option<String>,
+    click_event: Option<ClickEvent>,
+    hover_event: Option<HoverEvent>,
+}
+
+impl Style {
+    fn inherit(&self, component: &ChatComponent) -> Style {
+        Style {
+            color: match component.color {
+                ChatColor::NoColor => self.color,
+                color => color,
+            },
+            obfuscated: component.obfuscated.unwrap_or(self.obfuscated),
+            bold: component.bold.unwrap_or(self.bold),
+            strikethrough: component.strikethrough.unwrap_or(self.strikethrough),
+            underline: component.underline.unwrap_or(self.underline),
+            italic: component.italic.unwrap_or(self.italic),
+            insertion: component
+                .insertion
+                .clone()
+                .or_else(|| self.insertion.clone()),
+            click_event: component
+                .click_event
+                .clone()
+                .or_else(|| self.click_event.clone()),
+            hover_event: component
+                .hover_event
+                .clone()
+                .or_else(|| self.hover_event.clone()),
+        }
+    }
+
+    /// Applies this style to a component without a parent
+    fn apply(&self, mut component: ChatComponent) -> ChatComponent {
+        let set = |enabled: bool| if enabled { Some(true) } else { None };
+        component.color = self.color;
+        component.obfuscated = set(self.obfuscated);
+        component.bold = set(self.bold);
+        component.strikethrough = set(self.strikethrough);
+        component.underline = set(self.underline);
+        component.italic = set(self.italic);
+        component.insertion = self.insertion.clone();
+        component.click_event = self.click_event.clone();
+        component.hover_event = self.hover_event.clone();
+        component
+    }
+
+    /// Returns None if the character isn't a legacy code
+    fn with_legacy_code(&self, code: char) -> Option<Style> {
+        let code = code.to_ascii_lowercase();
+        if let Some(color) = ChatColor::from_legacy_code(code) {
+            // Colors reset the formatting
+            return Some(Style {
+                color,
+                ..Style::default()
+            });
+        }
+        let mut style = self.clone();
+        match code {
+            'k' => style.obfuscated = true,
+            'l' => style.bold = true,
+            'm' => style.strikethrough = true,
+            'n' => style.underline = true,
+            'o' => style.italic = true,
+            'r' => style = Style::default(),
+            _ => return None,
+        }
+        Some(style)
+    }
+
+    /// The legacy codes for this style, empty for the default style
+    fn legacy_codes(&self) -> String {
+        let mut codes = String::new();
+        let formatting = [
+            (self.obfuscated, 'k'),
+            (self.bold, 'l'),
+            (self.strikethrough, 'm'),
+            (self.underline, 'n'),
+            (self.italic, 'o'),
+        ];
+        if let Some(code) = self.color.legacy_code() {
+            codes.push('§');
+            codes.push(code);
+        } else if formatting.iter().any(|(enabled, _)| *enabled) {
+            codes.push_str("§r");
+        }
+        for (enabled, code) in formatting.iter() {
+            if *enabled {
+                codes.push('§');
+                codes.push(*code);
+            }
+        }
+        codes
+    }
+
+    /// Returns the canonical tag name and the style with the tag applied,
+    /// or None if the tag is unknown
+    fn with_tag(&self, tag: &str) -> Option<(&'static str, Style)> {
+        let mut parts = tag.splitn(2, ':');
+        let name = parts.next().unwrap_or("");
+        let argument = parts.next();
+        let mut style = self.clone();
+        let name = match (name, argument) {
+            ("bold", None) | ("b", None) => {
+                style.bold = true;
+                "bold"
+            }
+            ("italic", None) | ("i", None) | ("em", None) => {
+                style.italic = true;
+                "italic"
+            }
+            ("underlined", None) | ("u", None) => {
+                style.underline = true;
+                "underlined"
+            }
+            ("strikethrough", None) | ("st", None) => {
+                style.strikethrough = true;
+                "strikethrough"
+            }
+            ("obfuscated", None) | ("obf", None) => {
+                style.obfuscated = true;
+                "obfuscated"
+            }
+            ("color", Some(color)) | ("c", Some(color)) => {
+                style.color = ChatColor::from_name(color)?;
+                "color"
+            }
+            ("insert", Some(insertion)) => {
+                style.insertion = Some(insertion.to_string());
+                "insert"
+            }
+            ("click", Some(argument)) => {
+                let mut parts = argument.splitn(2, ':');
+                let action = match parts.next()? {
+                    "open_url" => ClickEventType::OpenUrl,
+                    "run_command" => ClickEventType::RunCommand,
+                    "suggest_command" => ClickEventType::SuggestCommand,
+                    "change_page" => ClickEventType::ChangePage,
+                    _ => return None,
+                };
+                style.click_event = Some(ClickEvent {
+                    action,
+                    value: unquote(parts.next()?).to_string(),
+                });
+                "click"
+            }
+            ("hover", Some(argument)) => {
+                let mut parts = argument.splitn(2, ':');
+                let action = match parts.next()? {
+                    "show_text" => HoverEventType::ShowText,
+                    "show_item" => HoverEventType::ShowItem,
+                    "show_entity" => HoverEventType::ShowEntity,
+                    _ => return None,
+                };
+                let value = unquote(parts.next()?);
+                let value = match action {
+                    HoverEventType::ShowText => ChatComponent::from_markup(value),
+                    _ => ChatComponent::new(value.to_string()),
+                };
+                style.hover_event = Some(HoverEvent {
+                    action,
+                    value: Box::new(value),
+                });
+                "hover"
+            }
+            (name, None) => {
+                // Named colors are tags of their own
+                let color = ChatColor::from_name(name)?;
+                style.color = color;
+                ChatComponent::color_to_str(color)
+            }
+            _ => return None,
+        };
+        Some((name, style))
+    }
+}
+
+/// The canonical name of a closing tag
+fn closing_tag_name(name: &str) -> &str {
+    match name {
+        "b" => "bold",
+        "i" | "em" => "italic",
+        "u" => "underlined",
+        "st" => "strikethrough",
+        "obf" => "obfuscated",
+        "c" => "color",
+        name => name,
+    }
+}
+
+/// Strips matching single or double quotes around a tag argument
+fn unquote(argument: &str) -> &str {
+    let quoted = argument.len() >= 2
+        && (argument.starts_with('\'') && argument.ends_with('\'')
+            || argument.starts_with('"') && argument.ends_with('"'));
+    if quoted {
+        &argument[1..argument.len() - 1]
+    } else {
+        argument
+    }
+}
+
+/// Finds the end of a tag starting at `start`, skipping `>` inside quoted arguments
+fn tag_end(text: &str, start: usize) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in text[start..].char_indices() {
+        match (quote, c) {
+            (None, '>') => return Some(start + i),
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// English translations for keys the server uses itself, so they can be shown in the console
+fn translation(key: &str) -> Option<&'static str> {
+    Some(match key {
+        "chat.type.text" => "<%s> %s",
+        "chat.type.announcement" => "[%s] %s",
+        "chat.type.emote" => "* %s %s",
+        "multiplayer.player.joined" => "%s joined the game",
+        "multiplayer.player.left" => "%s left the game",
+        "multiplayer.disconnect.outdated_client" => "Outdated client! Please use %s",
+        "multiplayer.disconnect.outdated_server" => "Outdated server! I'm still on %s",
+        "disconnect.genericReason" => "%s",
+        "container.crafting" => "Crafting",
+        "multiplayer.disconnect.kicked" => "Kicked by an operator",
+        "multiplayer.disconnect.server_shutdown" => "Server closed",
+        "multiplayer.disconnect.banned" => "You are banned from this server",
+        "multiplayer.disconnect.banned.reason" => "You are banned from this server.\nReason: %s",
+        "multiplayer.disconnect.banned.expiration" => "\nYour ban will be removed on %s",
+        "multiplayer.disconnect.banned_ip.reason" => {
+            "Your IP address is banned from this server.\nReason: %s"
+        }
+        "multiplayer.disconnect.banned_ip.expiration" => "\nYour ban will be removed on %s",
+        "multiplayer.disconnect.ip_banned" => "You have been IP banned from this server",
+        "multiplayer.disconnect.unverified_username" => "Failed to verify username!",
+        "multiplayer.disconnect.not_whitelisted" => "You are not white-listed on this server!",
+        "gameMode.survival" => "Survival Mode",
+        "gameMode.creative" => "Creative Mode",
+        "gameMode.adventure" => "Adventure Mode",
+        "gameMode.spectator" => "Spectator Mode",
+        "gameMode.changed" => "Your game mode has been updated to %s",
+        "commands.teleport.success.entity.single" => "Teleported %s to %s",
+        "commands.teleport.success.entity.multiple" => "Teleported %s entities to %s",
+        "commands.teleport.success.location.single" => "Teleported %s to %s, %s, %s",
+        "commands.teleport.success.location.multiple" => "Teleported %s entities to %s, %s, %s",
+        "commands.gamemode.success.self" => "Set own game mode to %s",
+        "commands.gamemode.success.other" => "Set %s's game mode to %s",
+        "commands.give.success.single" => "Gave %s %s to %s",
+        "commands.give.success.multiple" => "Gave %s %s to %s players",
+        "commands.kick.success" => "Kicked %s: %s",
+        "commands.time.set" => "Set the time to %s",
+        "commands.time.query" => "The time is %s",
+        "commands.weather.set.clear" => "Set the weather to clear",
+        "commands.weather.set.rain" => "Set the weather to rain",
+        "commands.weather.set.thunder" => "Set the weather to rain & thunder",
+        "commands.list.players" => "There are %s of a max %s players online: %s",
+        "commands.stop.stopping" => "Stopping the server",
+        "commands.save.saving" => "Saving the game (this may take a moment!)",
+        "commands.save.success" => "Saved the game",
+        "commands.op.success" => "Made %s a server operator",
+        "commands.deop.success" => "Made %s no longer a server operator",
+        "commands.whitelist.add.success" => "Added %s to the whitelist",
+        "commands.whitelist.remove.success" => "Removed %s from the whitelist",
+        "commands.whitelist.list" => "There are %s whitelisted players: %s",
+        "commands.whitelist.none" => "There are no whitelisted players",
+        "commands.whitelist.enabled" => "Whitelist is now turned on",
+        "commands.whitelist.disabled" => "Whitelist is now turned off",
+        "commands.whitelist.reloaded" => "Reloaded the whitelist",
+        "commands.ban.success" => "Banned %s: %s",
+        "commands.banip.success" => "Banned IP %s: %s",
+        "commands.banip.info" => "This ban affects %s players: %s",
+        "commands.pardon.success" => "Unbanned %s",
+        "commands.pardonip.success" => "Unbanned IP %s",
+        _ => return None,
+    })
+}
+
+/// Collects styled pieces of text, merging consecutive pieces with the same style
+struct StyledText {
+    root: ChatComponent,
+    text: String,
+    style: Style,
+}
+
+impl StyledText {
+    fn new() -> StyledText {
+        StyledText {
+            root: ChatComponent::new(String::new()),
+            text: String::new(),
+            style: Style::default(),
+        }
+    }
+
+    fn set_style(&mut self, style: Style) {
+        if style != self.style {
+            self.flush();
+            self.style = style;
+        }
+    }
+
+    fn flush(&mut self) {
+        if !self.text.is_empty() {
+            let text = std::mem::take(&mut self.text);
+            let component = self.style.apply(ChatComponent::new(text));
+            self.root.extra.push(component);
+        }
+    }
+
+    /// Adds a component that keeps its own style where it has one
+    fn push_component(&mut self, component: ChatComponent) {
+        self.flush();
+        if self.style == Style::default() {
+            self.root.extra.push(component);
+        } else {
+            let parent = self.style.apply(ChatComponent::new(String::new()));
+            self.root.extra.push(parent.append(component));
+        }
+    }
+
+    fn finish(mut self) -> ChatComponent {
+        self.flush();
+        if self.root.extra.len() == 1 {
+            self.root.extra.pop().unwrap()
+        } else {
+            self.root
+        }
+    }
+}
+
+impl ChatComponent {
+    /// Parses text with legacy formatting codes like `§a` or `&l`.
+    /// Codes can start with `§` or the given prefix, use `§` if there's no other prefix.
+    pub fn from_legacy(text: &str, prefix: char) -> ChatComponent {
+        let mut styled = StyledText::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c == '§' || c == prefix {
+                let style = chars
+                    .peek()
+                    .and_then(|code| styled.style.with_legacy_code(*code));
+                if let Some(style) = style {
+                    chars.next();
+                    styled.set_style(style);
+                    continue;
+                }
+            }
+            styled.text.push(c);
+        }
+        styled.finish()
+    }
+
+    /// Parses tag based markup like `<red>Hello <bold>world</bold></red>`.
+    ///
+    /// Supported tags are the color names, `<color:name>`, `<bold>`, `<italic>`, `<underlined>`,
+    /// `<strikethrough>`, `<obfuscated>`, `<click:action:value>`, `<hover:show_text:'markup'>`,
+    /// `<insert:text>` and `<reset>`, as well as `<key:name>` and `<lang:key>` for keybind and
+    /// translate components. Closing tags end the most recent tag with that name and everything
+    /// opened after it. Unknown tags are kept as text, `\<` escapes a tag.
+    pub fn from_markup(text: &str) -> ChatComponent {
+        ChatComponent::format(text, &[])
+    }
+
+    /// Parses markup like `from_markup`, replacing placeholders like `{name}` with components.
+    /// The components aren't parsed as markup, so they're safe to build from user input.
+    pub fn format(text: &str, placeholders: &[(&str, ChatComponent)]) -> ChatComponent {
+        let mut styled = StyledText::new();
+        let mut tags: Vec<(&str, Style)> = Vec::new();
+        let mut i = 0;
+        while let Some(c) = text[i..].chars().next() {
+            if c == '\\' && text[i + 1..].starts_with('<') {
+                styled.text.push('<');
+                i += 2;
+                continue;
+            }
+            if c == '{' {
+                let placeholder = text[i + 1..].find('}').and_then(|length| {
+                    let name = &text[i + 1..i + 1 + length];
+                    placeholders
+                        .iter()
+                        .find(|(placeholder, _)| *placeholder == name)
+                        .map(|(_, component)| (length, component))
+                });
+                if let Some((length, component)) = placeholder {
+                    styled.push_component(component.clone());
+                    i += length + 2;
+                    continue;
+                }
+            }
+            let end = if c == '<' { tag_end(text, i) } else { None };
+            let end = match end {
+                Some(end) => end,
+                None => {
+                    styled.text.push(c);
+                    i += c.len_utf8();
+                    continue;
+                }
+            };
+            let tag = &text[i + 1..end];
+            let current = tags
+                .last()
+                .map(|(_, style)| style.clone())
+                .unwrap_or_default();
+            let mut parts = tag.splitn(2, ':');
+            let handled = match (parts.next().unwrap_or(""), parts.next()) {
+                ("reset", None) => {
+                    tags.clear();
+                    true
+                }
+                ("key", Some(key)) => {
+                    styled.set_style(current);
+                    styled.push_component(ChatComponent::keybind(key));
+                    true
+                }
+                ("lang", Some(key)) => {
+                    styled.set_style(current);
+                    styled.push_component(ChatComponent::translate(key, Vec::new()));
+                    true
+                }
+                _ if tag.starts_with('/') => {
+                    let name = closing_tag_name(&tag[1..]);
+                    match tags.iter().rposition(|(tag, _)| *tag == name) {
+                        Some(index) => {
+                            tags.truncate(index);
+                            true
+                        }
+                        None => false,
+                    }
+                }
+                _ => match current.with_tag(tag) {
+                    Some(tag) => {
+                        tags.push(tag);
+                        true
+                    }
+                    None => false,
+                },
+            };
+            if handled {
+                let style = tags
+                    .last()
+                    .map(|(_, style)| style.clone())
+                    .unwrap_or_default();
+                styled.set_style(style);
+                i = end + 1;
+            } else {
+                styled.text.push('<');
+                i += 1;
+            }
+        }
+        styled.finish()
+    }
+
+    /// Converts the component to text with `§` formatting codes, e.g. for the console.
+    /// Events and insertions are lost and only the translations the server uses are known.
+    pub fn to_legacy(&self) -> String {
+        let mut out = String::new();
+        let mut codes = String::new();
+        self.write_legacy(&Style::default(), &mut codes, &mut out);
+        out
+    }
+
+    fn write_legacy(&self, parent: &Style, codes: &mut String, out: &mut String) {
+        let style = parent.inherit(self);
+        let write_text = |text: &str, out: &mut String, codes: &mut String| {
+            if text.is_empty() {
+                return;
+            }
+            let new_codes = style.legacy_codes();
+            if new_codes != *codes {
+                out.push_str(if new_codes.is_empty() {
+                    "§r"
+                } else {
+                    &new_codes
+                });
+                *codes = new_codes;
+            }
+            out.push_str(text);
+        };
+        match &self.content {
+            ChatContent::Text { text } => write_text(text, out, codes),
+            ChatContent::Translate { translate, with } => {
+                let format = translation(translate).unwrap_or(translate);
+                let mut next_argument = 0;
+                let mut rest = format;
+                while let Some(index) = rest.find('%') {
+                    write_text(&rest[..index], out, codes);
+                    rest = &rest[index + 1..];
+                    if rest.starts_with('%') {
+                        write_text("%", out, codes);
+                        rest = &rest[1..];
+                        continue;
+                    }
+                    // Either %s or a positional %1$s
+                    let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(0);
+                    let argument = if digits > 0 && rest[digits..].starts_with("$s") {
+                        let position: usize = rest[..digits].parse().unwrap_or(0);
+                        rest = &rest[digits + 2..];
+                        position.checked_sub(1)
+                    } else if rest.starts_with('s') {
+                        rest = &rest[1..];
+                        next_argument += 1;
+                        Some(next_argument - 1)
+                    } else {
+                        write_text("%", out, codes);
+                        continue;
+                    };
+                    if let Some(argument) = argument.and_then(|argument| with.get(argument)) {
+                        argument.write_legacy(&style, codes, out);
+                    }
+                }
+                write_text(rest, out, codes);
+            }
+            ChatContent::Score { score } => {
+                write_text(score.value.as_ref().unwrap_or(&score.name), out, codes)
+            }
+            ChatContent::Selector { selector } => write_text(selector, out, codes),
+            ChatContent::Keybind { keybind } => write_text(keybind, out, codes),
+        }
+        for child in &self.extra {
+            child.write_legacy(&style, codes, out);
+        }
+    }
+}
+
+/// The longest message a client is allowed to send
+pub const MAX_MESSAGE_LENGTH: usize = 256;
+
+/// Removes the characters vanilla doesn't allow in chat and collapses whitespace
+pub fn sanitize_message(message: &str) -> String {
+    let allowed: String = message
+        .chars()
+        .filter(|c| *c != '§' && *c >= ' ' && *c != '\u{7f}')
+        .collect();
+    allowed.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> ChatComponent {
+        ChatComponent::new(text.to_string())
+    }
+
+    /// Components joined under an empty root, like the parsers return them
+    fn joined(components: Vec<ChatComponent>) -> ChatComponent {
+        components.into_iter().fold(text(""), ChatComponent::append)
+    }
+
+    fn round_trip(component: &ChatComponent) {
+        let json = component.to_json();
+        assert_eq!(
+            &ChatComponent::from_json(&json).unwrap(),
+            component,
+            "{}",
+            json
+        );
+    }
+
+    #[test]
+    fn translate_with_arguments() {
+        let component = ChatComponent::translate(
+            "chat.type.text",
+            vec![text("Steve").color(ChatColor::Gold), text("hi")],
+        );
+        assert_eq!(
+            component.to_json(),
+            r#"{"translate":"chat.type.text","with":[{"text":"Steve","color":"gold"},{"text":"hi"}]}"#
+        );
+        round_trip(&component);
+        assert_eq!(component.to_legacy(), "<§6Steve§r> hi");
+
+        // Arguments can be plain strings
+        let parsed = ChatComponent::from_json(
+            r#"{"translate":"chat.type.text","with":["Steve",{"text":"hi"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            parsed,
+            ChatComponent::translate("chat.type.text", vec![text("Steve"), text("hi")])
+        );
+
+        // Unknown keys are shown as they are, with positional arguments and escaped percent signs
+        let positional =
+            ChatComponent::translate("%2$s before %1$s, 100%%", vec![text("a"), text("b")]);
+        assert_eq!(positional.to_legacy(), "b before a, 100%");
+        assert_eq!(
+            ChatComponent::translate("%s and %s", vec![text("a")]).to_legacy(),
+            "a and "
+        );
+    }
+
+    #[test]
+    fn events() {
+        let component = text("Help")
+            .click_event(ClickEventType::RunCommand, "/help")
+            .hover_event(HoverEventType::ShowText, text("Runs /help").italic(true))
+            .insertion("/help");
+        assert_eq!(
+            component.to_json(),
+            r#"{"text":"Help","insertion":"/help","clickEvent":{"action":"run_command","value":"/help"},"hoverEvent":{"action":"show_text","value":{"text":"Runs /help","italic":true}}}"#
+        );
+        round_trip(&component);
+
+        let parsed = ChatComponent::from_json(
+            r#"{"text":"Site","clickEvent":{"action":"open_url","value":"https://example.com"},"hoverEvent":{"action":"show_text","value":"Opens the site"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            parsed,
+            text("Site")
+                .click_event(ClickEventType::OpenUrl, "https://example.com")
+                .hover_event(HoverEventType::ShowText, text("Opens the site"))
+        );
+        // Events are lost in legacy text
+        assert_eq!(parsed.to_legacy(), "Site");
+    }
+
+    #[test]
+    fn extra() {
+        let component = text("a")
+            .color(ChatColor::Red)
+            .append(text("b").bold(true))
+            .append(text("c").color(ChatColor::Reset).bold(false));
+        assert_eq!(
+            component.to_json(),
+            r#"{"text":"a","color":"red","extra":[{"text":"b","bold":true},{"text":"c","color":"reset","bold":false}]}"#
+        );
+        round_trip(&component);
+        assert_eq!(component.to_legacy(), "§ca§c§lb§rc");
+
+        // Arrays are the first component with the rest as its children
+        let parsed = ChatComponent::from_json(r#"["a",{"text":"b","bold":true},"c"]"#).unwrap();
+        assert_eq!(
+            parsed,
+            text("a").append(text("b").bold(true)).append(text("c"))
+        );
+        assert_eq!(
+            ChatComponent::from_json(r#""plain""#).unwrap(),
+            text("plain")
+        );
+        assert_eq!(ChatComponent::from_json("[]").unwrap(), text(""));
+
+        round_trip(&ChatComponent::score("@p", "kills"));
+        round_trip(&ChatComponent::selector("@a[distance=..5]"));
+        round_trip(&ChatComponent::keybind("key.inventory"));
+    }
+
+    #[test]
+    fn markup() {
+        assert_eq!(ChatComponent::from_markup("plain"), text("plain"));
+        assert_eq!(
+            ChatComponent::from_markup("<red>Hello <bold>world</bold>!</red> plain"),
+            joined(vec![
+                text("Hello ").color(ChatColor::Red),
+                text("world").color(ChatColor::Red).bold(true),
+                text("!").color(ChatColor::Red),
+                text(" plain"),
+            ])
+        );
+    }
+
+    #[test]
+    fn nested_colors() {
+        assert_eq!(
+            ChatComponent::from_markup("<red>a<color:blue>b</color>c</red>d"),
+            joined(vec![
+                text("a").color(ChatColor::Red),
+                text("b").color(ChatColor::Blue),
+                text("c").color(ChatColor::Red),
+                text("d"),
+            ])
+        );
+        // Closing a tag closes the ones opened after it
+        assert_eq!(
+            ChatComponent::from_markup("<red>a<b>b</red>c"),
+            joined(vec![
+                text("a").color(ChatColor::Red),
+                text("b").color(ChatColor::Red).bold(true),
+                text("c"),
+            ])
+        );
+    }
+
+    #[test]
+    fn reset() {
+        assert_eq!(
+            ChatComponent::from_markup("<red><italic>a<reset>b"),
+            joined(vec![
+                text("a").color(ChatColor::Red).italic(true),
+                text("b")
+            ])
+        );
+        assert_eq!(
+            ChatComponent::from_legacy("&cRed &lbold&r plain &l&abright", '&'),
+            joined(vec![
+                text("Red ").color(ChatColor::Red),
+                text("bold").color(ChatColor::Red).bold(true),
+                text(" plain "),
+                // Colors reset the formatting
+                text("bright").color(ChatColor::Green),
+            ])
+        );
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(
+            ChatComponent::from_markup("\\<red>not red"),
+            text("<red>not red")
+        );
+        assert_eq!(
+            ChatComponent::from_markup("<unknown>tag"),
+            text("<unknown>tag")
+        );
+        assert_eq!(ChatComponent::from_markup("a </red> b"), text("a </red> b"));
+        // An `&` that doesn't start a code is kept, and `§` codes always work
+        assert_eq!(
+            ChatComponent::from_legacy("Fish & Chips &z §aok", '&'),
+            joined(vec![
+                text("Fish & Chips &z "),
+                text("ok").color(ChatColor::Green)
+            ])
+        );
+        assert_eq!(ChatComponent::from_legacy("&aok", '§'), text("&aok"));
+        // Placeholders aren't parsed as markup
+        assert_eq!(
+            ChatComponent::format("<gray>{name} joined", &[("name", text("<red>Steve"))]),
+            joined(vec![
+                text("").color(ChatColor::Gray).append(text("<red>Steve")),
+                text(" joined").color(ChatColor::Gray),
+            ])
+        );
+    }
+
+    #[test]
+    fn markup_events() {
+        let component = ChatComponent::from_markup(
+            "<click:run_command:'/tp <x>'><hover:show_text:'<red>Teleport'>go</hover></click>",
+        );
+        assert_eq!(
+            component,
+            text("go")
+                .click_event(ClickEventType::RunCommand, "/tp <x>")
+                .hover_event(
+                    HoverEventType::ShowText,
+                    text("Teleport").color(ChatColor::Red)
+                )
+        );
+        round_trip(&component);
+    }
+
+    #[test]
+    fn sanitize() {
+        assert_eq!(sanitize_message("  hi  \tthere§a\u{7f} "), "hi therea");
+    }
+}
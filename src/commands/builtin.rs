@@ -0,0 +1,1440 @@
+//! The vanilla commands, and `/perm` for managing permissions
+
+use crate::chat::{ChatColor, ChatComponent, HoverEventType};
+use crate::commands::{
+    self, argument, literal, ArgumentType, CommandContext, CommandError, CommandResult,
+    CommandSender, EntitySelector, SelectorCandidate, SelectorKind, StringKind,
+};
+use crate::inventory::{self, ItemStack};
+use crate::network::{Server, CONFIG_FILE};
+use crate::packets::NetworkState;
+use crate::packets::*;
+use crate::permissions;
+use crate::player::Gamemode;
+use crate::userlist::{Ban, IpBan, Operator, PlayerBan, Profile, WhitelistEntry, WHITELIST_FILE};
+use crate::utils::{self, Location};
+use crate::world::{self, Weather};
+use log::error;
+use std::net::IpAddr;
+
+/// How long `/weather` lasts without a duration, in ticks
+const DEFAULT_WEATHER_DURATION: i32 = 6000;
+
+/// Registers the built-in commands with the server's dispatcher
+pub fn register(server: &mut Server) {
+    server.commands.register(literal("help").executes(
+        |server: &mut Server, context: &CommandContext| {
+            let permissions = server.sender_permissions(&context.sender);
+            let names: Vec<String> = server
+                .commands
+                .command_names(&permissions)
+                .iter()
+                .map(|name| format!("/{}", name))
+                .collect();
+            for name in &names {
+                server.send_message(&context.sender, ChatComponent::new(name.clone()));
+            }
+            Ok(names.len() as i32)
+        },
+    ));
+
+    let entities = ArgumentType::Entity {
+        single: false,
+        players_only: false,
+    };
+    let entity = ArgumentType::Entity {
+        single: true,
+        players_only: false,
+    };
+    let players = ArgumentType::Entity {
+        single: false,
+        players_only: true,
+    };
+    for name in &["teleport", "tp"] {
+        server.commands.register(
+            literal(name)
+                .requires(commands::ADMIN_PERMISSION_LEVEL)
+                .then(argument("location", ArgumentType::Vec3).executes(
+                    |server: &mut Server, context: &CommandContext| {
+                        let target = sender_index(server, &context.sender)?;
+                        teleport_to_location(server, context, vec![target])
+                    },
+                ))
+                .then(argument("destination", entity.clone()).executes(
+                    |server: &mut Server, context: &CommandContext| {
+                        let target = sender_index(server, &context.sender)?;
+                        teleport_to_entity(server, context, vec![target])
+                    },
+                ))
+                .then(
+                    argument("targets", entities.clone())
+                        .then(argument("location", ArgumentType::Vec3).executes(
+                            |server: &mut Server, context: &CommandContext| {
+                                let targets = select_players(server, context, "targets")?;
+                                teleport_to_location(server, context, targets)
+                            },
+                        ))
+                        .then(argument("destination", entity.clone()).executes(
+                            |server: &mut Server, context: &CommandContext| {
+                                let targets = select_players(server, context, "targets")?;
+                                teleport_to_entity(server, context, targets)
+                            },
+                        )),
+                ),
+        );
+    }
+
+    let mut gamemode_command = literal("gamemode").requires(commands::ADMIN_PERMISSION_LEVEL);
+    for &gamemode in &[
+        Gamemode::Survival,
+        Gamemode::Creative,
+        Gamemode::Adventure,
+        Gamemode::Spectator,
+    ] {
+        gamemode_command = gamemode_command.then(
+            literal(gamemode.name())
+                .executes(move |server: &mut Server, context: &CommandContext| {
+                    let target = sender_index(server, &context.sender)?;
+                    set_gamemode(server, &context.sender, vec![target], gamemode)
+                })
+                .then(argument("target", players.clone()).executes(
+                    move |server: &mut Server, context: &CommandContext| {
+                        let targets = select_players(server, context, "target")?;
+                        set_gamemode(server, &context.sender, targets, gamemode)
+                    },
+                )),
+        );
+    }
+    server.commands.register(gamemode_command);
+
+    server.commands.register(
+        literal("give")
+            .requires(commands::ADMIN_PERMISSION_LEVEL)
+            .then(
+                argument("targets", players.clone()).then(
+                    argument("item", ArgumentType::ItemStack)
+                        .executes(|server: &mut Server, context: &CommandContext| {
+                            give(server, context, 1)
+                        })
+                        .then(
+                            argument(
+                                "count",
+                                ArgumentType::Integer {
+                                    min: Some(1),
+                                    max: None,
+                                },
+                            )
+                            .executes(
+                                |server: &mut Server, context: &CommandContext| {
+                                    let count = context.get_integer("count").unwrap();
+                                    give(server, context, count)
+                                },
+                            ),
+                        ),
+                ),
+            ),
+    );
+
+    server.commands.register(
+        literal("kick")
+            .requires(commands::MODERATOR_PERMISSION_LEVEL)
+            .then(
+                argument("targets", players.clone())
+                    .executes(|server: &mut Server, context: &CommandContext| {
+                        let reason =
+                            ChatComponent::translate("multiplayer.disconnect.kicked", vec![]);
+                        kick(server, context, reason)
+                    })
+                    .then(argument("reason", ArgumentType::Message).executes(
+                        |server: &mut Server, context: &CommandContext| {
+                            let reason = context.get_string("reason").unwrap().to_string();
+                            kick(server, context, ChatComponent::new(reason))
+                        },
+                    )),
+            ),
+    );
+
+    let mut time_set = literal("set").then(argument("time", ArgumentType::Time).executes(
+        |server: &mut Server, context: &CommandContext| {
+            let time = context.get_time("time").unwrap() as i64;
+            set_time(server, &context.sender, time)
+        },
+    ));
+    for &(name, time) in &[
+        ("day", 1000),
+        ("noon", 6000),
+        ("night", 13000),
+        ("midnight", 18000),
+    ] {
+        time_set = time_set.then(literal(name).executes(
+            move |server: &mut Server, context: &CommandContext| {
+                set_time(server, &context.sender, time)
+            },
+        ));
+    }
+    let mut time_query = literal("query");
+    for &name in &["daytime", "gametime", "day"] {
+        time_query = time_query.then(literal(name).executes(
+            move |server: &mut Server, context: &CommandContext| {
+                let time = match name {
+                    "daytime" => server.world.time % world::TICKS_PER_DAY,
+                    "gametime" => server.world.age,
+                    _ => server.world.time / world::TICKS_PER_DAY,
+                };
+                let message = ChatComponent::translate(
+                    "commands.time.query",
+                    vec![ChatComponent::new(time.to_string())],
+                );
+                server.send_message(&context.sender, message);
+                Ok(time as i32)
+            },
+        ));
+    }
+    server.commands.register(
+        literal("time")
+            .requires(commands::ADMIN_PERMISSION_LEVEL)
+            .then(time_set)
+            .then(
+                literal("add").then(argument("time", ArgumentType::Time).executes(
+                    |server: &mut Server, context: &CommandContext| {
+                        let time = server.world.time + context.get_time("time").unwrap() as i64;
+                        set_time(server, &context.sender, time)
+                    },
+                )),
+            )
+            .then(time_query),
+    );
+
+    let mut weather_command = literal("weather").requires(commands::ADMIN_PERMISSION_LEVEL);
+    for &weather in &[Weather::Clear, Weather::Rain, Weather::Thunder] {
+        weather_command = weather_command.then(
+            literal(weather.name())
+                .executes(move |server: &mut Server, context: &CommandContext| {
+                    set_weather(server, &context.sender, weather, DEFAULT_WEATHER_DURATION)
+                })
+                .then(
+                    argument(
+                        "duration",
+                        ArgumentType::Integer {
+                            min: Some(0),
+                            max: Some(1_000_000),
+                        },
+                    )
+                    .executes(
+                        move |server: &mut Server, context: &CommandContext| {
+                            // The duration is given in seconds
+                            let duration = context.get_integer("duration").unwrap() * 20;
+                            set_weather(server, &context.sender, weather, duration)
+                        },
+                    ),
+                ),
+        );
+    }
+    server.commands.register(weather_command);
+
+    server.commands.register(
+        literal("say")
+            .requires(commands::ADMIN_PERMISSION_LEVEL)
+            .then(argument("message", ArgumentType::Message).executes(
+                |server: &mut Server, context: &CommandContext| {
+                    // TODO: Replace selectors in the message with the names they select
+                    let message = context.get_string("message").unwrap().to_string();
+                    let message = ChatComponent::translate(
+                        "chat.type.announcement",
+                        vec![
+                            ChatComponent::new(sender_name(server, &context.sender)),
+                            ChatComponent::new(message),
+                        ],
+                    );
+                    server.broadcast_chat(message, ChatPosition::Chat);
+                    Ok(1)
+                },
+            )),
+    );
+
+    server.commands.register(literal("list").executes(
+        |server: &mut Server, context: &CommandContext| {
+            let names: Vec<&str> = server
+                .clients
+                .iter()
+                .filter(|client| client.state == NetworkState::PLAY)
+                .filter_map(|client| client.player.as_ref())
+                .map(|player| player.username.as_str())
+                .collect();
+            let count = names.len();
+            let message = ChatComponent::translate(
+                "commands.list.players",
+                vec![
+                    ChatComponent::new(count.to_string()),
+                    ChatComponent::new(server.config.max_players.to_string()),
+                    ChatComponent::new(names.join(", ")),
+                ],
+            );
+            server.send_message(&context.sender, message);
+            Ok(count as i32)
+        },
+    ));
+
+    server.commands.register(
+        literal("stop")
+            .requires(commands::MAX_PERMISSION_LEVEL)
+            .executes(|server: &mut Server, context: &CommandContext| {
+                let message = ChatComponent::translate("commands.stop.stopping", vec![]);
+                server.send_message(&context.sender, message);
+                server.running = false;
+                Ok(1)
+            }),
+    );
+
+    server.commands.register(
+        literal("save-all")
+            .requires(commands::MAX_PERMISSION_LEVEL)
+            .executes(|server: &mut Server, context: &CommandContext| {
+                let message = ChatComponent::translate("commands.save.saving", vec![]);
+                server.send_message(&context.sender, message);
+                if let Err(err) = server.world.save() {
+                    error!("Couldn't save the world: {}", err);
+                    return Err(CommandError::Failed(
+                        "Unable to save the game (is there enough disk space?)".to_string(),
+                    ));
+                }
+                let message = ChatComponent::translate("commands.save.success", vec![]);
+                server.send_message(&context.sender, message);
+                Ok(1)
+            }),
+    );
+
+    server.commands.register(literal("plugins").executes(
+        |server: &mut Server, context: &CommandContext| {
+            let plugins = server.plugins.list();
+            let mut message = ChatComponent::new(format!("Plugins ({}): ", plugins.len()));
+            for (i, (name, version, enabled)) in plugins.iter().enumerate() {
+                if i > 0 {
+                    message = message.append(ChatComponent::new(", ".to_string()));
+                }
+                let color = if *enabled {
+                    ChatColor::Green
+                } else {
+                    ChatColor::Red
+                };
+                let hover = if version.is_empty() {
+                    "Script".to_string()
+                } else {
+                    format!("Version {}", version)
+                };
+                let name = ChatComponent::new(name.to_string())
+                    .color(color)
+                    .hover_event(HoverEventType::ShowText, ChatComponent::new(hover));
+                message = message.append(name);
+            }
+            let count = plugins.len();
+            server.send_message(&context.sender, message);
+            Ok(count as i32)
+        },
+    ));
+
+    server.commands.register(
+        literal("reload")
+            .requires(commands::MAX_PERMISSION_LEVEL)
+            .executes(|server: &mut Server, context: &CommandContext| {
+                let message = ChatComponent::new("Reloading plugins".to_string());
+                server.send_message(&context.sender, message);
+                server.reload_plugins();
+                let message =
+                    ChatComponent::new(format!("Reloaded {} plugins", server.plugins.len()))
+                        .color(ChatColor::Green);
+                server.send_message(&context.sender, message);
+                Ok(server.plugins.len() as i32)
+            }),
+    );
+
+    server.commands.register(
+        literal("op")
+            .requires(commands::MODERATOR_PERMISSION_LEVEL)
+            .then(
+                argument("targets", ArgumentType::GameProfile)
+                    .executes(|server: &mut Server, context: &CommandContext| op(server, context)),
+            ),
+    );
+    server.commands.register(
+        literal("deop")
+            .requires(commands::MODERATOR_PERMISSION_LEVEL)
+            .then(
+                argument("targets", ArgumentType::GameProfile).executes(
+                    |server: &mut Server, context: &CommandContext| deop(server, context),
+                ),
+            ),
+    );
+
+    server.commands.register(
+        literal("whitelist")
+            .requires(commands::MODERATOR_PERMISSION_LEVEL)
+            .then(
+                literal("add").then(argument("targets", ArgumentType::GameProfile).executes(
+                    |server: &mut Server, context: &CommandContext| whitelist_add(server, context),
+                )),
+            )
+            .then(
+                literal("remove").then(argument("targets", ArgumentType::GameProfile).executes(
+                    |server: &mut Server, context: &CommandContext| {
+                        whitelist_remove(server, context)
+                    },
+                )),
+            )
+            .then(
+                literal("list").executes(|server: &mut Server, context: &CommandContext| {
+                    let names: Vec<&str> = server
+                        .user_lists
+                        .whitelist
+                        .entries
+                        .iter()
+                        .map(|entry| entry.name.as_str())
+                        .collect();
+                    let count = names.len();
+                    let message = if names.is_empty() {
+                        ChatComponent::translate("commands.whitelist.none", vec![])
+                    } else {
+                        ChatComponent::translate(
+                            "commands.whitelist.list",
+                            vec![
+                                ChatComponent::new(count.to_string()),
+                                ChatComponent::new(names.join(", ")),
+                            ],
+                        )
+                    };
+                    server.send_message(&context.sender, message);
+                    Ok(count as i32)
+                }),
+            )
+            .then(
+                literal("on").executes(|server: &mut Server, context: &CommandContext| {
+                    set_whitelist(server, &context.sender, true)
+                }),
+            )
+            .then(
+                literal("off").executes(|server: &mut Server, context: &CommandContext| {
+                    set_whitelist(server, &context.sender, false)
+                }),
+            )
+            .then(
+                literal("reload").executes(|server: &mut Server, context: &CommandContext| {
+                    if let Err(err) = server.user_lists.whitelist.reload() {
+                        return Err(CommandError::Failed(format!(
+                            "Couldn't reload {}: {}",
+                            WHITELIST_FILE, err
+                        )));
+                    }
+                    let message = ChatComponent::translate("commands.whitelist.reloaded", vec![]);
+                    server.send_message(&context.sender, message);
+                    Ok(1)
+                }),
+            ),
+    );
+
+    server.commands.register(
+        literal("ban")
+            .requires(commands::MODERATOR_PERMISSION_LEVEL)
+            .then(
+                argument("targets", ArgumentType::GameProfile)
+                    .executes(|server: &mut Server, context: &CommandContext| {
+                        ban(server, context, None)
+                    })
+                    .then(argument("reason", ArgumentType::Message).executes(
+                        |server: &mut Server, context: &CommandContext| {
+                            let reason = context.get_string("reason").unwrap().to_string();
+                            ban(server, context, Some(reason))
+                        },
+                    )),
+            ),
+    );
+    server.commands.register(
+        literal("ban-ip")
+            .requires(commands::MODERATOR_PERMISSION_LEVEL)
+            .then(
+                argument("target", ArgumentType::String(StringKind::Word))
+                    .executes(|server: &mut Server, context: &CommandContext| {
+                        ban_ip(server, context, None)
+                    })
+                    .then(argument("reason", ArgumentType::Message).executes(
+                        |server: &mut Server, context: &CommandContext| {
+                            let reason = context.get_string("reason").unwrap().to_string();
+                            ban_ip(server, context, Some(reason))
+                        },
+                    )),
+            ),
+    );
+    server.commands.register(
+        literal("pardon")
+            .requires(commands::MODERATOR_PERMISSION_LEVEL)
+            .then(
+                argument("targets", ArgumentType::GameProfile).executes(
+                    |server: &mut Server, context: &CommandContext| pardon(server, context),
+                ),
+            ),
+    );
+    server.commands.register(
+        literal("pardon-ip")
+            .requires(commands::MODERATOR_PERMISSION_LEVEL)
+            .then(
+                argument("target", ArgumentType::String(StringKind::Word)).executes(
+                    |server: &mut Server, context: &CommandContext| pardon_ip(server, context),
+                ),
+            ),
+    );
+
+    register_permission_commands(server);
+}
+
+/// `/perm` edits the permission nodes of players and groups
+fn register_permission_commands(server: &mut Server) {
+    let node = || argument("node", ArgumentType::String(StringKind::Greedy));
+    let group = |name: &str| argument(name, ArgumentType::String(StringKind::Word));
+
+    let mut player = argument("target", ArgumentType::GameProfile)
+        .then(
+            literal("info").executes(|server: &mut Server, context: &CommandContext| {
+                player_permission_info(server, context)
+            }),
+        )
+        .then(literal("check").then(node().executes(
+            |server: &mut Server, context: &CommandContext| {
+                check_player_permission(server, context)
+            },
+        )))
+        .then(
+            literal("group")
+                .then(literal("add").then(group("group").executes(
+                    |server: &mut Server, context: &CommandContext| {
+                        set_player_group(server, context, true)
+                    },
+                )))
+                .then(literal("remove").then(group("group").executes(
+                    |server: &mut Server, context: &CommandContext| {
+                        set_player_group(server, context, false)
+                    },
+                ))),
+        );
+    let mut group_command = group("group")
+        .then(
+            literal("create").executes(|server: &mut Server, context: &CommandContext| {
+                let name = context.get_string("group").unwrap().to_string();
+                if !server.permissions.create_group(&name) {
+                    return Err(CommandError::Failed(format!(
+                        "Group '{}' already exists",
+                        name
+                    )));
+                }
+                server.permissions.save();
+                let message = ChatComponent::new(format!("Created group {}", name));
+                server.send_message(&context.sender, message);
+                Ok(1)
+            }),
+        )
+        .then(
+            literal("delete").executes(|server: &mut Server, context: &CommandContext| {
+                let name = context.get_string("group").unwrap().to_string();
+                if name == permissions::DEFAULT_GROUP {
+                    return Err(CommandError::Failed(
+                        "The default group can't be deleted".to_string(),
+                    ));
+                }
+                if !server.permissions.delete_group(&name) {
+                    return Err(CommandError::Failed(format!("Unknown group '{}'", name)));
+                }
+                server.permissions.save();
+                server.resend_commands();
+                let message = ChatComponent::new(format!("Deleted group {}", name));
+                server.send_message(&context.sender, message);
+                Ok(1)
+            }),
+        )
+        .then(
+            literal("info").executes(|server: &mut Server, context: &CommandContext| {
+                group_permission_info(server, context)
+            }),
+        )
+        .then(
+            literal("parent")
+                .then(literal("add").then(group("parent").executes(
+                    |server: &mut Server, context: &CommandContext| {
+                        set_group_parent(server, context, true)
+                    },
+                )))
+                .then(literal("remove").then(group("parent").executes(
+                    |server: &mut Server, context: &CommandContext| {
+                        set_group_parent(server, context, false)
+                    },
+                ))),
+        );
+    for &(name, value) in &[
+        ("allow", Some(true)),
+        ("deny", Some(false)),
+        ("unset", None),
+    ] {
+        player = player.then(literal(name).then(node().executes(
+            move |server: &mut Server, context: &CommandContext| {
+                set_player_permission(server, context, value)
+            },
+        )));
+        group_command = group_command.then(literal(name).then(node().executes(
+            move |server: &mut Server, context: &CommandContext| {
+                set_group_permission(server, context, value)
+            },
+        )));
+    }
+
+    server.commands.register(
+        literal("perm")
+            .requires(commands::MAX_PERMISSION_LEVEL)
+            .then(literal("player").then(player))
+            .then(literal("group").then(group_command))
+            .then(
+                literal("groups").executes(|server: &mut Server, context: &CommandContext| {
+                    let names = server.permissions.group_names().join(", ");
+                    let count = server.permissions.group_names().len();
+                    let message = ChatComponent::new(format!("Groups: {}", names));
+                    server.send_message(&context.sender, message);
+                    Ok(count as i32)
+                }),
+            )
+            .then(
+                literal("reload").executes(|server: &mut Server, context: &CommandContext| {
+                    if let Err(err) = server.permissions.reload() {
+                        return Err(CommandError::Failed(format!(
+                            "Couldn't reload {}: {}",
+                            permissions::PERMISSIONS_FILE,
+                            err
+                        )));
+                    }
+                    server.resend_commands();
+                    let message = ChatComponent::new("Reloaded the permissions".to_string());
+                    server.send_message(&context.sender, message);
+                    Ok(1)
+                }),
+            ),
+    );
+}
+
+/// The index of the client running a command, for commands that need a player
+fn sender_index(server: &Server, sender: &CommandSender) -> Result<usize, CommandError> {
+    match sender {
+        CommandSender::Player(client_id) => server
+            .client_index(*client_id)
+            .ok_or(CommandError::RequiresPlayer),
+        CommandSender::Console => Err(CommandError::RequiresPlayer),
+    }
+}
+
+fn sender_name(server: &Server, sender: &CommandSender) -> String {
+    match sender_index(server, sender) {
+        Ok(index) => player_name(server, index),
+        Err(_) => "Server".to_string(),
+    }
+}
+
+fn player_name(server: &Server, client_index: usize) -> String {
+    match &server.clients[client_index].player {
+        Some(player) => player.username.clone(),
+        None => String::new(),
+    }
+}
+
+/// Where relative coordinates are relative to, the sender's position or the spawn for the console
+fn sender_location(server: &Server, sender: &CommandSender) -> Location {
+    sender_index(server, sender)
+        .ok()
+        .and_then(|index| server.clients[index].player.as_ref())
+        .map_or_else(|| server.world.spawn_location(), |player| player.position)
+}
+
+/// Resolves a selector argument to the indices of the clients it selects, there's at least one
+fn select_players(
+    server: &Server,
+    context: &CommandContext,
+    argument: &str,
+) -> Result<Vec<usize>, CommandError> {
+    let selector = context.get_entity(argument).unwrap();
+    let candidates: Vec<SelectorCandidate> = server
+        .clients
+        .iter()
+        .filter(|client| client.state == NetworkState::PLAY)
+        .filter_map(|client| {
+            client.player.as_ref().map(|player| SelectorCandidate {
+                id: client.id,
+                player,
+            })
+        })
+        .collect();
+    let sender = match context.sender {
+        CommandSender::Player(client_id) => {
+            Some((client_id, sender_location(server, &context.sender)))
+        }
+        CommandSender::Console => None,
+    };
+    let selected: Vec<usize> = selector
+        .select(sender, server.world.spawn_location(), &candidates)?
+        .into_iter()
+        .filter_map(|client_id| server.client_index(client_id))
+        .collect();
+    if selected.is_empty() {
+        return Err(match selector {
+            EntitySelector::Selector {
+                kind: SelectorKind::AllEntities,
+                ..
+            } => CommandError::NoEntities,
+            _ => CommandError::NoPlayers,
+        });
+    }
+    Ok(selected)
+}
+
+fn teleport_to_location(
+    server: &mut Server,
+    context: &CommandContext,
+    targets: Vec<usize>,
+) -> CommandResult {
+    let coordinates = context.get_coordinates("location").unwrap();
+    let (x, y, z) = coordinates.resolve(&sender_location(server, &context.sender));
+    for &target in &targets {
+        if let Some(player) = &server.clients[target].player {
+            let location = Location::new(x, y, z, player.position.yaw, player.position.pitch);
+            server.teleport_client(target, location);
+        }
+    }
+    let position = vec![
+        ChatComponent::new(format!("{:.2}", x)),
+        ChatComponent::new(format!("{:.2}", y)),
+        ChatComponent::new(format!("{:.2}", z)),
+    ];
+    let message = match targets.as_slice() {
+        [target] => {
+            let mut with = vec![ChatComponent::new(player_name(server, *target))];
+            with.extend(position);
+            ChatComponent::translate("commands.teleport.success.location.single", with)
+        }
+        _ => {
+            let mut with = vec![ChatComponent::new(targets.len().to_string())];
+            with.extend(position);
+            ChatComponent::translate("commands.teleport.success.location.multiple", with)
+        }
+    };
+    server.send_message(&context.sender, message);
+    Ok(targets.len() as i32)
+}
+
+fn teleport_to_entity(
+    server: &mut Server,
+    context: &CommandContext,
+    targets: Vec<usize>,
+) -> CommandResult {
+    let destination = select_players(server, context, "destination")?[0];
+    let location = match &server.clients[destination].player {
+        Some(player) => player.position,
+        None => return Err(CommandError::NoEntities),
+    };
+    for &target in &targets {
+        server.teleport_client(target, location);
+    }
+    let destination_name = ChatComponent::new(player_name(server, destination));
+    let message = match targets.as_slice() {
+        [target] => ChatComponent::translate(
+            "commands.teleport.success.entity.single",
+            vec![
+                ChatComponent::new(player_name(server, *target)),
+                destination_name,
+            ],
+        ),
+        _ => ChatComponent::translate(
+            "commands.teleport.success.entity.multiple",
+            vec![
+                ChatComponent::new(targets.len().to_string()),
+                destination_name,
+            ],
+        ),
+    };
+    server.send_message(&context.sender, message);
+    Ok(targets.len() as i32)
+}
+
+fn set_gamemode(
+    server: &mut Server,
+    sender: &CommandSender,
+    targets: Vec<usize>,
+    gamemode: Gamemode,
+) -> CommandResult {
+    let sender_index = sender_index(server, sender).ok();
+    let gamemode_name = ChatComponent::translate(&format!("gameMode.{}", gamemode.name()), vec![]);
+    for &target in &targets {
+        server.clients[target].set_gamemode(gamemode);
+        let message = if Some(target) == sender_index {
+            ChatComponent::translate(
+                "commands.gamemode.success.self",
+                vec![gamemode_name.clone()],
+            )
+        } else {
+            let changed = ChatComponent::translate("gameMode.changed", vec![gamemode_name.clone()]);
+            let target_sender = CommandSender::Player(server.clients[target].id);
+            server.send_message(&target_sender, changed);
+            ChatComponent::translate(
+                "commands.gamemode.success.other",
+                vec![
+                    ChatComponent::new(player_name(server, target)),
+                    gamemode_name.clone(),
+                ],
+            )
+        };
+        server.send_message(sender, message);
+    }
+    Ok(targets.len() as i32)
+}
+
+fn give(server: &mut Server, context: &CommandContext, count: i32) -> CommandResult {
+    let targets = select_players(server, context, "targets")?;
+    let item = context.get_item("item").unwrap();
+    for &target in &targets {
+        let client = &mut server.clients[target];
+        let player = match &mut client.player {
+            Some(player) => player,
+            None => continue,
+        };
+        let mut remaining = count;
+        while remaining > 0 {
+            let amount = remaining.min(inventory::MAX_STACK_SIZE as i32);
+            remaining -= amount;
+            if player
+                .inventory
+                .add_item(ItemStack::new(item, amount as i8))
+                .is_some()
+            {
+                // TODO: Drop the items that don't fit once there are item entities
+                break;
+            }
+        }
+        let items_encoder = C15WindowItems {
+            window_id: 0,
+            slot_data: player.inventory.window_slots(0),
+        }
+        .encode(client.protocol);
+        client.send_packet(&items_encoder);
+    }
+    let count = ChatComponent::new(count.to_string());
+    let item = ChatComponent::new(item.name().to_string());
+    let message = match targets.as_slice() {
+        [target] => ChatComponent::translate(
+            "commands.give.success.single",
+            vec![
+                count,
+                item,
+                ChatComponent::new(player_name(server, *target)),
+            ],
+        ),
+        _ => ChatComponent::translate(
+            "commands.give.success.multiple",
+            vec![count, item, ChatComponent::new(targets.len().to_string())],
+        ),
+    };
+    server.send_message(&context.sender, message);
+    Ok(targets.len() as i32)
+}
+
+fn kick(server: &mut Server, context: &CommandContext, reason: ChatComponent) -> CommandResult {
+    let targets = select_players(server, context, "targets")?;
+    for &target in &targets {
+        let message = ChatComponent::translate(
+            "commands.kick.success",
+            vec![
+                ChatComponent::new(player_name(server, target)),
+                reason.clone(),
+            ],
+        );
+        server.clients[target].disconnect(reason.clone());
+        server.send_message(&context.sender, message);
+    }
+    Ok(targets.len() as i32)
+}
+
+/// The UUID and name of an authenticated player
+fn profile(server: &Server, client_index: usize) -> Option<Profile> {
+    let client = &server.clients[client_index];
+    match (client.uuid, &client.player) {
+        (Some(uuid), Some(player)) => Some(Profile {
+            uuid: utils::format_uuid(uuid),
+            name: player.username.clone(),
+        }),
+        _ => None,
+    }
+}
+
+/// Resolves a game profile argument, a name of a player who isn't online is looked up in the user lists
+fn select_profiles(
+    server: &Server,
+    context: &CommandContext,
+    argument: &str,
+) -> Result<Vec<Profile>, CommandError> {
+    if let EntitySelector::Name(name) = context.get_entity(argument).unwrap() {
+        let online = (0..server.clients.len())
+            .filter_map(|index| profile(server, index))
+            .find(|profile| profile.name.eq_ignore_ascii_case(name));
+        return online
+            .or_else(|| server.user_lists.find_profile(name))
+            .map(|profile| vec![profile])
+            .ok_or(CommandError::UnknownPlayer);
+    }
+    Ok(select_players(server, context, argument)?
+        .into_iter()
+        .filter_map(|index| profile(server, index))
+        .collect())
+}
+
+/// The indices of the clients a player with this UUID is connected with
+fn clients_with_uuid(server: &Server, uuid: &str) -> Vec<usize> {
+    (0..server.clients.len())
+        .filter(|&index| {
+            profile(server, index).is_some_and(|profile| profile.uuid.eq_ignore_ascii_case(uuid))
+        })
+        .collect()
+}
+
+/// Tells an online player which commands they can use now
+fn on_permissions_changed(server: &mut Server, uuid: &str) {
+    for index in clients_with_uuid(server, uuid) {
+        if server.clients[index].state == NetworkState::PLAY {
+            server.send_commands(index);
+        }
+    }
+}
+
+fn op(server: &mut Server, context: &CommandContext) -> CommandResult {
+    let mut count = 0;
+    for profile in select_profiles(server, context, "targets")? {
+        if server.user_lists.operator(&profile.uuid).is_some() {
+            continue;
+        }
+        let message = ChatComponent::translate(
+            "commands.op.success",
+            vec![ChatComponent::new(profile.name.clone())],
+        );
+        server.user_lists.ops.entries.push(Operator {
+            uuid: profile.uuid.clone(),
+            name: profile.name,
+            level: server.config.op_permission_level,
+            bypasses_player_limit: false,
+        });
+        server.send_message(&context.sender, message);
+        on_permissions_changed(server, &profile.uuid);
+        count += 1;
+    }
+    if count == 0 {
+        return Err(CommandError::Failed(
+            "Nothing changed. The player already is an operator".to_string(),
+        ));
+    }
+    server.user_lists.ops.save();
+    Ok(count)
+}
+
+fn deop(server: &mut Server, context: &CommandContext) -> CommandResult {
+    let mut count = 0;
+    for profile in select_profiles(server, context, "targets")? {
+        let uuid = &profile.uuid;
+        if !server
+            .user_lists
+            .ops
+            .remove(|op| op.uuid.eq_ignore_ascii_case(uuid))
+        {
+            continue;
+        }
+        let message = ChatComponent::translate(
+            "commands.deop.success",
+            vec![ChatComponent::new(profile.name.clone())],
+        );
+        server.send_message(&context.sender, message);
+        on_permissions_changed(server, uuid);
+        count += 1;
+    }
+    if count == 0 {
+        return Err(CommandError::Failed(
+            "Nothing changed. The player is not an operator".to_string(),
+        ));
+    }
+    server.user_lists.ops.save();
+    Ok(count)
+}
+
+fn whitelist_add(server: &mut Server, context: &CommandContext) -> CommandResult {
+    let mut count = 0;
+    for profile in select_profiles(server, context, "targets")? {
+        if server.user_lists.is_whitelisted(&profile.uuid) {
+            continue;
+        }
+        let message = ChatComponent::translate(
+            "commands.whitelist.add.success",
+            vec![ChatComponent::new(profile.name.clone())],
+        );
+        server.user_lists.whitelist.entries.push(WhitelistEntry {
+            uuid: profile.uuid,
+            name: profile.name,
+        });
+        server.send_message(&context.sender, message);
+        count += 1;
+    }
+    if count == 0 {
+        return Err(CommandError::Failed(
+            "Player is already whitelisted".to_string(),
+        ));
+    }
+    server.user_lists.whitelist.save();
+    Ok(count)
+}
+
+fn whitelist_remove(server: &mut Server, context: &CommandContext) -> CommandResult {
+    let mut count = 0;
+    for profile in select_profiles(server, context, "targets")? {
+        let uuid = &profile.uuid;
+        if !server
+            .user_lists
+            .whitelist
+            .remove(|entry| entry.uuid.eq_ignore_ascii_case(uuid))
+        {
+            continue;
+        }
+        let message = ChatComponent::translate(
+            "commands.whitelist.remove.success",
+            vec![ChatComponent::new(profile.name.clone())],
+        );
+        server.send_message(&context.sender, message);
+        count += 1;
+    }
+    if count == 0 {
+        return Err(CommandError::Failed(
+            "Player is not whitelisted".to_string(),
+        ));
+    }
+    server.user_lists.whitelist.save();
+    Ok(count)
+}
+
+fn set_whitelist(server: &mut Server, sender: &CommandSender, enabled: bool) -> CommandResult {
+    if server.config.whitelist == enabled {
+        let state = if enabled { "on" } else { "off" };
+        return Err(CommandError::Failed(format!(
+            "Whitelist is already turned {}",
+            state
+        )));
+    }
+    server.config.whitelist = enabled;
+    if let Err(err) = server.config.save(CONFIG_FILE) {
+        error!("Couldn't save {}: {}", CONFIG_FILE, err);
+    }
+    let key = if enabled {
+        "commands.whitelist.enabled"
+    } else {
+        "commands.whitelist.disabled"
+    };
+    server.send_message(sender, ChatComponent::translate(key, vec![]));
+    Ok(1)
+}
+
+fn ban(server: &mut Server, context: &CommandContext, reason: Option<String>) -> CommandResult {
+    let source = sender_name(server, &context.sender);
+    let mut count = 0;
+    for profile in select_profiles(server, context, "targets")? {
+        let uuid = profile.uuid.clone();
+        if server.user_lists.player_ban(&uuid).is_some() {
+            continue;
+        }
+        let ban = Ban::new(source.clone(), reason.clone());
+        let message = ChatComponent::translate(
+            "commands.ban.success",
+            vec![
+                ChatComponent::new(profile.name.clone()),
+                ChatComponent::new(ban.reason.clone()),
+            ],
+        );
+        // Replaces an expired ban
+        server
+            .user_lists
+            .banned_players
+            .remove(|ban| ban.uuid.eq_ignore_ascii_case(&uuid));
+        server.user_lists.banned_players.entries.push(PlayerBan {
+            uuid: profile.uuid,
+            name: profile.name,
+            ban,
+        });
+        server.send_message(&context.sender, message);
+        for index in clients_with_uuid(server, &uuid) {
+            server.clients[index].disconnect(ChatComponent::translate(
+                "multiplayer.disconnect.banned",
+                vec![],
+            ));
+        }
+        count += 1;
+    }
+    if count == 0 {
+        return Err(CommandError::Failed(
+            "Nothing changed. The player is already banned".to_string(),
+        ));
+    }
+    server.user_lists.banned_players.save();
+    Ok(count)
+}
+
+fn ban_ip(server: &mut Server, context: &CommandContext, reason: Option<String>) -> CommandResult {
+    let target = context.get_string("target").unwrap();
+    // The target is either an address or the name of a player who's online
+    let ip = match target.parse::<IpAddr>() {
+        Ok(ip) => ip,
+        Err(_) => {
+            let index = (0..server.clients.len())
+                .find(|&index| player_name(server, index).eq_ignore_ascii_case(target));
+            match index {
+                Some(index) => server.clients[index].address.ip(),
+                None => {
+                    return Err(CommandError::Failed(
+                        "Invalid IP address or unknown player".to_string(),
+                    ))
+                }
+            }
+        }
+    };
+    let ip_string = ip.to_string();
+    if server.user_lists.ip_ban(&ip_string).is_some() {
+        return Err(CommandError::Failed(
+            "Nothing changed. That IP is already banned".to_string(),
+        ));
+    }
+    let ban = Ban::new(sender_name(server, &context.sender), reason);
+    let message = ChatComponent::translate(
+        "commands.banip.success",
+        vec![
+            ChatComponent::new(ip_string.clone()),
+            ChatComponent::new(ban.reason.clone()),
+        ],
+    );
+    server
+        .user_lists
+        .banned_ips
+        .remove(|ban| ban.ip == ip_string);
+    server
+        .user_lists
+        .banned_ips
+        .entries
+        .push(IpBan { ip: ip_string, ban });
+    server.user_lists.banned_ips.save();
+    server.send_message(&context.sender, message);
+
+    let banned: Vec<usize> = (0..server.clients.len())
+        .filter(|&index| server.clients[index].address.ip() == ip)
+        .collect();
+    let names: Vec<String> = banned
+        .iter()
+        .map(|&index| player_name(server, index))
+        .filter(|name| !name.is_empty())
+        .collect();
+    if !names.is_empty() {
+        let message = ChatComponent::translate(
+            "commands.banip.info",
+            vec![
+                ChatComponent::new(names.len().to_string()),
+                ChatComponent::new(names.join(", ")),
+            ],
+        );
+        server.send_message(&context.sender, message);
+    }
+    for &index in &banned {
+        server.clients[index].disconnect(ChatComponent::translate(
+            "multiplayer.disconnect.ip_banned",
+            vec![],
+        ));
+    }
+    Ok(names.len() as i32)
+}
+
+fn pardon(server: &mut Server, context: &CommandContext) -> CommandResult {
+    let mut count = 0;
+    for profile in select_profiles(server, context, "targets")? {
+        let uuid = &profile.uuid;
+        if !server
+            .user_lists
+            .banned_players
+            .remove(|ban| ban.uuid.eq_ignore_ascii_case(uuid))
+        {
+            continue;
+        }
+        let message = ChatComponent::translate(
+            "commands.pardon.success",
+            vec![ChatComponent::new(profile.name.clone())],
+        );
+        server.send_message(&context.sender, message);
+        count += 1;
+    }
+    if count == 0 {
+        return Err(CommandError::Failed(
+            "Nothing changed. The player isn't banned".to_string(),
+        ));
+    }
+    server.user_lists.banned_players.save();
+    Ok(count)
+}
+
+fn pardon_ip(server: &mut Server, context: &CommandContext) -> CommandResult {
+    let ip = match context.get_string("target").unwrap().parse::<IpAddr>() {
+        Ok(ip) => ip.to_string(),
+        Err(_) => return Err(CommandError::Failed("Invalid IP address".to_string())),
+    };
+    if !server.user_lists.banned_ips.remove(|ban| ban.ip == ip) {
+        return Err(CommandError::Failed(
+            "Nothing changed. That IP isn't banned".to_string(),
+        ));
+    }
+    server.user_lists.banned_ips.save();
+    let message =
+        ChatComponent::translate("commands.pardonip.success", vec![ChatComponent::new(ip)]);
+    server.send_message(&context.sender, message);
+    Ok(1)
+}
+
+/// The node argument of `/perm`
+fn permission_node(context: &CommandContext) -> Result<String, CommandError> {
+    let node = context.get_string("node").unwrap().trim();
+    if !permissions::is_valid_node(node) {
+        return Err(CommandError::Invalid {
+            expected: "permission node",
+            found: node.to_string(),
+        });
+    }
+    Ok(node.to_ascii_lowercase())
+}
+
+/// How a node is set, for `/perm` feedback
+fn permission_value_name(value: Option<bool>) -> &'static str {
+    match value {
+        Some(true) => "allowed",
+        Some(false) => "denied",
+        None => "not set",
+    }
+}
+
+fn set_player_permission(
+    server: &mut Server,
+    context: &CommandContext,
+    value: Option<bool>,
+) -> CommandResult {
+    let node = permission_node(context)?;
+    let mut count = 0;
+    for profile in select_profiles(server, context, "target")? {
+        if !server
+            .permissions
+            .set_player(&profile.uuid, &profile.name, &node, value)
+        {
+            continue;
+        }
+        let message = ChatComponent::new(format!(
+            "{} is now {} for {}",
+            node,
+            permission_value_name(value),
+            profile.name
+        ));
+        server.send_message(&context.sender, message);
+        on_permissions_changed(server, &profile.uuid);
+        count += 1;
+    }
+    if count == 0 {
+        return Err(CommandError::Failed("Nothing changed".to_string()));
+    }
+    server.permissions.save();
+    Ok(count)
+}
+
+fn check_player_permission(server: &mut Server, context: &CommandContext) -> CommandResult {
+    let node = permission_node(context)?;
+    let mut granted = 0;
+    for profile in select_profiles(server, context, "target")? {
+        if server.permissions.has(&profile.uuid, &node) {
+            granted += 1;
+        }
+        let value = server.permissions.check(&profile.uuid, &node);
+        let message = ChatComponent::new(format!(
+            "{} is {} for {}",
+            node,
+            permission_value_name(value),
+            profile.name
+        ));
+        server.send_message(&context.sender, message);
+    }
+    Ok(granted)
+}
+
+fn player_permission_info(server: &mut Server, context: &CommandContext) -> CommandResult {
+    let profiles = select_profiles(server, context, "target")?;
+    for profile in &profiles {
+        let mut groups = vec![permissions::DEFAULT_GROUP.to_string()];
+        let mut nodes = Vec::new();
+        if let Some(player) = server.permissions.player(&profile.uuid) {
+            groups.extend(player.groups.iter().cloned());
+            nodes.extend(
+                player
+                    .permissions
+                    .iter()
+                    .map(|(node, value)| format!("{}={}", node, value)),
+            );
+        }
+        let message = ChatComponent::new(format!(
+            "{} is in the groups {} and has the nodes {}",
+            profile.name,
+            groups.join(", "),
+            if nodes.is_empty() {
+                "-".to_string()
+            } else {
+                nodes.join(", ")
+            }
+        ));
+        server.send_message(&context.sender, message);
+    }
+    Ok(profiles.len() as i32)
+}
+
+fn set_player_group(server: &mut Server, context: &CommandContext, add: bool) -> CommandResult {
+    let group = context.get_string("group").unwrap().to_string();
+    if server.permissions.group(&group).is_none() {
+        return Err(CommandError::Failed(format!("Unknown group '{}'", group)));
+    }
+    let mut count = 0;
+    for profile in select_profiles(server, context, "target")? {
+        let changed = if add {
+            server
+                .permissions
+                .add_player_group(&profile.uuid, &profile.name, &group)
+        } else {
+            server
+                .permissions
+                .remove_player_group(&profile.uuid, &group)
+        };
+        if !changed {
+            continue;
+        }
+        let message = if add {
+            format!("Added {} to the group {}", profile.name, group)
+        } else {
+            format!("Removed {} from the group {}", profile.name, group)
+        };
+        server.send_message(&context.sender, ChatComponent::new(message));
+        on_permissions_changed(server, &profile.uuid);
+        count += 1;
+    }
+    if count == 0 {
+        return Err(CommandError::Failed("Nothing changed".to_string()));
+    }
+    server.permissions.save();
+    Ok(count)
+}
+
+fn set_group_permission(
+    server: &mut Server,
+    context: &CommandContext,
+    value: Option<bool>,
+) -> CommandResult {
+    let group = context.get_string("group").unwrap().to_string();
+    let node = permission_node(context)?;
+    match server.permissions.set_group(&group, &node, value) {
+        None => return Err(CommandError::Failed(format!("Unknown group '{}'", group))),
+        Some(false) => return Err(CommandError::Failed("Nothing changed".to_string())),
+        Some(true) => {}
+    }
+    server.permissions.save();
+    server.resend_commands();
+    let message = ChatComponent::new(format!(
+        "{} is now {} for the group {}",
+        node,
+        permission_value_name(value),
+        group
+    ));
+    server.send_message(&context.sender, message);
+    Ok(1)
+}
+
+fn group_permission_info(server: &mut Server, context: &CommandContext) -> CommandResult {
+    let name = context.get_string("group").unwrap();
+    let group = match server.permissions.group(name) {
+        Some(group) => group,
+        None => return Err(CommandError::Failed(format!("Unknown group '{}'", name))),
+    };
+    let nodes: Vec<String> = group
+        .permissions
+        .iter()
+        .map(|(node, value)| format!("{}={}", node, value))
+        .collect();
+    let message = ChatComponent::new(format!(
+        "{} inherits from {} and has the nodes {}",
+        name,
+        if group.inherits.is_empty() {
+            "-".to_string()
+        } else {
+            group.inherits.join(", ")
+        },
+        if nodes.is_empty() {
+            "-".to_string()
+        } else {
+            nodes.join(", ")
+        }
+    ));
+    server.send_message(&context.sender, message);
+    Ok(nodes.len() as i32)
+}
+
+fn set_group_parent(server: &mut Server, context: &CommandContext, add: bool) -> CommandResult {
+    let group = context.get_string("group").unwrap().to_string();
+    let parent = context.get_string("parent").unwrap().to_string();
+    let changed = if add {
+        server.permissions.add_parent(&group, &parent)
+    } else {
+        server.permissions.remove_parent(&group, &parent)
+    };
+    match changed {
+        None => {
+            return Err(CommandError::Failed(format!(
+                "Unknown group '{}'",
+                if server.permissions.group(&group).is_none() {
+                    &group
+                } else {
+                    &parent
+                }
+            )))
+        }
+        Some(false) => return Err(CommandError::Failed("Nothing changed".to_string())),
+        Some(true) => {}
+    }
+    server.permissions.save();
+    server.resend_commands();
+    let message = if add {
+        format!("{} now inherits from {}", group, parent)
+    } else {
+        format!("{} no longer inherits from {}", group, parent)
+    };
+    server.send_message(&context.sender, ChatComponent::new(message));
+    Ok(1)
+}
+
+fn set_time(server: &mut Server, sender: &CommandSender, time: i64) -> CommandResult {
+    server.world.time = time;
+    server.broadcast_time();
+    let message = ChatComponent::translate(
+        "commands.time.set",
+        vec![ChatComponent::new(time.to_string())],
+    );
+    server.send_message(sender, message);
+    Ok((time % world::TICKS_PER_DAY) as i32)
+}
+
+fn set_weather(
+    server: &mut Server,
+    sender: &CommandSender,
+    weather: Weather,
+    duration: i32,
+) -> CommandResult {
+    server.world.set_weather(weather, duration);
+    server.broadcast_weather();
+    let key = format!("commands.weather.set.{}", weather.name());
+    server.send_message(sender, ChatComponent::translate(&key, vec![]));
+    Ok(duration)
+}
@@ -0,0 +1,1722 @@
+//! A command dispatcher compatible with Brigadier, the command library of the vanilla client.
+//! Commands are trees of literal and argument nodes. Clients get the tree in Declare Commands
+//! so they can highlight and complete commands while they're typed.
+
+pub mod builtin;
+
+use crate::player::{Gamemode, Player};
+use crate::registry::Item;
+use crate::utils::Location;
+use rand::seq::SliceRandom;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+
+/// The permission level needed for most admin commands, like vanilla's op level 2
+pub const ADMIN_PERMISSION_LEVEL: u8 = 2;
+/// The permission level needed to kick and ban players
+pub const MODERATOR_PERMISSION_LEVEL: u8 = 3;
+/// The highest permission level, which the console has
+pub const MAX_PERMISSION_LEVEL: u8 = 4;
+
+/// What a command sender is allowed to use
+pub trait Permissible {
+    fn permission_level(&self) -> u8;
+    /// Whether a permission node is granted or denied, None if it isn't set
+    fn permission(&self, node: &str) -> Option<bool>;
+}
+
+/// Whoever runs a command
+#[derive(Clone, Debug, PartialEq)]
+pub enum CommandSender {
+    Console,
+    /// A player by the id of their client
+    Player(u32),
+}
+
+#[derive(Debug)]
+pub enum CommandError {
+    UnknownCommand,
+    /// The input doesn't match any argument of the command
+    IncorrectArgument,
+    /// The command needs more arguments
+    Incomplete,
+    ExpectedSeparator,
+    Expected(&'static str),
+    Invalid {
+        expected: &'static str,
+        found: String,
+    },
+    TooLow {
+        min: String,
+        found: String,
+    },
+    TooHigh {
+        max: String,
+        found: String,
+    },
+    UnclosedQuote,
+    UnknownItem(String),
+    UnknownSelector(String),
+    /// The selector can select more than one entity where only one is allowed
+    TooManyEntities,
+    /// The selector can select other entities where only players are allowed
+    OnlyPlayers,
+    /// The local `^` coordinates can't be mixed with the others
+    MixedCoordinates,
+    UnknownOption(String),
+    /// A selector or name didn't match any player
+    NoPlayers,
+    NoEntities,
+    /// A name didn't match any player, online or known from the user lists
+    UnknownPlayer,
+    /// The command was run by the console, but only works for players
+    RequiresPlayer,
+    /// The command itself failed, with a message for the sender
+    Failed(String),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandError::UnknownCommand => write!(f, "Unknown command"),
+            CommandError::IncorrectArgument => write!(f, "Incorrect argument for command"),
+            CommandError::Incomplete => write!(f, "Incomplete command"),
+            CommandError::ExpectedSeparator => {
+                write!(f, "Expected whitespace to end one argument, but found trailing data")
+            }
+            CommandError::Expected(expected) => write!(f, "Expected {}", expected),
+            CommandError::Invalid { expected, found } => {
+                write!(f, "Invalid {} '{}'", expected, found)
+            }
+            CommandError::TooLow { min, found } => {
+                write!(f, "Must not be less than {}, found {}", min, found)
+            }
+            CommandError::TooHigh { max, found } => {
+                write!(f, "Must not be more than {}, found {}", max, found)
+            }
+            CommandError::UnclosedQuote => write!(f, "Unclosed quoted string"),
+            CommandError::UnknownItem(item) => write!(f, "Unknown item '{}'", item),
+            CommandError::UnknownSelector(selector) => {
+                write!(f, "Unknown selector type '{}'", selector)
+            }
+            CommandError::TooManyEntities => write!(
+                f,
+                "Only one entity is allowed, but the provided selector allows more than one"
+            ),
+            CommandError::OnlyPlayers => write!(
+                f,
+                "Only players may be affected by this command, but the provided selector includes entities"
+            ),
+            CommandError::MixedCoordinates => write!(
+                f,
+                "Cannot mix world & local coordinates (everything must either use ^ or not)"
+            ),
+            CommandError::UnknownOption(option) => write!(f, "Unknown option '{}'", option),
+            CommandError::NoPlayers => write!(f, "No player was found"),
+            CommandError::NoEntities => write!(f, "No entity was found"),
+            CommandError::UnknownPlayer => write!(f, "That player does not exist"),
+            CommandError::RequiresPlayer => {
+                write!(f, "A player is required to run this command here")
+            }
+            CommandError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for CommandError {}
+
+/// The result of a successful command, as in vanilla usually the number of things it affected
+pub type CommandResult = Result<i32, CommandError>;
+
+/// Reads arguments from the command input
+pub struct StringReader<'a> {
+    input: &'a str,
+    cursor: usize,
+}
+
+impl<'a> StringReader<'a> {
+    pub fn new(input: &'a str, cursor: usize) -> StringReader<'a> {
+        StringReader { input, cursor }
+    }
+
+    pub fn remaining(&self) -> &'a str {
+        &self.input[self.cursor..]
+    }
+
+    pub fn can_read(&self) -> bool {
+        self.cursor < self.input.len()
+    }
+
+    pub fn peek(&self) -> Option<char> {
+        self.remaining().chars().next()
+    }
+
+    pub fn skip(&mut self) {
+        if let Some(c) = self.peek() {
+            self.cursor += c.len_utf8();
+        }
+    }
+
+    /// Reads while the characters match, returning what was read
+    pub fn read_while<F: Fn(char) -> bool>(&mut self, predicate: F) -> &'a str {
+        let start = self.cursor;
+        while let Some(c) = self.peek() {
+            if !predicate(c) {
+                break;
+            }
+            self.cursor += c.len_utf8();
+        }
+        &self.input[start..self.cursor]
+    }
+
+    pub fn read_unquoted_string(&mut self) -> &'a str {
+        self.read_while(|c| {
+            c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.' || c == '+'
+        })
+    }
+
+    /// Reads a string that can be quoted to contain spaces
+    pub fn read_string(&mut self) -> Result<String, CommandError> {
+        let quote = match self.peek() {
+            Some(quote) if quote == '"' || quote == '\'' => quote,
+            _ => return Ok(self.read_unquoted_string().to_string()),
+        };
+        self.skip();
+        let mut string = String::new();
+        let mut escaped = false;
+        while let Some(c) = self.peek() {
+            self.skip();
+            if escaped {
+                string.push(c);
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == quote {
+                return Ok(string);
+            } else {
+                string.push(c);
+            }
+        }
+        Err(CommandError::UnclosedQuote)
+    }
+
+    fn read_number(&mut self) -> &'a str {
+        self.read_while(|c| c.is_ascii_digit() || c == '.' || c == '-')
+    }
+
+    pub fn read_int(&mut self) -> Result<i32, CommandError> {
+        let number = self.read_number();
+        if number.is_empty() {
+            return Err(CommandError::Expected("integer"));
+        }
+        number.parse().map_err(|_| CommandError::Invalid {
+            expected: "integer",
+            found: number.to_string(),
+        })
+    }
+
+    pub fn read_double(&mut self) -> Result<f64, CommandError> {
+        let number = self.read_number();
+        if number.is_empty() {
+            return Err(CommandError::Expected("double"));
+        }
+        number.parse().map_err(|_| CommandError::Invalid {
+            expected: "double",
+            found: number.to_string(),
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StringKind {
+    /// A single word
+    Word,
+    /// A word, or a quoted string with spaces
+    Phrase,
+    /// The rest of the input
+    Greedy,
+}
+
+/// How an argument is parsed, named after the parsers of the vanilla client
+#[derive(Clone, Debug, PartialEq)]
+pub enum ArgumentType {
+    Integer {
+        min: Option<i32>,
+        max: Option<i32>,
+    },
+    String(StringKind),
+    Entity {
+        single: bool,
+        players_only: bool,
+    },
+    /// A player name or a selector for players
+    GameProfile,
+    Vec3,
+    ItemStack,
+    /// The rest of the input, which may contain selectors
+    Message,
+    /// A duration in ticks, with an optional `d`, `s` or `t` unit
+    Time,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SelectorKind {
+    /// `@p`
+    NearestPlayer,
+    /// `@a`
+    AllPlayers,
+    /// `@r`
+    RandomPlayer,
+    /// `@s`
+    Sender,
+    /// `@e`
+    AllEntities,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EntitySelector {
+    Name(String),
+    Selector {
+        kind: SelectorKind,
+        /// The `key=value` pairs between the brackets, values are kept as they were written
+        arguments: Vec<(String, String)>,
+    },
+}
+
+impl EntitySelector {
+    fn parse(reader: &mut StringReader) -> Result<EntitySelector, CommandError> {
+        if reader.peek() != Some('@') {
+            let name = reader.read_while(|c| c != ' ');
+            if name.is_empty() {
+                return Err(CommandError::Expected("entity"));
+            }
+            return Ok(EntitySelector::Name(name.to_string()));
+        }
+        reader.skip();
+        let kind = match reader.peek() {
+            Some('p') => SelectorKind::NearestPlayer,
+            Some('a') => SelectorKind::AllPlayers,
+            Some('r') => SelectorKind::RandomPlayer,
+            Some('s') => SelectorKind::Sender,
+            Some('e') => SelectorKind::AllEntities,
+            other => {
+                let found = other.map(|c| c.to_string()).unwrap_or_default();
+                return Err(CommandError::UnknownSelector(format!("@{}", found)));
+            }
+        };
+        reader.skip();
+        let mut arguments = Vec::new();
+        if reader.peek() == Some('[') {
+            reader.skip();
+            loop {
+                reader.read_while(|c| c == ' ');
+                if reader.peek() == Some(']') {
+                    reader.skip();
+                    break;
+                }
+                let key = reader.read_unquoted_string().to_string();
+                reader.read_while(|c| c == ' ');
+                if reader.peek() != Some('=') {
+                    return Err(CommandError::Expected("'=' after a selector option"));
+                }
+                reader.skip();
+                reader.read_while(|c| c == ' ');
+                // Values like scores={...} can contain commas and brackets themselves
+                let start = reader.cursor;
+                let mut depth = 0;
+                while let Some(c) = reader.peek() {
+                    match c {
+                        '{' | '[' => depth += 1,
+                        '}' | ']' if depth > 0 => depth -= 1,
+                        ',' | ']' if depth == 0 => break,
+                        _ => {}
+                    }
+                    reader.skip();
+                }
+                let value = reader.input[start..reader.cursor].trim().to_string();
+                arguments.push((key, value));
+                match reader.peek() {
+                    Some(',') => reader.skip(),
+                    Some(']') => {}
+                    _ => return Err(CommandError::Expected("end of selector options")),
+                }
+            }
+        }
+        Ok(EntitySelector::Selector { kind, arguments })
+    }
+
+    /// The value of an option, like `limit` in `@e[limit=1]`
+    pub fn argument(&self, key: &str) -> Option<&str> {
+        match self {
+            EntitySelector::Name(_) => None,
+            EntitySelector::Selector { arguments, .. } => arguments
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, value)| value.as_str()),
+        }
+    }
+
+    /// Whether this can select more than one entity
+    pub fn is_multiple(&self) -> bool {
+        match self {
+            EntitySelector::Name(_) => false,
+            EntitySelector::Selector { kind, .. } => match kind {
+                SelectorKind::AllPlayers | SelectorKind::AllEntities => {
+                    self.argument("limit") != Some("1")
+                }
+                _ => false,
+            },
+        }
+    }
+
+    /// Whether this only selects players
+    pub fn is_players_only(&self) -> bool {
+        match self {
+            EntitySelector::Selector {
+                kind: SelectorKind::AllEntities,
+                ..
+            } => {
+                self.argument("type") == Some("player")
+                    || self.argument("type") == Some("minecraft:player")
+            }
+            _ => true,
+        }
+    }
+}
+
+/// A player a selector can pick
+pub struct SelectorCandidate<'a> {
+    /// The id of the player's client
+    pub id: u32,
+    pub player: &'a Player,
+}
+
+/// Parses a number, rejecting `NaN` and infinities which `f64::from_str` accepts
+fn parse_finite(value: &str) -> Option<f64> {
+    value.parse().ok().filter(|value: &f64| value.is_finite())
+}
+
+/// Parses a range like `5`, `..5`, `5..` or `1..5`
+fn parse_range(value: &str) -> Result<(Option<f64>, Option<f64>), CommandError> {
+    let invalid = || CommandError::Invalid {
+        expected: "range",
+        found: value.to_string(),
+    };
+    let bound = |bound: &str| -> Result<Option<f64>, CommandError> {
+        match bound {
+            "" => Ok(None),
+            bound => parse_finite(bound).map(Some).ok_or_else(invalid),
+        }
+    };
+    match value.find("..") {
+        Some(index) => Ok((bound(&value[..index])?, bound(&value[index + 2..])?)),
+        None => {
+            let exact = bound(value)?.ok_or_else(invalid)?;
+            Ok((Some(exact), Some(exact)))
+        }
+    }
+}
+
+impl EntitySelector {
+    /// Picks the players this selects, by the ids of their clients.
+    /// `sender` is the player running the command, and their position, if it's run by a player.
+    pub fn select(
+        &self,
+        sender: Option<(u32, Location)>,
+        origin: Location,
+        candidates: &[SelectorCandidate],
+    ) -> Result<Vec<u32>, CommandError> {
+        let (kind, arguments) = match self {
+            EntitySelector::Name(name) => {
+                return Ok(candidates
+                    .iter()
+                    .filter(|candidate| candidate.player.username.eq_ignore_ascii_case(name))
+                    .map(|candidate| candidate.id)
+                    .take(1)
+                    .collect())
+            }
+            EntitySelector::Selector { kind, arguments } => (*kind, arguments),
+        };
+        let mut origin = sender.map_or(origin, |(_, position)| position);
+        let mut distance = (None, None);
+        let mut limit = match kind {
+            SelectorKind::NearestPlayer | SelectorKind::RandomPlayer => Some(1),
+            _ => None,
+        };
+        let mut sort = match kind {
+            SelectorKind::NearestPlayer => "nearest",
+            SelectorKind::RandomPlayer => "random",
+            _ => "arbitrary",
+        };
+        let mut selected: Vec<&SelectorCandidate> = match (kind, sender) {
+            (SelectorKind::Sender, Some((id, _))) => candidates
+                .iter()
+                .filter(|candidate| candidate.id == id)
+                .collect(),
+            (SelectorKind::Sender, None) => Vec::new(),
+            _ => candidates.iter().collect(),
+        };
+        for (key, value) in arguments {
+            // Most options can be negated with a `!` in front of the value
+            let negated = value.starts_with('!');
+            let unnegated = value.trim_start_matches('!');
+            let invalid = || CommandError::Invalid {
+                expected: "selector option value",
+                found: value.to_string(),
+            };
+            match key.as_str() {
+                "x" => origin.x = parse_finite(value).ok_or_else(invalid)?,
+                "y" => origin.y = parse_finite(value).ok_or_else(invalid)?,
+                "z" => origin.z = parse_finite(value).ok_or_else(invalid)?,
+                "distance" => distance = parse_range(value)?,
+                "limit" => {
+                    let value: usize = value.parse().map_err(|_| invalid())?;
+                    if value == 0 {
+                        return Err(CommandError::TooLow {
+                            min: "1".to_string(),
+                            found: "0".to_string(),
+                        });
+                    }
+                    limit = Some(value);
+                }
+                "sort" => match value.as_str() {
+                    "nearest" | "furthest" | "random" | "arbitrary" => sort = value.as_str(),
+                    _ => return Err(invalid()),
+                },
+                "name" => {
+                    selected.retain(|candidate| (candidate.player.username == unnegated) != negated)
+                }
+                "gamemode" => {
+                    let gamemode = Gamemode::from_name(unnegated).ok_or_else(invalid)?;
+                    selected.retain(|candidate| (candidate.player.gamemode == gamemode) != negated);
+                }
+                "type" => {
+                    // Players are the only entities so far
+                    let player = unnegated == "player" || unnegated == "minecraft:player";
+                    if player == negated {
+                        selected.clear();
+                    }
+                }
+                _ => return Err(CommandError::UnknownOption(key.clone())),
+            }
+        }
+        let distance_squared = |candidate: &SelectorCandidate| {
+            let position = &candidate.player.position;
+            let (x, y, z) = (
+                position.x - origin.x,
+                position.y - origin.y,
+                position.z - origin.z,
+            );
+            x * x + y * y + z * z
+        };
+        if let (Some(_), _) | (_, Some(_)) = distance {
+            let (min, max) = distance;
+            selected.retain(|candidate| {
+                let distance = distance_squared(candidate).sqrt();
+                min.is_none_or(|min| distance >= min) && max.is_none_or(|max| distance <= max)
+            });
+        }
+        match sort {
+            "nearest" => {
+                selected.sort_by(|a, b| distance_squared(a).total_cmp(&distance_squared(b)))
+            }
+            "furthest" => {
+                selected.sort_by(|a, b| distance_squared(b).total_cmp(&distance_squared(a)))
+            }
+            "random" => selected.shuffle(&mut rand::thread_rng()),
+            _ => {}
+        }
+        if let Some(limit) = limit {
+            selected.truncate(limit);
+        }
+        Ok(selected.into_iter().map(|candidate| candidate.id).collect())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CoordinateKind {
+    Absolute,
+    /// `~`, relative to the sender's position
+    Relative,
+    /// `^`, relative to the sender's position and rotation
+    Local,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Coordinate {
+    pub kind: CoordinateKind,
+    pub value: f64,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Coordinates {
+    pub x: Coordinate,
+    pub y: Coordinate,
+    pub z: Coordinate,
+}
+
+impl Coordinates {
+    /// Reads three coordinates
+    fn parse(reader: &mut StringReader) -> Result<Coordinates, CommandError> {
+        let mut coordinates = [Coordinate {
+            kind: CoordinateKind::Absolute,
+            value: 0.0,
+        }; 3];
+        for (i, coordinate) in coordinates.iter_mut().enumerate() {
+            if i > 0 {
+                if reader.peek() != Some(' ') {
+                    return Err(CommandError::Incomplete);
+                }
+                reader.skip();
+            }
+            coordinate.kind = match reader.peek() {
+                Some('~') => CoordinateKind::Relative,
+                Some('^') => CoordinateKind::Local,
+                _ => CoordinateKind::Absolute,
+            };
+            if coordinate.kind != CoordinateKind::Absolute {
+                reader.skip();
+                let offset_given = match reader.peek() {
+                    Some(c) => c != ' ',
+                    None => false,
+                };
+                if offset_given {
+                    coordinate.value = reader.read_double()?;
+                }
+            } else {
+                let number = reader.read_number();
+                coordinate.value = number.parse().map_err(|_| CommandError::Invalid {
+                    expected: "coordinate",
+                    found: number.to_string(),
+                })?;
+                // Whole numbers are centered on the block horizontally
+                if i != 1 && !number.contains('.') {
+                    coordinate.value += 0.5;
+                }
+            }
+        }
+        let local = coordinates
+            .iter()
+            .filter(|coordinate| coordinate.kind == CoordinateKind::Local)
+            .count();
+        if local != 0 && local != 3 {
+            return Err(CommandError::MixedCoordinates);
+        }
+        Ok(Coordinates {
+            x: coordinates[0],
+            y: coordinates[1],
+            z: coordinates[2],
+        })
+    }
+
+    /// The position these coordinates point to from the origin
+    pub fn resolve(&self, origin: &Location) -> (f64, f64, f64) {
+        if self.x.kind == CoordinateKind::Local {
+            // Left, up and forwards relative to where the origin is looking
+            let yaw = (origin.yaw as f64 + 90.0).to_radians();
+            let pitch = -(origin.pitch as f64).to_radians();
+            let up_pitch = pitch + std::f64::consts::FRAC_PI_2;
+            let forwards = (
+                yaw.cos() * pitch.cos(),
+                pitch.sin(),
+                yaw.sin() * pitch.cos(),
+            );
+            let up = (
+                yaw.cos() * up_pitch.cos(),
+                up_pitch.sin(),
+                yaw.sin() * up_pitch.cos(),
+            );
+            let left = (
+                -(forwards.1 * up.2 - forwards.2 * up.1),
+                -(forwards.2 * up.0 - forwards.0 * up.2),
+                -(forwards.0 * up.1 - forwards.1 * up.0),
+            );
+            let (l, u, f) = (self.x.value, self.y.value, self.z.value);
+            return (
+                origin.x + forwards.0 * f + up.0 * u + left.0 * l,
+                origin.y + forwards.1 * f + up.1 * u + left.1 * l,
+                origin.z + forwards.2 * f + up.2 * u + left.2 * l,
+            );
+        }
+        let resolve = |coordinate: &Coordinate, origin: f64| match coordinate.kind {
+            CoordinateKind::Absolute => coordinate.value,
+            _ => origin + coordinate.value,
+        };
+        (
+            resolve(&self.x, origin.x),
+            resolve(&self.y, origin.y),
+            resolve(&self.z, origin.z),
+        )
+    }
+}
+
+/// A parsed argument
+#[derive(Clone, Debug, PartialEq)]
+pub enum ArgumentValue {
+    Integer(i32),
+    String(String),
+    Entity(EntitySelector),
+    Coordinates(Coordinates),
+    Item(Item),
+    Time(i32),
+}
+
+fn check_range<T: PartialOrd + fmt::Display>(
+    value: T,
+    min: Option<T>,
+    max: Option<T>,
+) -> Result<T, CommandError> {
+    match (min, max) {
+        (Some(min), _) if value < min => Err(CommandError::TooLow {
+            min: min.to_string(),
+            found: value.to_string(),
+        }),
+        (_, Some(max)) if value > max => Err(CommandError::TooHigh {
+            max: max.to_string(),
+            found: value.to_string(),
+        }),
+        _ => Ok(value),
+    }
+}
+
+impl ArgumentType {
+    /// The name of the parser on the client
+    pub fn identifier(&self) -> &'static str {
+        match self {
+            ArgumentType::Integer { .. } => "brigadier:integer",
+            ArgumentType::String(_) => "brigadier:string",
+            ArgumentType::Entity { .. } => "minecraft:entity",
+            ArgumentType::GameProfile => "minecraft:game_profile",
+            ArgumentType::Vec3 => "minecraft:vec3",
+            ArgumentType::ItemStack => "minecraft:item_stack",
+            ArgumentType::Message => "minecraft:message",
+            ArgumentType::Time => "minecraft:time",
+        }
+    }
+
+    pub fn parse(&self, reader: &mut StringReader) -> Result<ArgumentValue, CommandError> {
+        Ok(match self {
+            ArgumentType::Integer { min, max } => {
+                ArgumentValue::Integer(check_range(reader.read_int()?, *min, *max)?)
+            }
+            ArgumentType::String(kind) => ArgumentValue::String(match kind {
+                StringKind::Word => reader.read_unquoted_string().to_string(),
+                StringKind::Phrase => reader.read_string()?,
+                StringKind::Greedy => reader.read_while(|_| true).to_string(),
+            }),
+            ArgumentType::Entity {
+                single,
+                players_only,
+            } => {
+                let selector = EntitySelector::parse(reader)?;
+                if *single && selector.is_multiple() {
+                    return Err(CommandError::TooManyEntities);
+                }
+                if *players_only && !selector.is_players_only() {
+                    return Err(CommandError::OnlyPlayers);
+                }
+                ArgumentValue::Entity(selector)
+            }
+            ArgumentType::GameProfile => {
+                let selector = EntitySelector::parse(reader)?;
+                if !selector.is_players_only() {
+                    return Err(CommandError::OnlyPlayers);
+                }
+                ArgumentValue::Entity(selector)
+            }
+            ArgumentType::Vec3 => ArgumentValue::Coordinates(Coordinates::parse(reader)?),
+            ArgumentType::ItemStack => {
+                let name = reader.read_while(|c| {
+                    c.is_ascii_lowercase()
+                        || c.is_ascii_digit()
+                        || c == '_'
+                        || c == ':'
+                        || c == '.'
+                        || c == '/'
+                        || c == '-'
+                });
+                if name.is_empty() {
+                    return Err(CommandError::Expected("item"));
+                }
+                let item = if name.contains(':') {
+                    Item::from_name(name)
+                } else {
+                    Item::from_name(&format!("minecraft:{}", name))
+                };
+                match item {
+                    Some(item) => ArgumentValue::Item(item),
+                    None => return Err(CommandError::UnknownItem(name.to_string())),
+                }
+            }
+            ArgumentType::Message => ArgumentValue::String(reader.read_while(|_| true).to_string()),
+            ArgumentType::Time => {
+                let time = reader.read_double()?;
+                let ticks_per_unit = match reader.peek() {
+                    Some('d') => 24000.0,
+                    Some('s') => 20.0,
+                    Some('t') => 1.0,
+                    _ => 1.0,
+                };
+                if let Some('d') | Some('s') | Some('t') = reader.peek() {
+                    reader.skip();
+                }
+                let ticks = (time * ticks_per_unit).round();
+                if ticks < 0.0 {
+                    return Err(CommandError::TooLow {
+                        min: "0".to_string(),
+                        found: ticks.to_string(),
+                    });
+                }
+                ArgumentValue::Time(ticks as i32)
+            }
+        })
+    }
+
+    /// Whether the client should ask the server for suggestions instead of making its own
+    fn asks_server(&self) -> bool {
+        match self {
+            // There's no tab list to take player names from yet
+            ArgumentType::Entity { .. } | ArgumentType::GameProfile => true,
+            _ => false,
+        }
+    }
+
+    fn suggestions(&self, player_names: &[String]) -> Vec<String> {
+        match self {
+            ArgumentType::Entity {
+                single,
+                players_only,
+            } => {
+                let mut suggestions = vec!["@p", "@r", "@s"];
+                if !*single {
+                    suggestions.push("@a");
+                    if !*players_only {
+                        suggestions.push("@e");
+                    }
+                }
+                let mut suggestions: Vec<String> =
+                    suggestions.into_iter().map(String::from).collect();
+                suggestions.extend(player_names.iter().cloned());
+                suggestions
+            }
+            ArgumentType::GameProfile => {
+                let mut suggestions: Vec<String> = vec![
+                    "@p".to_string(),
+                    "@r".to_string(),
+                    "@s".to_string(),
+                    "@a".to_string(),
+                ];
+                suggestions.extend(player_names.iter().cloned());
+                suggestions
+            }
+            ArgumentType::Vec3 => vec!["~ ~ ~".to_string()],
+            ArgumentType::ItemStack => {
+                let mut suggestions = Vec::new();
+                let mut id = 0;
+                while let Some(item) = Item::from_id(id) {
+                    suggestions.push(item.name().to_string());
+                    id += 1;
+                }
+                suggestions
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// The sender and parsed arguments of a command being executed
+pub struct CommandContext {
+    pub sender: CommandSender,
+    pub input: String,
+    arguments: HashMap<String, ArgumentValue>,
+}
+
+impl CommandContext {
+    pub fn argument(&self, name: &str) -> Option<&ArgumentValue> {
+        self.arguments.get(name)
+    }
+
+    pub fn get_integer(&self, name: &str) -> Option<i32> {
+        match self.arguments.get(name) {
+            Some(ArgumentValue::Integer(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_string(&self, name: &str) -> Option<&str> {
+        match self.arguments.get(name) {
+            Some(ArgumentValue::String(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_entity(&self, name: &str) -> Option<&EntitySelector> {
+        match self.arguments.get(name) {
+            Some(ArgumentValue::Entity(selector)) => Some(selector),
+            _ => None,
+        }
+    }
+
+    pub fn get_coordinates(&self, name: &str) -> Option<&Coordinates> {
+        match self.arguments.get(name) {
+            Some(ArgumentValue::Coordinates(coordinates)) => Some(coordinates),
+            _ => None,
+        }
+    }
+
+    pub fn get_item(&self, name: &str) -> Option<Item> {
+        match self.arguments.get(name) {
+            Some(ArgumentValue::Item(item)) => Some(*item),
+            _ => None,
+        }
+    }
+
+    pub fn get_time(&self, name: &str) -> Option<i32> {
+        match self.arguments.get(name) {
+            Some(ArgumentValue::Time(ticks)) => Some(*ticks),
+            _ => None,
+        }
+    }
+}
+
+/// Runs a command on the state `S` the dispatcher belongs to
+pub type Executor<S> = Rc<dyn Fn(&mut S, &CommandContext) -> CommandResult>;
+
+enum NodeKind {
+    Root,
+    Literal(String),
+    Argument { name: String, parser: ArgumentType },
+}
+
+struct CommandNode<S> {
+    kind: NodeKind,
+    children: Vec<usize>,
+    /// The permission level needed to use this node
+    requires: u8,
+    /// Overrides the permission level if the sender has it set
+    permission: Option<String>,
+    executor: Option<Executor<S>>,
+}
+
+impl<S> CommandNode<S> {
+    fn is_permitted(&self, sender: &dyn Permissible) -> bool {
+        self.permission
+            .as_ref()
+            .and_then(|permission| sender.permission(permission))
+            .unwrap_or_else(|| self.requires <= sender.permission_level())
+    }
+}
+
+/// A command node with its children, created with `literal` and `argument`
+pub struct CommandBuilder<S> {
+    kind: NodeKind,
+    requires: u8,
+    /// `minecraft.command.<name>` for the top node, set when the command is registered
+    permission: Option<String>,
+    executor: Option<Executor<S>>,
+    children: Vec<CommandBuilder<S>>,
+}
+
+pub fn literal<S>(name: &str) -> CommandBuilder<S> {
+    CommandBuilder::new(NodeKind::Literal(name.to_string()))
+}
+
+pub fn argument<S>(name: &str, parser: ArgumentType) -> CommandBuilder<S> {
+    CommandBuilder::new(NodeKind::Argument {
+        name: name.to_string(),
+        parser,
+    })
+}
+
+impl<S> CommandBuilder<S> {
+    fn new(kind: NodeKind) -> CommandBuilder<S> {
+        CommandBuilder {
+            kind,
+            requires: 0,
+            permission: None,
+            executor: None,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn requires(mut self, permission_level: u8) -> Self {
+        self.requires = permission_level;
+        self
+    }
+
+    /// The name of a literal node, which for the top node is the name of the command
+    pub fn name(&self) -> Option<&str> {
+        match &self.kind {
+            NodeKind::Literal(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    pub fn then(mut self, child: CommandBuilder<S>) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    pub fn executes<F>(mut self, executor: F) -> Self
+    where
+        F: Fn(&mut S, &CommandContext) -> CommandResult + 'static,
+    {
+        self.executor = Some(Rc::new(executor));
+        self
+    }
+}
+
+/// A node as sent in Declare Commands, children are indices into the sent nodes
+pub struct DeclaredNode<'a> {
+    pub executable: bool,
+    pub children: Vec<i32>,
+    /// None for the root node
+    pub name: Option<&'a str>,
+    /// None for the root and literal nodes
+    pub parser: Option<&'a ArgumentType>,
+    /// Whether the client asks the server for suggestions with Tab-Complete
+    pub ask_server: bool,
+}
+
+pub struct CommandDispatcher<S> {
+    /// The root node is always the first one
+    nodes: Vec<CommandNode<S>>,
+}
+
+impl<S> CommandDispatcher<S> {
+    pub fn new() -> CommandDispatcher<S> {
+        CommandDispatcher {
+            nodes: vec![CommandNode {
+                kind: NodeKind::Root,
+                children: Vec::new(),
+                requires: 0,
+                permission: None,
+                executor: None,
+            }],
+        }
+    }
+
+    /// Adds a command, merging it with existing nodes of the same name
+    pub fn register(&mut self, mut command: CommandBuilder<S>) {
+        if let NodeKind::Literal(name) = &command.kind {
+            command.permission = Some(format!("minecraft.command.{}", name));
+        }
+        self.add_node(0, command);
+    }
+
+    /// Removes a command. Its nodes stay allocated, but can't be reached anymore
+    /// and their executors are dropped.
+    pub fn unregister(&mut self, name: &str) {
+        let removed: Vec<usize> = self.nodes[0]
+            .children
+            .iter()
+            .copied()
+            .filter(|child| match &self.nodes[*child].kind {
+                NodeKind::Literal(literal) => literal == name,
+                _ => false,
+            })
+            .collect();
+        self.nodes[0]
+            .children
+            .retain(|child| !removed.contains(child));
+        let mut pending = removed;
+        while let Some(index) = pending.pop() {
+            let node = &mut self.nodes[index];
+            node.executor = None;
+            pending.extend(node.children.iter().copied());
+        }
+    }
+
+    fn add_node(&mut self, parent: usize, builder: CommandBuilder<S>) {
+        let existing = self.nodes[parent].children.iter().copied().find(|child| {
+            match (&self.nodes[*child].kind, &builder.kind) {
+                (NodeKind::Literal(a), NodeKind::Literal(b)) => a == b,
+                (NodeKind::Argument { name: a, .. }, NodeKind::Argument { name: b, .. }) => a == b,
+                _ => false,
+            }
+        });
+        let index = match existing {
+            Some(index) => {
+                let node = &mut self.nodes[index];
+                node.requires = builder.requires;
+                node.permission = builder.permission;
+                if builder.executor.is_some() {
+                    node.executor = builder.executor;
+                }
+                index
+            }
+            None => {
+                self.nodes.push(CommandNode {
+                    kind: builder.kind,
+                    children: Vec::new(),
+                    requires: builder.requires,
+                    permission: builder.permission,
+                    executor: builder.executor,
+                });
+                let index = self.nodes.len() - 1;
+                self.nodes[parent].children.push(index);
+                index
+            }
+        };
+        for child in builder.children {
+            self.add_node(index, child);
+        }
+    }
+
+    /// The children of a node the sender can use, literals first
+    fn visible_children(&self, node: usize, permissions: &dyn Permissible) -> Vec<usize> {
+        let mut children: Vec<usize> = self.nodes[node]
+            .children
+            .iter()
+            .copied()
+            .filter(|child| self.nodes[*child].is_permitted(permissions))
+            .collect();
+        children.sort_by_key(|child| match self.nodes[*child].kind {
+            NodeKind::Literal(_) => 0,
+            _ => 1,
+        });
+        children
+    }
+
+    /// The names of the commands the sender can use
+    pub fn command_names(&self, permissions: &dyn Permissible) -> Vec<&str> {
+        self.visible_children(0, permissions)
+            .into_iter()
+            .filter_map(|child| match &self.nodes[child].kind {
+                NodeKind::Literal(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Parses a command without the leading slash, returning what to execute
+    pub fn parse(
+        &self,
+        sender: CommandSender,
+        input: &str,
+        permissions: &dyn Permissible,
+    ) -> Result<(Executor<S>, CommandContext), CommandError> {
+        let mut arguments = Vec::new();
+        let executor = self
+            .parse_children(0, input, 0, permissions, &mut arguments)
+            .map_err(|(_, err)| err)?;
+        let context = CommandContext {
+            sender,
+            input: input.to_string(),
+            arguments: arguments.into_iter().collect(),
+        };
+        Ok((executor, context))
+    }
+
+    /// Errors come with how far into the input they happened,
+    /// so the alternative that got furthest gets reported
+    fn parse_children(
+        &self,
+        node: usize,
+        input: &str,
+        cursor: usize,
+        permissions: &dyn Permissible,
+        arguments: &mut Vec<(String, ArgumentValue)>,
+    ) -> Result<Executor<S>, (usize, CommandError)> {
+        let word = input[cursor..].split(' ').next().unwrap_or("");
+        let mut error: Option<(usize, CommandError)> = None;
+        for child in self.visible_children(node, permissions) {
+            match &self.nodes[child].kind {
+                NodeKind::Literal(name) if name == word => {
+                    // Literals take precedence over arguments
+                    return self.parse_rest(
+                        child,
+                        input,
+                        cursor + word.len(),
+                        permissions,
+                        arguments,
+                    );
+                }
+                NodeKind::Argument { name, parser } => {
+                    let mut reader = StringReader::new(input, cursor);
+                    let result = match parser.parse(&mut reader) {
+                        Err(err) => Err((reader.cursor, err)),
+                        Ok(_) if reader.can_read() && reader.peek() != Some(' ') => {
+                            Err((reader.cursor, CommandError::ExpectedSeparator))
+                        }
+                        Ok(value) => {
+                            arguments.push((name.clone(), value));
+                            let result = self.parse_rest(
+                                child,
+                                input,
+                                reader.cursor,
+                                permissions,
+                                arguments,
+                            );
+                            if result.is_err() {
+                                arguments.pop();
+                            }
+                            result
+                        }
+                    };
+                    match result {
+                        Ok(executor) => return Ok(executor),
+                        Err(err) => {
+                            if error.as_ref().is_none_or(|(position, _)| err.0 > *position) {
+                                error = Some(err);
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Err(error.unwrap_or_else(|| {
+            let err = if node == 0 {
+                CommandError::UnknownCommand
+            } else {
+                CommandError::IncorrectArgument
+            };
+            (cursor, err)
+        }))
+    }
+
+    /// Continues parsing after a node that was matched up to the cursor
+    fn parse_rest(
+        &self,
+        node: usize,
+        input: &str,
+        cursor: usize,
+        permissions: &dyn Permissible,
+        arguments: &mut Vec<(String, ArgumentValue)>,
+    ) -> Result<Executor<S>, (usize, CommandError)> {
+        if cursor >= input.len() {
+            return self.nodes[node]
+                .executor
+                .clone()
+                .ok_or((cursor, CommandError::Incomplete));
+        }
+        self.parse_children(node, input, cursor + 1, permissions, arguments)
+    }
+
+    /// Suggestions for the end of the input, with where in the input they start
+    pub fn suggest(
+        &self,
+        input: &str,
+        permissions: &dyn Permissible,
+        player_names: &[String],
+    ) -> (usize, Vec<String>) {
+        let mut suggestions = Vec::new();
+        self.suggest_children(0, input, 0, permissions, player_names, &mut suggestions);
+        let start = suggestions
+            .iter()
+            .map(|(start, _)| *start)
+            .min()
+            .unwrap_or(input.len());
+        // Suggestions that start later get the input in between prepended
+        let mut suggestions: Vec<String> = suggestions
+            .into_iter()
+            .map(|(from, suggestion)| format!("{}{}", &input[start..from], suggestion))
+            .collect();
+        suggestions.sort();
+        suggestions.dedup();
+        (start, suggestions)
+    }
+
+    fn suggest_children(
+        &self,
+        node: usize,
+        input: &str,
+        cursor: usize,
+        permissions: &dyn Permissible,
+        player_names: &[String],
+        suggestions: &mut Vec<(usize, String)>,
+    ) {
+        let rest = &input[cursor..];
+        for child in self.visible_children(node, permissions) {
+            let child_node = &self.nodes[child];
+            let (parser, end) = match &child_node.kind {
+                NodeKind::Literal(name) => {
+                    if name.starts_with(rest) {
+                        suggestions.push((cursor, name.clone()));
+                    }
+                    (
+                        None,
+                        Some(cursor + name.len()).filter(|_| rest.starts_with(name.as_str())),
+                    )
+                }
+                NodeKind::Argument { parser, .. } => {
+                    let mut reader = StringReader::new(input, cursor);
+                    let end = parser.parse(&mut reader).ok().map(|_| reader.cursor);
+                    (Some(parser), end)
+                }
+                NodeKind::Root => (None, None),
+            };
+            match end {
+                // The argument is complete and followed by another one
+                Some(end) if input[end..].starts_with(' ') => self.suggest_children(
+                    child,
+                    input,
+                    end + 1,
+                    permissions,
+                    player_names,
+                    suggestions,
+                ),
+                _ => {
+                    if let Some(parser) = parser {
+                        for option in parser.suggestions(player_names) {
+                            let matches = option.starts_with(rest)
+                                || option.starts_with(&format!("minecraft:{}", rest));
+                            if matches {
+                                suggestions.push((cursor, option));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// The nodes the sender can use for Declare Commands,
+    /// with the index of the root node
+    pub fn declare(&self, permissions: &dyn Permissible) -> (Vec<DeclaredNode<'_>>, i32) {
+        let mut indices: HashMap<usize, i32> = HashMap::new();
+        let mut order = vec![0];
+        indices.insert(0, 0);
+        let mut i = 0;
+        while i < order.len() {
+            for child in self.visible_children(order[i], permissions) {
+                if let Entry::Vacant(entry) = indices.entry(child) {
+                    entry.insert(order.len() as i32);
+                    order.push(child);
+                }
+            }
+            i += 1;
+        }
+        let declared = order
+            .iter()
+            .map(|index| {
+                let node = &self.nodes[*index];
+                let (name, parser) = match &node.kind {
+                    NodeKind::Root => (None, None),
+                    NodeKind::Literal(name) => (Some(name.as_str()), None),
+                    NodeKind::Argument { name, parser } => (Some(name.as_str()), Some(parser)),
+                };
+                DeclaredNode {
+                    executable: node.executor.is_some(),
+                    children: self
+                        .visible_children(*index, permissions)
+                        .iter()
+                        .map(|child| indices[child])
+                        .collect(),
+                    name,
+                    parser,
+                    ask_server: parser.is_some_and(ArgumentType::asks_server),
+                }
+            })
+            .collect();
+        (declared, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory::Inventory;
+
+    struct Permissions {
+        level: u8,
+        nodes: HashMap<String, bool>,
+    }
+
+    impl Permissible for Permissions {
+        fn permission_level(&self) -> u8 {
+            self.level
+        }
+
+        fn permission(&self, node: &str) -> Option<bool> {
+            self.nodes.get(node).copied()
+        }
+    }
+
+    fn level(level: u8) -> Permissions {
+        Permissions {
+            level,
+            nodes: HashMap::new(),
+        }
+    }
+
+    fn player(username: &str, x: f64, gamemode: Gamemode) -> Player {
+        Player {
+            username: username.to_string(),
+            position: Location::new(x, 0.0, 0.0, 0.0, 0.0),
+            inventory: Inventory::new(),
+            gamemode,
+            selected_slot: 0,
+            digging: None,
+        }
+    }
+
+    fn selector(input: &str) -> Result<EntitySelector, CommandError> {
+        EntitySelector::parse(&mut StringReader::new(input, 0))
+    }
+
+    /// Selects from players standing at x = 0, 10 and 5 with ids 1, 2 and 3,
+    /// the sender being player 1
+    fn select(input: &str) -> Result<Vec<u32>, CommandError> {
+        let players = [
+            player("Alice", 0.0, Gamemode::Survival),
+            player("Bob", 10.0, Gamemode::Creative),
+            player("Carol", 5.0, Gamemode::Survival),
+        ];
+        let candidates: Vec<SelectorCandidate> = players
+            .iter()
+            .enumerate()
+            .map(|(i, player)| SelectorCandidate {
+                id: i as u32 + 1,
+                player,
+            })
+            .collect();
+        let origin = Location::new(0.0, 0.0, 0.0, 0.0, 0.0);
+        selector(input)?.select(Some((1, origin)), origin, &candidates)
+    }
+
+    fn coordinates(input: &str) -> Result<Coordinates, CommandError> {
+        Coordinates::parse(&mut StringReader::new(input, 0))
+    }
+
+    fn assert_close((x, y, z): (f64, f64, f64), expected: (f64, f64, f64)) {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        assert!(
+            close(x, expected.0) && close(y, expected.1) && close(z, expected.2),
+            "{:?} != {:?}",
+            (x, y, z),
+            expected
+        );
+    }
+
+    #[test]
+    fn string_reader() {
+        let mut reader = StringReader::new("word \"quoted \\\"string\\\"\" 'single' rest", 0);
+        assert_eq!(reader.read_string().unwrap(), "word");
+        reader.skip();
+        assert_eq!(reader.read_string().unwrap(), "quoted \"string\"");
+        reader.skip();
+        assert_eq!(reader.read_string().unwrap(), "single");
+        assert_eq!(reader.remaining(), " rest");
+        assert!(matches!(
+            StringReader::new("\"unclosed", 0).read_string(),
+            Err(CommandError::UnclosedQuote)
+        ));
+
+        let mut reader = StringReader::new("-12 3.5", 0);
+        assert_eq!(reader.read_int().unwrap(), -12);
+        reader.skip();
+        assert_eq!(reader.read_double().unwrap(), 3.5);
+        assert!(!reader.can_read());
+        assert!(matches!(
+            StringReader::new("abc", 0).read_int(),
+            Err(CommandError::Expected("integer"))
+        ));
+        assert!(matches!(
+            StringReader::new("1.5", 0).read_int(),
+            Err(CommandError::Invalid { .. })
+        ));
+        assert!(matches!(
+            StringReader::new("1-2", 0).read_double(),
+            Err(CommandError::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    fn selector_parsing() {
+        assert_eq!(
+            selector("Steve").unwrap(),
+            EntitySelector::Name("Steve".to_string())
+        );
+        let parsed = selector("@e[type=player, limit = 1,scores={a=1,b=2}]").unwrap();
+        assert_eq!(
+            parsed,
+            EntitySelector::Selector {
+                kind: SelectorKind::AllEntities,
+                arguments: vec![
+                    ("type".to_string(), "player".to_string()),
+                    ("limit".to_string(), "1".to_string()),
+                    ("scores".to_string(), "{a=1,b=2}".to_string()),
+                ],
+            }
+        );
+        assert!(!parsed.is_multiple());
+        assert!(parsed.is_players_only());
+        assert!(selector("@a").unwrap().is_multiple());
+        assert!(!selector("@e").unwrap().is_players_only());
+        assert!(matches!(
+            selector("@x"),
+            Err(CommandError::UnknownSelector(_))
+        ));
+        assert!(matches!(
+            selector("@a[limit]"),
+            Err(CommandError::Expected(_))
+        ));
+        assert!(matches!(
+            selector("@a[limit=1"),
+            Err(CommandError::Expected(_))
+        ));
+    }
+
+    #[test]
+    fn selector_options() {
+        assert_eq!(select("@s").unwrap(), [1]);
+        assert_eq!(select("@p").unwrap(), [1]);
+        assert_eq!(select("Bob").unwrap(), [2]);
+        assert_eq!(select("bob").unwrap(), [2]);
+        assert_eq!(select("@a").unwrap(), [1, 2, 3]);
+        assert_eq!(select("@a[name=Carol]").unwrap(), [3]);
+        assert_eq!(select("@a[name=!Carol]").unwrap(), [1, 2]);
+        assert_eq!(select("@a[gamemode=survival]").unwrap(), [1, 3]);
+        assert_eq!(select("@a[gamemode=!survival]").unwrap(), [2]);
+        assert_eq!(select("@e[type=player]").unwrap(), [1, 2, 3]);
+        assert!(select("@e[type=!player]").unwrap().is_empty());
+        assert_eq!(select("@a[distance=..5]").unwrap(), [1, 3]);
+        assert_eq!(select("@a[distance=5..]").unwrap(), [2, 3]);
+        assert_eq!(select("@a[x=10,distance=..5]").unwrap(), [2, 3]);
+        assert!(matches!(
+            select("@a[gamemode=flying]"),
+            Err(CommandError::Invalid { .. })
+        ));
+        assert!(matches!(
+            select("@a[color=red]"),
+            Err(CommandError::UnknownOption(_))
+        ));
+    }
+
+    #[test]
+    fn selector_limit_and_sort() {
+        assert_eq!(select("@a[sort=nearest]").unwrap(), [1, 3, 2]);
+        assert_eq!(select("@a[sort=furthest]").unwrap(), [2, 3, 1]);
+        assert_eq!(select("@a[sort=furthest,limit=2]").unwrap(), [2, 3]);
+        assert_eq!(select("@a[x=10,sort=nearest,limit=1]").unwrap(), [2]);
+        assert_eq!(select("@p[x=10]").unwrap(), [2]);
+        assert_eq!(select("@r").unwrap().len(), 1);
+        assert!(matches!(
+            select("@a[limit=0]"),
+            Err(CommandError::TooLow { .. })
+        ));
+        assert!(matches!(
+            select("@a[sort=sideways]"),
+            Err(CommandError::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    fn selector_rejects_non_finite_numbers() {
+        for input in &[
+            "@a[x=NaN,sort=nearest]",
+            "@a[y=inf]",
+            "@a[z=-infinity]",
+            "@a[distance=..NaN]",
+            "@a[distance=inf..]",
+        ] {
+            assert!(
+                matches!(select(input), Err(CommandError::Invalid { .. })),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn absolute_coordinates() {
+        let origin = Location::new(100.0, 64.0, -100.0, 0.0, 0.0);
+        assert_close(
+            coordinates("1.5 -2 3.0").unwrap().resolve(&origin),
+            (1.5, -2.0, 3.0),
+        );
+        // Whole numbers are centered horizontally
+        assert_close(
+            coordinates("1 2 3.25").unwrap().resolve(&origin),
+            (1.5, 2.0, 3.25),
+        );
+        assert!(matches!(coordinates("1 2"), Err(CommandError::Incomplete)));
+    }
+
+    #[test]
+    fn relative_coordinates() {
+        let origin = Location::new(100.5, 64.0, -100.5, 0.0, 0.0);
+        assert_close(
+            coordinates("~ ~1 ~-2.5").unwrap().resolve(&origin),
+            (100.5, 65.0, -103.0),
+        );
+        assert_close(
+            coordinates("~ ~-1 5").unwrap().resolve(&origin),
+            (100.5, 63.0, 5.5),
+        );
+    }
+
+    #[test]
+    fn local_coordinates() {
+        // Looking south, towards positive z, with the left being positive x
+        let south = Location::new(0.0, 64.0, 0.0, 0.0, 0.0);
+        assert_close(
+            coordinates("^ ^ ^2").unwrap().resolve(&south),
+            (0.0, 64.0, 2.0),
+        );
+        assert_close(
+            coordinates("^1 ^2 ^").unwrap().resolve(&south),
+            (1.0, 66.0, 0.0),
+        );
+        // Looking west, towards negative x
+        let west = Location::new(0.0, 64.0, 0.0, 0.0, 90.0);
+        assert_close(
+            coordinates("^ ^ ^2").unwrap().resolve(&west),
+            (-2.0, 64.0, 0.0),
+        );
+        // Looking straight up
+        let up = Location::new(0.0, 64.0, 0.0, -90.0, 0.0);
+        assert_close(
+            coordinates("^ ^ ^2").unwrap().resolve(&up),
+            (0.0, 66.0, 0.0),
+        );
+        assert!(matches!(
+            coordinates("^ ~ ^"),
+            Err(CommandError::MixedCoordinates)
+        ));
+        assert!(matches!(
+            coordinates("1 2 ^"),
+            Err(CommandError::MixedCoordinates)
+        ));
+    }
+
+    fn dispatcher() -> CommandDispatcher<Vec<String>> {
+        let mut dispatcher = CommandDispatcher::new();
+        dispatcher.register(
+            literal("give").requires(ADMIN_PERMISSION_LEVEL).then(
+                argument(
+                    "targets",
+                    ArgumentType::Entity {
+                        single: false,
+                        players_only: true,
+                    },
+                )
+                .then(
+                    argument(
+                        "count",
+                        ArgumentType::Integer {
+                            min: Some(1),
+                            max: Some(64),
+                        },
+                    )
+                    .executes(|ran: &mut Vec<String>, context| {
+                        let count = context.get_integer("count").unwrap();
+                        ran.push(format!("give {}", count));
+                        Ok(count)
+                    }),
+                ),
+            ),
+        );
+        let mut gamemode = literal("gamemode");
+        for name in &["survival", "creative"] {
+            gamemode = gamemode.then(literal(name).executes(move |ran: &mut Vec<String>, _| {
+                ran.push(format!("gamemode {}", name));
+                Ok(1)
+            }));
+        }
+        dispatcher.register(gamemode);
+        dispatcher.register(literal("say").then(
+            argument("message", ArgumentType::Message).executes(
+                |ran: &mut Vec<String>, context| {
+                    ran.push(context.get_string("message").unwrap().to_string());
+                    Ok(1)
+                },
+            ),
+        ));
+        dispatcher
+    }
+
+    fn run(
+        dispatcher: &CommandDispatcher<Vec<String>>,
+        input: &str,
+        permissions: &dyn Permissible,
+    ) -> Result<String, CommandError> {
+        let (executor, context) = dispatcher.parse(CommandSender::Console, input, permissions)?;
+        let mut ran = Vec::new();
+        executor(&mut ran, &context)?;
+        Ok(ran.remove(0))
+    }
+
+    #[test]
+    fn dispatcher_parse() {
+        let dispatcher = dispatcher();
+        let op = level(MAX_PERMISSION_LEVEL);
+        assert_eq!(run(&dispatcher, "give @a 5", &op).unwrap(), "give 5");
+        assert_eq!(
+            run(&dispatcher, "say hello there", &op).unwrap(),
+            "hello there"
+        );
+        assert_eq!(
+            run(&dispatcher, "gamemode creative", &op).unwrap(),
+            "gamemode creative"
+        );
+        let error = |input| run(&dispatcher, input, &op).unwrap_err();
+        assert!(matches!(error("fly"), CommandError::UnknownCommand));
+        assert!(matches!(error("give @a"), CommandError::Incomplete));
+        assert!(matches!(error("give @a 65"), CommandError::TooHigh { .. }));
+        assert!(matches!(
+            error("give @a 5x"),
+            CommandError::ExpectedSeparator
+        ));
+        assert!(matches!(error("give @e 5"), CommandError::OnlyPlayers));
+        assert!(matches!(
+            error("give @a 5 6"),
+            CommandError::IncorrectArgument
+        ));
+    }
+
+    #[test]
+    fn dispatcher_suggest() {
+        let dispatcher = dispatcher();
+        let op = level(MAX_PERMISSION_LEVEL);
+        let players = ["Steve".to_string(), "Alex".to_string()];
+        let suggest = |input| dispatcher.suggest(input, &op, &players);
+        assert_eq!(suggest("ga"), (0, vec!["gamemode".to_string()]));
+        assert_eq!(
+            suggest("g"),
+            (0, vec!["gamemode".to_string(), "give".to_string()])
+        );
+        assert_eq!(suggest("gamemode c"), (9, vec!["creative".to_string()]));
+        let (start, targets) = suggest("give ");
+        assert_eq!(start, 5);
+        assert_eq!(targets, ["@a", "@p", "@r", "@s", "Alex", "Steve"]);
+        assert_eq!(suggest("give S"), (5, vec!["Steve".to_string()]));
+    }
+
+    #[test]
+    fn permission_filtering() {
+        let dispatcher = dispatcher();
+        let player = level(0);
+        assert_eq!(dispatcher.command_names(&player), ["gamemode", "say"]);
+        assert!(matches!(
+            run(&dispatcher, "give @a 1", &player),
+            Err(CommandError::UnknownCommand)
+        ));
+        assert!(dispatcher.suggest("gi", &player, &[]).1.is_empty());
+
+        // A granted node overrides the permission level, a denied one takes the command away
+        let mut nodes = HashMap::new();
+        nodes.insert("minecraft.command.give".to_string(), true);
+        nodes.insert("minecraft.command.say".to_string(), false);
+        let granted = Permissions { level: 0, nodes };
+        assert_eq!(dispatcher.command_names(&granted), ["give", "gamemode"]);
+        assert_eq!(run(&dispatcher, "give @a 1", &granted).unwrap(), "give 1");
+        assert!(matches!(
+            run(&dispatcher, "say hi", &granted),
+            Err(CommandError::UnknownCommand)
+        ));
+
+        let (nodes, root) = dispatcher.declare(&player);
+        let names: Vec<_> = nodes[root as usize]
+            .children
+            .iter()
+            .map(|child| nodes[*child as usize].name.unwrap())
+            .collect();
+        assert_eq!(names, ["gamemode", "say"]);
+    }
+}
@@ -0,0 +1,231 @@
+//! The server console, which reads commands from stdin on its own thread.
+//! In a terminal lines can be edited and earlier commands recalled with the arrow keys,
+//! otherwise stdin is read line by line.
+
+use linefeed::{DefaultTerminal, Interface, ReadResult, Signal};
+use log::{error, warn};
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const HISTORY_FILE: &str = ".console_history";
+const HISTORY_SIZE: usize = 500;
+/// How often the console thread checks whether it should stop while waiting for input
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Whether stdin and stdout are a terminal, otherwise there's nothing to edit lines in
+fn is_terminal() -> bool {
+    unsafe { libc::isatty(libc::STDIN_FILENO) == 1 && libc::isatty(libc::STDOUT_FILENO) == 1 }
+}
+
+/// Replaces `§` formatting codes with ANSI escape codes, or removes them if `ansi` is false
+pub fn format_legacy(text: &str, ansi: bool) -> String {
+    let mut out = String::new();
+    let mut formatted = false;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '§' {
+            out.push(c);
+            continue;
+        }
+        let code = match chars.next() {
+            Some(code) => code.to_ascii_lowercase(),
+            None => break,
+        };
+        let escape = match code {
+            '0' => "30",
+            '1' => "34",
+            '2' => "32",
+            '3' => "36",
+            '4' => "31",
+            '5' => "35",
+            '6' => "33",
+            '7' => "37",
+            '8' => "90",
+            '9' => "94",
+            'a' => "92",
+            'b' => "96",
+            'c' => "91",
+            'd' => "95",
+            'e' => "93",
+            'f' => "97",
+            'l' => "1",
+            'm' => "9",
+            'n' => "4",
+            'o' => "3",
+            'r' => "0",
+            // Obfuscated text has no equivalent
+            _ => continue,
+        };
+        if ansi {
+            // Colors reset the formatting like they do in the client
+            if code.is_ascii_hexdigit() {
+                out.push_str("\x1b[0m");
+            }
+            out.push_str("\x1b[");
+            out.push_str(escape);
+            out.push('m');
+            formatted = code != 'r';
+        }
+    }
+    if formatted {
+        out.push_str("\x1b[0m");
+    }
+    out
+}
+
+/// Prints to the console, can be shared with other threads
+#[derive(Clone)]
+pub struct ConsoleWriter {
+    /// None if stdin isn't a terminal
+    interface: Option<Arc<Interface<DefaultTerminal>>>,
+}
+
+impl ConsoleWriter {
+    /// Prints a line with `§` formatting codes without breaking up the line being typed
+    pub fn print(&self, line: &str) {
+        match &self.interface {
+            Some(interface) => {
+                let line = format_legacy(line, true);
+                // Escape sequences in text written through linefeed would be escaped themselves
+                if let Ok(mut writer) = interface.lock_writer_erase() {
+                    let _ = writeln!(writer, "{}", line);
+                } else {
+                    println!("{}", line);
+                }
+            }
+            None => println!("{}", format_legacy(line, false)),
+        }
+    }
+}
+
+pub struct Console {
+    writer: ConsoleWriter,
+    command_receiver: mpsc::Receiver<String>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Console {
+    pub fn start() -> Console {
+        let (sender, receiver) = mpsc::channel();
+        let running = Arc::new(AtomicBool::new(true));
+        let interface = if is_terminal() {
+            Interface::new("rustmcserver").ok().map(Arc::new)
+        } else {
+            None
+        };
+        if let Some(interface) = &interface {
+            let _ = interface.set_prompt("> ");
+            interface.set_history_size(HISTORY_SIZE);
+            // There's no history before the first start
+            let _ = interface.load_history(HISTORY_FILE);
+            interface.set_report_signal(Signal::Interrupt, true);
+        }
+
+        let thread_interface = interface.clone();
+        let thread_running = running.clone();
+        let thread = thread::Builder::new()
+            .name("Console".to_string())
+            .spawn(move || match thread_interface {
+                Some(interface) => Console::read_terminal(&interface, &sender, &thread_running),
+                None => Console::read_lines(&sender),
+            })
+            .unwrap();
+        Console {
+            writer: ConsoleWriter { interface },
+            command_receiver: receiver,
+            running,
+            thread: Some(thread),
+        }
+    }
+
+    /// A console that doesn't read anything, for servers in tests
+    #[cfg(test)]
+    pub fn detached() -> Console {
+        let (_, receiver) = mpsc::channel();
+        Console {
+            writer: ConsoleWriter { interface: None },
+            command_receiver: receiver,
+            running: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        }
+    }
+
+    /// Reads edited lines from the terminal until the console is stopped or stdin is closed
+    fn read_terminal(
+        interface: &Interface<DefaultTerminal>,
+        sender: &mpsc::Sender<String>,
+        running: &AtomicBool,
+    ) {
+        while running.load(Ordering::SeqCst) {
+            let line = match interface.read_line_step(Some(POLL_INTERVAL)) {
+                Ok(None) => continue,
+                Ok(Some(ReadResult::Input(line))) => {
+                    if !line.trim().is_empty() {
+                        interface.add_history_unique(line.clone());
+                    }
+                    line
+                }
+                Ok(Some(ReadResult::Signal(Signal::Interrupt))) => {
+                    // Ctrl-C stops the server properly instead of killing it
+                    let _ = interface.cancel_read_line();
+                    "stop".to_string()
+                }
+                Ok(Some(ReadResult::Signal(_))) => continue,
+                Ok(Some(ReadResult::Eof)) => return,
+                Err(err) => {
+                    error!("Couldn't read from the console: {}", err);
+                    return;
+                }
+            };
+            if sender.send(line).is_err() {
+                return;
+            }
+        }
+        let _ = interface.cancel_read_line();
+    }
+
+    /// Reads plain lines from stdin until it's closed. Blocks on stdin, so this can't be stopped.
+    fn read_lines(sender: &mpsc::Sender<String>) {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => return,
+            };
+            if sender.send(line).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// The commands entered since the last call, without empty lines or the optional leading slash
+    pub fn receive_commands(&self) -> Vec<String> {
+        self.command_receiver
+            .try_iter()
+            .map(|line| line.trim().trim_start_matches('/').to_string())
+            .filter(|line| !line.is_empty())
+            .collect()
+    }
+
+    pub fn writer(&self) -> ConsoleWriter {
+        self.writer.clone()
+    }
+
+    /// Stops reading, restores the terminal and saves the history
+    pub fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(interface) = &self.writer.interface {
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+            if let Err(err) = interface.save_history(HISTORY_FILE) {
+                warn!("Couldn't save the console history: {}", err);
+            }
+        }
+    }
+}
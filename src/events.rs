@@ -0,0 +1,467 @@
+//! Events plugins can handle. Handlers run from the `Lowest` to the `Monitor` priority,
+//! so the higher priorities get the last say. Cancelling an event stops the server from
+//! doing what the event is about, like breaking the block.
+
+use crate::blocks::BlockState;
+use crate::chat::ChatComponent;
+use crate::packets::{NetworkState, PacketBuffer};
+use crate::utils::{BlockPosition, Location};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+pub trait Event: 'static {
+    /// Whether a handler cancelled the event, events that can't be cancelled never are
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EventPriority {
+    Lowest,
+    Low,
+    Normal,
+    High,
+    Highest,
+    /// For handlers that only look at the outcome and don't change the event
+    Monitor,
+}
+
+/// A player finished logging in
+pub struct PlayerJoinEvent {
+    pub client_id: u32,
+    /// Broadcast to everyone, None to not announce the player
+    pub join_message: Option<ChatComponent>,
+}
+
+impl Event for PlayerJoinEvent {}
+
+/// A player left, their client is already gone
+pub struct PlayerQuitEvent {
+    pub client_id: u32,
+    pub username: String,
+    pub quit_message: Option<ChatComponent>,
+}
+
+impl Event for PlayerQuitEvent {}
+
+/// A player sent a chat message that isn't a command
+pub struct ChatEvent {
+    pub client_id: u32,
+    pub message: String,
+    pub cancelled: bool,
+}
+
+impl Event for ChatEvent {
+    fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+}
+
+pub struct BlockBreakEvent {
+    pub client_id: u32,
+    pub position: BlockPosition,
+    pub block: BlockState,
+    pub cancelled: bool,
+}
+
+impl Event for BlockBreakEvent {
+    fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+}
+
+pub struct BlockPlaceEvent {
+    pub client_id: u32,
+    pub position: BlockPosition,
+    /// The block that will be placed, handlers can replace it
+    pub block: BlockState,
+    pub cancelled: bool,
+}
+
+impl Event for BlockPlaceEvent {
+    fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+}
+
+/// A player moved or looked around. Cancelling teleports them back.
+pub struct MoveEvent {
+    pub client_id: u32,
+    pub from: Location,
+    /// Where the player ends up, handlers can change it
+    pub to: Location,
+    pub cancelled: bool,
+}
+
+impl Event for MoveEvent {
+    fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+}
+
+/// A packet was received and is about to be handled. Cancelling drops it.
+pub struct PacketReceiveEvent {
+    pub client_id: u32,
+    pub state: NetworkState,
+    /// The id as the client's protocol version numbers it
+    pub packet_id: i32,
+    /// The whole packet including its length prefix
+    pub buffer: PacketBuffer,
+    pub cancelled: bool,
+}
+
+impl Event for PacketReceiveEvent {
+    fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+}
+
+// The events plugins that aren't written in Rust can handle
+pub const EVENT_PLAYER_JOIN: u32 = 0;
+pub const EVENT_PLAYER_QUIT: u32 = 1;
+pub const EVENT_CHAT: u32 = 2;
+pub const EVENT_BLOCK_BREAK: u32 = 3;
+pub const EVENT_BLOCK_PLACE: u32 = 4;
+pub const EVENT_MOVE: u32 = 5;
+
+/// The parts of an event plugins that aren't written in Rust can see
+pub struct EventData {
+    /// One of the `EVENT_` constants
+    pub kind: u32,
+    pub client_id: u32,
+    /// The chat message or the name of the player that quit, empty otherwise
+    pub text: String,
+    /// The block of block events, or where the player moves to
+    pub position: (f64, f64, f64),
+    pub cancelled: bool,
+}
+
+/// Events plugins that aren't written in Rust can handle
+pub trait DynamicEvent: Event {
+    const KIND: u32;
+
+    fn data(&self) -> EventData;
+
+    /// Takes over what the plugin changed
+    fn update(&mut self, _data: &EventData) {}
+}
+
+fn event_data(kind: u32, client_id: u32) -> EventData {
+    EventData {
+        kind,
+        client_id,
+        text: String::new(),
+        position: (0.0, 0.0, 0.0),
+        cancelled: false,
+    }
+}
+
+impl DynamicEvent for PlayerJoinEvent {
+    const KIND: u32 = EVENT_PLAYER_JOIN;
+
+    fn data(&self) -> EventData {
+        event_data(Self::KIND, self.client_id)
+    }
+}
+
+impl DynamicEvent for PlayerQuitEvent {
+    const KIND: u32 = EVENT_PLAYER_QUIT;
+
+    fn data(&self) -> EventData {
+        EventData {
+            text: self.username.clone(),
+            ..event_data(Self::KIND, self.client_id)
+        }
+    }
+}
+
+impl DynamicEvent for ChatEvent {
+    const KIND: u32 = EVENT_CHAT;
+
+    fn data(&self) -> EventData {
+        EventData {
+            text: self.message.clone(),
+            cancelled: self.cancelled,
+            ..event_data(Self::KIND, self.client_id)
+        }
+    }
+
+    fn update(&mut self, data: &EventData) {
+        self.cancelled = data.cancelled;
+    }
+}
+
+impl DynamicEvent for BlockBreakEvent {
+    const KIND: u32 = EVENT_BLOCK_BREAK;
+
+    fn data(&self) -> EventData {
+        let position = self.position;
+        EventData {
+            position: (position.x as f64, position.y as f64, position.z as f64),
+            cancelled: self.cancelled,
+            ..event_data(Self::KIND, self.client_id)
+        }
+    }
+
+    fn update(&mut self, data: &EventData) {
+        self.cancelled = data.cancelled;
+    }
+}
+
+impl DynamicEvent for BlockPlaceEvent {
+    const KIND: u32 = EVENT_BLOCK_PLACE;
+
+    fn data(&self) -> EventData {
+        let position = self.position;
+        EventData {
+            position: (position.x as f64, position.y as f64, position.z as f64),
+            cancelled: self.cancelled,
+            ..event_data(Self::KIND, self.client_id)
+        }
+    }
+
+    fn update(&mut self, data: &EventData) {
+        self.cancelled = data.cancelled;
+    }
+}
+
+impl DynamicEvent for MoveEvent {
+    const KIND: u32 = EVENT_MOVE;
+
+    fn data(&self) -> EventData {
+        EventData {
+            position: (self.to.x, self.to.y, self.to.z),
+            cancelled: self.cancelled,
+            ..event_data(Self::KIND, self.client_id)
+        }
+    }
+
+    fn update(&mut self, data: &EventData) {
+        self.cancelled = data.cancelled;
+    }
+}
+
+pub type EventHandler<S, E> = Rc<dyn Fn(&mut S, &mut E)>;
+
+struct RegisteredHandler<S, E> {
+    /// The plugin that registered the handler, to remove it when the plugin is disabled
+    plugin: String,
+    priority: EventPriority,
+    handler: EventHandler<S, E>,
+}
+
+/// The handlers of one event type, with the type erased so they can be stored together
+trait HandlerList {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn remove_plugin(&mut self, plugin: &str);
+}
+
+impl<S: 'static, E: Event> HandlerList for Vec<RegisteredHandler<S, E>> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn remove_plugin(&mut self, plugin: &str) {
+        self.retain(|handler| handler.plugin != plugin);
+    }
+}
+
+/// The event handlers of every plugin, by event type
+pub struct EventBus<S> {
+    handlers: HashMap<TypeId, Box<dyn HandlerList>>,
+    server: PhantomData<S>,
+}
+
+impl<S: 'static> EventBus<S> {
+    pub fn new() -> EventBus<S> {
+        EventBus {
+            handlers: HashMap::new(),
+            server: PhantomData,
+        }
+    }
+
+    pub fn register<E, F>(&mut self, plugin: &str, priority: EventPriority, handler: F)
+    where
+        E: Event,
+        F: Fn(&mut S, &mut E) + 'static,
+    {
+        let handlers = self
+            .handlers
+            .entry(TypeId::of::<E>())
+            .or_insert_with(|| Box::new(Vec::<RegisteredHandler<S, E>>::new()))
+            .as_any_mut()
+            .downcast_mut::<Vec<RegisteredHandler<S, E>>>()
+            .unwrap();
+        // Handlers with the same priority run in the order they were registered
+        let index = handlers
+            .iter()
+            .position(|registered| registered.priority > priority)
+            .unwrap_or(handlers.len());
+        handlers.insert(
+            index,
+            RegisteredHandler {
+                plugin: plugin.to_string(),
+                priority,
+                handler: Rc::new(handler),
+            },
+        );
+    }
+
+    /// The handlers of an event in the order they run. They're cloned so they can be
+    /// called with the server the bus belongs to.
+    pub fn handlers<E: Event>(&self) -> Vec<EventHandler<S, E>> {
+        self.handlers
+            .get(&TypeId::of::<E>())
+            .and_then(|handlers| {
+                handlers
+                    .as_any()
+                    .downcast_ref::<Vec<RegisteredHandler<S, E>>>()
+            })
+            .map_or_else(Vec::new, |handlers| {
+                handlers
+                    .iter()
+                    .map(|registered| registered.handler.clone())
+                    .collect()
+            })
+    }
+
+    /// Whether anyone listens to an event, to skip creating events that are expensive to create
+    pub fn has_handlers<E: Event>(&self) -> bool {
+        self.handlers
+            .get(&TypeId::of::<E>())
+            .and_then(|handlers| {
+                handlers
+                    .as_any()
+                    .downcast_ref::<Vec<RegisteredHandler<S, E>>>()
+            })
+            .is_some_and(|handlers| !handlers.is_empty())
+    }
+
+    pub fn unregister_plugin(&mut self, plugin: &str) {
+        for handlers in self.handlers.values_mut() {
+            handlers.remove_plugin(plugin);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct State {
+        ran: Vec<&'static str>,
+    }
+
+    struct TestEvent {
+        cancelled: bool,
+    }
+
+    impl Event for TestEvent {
+        fn is_cancelled(&self) -> bool {
+            self.cancelled
+        }
+    }
+
+    /// Calls the handlers like the server does
+    fn post(bus: &EventBus<State>, state: &mut State, event: &mut TestEvent) {
+        for handler in bus.handlers::<TestEvent>() {
+            handler(state, event);
+        }
+    }
+
+    #[test]
+    fn priorities() {
+        let mut bus = EventBus::new();
+        bus.register(
+            "a",
+            EventPriority::Monitor,
+            |state: &mut State, _: &mut TestEvent| state.ran.push("monitor"),
+        );
+        bus.register(
+            "a",
+            EventPriority::Normal,
+            |state: &mut State, _: &mut TestEvent| state.ran.push("normal 1"),
+        );
+        bus.register(
+            "b",
+            EventPriority::Lowest,
+            |state: &mut State, _: &mut TestEvent| state.ran.push("lowest"),
+        );
+        bus.register(
+            "b",
+            EventPriority::Normal,
+            |state: &mut State, _: &mut TestEvent| state.ran.push("normal 2"),
+        );
+        bus.register(
+            "b",
+            EventPriority::High,
+            |state: &mut State, _: &mut TestEvent| state.ran.push("high"),
+        );
+        let mut state = State::default();
+        post(&bus, &mut state, &mut TestEvent { cancelled: false });
+        assert_eq!(
+            state.ran,
+            vec!["lowest", "normal 1", "normal 2", "high", "monitor"]
+        );
+    }
+
+    #[test]
+    fn cancelling() {
+        let mut bus = EventBus::new();
+        bus.register(
+            "a",
+            EventPriority::Low,
+            |_: &mut State, event: &mut TestEvent| event.cancelled = true,
+        );
+        bus.register(
+            "a",
+            EventPriority::High,
+            |state: &mut State, event: &mut TestEvent| {
+                if event.is_cancelled() {
+                    state.ran.push("saw cancel");
+                    event.cancelled = false;
+                }
+            },
+        );
+        let mut state = State::default();
+        let mut event = TestEvent { cancelled: false };
+        post(&bus, &mut state, &mut event);
+        assert_eq!(state.ran, vec!["saw cancel"]);
+        assert!(!event.is_cancelled());
+    }
+
+    #[test]
+    fn unregister_plugin() {
+        let mut bus = EventBus::new();
+        assert!(!bus.has_handlers::<TestEvent>());
+        bus.register(
+            "a",
+            EventPriority::Normal,
+            |state: &mut State, _: &mut TestEvent| state.ran.push("a"),
+        );
+        bus.register(
+            "b",
+            EventPriority::Normal,
+            |state: &mut State, _: &mut TestEvent| state.ran.push("b"),
+        );
+        assert!(bus.has_handlers::<TestEvent>());
+
+        bus.unregister_plugin("a");
+        let mut state = State::default();
+        post(&bus, &mut state, &mut TestEvent { cancelled: false });
+        assert_eq!(state.ran, vec!["b"]);
+
+        bus.unregister_plugin("b");
+        assert!(!bus.has_handlers::<TestEvent>());
+        assert!(bus.handlers::<TestEvent>().is_empty());
+    }
+}
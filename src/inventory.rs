@@ -0,0 +1,424 @@
+use crate::recipes::RecipeRegistry;
+use crate::registry::Item;
+
+pub const MAX_STACK_SIZE: i8 = 64; // TODO: Per item stack sizes
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ItemStack {
+    pub item: Item,
+    pub count: i8,
+    /// Raw NBT compound, if the stack has one
+    pub nbt: Option<Vec<u8>>,
+}
+
+impl ItemStack {
+    pub fn new(item: Item, count: i8) -> ItemStack {
+        ItemStack {
+            item,
+            count,
+            nbt: None,
+        }
+    }
+
+    pub fn is_similar(&self, other: &ItemStack) -> bool {
+        self.item == other.item && self.nbt == other.nbt
+    }
+}
+
+pub type Slot = Option<ItemStack>;
+
+pub struct CraftingGrid {
+    pub size: usize,
+    pub slots: Vec<Slot>,
+    pub result: Slot,
+}
+
+impl CraftingGrid {
+    pub fn new(size: usize) -> CraftingGrid {
+        CraftingGrid {
+            size,
+            slots: vec![None; size * size],
+            result: None,
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> &Slot {
+        &self.slots[y * self.size + x]
+    }
+
+    pub fn update_result(&mut self, recipes: &RecipeRegistry) {
+        self.result = recipes
+            .match_crafting(self)
+            .and_then(|recipe| recipe.crafting_result())
+            .cloned();
+    }
+
+    /// Removes one item from every occupied slot of the grid after the result was taken.
+    fn consume_ingredients(&mut self) {
+        for slot in self.slots.iter_mut() {
+            if let Some(stack) = slot {
+                stack.count -= 1;
+                if stack.count <= 0 {
+                    *slot = None;
+                }
+            }
+        }
+    }
+
+    fn take_all(&mut self) -> Vec<ItemStack> {
+        self.result = None;
+        self.slots
+            .iter_mut()
+            .filter_map(|slot| slot.take())
+            .collect()
+    }
+}
+
+/// A crafting table window opened by the player.
+pub struct CraftingWindow {
+    pub id: u8,
+    pub grid: CraftingGrid,
+}
+
+impl CraftingWindow {
+    pub fn new(id: u8) -> CraftingWindow {
+        CraftingWindow {
+            id,
+            grid: CraftingGrid::new(3),
+        }
+    }
+}
+
+pub struct Inventory {
+    pub crafting: CraftingGrid,
+    pub armor: Vec<Slot>,
+    /// The main inventory followed by the hotbar, as ordered in the player window
+    pub storage: Vec<Slot>,
+    pub offhand: Slot,
+    pub cursor: Slot,
+    pub open_window: Option<CraftingWindow>,
+    next_window_id: u8,
+}
+
+impl Inventory {
+    pub fn new() -> Inventory {
+        Inventory {
+            crafting: CraftingGrid::new(2),
+            armor: vec![None; 4],
+            storage: vec![None; 36],
+            offhand: None,
+            cursor: None,
+            open_window: None,
+            next_window_id: 1,
+        }
+    }
+
+    /// Opens a crafting table window and returns its id.
+    /// Any previously open window is replaced.
+    pub fn open_crafting_window(&mut self) -> u8 {
+        let id = self.next_window_id;
+        self.next_window_id = id % 100 + 1;
+        self.open_window = Some(CraftingWindow::new(id));
+        id
+    }
+
+    /// The hotbar slot at the given index, from 0 to 8
+    pub fn hotbar(&self, index: usize) -> &Slot {
+        &self.storage[27 + index]
+    }
+
+    pub fn hotbar_mut(&mut self, index: usize) -> &mut Slot {
+        &mut self.storage[27 + index]
+    }
+
+    /// Sets a slot of the player window, used for the creative inventory.
+    pub fn set_slot(&mut self, slot: i16, item: Slot) -> bool {
+        if slot < 0 {
+            return false;
+        }
+        match self.slot_mut(0, slot as usize) {
+            Some(target) => {
+                *target = item;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the crafting grid used by the given window.
+    pub fn crafting_grid(&mut self, window_id: u8) -> Option<&mut CraftingGrid> {
+        if window_id == 0 {
+            return Some(&mut self.crafting);
+        }
+        match &mut self.open_window {
+            Some(window) if window.id == window_id => Some(&mut window.grid),
+            _ => None,
+        }
+    }
+
+    /// Every slot of a window in protocol order.
+    pub fn window_slots(&self, window_id: u8) -> Vec<Slot> {
+        let mut slots = Vec::new();
+        if window_id == 0 {
+            slots.push(self.crafting.result.clone());
+            slots.extend(self.crafting.slots.iter().cloned());
+            slots.extend(self.armor.iter().cloned());
+            slots.extend(self.storage.iter().cloned());
+            slots.push(self.offhand.clone());
+        } else if let Some(window) = &self.open_window {
+            slots.push(window.grid.result.clone());
+            slots.extend(window.grid.slots.iter().cloned());
+            slots.extend(self.storage.iter().cloned());
+        }
+        slots
+    }
+
+    fn slot_mut(&mut self, window_id: u8, slot: usize) -> Option<&mut Slot> {
+        if window_id == 0 {
+            match slot {
+                1..=4 => self.crafting.slots.get_mut(slot - 1),
+                5..=8 => self.armor.get_mut(slot - 5),
+                9..=44 => self.storage.get_mut(slot - 9),
+                45 => Some(&mut self.offhand),
+                _ => None,
+            }
+        } else {
+            match &mut self.open_window {
+                Some(window) if window.id == window_id => match slot {
+                    1..=9 => window.grid.slots.get_mut(slot - 1),
+                    10..=45 => self.storage.get_mut(slot - 10),
+                    _ => None,
+                },
+                _ => None,
+            }
+        }
+    }
+
+    fn is_crafting_slot(window_id: u8, slot: usize) -> bool {
+        if window_id == 0 {
+            (1..=4).contains(&slot)
+        } else {
+            (1..=9).contains(&slot)
+        }
+    }
+
+    /// Applies a normal left (button 0) or right (button 1) click to a window slot.
+    /// Returns false if the click could not be applied and the client needs to be resynced.
+    pub fn click(
+        &mut self,
+        window_id: u8,
+        slot: i16,
+        button: i8,
+        recipes: &RecipeRegistry,
+    ) -> bool {
+        if slot < 0 || button > 1 {
+            return false;
+        }
+        let slot = slot as usize;
+        if slot == 0 {
+            return self.take_crafting_result(window_id, recipes);
+        }
+
+        let mut cursor = self.cursor.take();
+        let target = match self.slot_mut(window_id, slot) {
+            Some(target) => target,
+            None => {
+                self.cursor = cursor;
+                return false;
+            }
+        };
+        match (&mut cursor, target.as_mut()) {
+            (None, None) => {}
+            (None, Some(stack)) => {
+                if button == 0 {
+                    cursor = target.take();
+                } else {
+                    let half = (stack.count + 1) / 2;
+                    let mut picked = stack.clone();
+                    picked.count = half;
+                    stack.count -= half;
+                    if stack.count == 0 {
+                        *target = None;
+                    }
+                    cursor = Some(picked);
+                }
+            }
+            (Some(held), None) => {
+                if button == 0 {
+                    *target = cursor.take();
+                } else {
+                    let mut placed = held.clone();
+                    placed.count = 1;
+                    held.count -= 1;
+                    if held.count == 0 {
+                        cursor = None;
+                    }
+                    *target = Some(placed);
+                }
+            }
+            (Some(held), Some(stack)) => {
+                if held.is_similar(stack) {
+                    let amount = if button == 0 { held.count } else { 1 };
+                    let amount = amount.min(MAX_STACK_SIZE - stack.count);
+                    stack.count += amount;
+                    held.count -= amount;
+                    if held.count == 0 {
+                        cursor = None;
+                    }
+                } else {
+                    std::mem::swap(&mut cursor, target);
+                }
+            }
+        }
+        self.cursor = cursor;
+
+        if Inventory::is_crafting_slot(window_id, slot) {
+            if let Some(grid) = self.crafting_grid(window_id) {
+                grid.update_result(recipes);
+            }
+        }
+        true
+    }
+
+    fn take_crafting_result(&mut self, window_id: u8, recipes: &RecipeRegistry) -> bool {
+        let mut cursor = self.cursor.take();
+        let grid = match self.crafting_grid(window_id) {
+            Some(grid) => grid,
+            None => {
+                self.cursor = cursor;
+                return false;
+            }
+        };
+        if let Some(result) = grid.result.clone() {
+            match &mut cursor {
+                None => cursor = Some(result),
+                Some(held)
+                    if held.is_similar(&result) && held.count + result.count <= MAX_STACK_SIZE =>
+                {
+                    held.count += result.count
+                }
+                _ => {
+                    self.cursor = cursor;
+                    return true;
+                }
+            }
+            grid.consume_ingredients();
+            grid.update_result(recipes);
+        }
+        self.cursor = cursor;
+        true
+    }
+
+    /// Puts the given stack into the first free or matching storage slots.
+    /// Returns whatever did not fit.
+    pub fn add_item(&mut self, mut stack: ItemStack) -> Slot {
+        for existing in self.storage.iter_mut().flatten() {
+            if existing.is_similar(&stack) && existing.count < MAX_STACK_SIZE {
+                let amount = stack.count.min(MAX_STACK_SIZE - existing.count);
+                existing.count += amount;
+                stack.count -= amount;
+                if stack.count == 0 {
+                    return None;
+                }
+            }
+        }
+        for slot in self.storage.iter_mut() {
+            if slot.is_none() {
+                *slot = Some(stack);
+                return None;
+            }
+        }
+        Some(stack)
+    }
+
+    /// Closes a window, moving the contents of its crafting grid and the cursor back into the inventory.
+    /// Returns any items that did not fit.
+    pub fn close_window(&mut self, window_id: u8) -> Vec<ItemStack> {
+        let mut items = match self.crafting_grid(window_id) {
+            Some(grid) => grid.take_all(),
+            None => Vec::new(),
+        };
+        if window_id != 0 {
+            self.open_window = None;
+        }
+        items.extend(self.cursor.take());
+        items
+            .into_iter()
+            .filter_map(|stack| self.add_item(stack))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stack(item: Item, count: i8) -> Slot {
+        Some(ItemStack::new(item, count))
+    }
+
+    #[test]
+    fn crafting() {
+        let recipes = RecipeRegistry::load("res/recipes", "res/tags/items");
+        let mut inventory = Inventory::new();
+        inventory.storage[0] = stack(Item::Dirt, 3);
+        inventory.storage[1] = stack(Item::Gravel, 2);
+        // Pick up the dirt and place it one at a time in the crafting grid
+        assert!(inventory.click(0, 9, 0, &recipes));
+        assert!(inventory.click(0, 1, 1, &recipes));
+        assert!(inventory.click(0, 4, 0, &recipes));
+        assert_eq!(inventory.crafting.result, None);
+        assert!(inventory.click(0, 10, 0, &recipes));
+        assert!(inventory.click(0, 2, 1, &recipes));
+        assert!(inventory.click(0, 3, 1, &recipes));
+        assert_eq!(inventory.cursor, None);
+        assert_eq!(inventory.crafting.result, stack(Item::CoarseDirt, 4));
+        let grid = |inventory: &Inventory| -> Vec<Option<i8>> {
+            let counts = inventory.crafting.slots.iter();
+            counts
+                .map(|slot| slot.as_ref().map(|stack| stack.count))
+                .collect()
+        };
+        assert_eq!(grid(&inventory), vec![Some(1), Some(1), Some(1), Some(2)]);
+
+        // Taking the result uses up one of each ingredient
+        assert!(inventory.click(0, 0, 0, &recipes));
+        assert_eq!(inventory.cursor, stack(Item::CoarseDirt, 4));
+        assert_eq!(grid(&inventory), vec![None, None, None, Some(1)]);
+        assert_eq!(inventory.crafting.result, None);
+        // Nothing happens without a result
+        assert!(inventory.click(0, 0, 0, &recipes));
+        assert_eq!(inventory.cursor, stack(Item::CoarseDirt, 4));
+        assert_eq!(grid(&inventory), vec![None, None, None, Some(1)]);
+    }
+
+    #[test]
+    fn crafting_result_onto_cursor() {
+        let recipes = RecipeRegistry::load("res/recipes", "res/tags/items");
+        let mut inventory = Inventory::new();
+        for (i, item) in [Item::Dirt, Item::Gravel, Item::Gravel, Item::Dirt]
+            .iter()
+            .enumerate()
+        {
+            inventory.crafting.slots[i] = stack(*item, 2);
+        }
+        inventory.crafting.update_result(&recipes);
+        inventory.cursor = stack(Item::CoarseDirt, 61);
+        // The result doesn't fit onto the stack that's held, so it stays
+        assert!(inventory.click(0, 0, 0, &recipes));
+        assert_eq!(inventory.cursor, stack(Item::CoarseDirt, 61));
+        assert_eq!(inventory.crafting.slots[0], stack(Item::Dirt, 2));
+
+        inventory.cursor = stack(Item::CoarseDirt, 56);
+        assert!(inventory.click(0, 0, 0, &recipes));
+        assert_eq!(inventory.cursor, stack(Item::CoarseDirt, 60));
+        assert_eq!(inventory.crafting.slots[0], stack(Item::Dirt, 1));
+        assert_eq!(inventory.crafting.result, stack(Item::CoarseDirt, 4));
+
+        // Something else on the cursor blocks taking the result
+        inventory.cursor = stack(Item::Dirt, 1);
+        assert!(inventory.click(0, 0, 0, &recipes));
+        assert_eq!(inventory.cursor, stack(Item::Dirt, 1));
+        assert_eq!(inventory.crafting.slots[3], stack(Item::Dirt, 1));
+    }
+}
@@ -0,0 +1,297 @@
+//! A logger for the `log` macros, which writes to the console and `logs/latest.log`.
+//! Like in vanilla the last log is compressed to `logs/<date>-<n>.log.gz` on startup and every day.
+//! Levels are set per module with a filter like `info,network=debug`.
+
+use crate::console::{self, ConsoleWriter};
+use chrono::{DateTime, Local, NaiveDate};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{warn, LevelFilter, Log, Metadata, Record};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+
+const LOG_DIRECTORY: &str = "logs";
+
+/// The level of each module, the most specific module that matches a target wins
+struct Filter {
+    default: LevelFilter,
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    /// Parses comma separated levels, with `module=level` for a module and `level` for the rest
+    fn parse(spec: &str) -> Result<Filter, String> {
+        let mut filter = Filter {
+            default: LevelFilter::Info,
+            modules: Vec::new(),
+        };
+        for part in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+        {
+            let (module, level) = match part.find('=') {
+                Some(index) => (Some(&part[..index]), &part[index + 1..]),
+                None => (None, part),
+            };
+            let level: LevelFilter = level
+                .parse()
+                .map_err(|_| format!("Unknown log level '{}'", level))?;
+            match module {
+                Some(module) => filter.modules.push((module.to_string(), level)),
+                None => filter.default = level,
+            }
+        }
+        Ok(filter)
+    }
+
+    fn level(&self, target: &str) -> LevelFilter {
+        let module = module_name(target);
+        self.modules
+            .iter()
+            .filter(|(name, _)| {
+                module == name || module.starts_with(name) && module[name.len()..].starts_with("::")
+            })
+            .max_by_key(|(name, _)| name.len())
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, |max, level| max.max(level))
+    }
+}
+
+/// The module path of a target without the name of the crate, like `network` or `world`
+fn module_name(target: &str) -> &str {
+    match target.find("::") {
+        Some(index) if &target[..index] == env!("CARGO_PKG_NAME") => &target[index + 2..],
+        _ => target,
+    }
+}
+
+struct LogFile {
+    directory: PathBuf,
+    file: File,
+    /// The day the file was started, it gets archived once the day is over
+    date: NaiveDate,
+}
+
+impl LogFile {
+    fn open(directory: &Path) -> io::Result<LogFile> {
+        fs::create_dir_all(directory)?;
+        let latest = directory.join("latest.log");
+        if let Ok(metadata) = fs::metadata(&latest) {
+            let modified: DateTime<Local> = metadata.modified()?.into();
+            LogFile::archive(directory, modified.naive_local().date())?;
+        }
+        Ok(LogFile {
+            directory: directory.to_path_buf(),
+            file: File::create(latest)?,
+            date: Local::now().naive_local().date(),
+        })
+    }
+
+    /// Compresses `latest.log` into the first free `<date>-<n>.log.gz`
+    fn archive(directory: &Path, date: NaiveDate) -> io::Result<()> {
+        let latest = directory.join("latest.log");
+        let mut index = 1;
+        let archive = loop {
+            let name = format!("{}-{}.log.gz", date.format("%Y-%m-%d"), index);
+            let archive = directory.join(name);
+            if !archive.exists() {
+                break archive;
+            }
+            index += 1;
+        };
+        let mut encoder = GzEncoder::new(File::create(archive)?, Compression::default());
+        io::copy(&mut File::open(&latest)?, &mut encoder)?;
+        encoder.finish()?;
+        fs::remove_file(latest)
+    }
+
+    /// Starts a new file if the day changed since this one was started
+    fn rotate(&mut self, today: NaiveDate) -> io::Result<()> {
+        if today == self.date {
+            return Ok(());
+        }
+        self.file.flush()?;
+        *self = LogFile::open(&self.directory)?;
+        Ok(())
+    }
+}
+
+struct Logger {
+    filter: Filter,
+    console: ConsoleWriter,
+    /// None if the log file couldn't be opened
+    file: Mutex<Option<LogFile>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let now = Local::now();
+        let thread = thread::current();
+        let thread_name = thread.name().unwrap_or("unnamed");
+        let message = record.args().to_string();
+        self.console.print(&format!(
+            "[{}] [{}/{}]: {}",
+            now.format("%H:%M:%S"),
+            thread_name,
+            record.level(),
+            message
+        ));
+
+        let mut file = self.file.lock().unwrap();
+        let log_file = match file.as_mut() {
+            Some(log_file) => log_file,
+            None => return,
+        };
+        let result = log_file.rotate(now.naive_local().date()).and_then(|_| {
+            writeln!(
+                log_file.file,
+                "[{}] [{}/{}] ({}): {}",
+                now.format("%d%b%Y %H:%M:%S%.3f"),
+                thread_name,
+                record.level(),
+                module_name(record.target()),
+                console::format_legacy(&message, false)
+            )
+        });
+        if let Err(err) = result {
+            *file = None;
+            self.console
+                .print(&format!("Couldn't write to the log file: {}", err));
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(log_file) = self.file.lock().unwrap().as_mut() {
+            let _ = log_file.file.flush();
+        }
+    }
+}
+
+/// Sets up the logger with a filter like `info,network=debug`, can only be called once
+pub fn init(filter: &str, console: ConsoleWriter) {
+    let (filter, filter_error) = match Filter::parse(filter) {
+        Ok(filter) => (filter, None),
+        Err(err) => (Filter::parse("info").unwrap(), Some(err)),
+    };
+    let (file, file_error) = match LogFile::open(Path::new(LOG_DIRECTORY)) {
+        Ok(file) => (Some(file), None),
+        Err(err) => (None, Some(err)),
+    };
+    log::set_max_level(filter.max_level());
+    log::set_boxed_logger(Box::new(Logger {
+        filter,
+        console,
+        file: Mutex::new(file),
+    }))
+    .unwrap();
+    if let Some(err) = filter_error {
+        warn!("Invalid log filter, using the default: {}", err);
+    }
+    if let Some(err) = file_error {
+        warn!("Couldn't open the log file: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn filter_levels() {
+        let filter = Filter::parse("warn,network::sub=trace,network=debug,world=error").unwrap();
+        assert_eq!(filter.level("rustmcserver::network"), LevelFilter::Debug);
+        // The most specific module wins, whatever order they're given in
+        assert_eq!(
+            filter.level("rustmcserver::network::sub"),
+            LevelFilter::Trace
+        );
+        assert_eq!(
+            filter.level("rustmcserver::network::sub::inner"),
+            LevelFilter::Trace
+        );
+        assert_eq!(
+            filter.level("rustmcserver::network::other"),
+            LevelFilter::Debug
+        );
+        // A module only matches whole path segments
+        assert_eq!(filter.level("rustmcserver::networking"), LevelFilter::Warn);
+        assert_eq!(
+            filter.level("rustmcserver::network::subway"),
+            LevelFilter::Debug
+        );
+        assert_eq!(filter.level("rustmcserver::world"), LevelFilter::Error);
+        assert_eq!(filter.level("tokio::net"), LevelFilter::Warn);
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+
+        let filter = Filter::parse(" , network=off").unwrap();
+        assert_eq!(filter.level("rustmcserver::main"), LevelFilter::Info);
+        assert_eq!(filter.level("rustmcserver::network"), LevelFilter::Off);
+    }
+
+    #[test]
+    fn invalid_filters() {
+        assert!(Filter::parse("verbose").is_err());
+        let err = Filter::parse("info,network=loud").err().unwrap();
+        assert_eq!(err, "Unknown log level 'loud'");
+        assert!(Filter::parse("network=").is_err());
+    }
+
+    #[test]
+    fn archive_names() {
+        let directory =
+            std::env::temp_dir().join(format!("rustmcserver-logs-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let archive = |text: &str, date: NaiveDate| {
+            fs::write(directory.join("latest.log"), text).unwrap();
+            LogFile::archive(&directory, date).unwrap();
+            assert!(!directory.join("latest.log").exists());
+        };
+        let read = |name: &str| {
+            let mut text = String::new();
+            GzDecoder::new(File::open(directory.join(name)).unwrap())
+                .read_to_string(&mut text)
+                .unwrap();
+            text
+        };
+        let date = NaiveDate::from_ymd_opt(2020, 1, 2).unwrap();
+        archive("first", date);
+        archive("second", date);
+        // Another day starts counting again
+        archive("third", date.succ_opt().unwrap());
+        let mut names: Vec<String> = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        let contents: Vec<String> = names.iter().map(|name| read(name)).collect();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(
+            names,
+            [
+                "2020-01-02-1.log.gz",
+                "2020-01-02-2.log.gz",
+                "2020-01-03-1.log.gz"
+            ]
+        );
+        assert_eq!(contents, ["first", "second", "third"]);
+    }
+}
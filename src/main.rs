@@ -1,12 +1,29 @@
+mod blocks;
+mod channels;
 mod chat;
+mod commands;
+mod console;
+mod events;
+mod inventory;
+mod logging;
 mod mojang;
 mod network;
 mod packets;
+mod permissions;
 mod player;
+mod plugin;
+mod plugin_loader;
+mod protocol;
+mod recipes;
+mod registry;
+mod scripting;
+mod scheduler;
+mod userlist;
 mod utils;
 mod world;
 
 fn main() {
-    println!("Loading world...");
-    network::start_server();
+    // Plugins built into the server go here
+    let plugins: Vec<Box<dyn plugin::Plugin>> = Vec::new();
+    network::start_server(plugins);
 }
@@ -30,8 +30,9 @@ pub struct MojangHasJoinedResponseProperties {
 
 #[derive(Serialize, Deserialize)]
 pub struct MojangHasJoinedResponse {
-    id: String,
-    name: String,
+    /// The UUID without dashes
+    pub id: String,
+    pub name: String,
     properties: Vec<MojangHasJoinedResponseProperties>,
 }
 
@@ -89,6 +90,6 @@ impl Mojang {
     }
 
     pub fn clean(&mut self) {
-        self.has_joined_pending.retain(|p| p.result.is_some());
+        self.has_joined_pending.retain(|p| p.result.is_none());
     }
 }
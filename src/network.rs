@@ -1,65 +1,205 @@
 extern crate openssl;
 extern crate rand;
 extern crate reqwest;
-use crate::utils;
+use crate::blocks::{Block, BlockState};
+use crate::channels::{self, BungeeRequest, ChannelRegistry};
+use crate::chat::{self, ChatColor, ChatComponent, ClickEventType};
+use crate::commands::{self, builtin, CommandBuilder, CommandDispatcher, CommandSender};
+use crate::console::Console;
+use crate::events::{
+    BlockBreakEvent, BlockPlaceEvent, ChatEvent, Event, EventBus, EventPriority, MoveEvent,
+    PacketReceiveEvent, PlayerJoinEvent, PlayerQuitEvent,
+};
+use crate::logging;
+use crate::utils::{self, Location};
 use crate::mojang::{Mojang, MojangHasJoinedResponse};
 use crate::packets::*;
-use crate::player::Player;
+use crate::permissions::{Permissions, SenderPermissions};
+use crate::player::{Digging, Gamemode, Player};
+use crate::plugin::{self, Plugin, PluginContext, PluginManager};
+use crate::plugin_loader::{self, LoadError};
+use crate::protocol::ProtocolVersion;
+use crate::recipes::{RecipeKind, RecipeRegistry};
+use crate::registry::Menu;
+use crate::scheduler::{Scheduler, TaskId};
+use crate::scripting::{self, ScriptChange, ScriptWatcher};
+use crate::userlist::{Ban, UserLists};
+use crate::world::{self, Weather, World};
+use futures::future::{self, Either};
+use log::{debug, error, info, warn};
 use openssl::pkey::Private;
 use openssl::rsa::{Padding, Rsa};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::io::prelude::*;
-use std::net::{TcpListener, TcpStream};
-use std::sync::mpsc;
+use std::fs;
+use std::io;
+use std::mem;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::thread;
-use std::time::{Duration};
+use std::time::{Duration, Instant};
+use tokio::io::{self as async_io, AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+use tokio::sync::{mpsc, oneshot};
+
+pub(crate) const CONFIG_FILE: &str = "config.json";
+/// View distance in chunks
+const VIEW_DISTANCE: i32 = 10;
+const TICK_DURATION: Duration = Duration::from_millis(50);
+/// How many received packets are buffered before we stop reading from the connection
+const INBOUND_QUEUE_SIZE: usize = 256;
+/// How many flushes can wait to be written, the byte limit is usually reached first
+const OUTBOUND_QUEUE_SIZE: usize = 256;
+/// How long to wait for the last packets to be written when the server stops
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+/// Ticks between saving the world in the background, five minutes like vanilla
+const AUTOSAVE_PERIOD: u64 = 6000;
 
 struct Connection {
+    /// Received packets, each with its length prefix
     packet_receiver: mpsc::Receiver<PacketBuffer>,
-    stream: TcpStream,
+    /// Flushed packets to be written, None once the connection is closed
+    packet_sender: Option<mpsc::Sender<Vec<u8>>>,
+    /// Stops the reading task when dropped
+    close_sender: Option<oneshot::Sender<()>>,
+    /// Packets sent since the last flush
+    write_buffer: Vec<u8>,
+    /// Bytes that have been flushed but not written to the socket yet
+    queued_bytes: Arc<AtomicUsize>,
+    max_queued_bytes: usize,
     alive: bool,
 }
 
 impl Connection {
-    fn new(stream: TcpStream) -> Connection {
-        println!("New connection!");
-        let reader = stream.try_clone().unwrap();
-        let (tx, rx) = mpsc::channel();
-        let connection = Connection {
-            packet_receiver: rx,
-            stream,
-            alive: true,
-        };
+    /// Spawns the tasks reading from and writing to the stream, must be called within the runtime
+    fn new(stream: TcpStream, max_queued_bytes: usize) -> Connection {
+        debug!("New connection!");
+        let (reader, writer) = async_io::split(stream);
+        let (inbound_sender, inbound_receiver) = mpsc::channel(INBOUND_QUEUE_SIZE);
+        let (outbound_sender, outbound_receiver) = mpsc::channel(OUTBOUND_QUEUE_SIZE);
+        let (close_sender, close_receiver) = oneshot::channel();
+        let queued_bytes = Arc::new(AtomicUsize::new(0));
 
-        thread::spawn(|| {
-            Connection::handle_connection(reader, tx);
+        tokio::spawn(async move {
+            let read = Box::pin(Connection::read_packets(reader, inbound_sender));
+            if let Either::Left((Err(err), _)) = future::select(read, close_receiver).await {
+                warn!("Closing connection: {}", err);
+            }
         });
-        connection
+        tokio::spawn(Connection::write_packets(
+            writer,
+            outbound_receiver,
+            queued_bytes.clone(),
+        ));
+        Connection {
+            packet_receiver: inbound_receiver,
+            packet_sender: Some(outbound_sender),
+            close_sender: Some(close_sender),
+            write_buffer: Vec::new(),
+            queued_bytes,
+            max_queued_bytes,
+            alive: true,
+        }
     }
 
-    fn handle_connection(mut stream: TcpStream, packet_sender: mpsc::Sender<PacketBuffer>) {
+    /// Splits the stream into packets until it's closed.
+    /// Returning drops the sender, which marks the connection as closed.
+    async fn read_packets(
+        mut reader: ReadHalf<TcpStream>,
+        mut packet_sender: mpsc::Sender<PacketBuffer>,
+    ) -> ProtocolResult<()> {
+        let mut buffer = Vec::new();
+        let mut data = [0u8; 4096];
         loop {
-            let mut data = vec![0u8; 512];
-            let length = stream.read(&mut data).unwrap();
-            if length == 0 {
-                thread::sleep(Duration::from_millis(2));
-                continue;
+            let length = match reader.read(&mut data).await {
+                Ok(0) | Err(_) => return Ok(()),
+                Ok(length) => length,
+            };
+            buffer.extend_from_slice(&data[..length]);
+            while let Some(frame_length) = PacketDecoder::frame_length(&buffer)? {
+                let rest = buffer.split_off(frame_length);
+                let packet = std::mem::replace(&mut buffer, rest);
+                // Waits while the server is behind on this client's packets
+                if packet_sender.send(packet).await.is_err() {
+                    return Ok(());
+                }
             }
-            data.drain(length..);
-            data.shrink_to_fit();
-            packet_sender.send(data).unwrap();
         }
     }
 
+    /// Writes flushed packets until the connection is closed, then writes whatever is left.
+    /// `write_all` waits for the socket to become writable again after partial writes.
+    async fn write_packets(
+        mut writer: WriteHalf<TcpStream>,
+        mut packet_receiver: mpsc::Receiver<Vec<u8>>,
+        queued_bytes: Arc<AtomicUsize>,
+    ) {
+        while let Some(buffer) = packet_receiver.recv().await {
+            if writer.write_all(&buffer).await.is_err() {
+                return;
+            }
+            queued_bytes.fetch_sub(buffer.len(), Ordering::SeqCst);
+        }
+        let _ = writer.shutdown().await;
+    }
+
+    /// Queues a packet until the next flush
+    fn send(&mut self, buffer: Vec<u8>) {
+        if self.packet_sender.is_some() {
+            self.write_buffer.extend_from_slice(&buffer);
+        }
+    }
+
+    /// Hands everything sent since the last flush to the writing task in one piece.
+    /// Drops the connection if the client isn't reading fast enough to keep up.
+    fn flush(&mut self) {
+        if self.write_buffer.is_empty() {
+            return;
+        }
+        let buffer = mem::take(&mut self.write_buffer);
+        let sender = match &mut self.packet_sender {
+            Some(sender) => sender,
+            None => return,
+        };
+        let length = buffer.len();
+        let queued = self.queued_bytes.fetch_add(length, Ordering::SeqCst) + length;
+        if queued > self.max_queued_bytes || sender.try_send(buffer).is_err() {
+            warn!(
+                "Dropping connection that can't keep up with its packets ({} bytes queued)",
+                queued
+            );
+            self.shutdown();
+        }
+    }
+
+    /// Flushes and closes the connection once all sent packets are written
+    fn close(&mut self) {
+        self.flush();
+        self.shutdown();
+    }
+
+    /// Stops reading and sending without flushing
+    fn shutdown(&mut self) {
+        self.packet_sender = None;
+        self.close_sender = None;
+        self.alive = false;
+    }
+
+    /// Whether everything that was flushed has been written to the socket
+    fn is_written(&self) -> bool {
+        self.queued_bytes.load(Ordering::SeqCst) == 0
+    }
+
     fn receive_packets(&mut self) -> Vec<PacketBuffer> {
         let mut packets = Vec::new();
         loop {
             match self.packet_receiver.try_recv() {
                 Ok(packet) => packets.push(packet),
-                Err(mpsc::TryRecvError::Empty) => return packets,
-                Err(mpsc::TryRecvError::Disconnected) => {
+                Err(mpsc::error::TryRecvError::Empty) => return packets,
+                Err(mpsc::error::TryRecvError::Closed) => {
                     self.alive = false;
                     return packets;
                 }
@@ -70,125 +210,473 @@ impl Connection {
 
 pub struct Client {
     connection: Connection,
-    state: NetworkState,
+    pub(crate) state: NetworkState,
+    /// The version from the handshake, the latest version until then
+    pub(crate) protocol: ProtocolVersion,
     pub shared_secret: Option<Vec<u8>>,
     pub compressed: bool,
     verify_token: Option<Vec<u8>>,
-    player: Option<Player>,
+    pub(crate) player: Option<Player>,
     username: Option<String>,
-    id: u32,
+    /// Known once the player is authenticated
+    pub(crate) uuid: Option<u128>,
+    pub(crate) address: SocketAddr,
+    pub(crate) id: u32,
+    /// The id of the last teleport sent to the client
+    teleport_id: i32,
+    /// Movement is ignored until the client confirms the last teleport
+    teleport_pending: bool,
+    /// The plugin channels the client registered to receive messages on
+    channels: Vec<String>,
+    /// The client's mod loader or "vanilla", once it's sent it
+    brand: Option<String>,
 }
 
 impl Client {
-    fn new(stream: TcpStream, id: u32) -> Client {
-        let connection = Connection::new(stream);
+    fn new(stream: TcpStream, address: SocketAddr, id: u32, max_queued_bytes: usize) -> Client {
+        let connection = Connection::new(stream, max_queued_bytes);
         Client {
             connection,
             state: NetworkState::HANDSHAKING,
+            protocol: ProtocolVersion::LATEST,
             shared_secret: None,
             compressed: false,
             verify_token: None,
             player: None,
             username: None,
+            uuid: None,
+            address,
             id,
+            teleport_id: 0,
+            teleport_pending: false,
+            channels: Vec::new(),
+            brand: None,
         }
     }
 
-    fn send_packet(&mut self, encoder: &PacketEncoder) {
-        let buffer = encoder.finalize(self.compressed, &self.shared_secret);
-        self.connection.stream.write(buffer.as_slice()).unwrap();
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// The player, once the client is logged in
+    pub fn player(&self) -> Option<&Player> {
+        self.player.as_ref()
+    }
+
+    pub fn uuid(&self) -> Option<u128> {
+        self.uuid
+    }
+
+    pub fn brand(&self) -> Option<&str> {
+        self.brand.as_deref()
+    }
+
+    pub fn is_listening(&self, channel: &str) -> bool {
+        !channels::needs_registration(channel) || self.channels.iter().any(|c| c == channel)
+    }
+
+    fn send_plugin_message(&mut self, channel: &str, data: Vec<u8>) {
+        let message_encoder = C19PluginMessage {
+            channel: channel.to_string(),
+            data,
+        }
+        .encode();
+        self.send_packet(&message_encoder);
+    }
+
+    /// Finishes logging in, switches to the play state and spawns the player
+    fn join(&mut self, uuid: u128, player: Player, max_players: i32) {
+        let success_encoder = C02LoginSuccess {
+            uuid: utils::format_uuid(uuid),
+            username: player.username.clone(),
+        }
+        .encode();
+        self.send_packet(&success_encoder);
+        self.state = NetworkState::PLAY;
+        let join_encoder = C26JoinGame {
+            // Players are the only entities so far
+            entity_id: self.id as i32,
+            gamemode: player.gamemode.id(),
+            dimension: 0,
+            // TODO: World seeds
+            hashed_seed: 0,
+            max_players: max_players.clamp(0, 255) as u8,
+            level_type: "flat".to_string(),
+            view_distance: VIEW_DISTANCE,
+            reduced_debug_info: false,
+            enable_respawn_screen: true,
+        }
+        .encode(self.protocol);
+        self.send_packet(&join_encoder);
+        let (gamemode, location) = (player.gamemode, player.position);
+        self.player = Some(player);
+        self.send_abilities(gamemode);
+        self.teleport(location);
+    }
+
+    /// Moves the player and tells the client where it is now, `Server::teleport` also sends the
+    /// chunks around the new position
+    fn teleport(&mut self, location: Location) {
+        let player = match &mut self.player {
+            Some(player) => player,
+            None => return,
+        };
+        player.position = location;
+        self.teleport_id += 1;
+        self.teleport_pending = true;
+        let teleport_encoder = C36PlayerPositionAndLook {
+            x: location.x,
+            y: location.y,
+            z: location.z,
+            yaw: location.yaw,
+            pitch: location.pitch,
+            flags: 0,
+            teleport_id: self.teleport_id,
+        }
+        .encode();
+        self.send_packet(&teleport_encoder);
+    }
+
+    pub(crate) fn set_gamemode(&mut self, gamemode: Gamemode) {
+        let player = match &mut self.player {
+            Some(player) => player,
+            None => return,
+        };
+        player.gamemode = gamemode;
+        let state_encoder = C1FChangeGameState {
+            reason: GameStateReason::ChangeGamemode,
+            value: gamemode.id() as f32,
+        }
+        .encode();
+        self.send_packet(&state_encoder);
+        self.send_abilities(gamemode);
+    }
+
+    fn send_abilities(&mut self, gamemode: Gamemode) {
+        let abilities_encoder = C32PlayerAbilities {
+            flags: gamemode.ability_flags(),
+            flying_speed: 0.05,
+            field_of_view_modifier: 0.1,
+        }
+        .encode();
+        self.send_packet(&abilities_encoder);
+    }
+
+    /// Sends the reason to clients that are logging in or playing, then flushes and closes the connection
+    /// Undoes the client's changes to its inventory by sending it the server's copy
+    fn resend_inventory(&mut self) {
+        let inventory = match &self.player {
+            Some(player) => &player.inventory,
+            None => return,
+        };
+        let items_encoder = C15WindowItems {
+            window_id: 0,
+            slot_data: inventory.window_slots(0),
+        }
+        .encode(self.protocol);
+        let cursor_encoder = C17SetSlot {
+            window_id: -1,
+            slot: -1,
+            slot_data: inventory.cursor.clone(),
+        }
+        .encode(self.protocol);
+        self.send_packet(&items_encoder);
+        self.send_packet(&cursor_encoder);
+    }
+
+    pub(crate) fn disconnect(&mut self, reason: ChatComponent) {
+        info!("Disconnecting client {}: {}", self.id, reason.to_legacy());
+        let reason = reason.to_json();
+        match self.state {
+            NetworkState::LOGIN => self.send_packet(&C00Disconnect { reason }.encode()),
+            NetworkState::PLAY => self.send_packet(&C1BDisconnect { reason }.encode()),
+            // There's no way to show a reason during the handshake or a status request
+            NetworkState::HANDSHAKING | NetworkState::STATUS => {}
+        }
+        self.connection.close();
+    }
+
+    pub fn send_packet(&mut self, encoder: &PacketEncoder) {
+        let buffer = encoder.finalize(
+            self.compressed,
+            &self.shared_secret,
+            self.state,
+            self.protocol,
+        );
+        self.connection.send(buffer);
+    }
+
+    fn is_viewing_chunk(&self, chunk_x: i32, chunk_z: i32) -> bool {
+        match &self.player {
+            Some(player) if self.state == NetworkState::PLAY => {
+                let position = player.position.block_position();
+                (position.x >> 4) - chunk_x <= VIEW_DISTANCE
+                    && chunk_x - (position.x >> 4) <= VIEW_DISTANCE
+                    && (position.z >> 4) - chunk_z <= VIEW_DISTANCE
+                    && chunk_z - (position.z >> 4) <= VIEW_DISTANCE
+            }
+            _ => false,
+        }
     }
 }
 
 #[derive(Serialize, Deserialize)]
+#[serde(default)]
 pub struct ServerConfig {
-    max_players: i32,
+    pub(crate) max_players: i32,
+    /// Markup as understood by `ChatComponent::from_markup`
     motd: String,
+    /// Markup for chat messages, with the `{name}` and `{message}` placeholders
+    chat_format: String,
+    /// How many bytes a client can fall behind on before it's disconnected
+    max_queued_bytes: usize,
+    /// Log levels per module, like `info,network=debug`
+    log_filter: String,
+    /// Logs the contents of every received packet at the debug level
+    log_packets: bool,
+    /// Only lets players on the whitelist and operators join
+    pub(crate) whitelist: bool,
+    /// The permission level of new operators
+    pub(crate) op_permission_level: u8,
+    /// Authenticates players with Mojang, otherwise anyone can join with any name.
+    /// The server refuses to start with this on until connections can be encrypted
+    online_mode: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            max_players: 100,
+            motd: "<gold>Hello World!</gold>".to_string(),
+            chat_format: "<{name}> {message}".to_string(),
+            max_queued_bytes: 32 * 1024 * 1024,
+            log_filter: "info".to_string(),
+            log_packets: false,
+            whitelist: false,
+            op_permission_level: commands::MAX_PERMISSION_LEVEL,
+            online_mode: false,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Loads the config, or the default config if the file doesn't exist
+    fn load(path: &str) -> serde_json::Result<ServerConfig> {
+        match fs::read_to_string(path) {
+            Ok(data) => serde_json::from_str(&data),
+            Err(_) => Ok(ServerConfig::default()),
+        }
+    }
+
+    pub(crate) fn save(&self, path: &str) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self).unwrap())
+    }
+}
+
+/// The disconnect message for a ban, `key` is the translation key without `.reason`
+fn ban_message(key: &str, ban: &Ban) -> ChatComponent {
+    let reason = ChatComponent::new(ban.reason.clone());
+    let message = ChatComponent::translate(&format!("{}.reason", key), vec![reason]);
+    match ban.expiration() {
+        Some(_) => message.append(ChatComponent::translate(
+            &format!("{}.expiration", key),
+            vec![ChatComponent::new(ban.expires.clone())],
+        )),
+        None => message,
+    }
 }
 
 pub struct Server {
-    clients: Vec<Client>,
+    pub(crate) config: ServerConfig,
+    /// Set to false to stop the server at the end of the tick
+    pub(crate) running: bool,
+    console: Console,
+    runtime: Runtime,
+    pub(crate) commands: CommandDispatcher<Server>,
+    pub(crate) clients: Vec<Client>,
     client_receiver: mpsc::Receiver<Client>,
     key_pair: Rsa<Private>,
     mojang: Mojang,
+    recipes: RecipeRegistry,
+    pub(crate) user_lists: UserLists,
+    pub(crate) permissions: Permissions,
+    events: EventBus<Server>,
+    channels: ChannelRegistry<Server>,
+    pub(crate) plugins: PluginManager,
+    scheduler: Scheduler<Server>,
+    scripts: ScriptWatcher,
+    pub(crate) world: World,
+    /// Whether a background save of the world hasn't been written yet
+    saving_world: bool,
 }
 
 impl Server {
-    fn new() -> Server {
+    fn new(config: ServerConfig, console: Console) -> Server {
         let rsa = Rsa::generate(1024).unwrap();
-        let (tx, rx) = mpsc::channel();
-        let server = Server {
+        let (tx, rx) = mpsc::channel(64);
+        info!("Loading world...");
+        let mut server = Server {
+            config,
+            running: true,
+            console,
+            runtime: Runtime::new().unwrap(),
+            commands: CommandDispatcher::new(),
             clients: Vec::new(),
             key_pair: rsa,
             mojang: Mojang::new(),
-            client_receiver: rx
+            client_receiver: rx,
+            recipes: RecipeRegistry::load("res/recipes", "res/tags/items"),
+            user_lists: UserLists::load(),
+            permissions: Permissions::load(),
+            events: EventBus::new(),
+            channels: ChannelRegistry::new(),
+            plugins: PluginManager::new(),
+            scheduler: Scheduler::new(),
+            scripts: ScriptWatcher::new(),
+            world: World::load_world(),
+            saving_world: false,
         };
+        builtin::register(&mut server);
         server.listen_for_connections(tx);
         server
     }
 
+    /// A server that doesn't listen for connections or use any files, for tests
+    #[cfg(test)]
+    pub(crate) fn unstarted() -> Server {
+        let (_, client_receiver) = mpsc::channel(1);
+        Server {
+            config: ServerConfig::default(),
+            running: true,
+            console: Console::detached(),
+            runtime: Runtime::new().unwrap(),
+            commands: CommandDispatcher::new(),
+            clients: Vec::new(),
+            client_receiver,
+            key_pair: Rsa::generate(1024).unwrap(),
+            mojang: Mojang::new(),
+            recipes: RecipeRegistry::load("res/recipes", "res/tags/items"),
+            user_lists: UserLists::unsaved(),
+            permissions: Permissions::unsaved(),
+            events: EventBus::new(),
+            channels: ChannelRegistry::new(),
+            plugins: PluginManager::new(),
+            scheduler: Scheduler::new(),
+            scripts: ScriptWatcher::new(),
+            world: World::empty(),
+            saving_world: false,
+        }
+    }
+
     fn get_client(&self, client_id: u32) -> &Client {
         self.clients.iter().filter(|client| client.id == client_id).collect::<Vec<&Client>>()[0]
     }
 
-    fn listen_for_connections(&self, sender: mpsc::Sender<Client>) {
-        let mut next_id = 0;
-        thread::spawn(move || {
-            let listener = TcpListener::bind("0.0.0.0:25566").unwrap();
-            for stream in listener.incoming() {
-                let stream = stream.unwrap();
-
-                let client = Client::new(stream, next_id);
-                sender.send(client).unwrap();
+    fn listen_for_connections(&self, mut sender: mpsc::Sender<Client>) {
+        let listener = std::net::TcpListener::bind("0.0.0.0:25566").unwrap();
+        let max_queued_bytes = self.config.max_queued_bytes;
+        self.runtime.spawn(async move {
+            let mut listener = TcpListener::from_std(listener).unwrap();
+            let mut next_id = 0;
+            loop {
+                let (stream, address) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        error!("Couldn't accept connection: {}", err);
+                        continue;
+                    }
+                };
+                let client = Client::new(stream, address, next_id, max_queued_bytes);
+                if sender.send(client).await.is_err() {
+                    return;
+                }
                 next_id += 1;
             }
         });
     }
 
     fn unknown_packet(id: i32) {
-        eprintln!("Unknown packet with id: {}", id);
+        debug!("Unknown packet with id: {}", id);
     }
 
-    fn handle_packet(&mut self, client: usize, packet: PacketBuffer) {
-        let client = self.clients.get_mut(client).unwrap();
-        let decoder = PacketDecoder::new(packet, client);
-        println!(
+    fn handle_packet(&mut self, client_index: usize, packet: PacketBuffer) -> ProtocolResult<()> {
+        let client = self.clients.get_mut(client_index).unwrap();
+        let decoder = PacketDecoder::new(packet, client)?;
+        debug!(
             "Packet received: {}, with the length of: {}",
             decoder.packet_id, decoder.length
         );
         let state = client.state;
+        // Copying the packet for the event is only worth it if anyone's listening
+        if self.events.has_handlers::<PacketReceiveEvent>() {
+            let mut event = PacketReceiveEvent {
+                client_id: client.id,
+                state,
+                packet_id: decoder.packet_id,
+                buffer: decoder.buffer.clone(),
+                cancelled: false,
+            };
+            if !self.fire_event(&mut event) {
+                return Ok(());
+            }
+        }
+        let client = &mut self.clients[client_index];
         match state {
             NetworkState::HANDSHAKING => match decoder.packet_id {
-                0x00 => {
-                    let packet = S00Handshake::decode(decoder);
-                    println!("New state: {:#?}", packet.next_state);
+                S00Handshake::ID => {
+                    let packet = S00Handshake::decode(decoder)?;
+                    debug!("New state: {:?}", packet.next_state);
                     client.state = packet.next_state;
+                    match ProtocolVersion::from_id(packet.protocol_version) {
+                        Some(protocol) => client.protocol = protocol,
+                        None if packet.next_state == NetworkState::LOGIN => {
+                            let outdated_client =
+                                packet.protocol_version < ProtocolVersion::OLDEST.id();
+                            let translate = if outdated_client {
+                                "multiplayer.disconnect.outdated_client"
+                            } else {
+                                "multiplayer.disconnect.outdated_server"
+                            };
+                            let version =
+                                ChatComponent::new(ProtocolVersion::LATEST.name().to_string());
+                            client.disconnect(ChatComponent::translate(translate, vec![version]));
+                        }
+                        // Let the client show that it's incompatible in the server list
+                        None => {}
+                    }
                 }
                 _ => Server::unknown_packet(decoder.packet_id),
             },
             NetworkState::STATUS => match decoder.packet_id {
-                0x00 => {
+                S00Request::ID => {
+                    let online = self
+                        .clients
+                        .iter()
+                        .filter(|client| client.state == NetworkState::PLAY)
+                        .count();
                     let json_response = json!({
                         "version": {
-                            "name": "RustMC 1.15.1",
-                            "protocol": 575
+                            "name": format!(
+                                "RustMC {}-{}",
+                                ProtocolVersion::OLDEST.name(),
+                                ProtocolVersion::LATEST.name()
+                            ),
+                            // Unsupported clients get the latest version and show the server as incompatible
+                            "protocol": self.clients[client_index].protocol.id()
                         },
                         "players": {
-                            "max": 100,
-                            "online": 1,
+                            "max": self.config.max_players,
+                            "online": online,
                             "sample": [],
                         },
-                        "description": {
-                            "text": "Hello World!",
-                            "color": "gold"
-                        }
+                        "description": ChatComponent::from_markup(&self.config.motd)
                     })
                     .to_string();
                     let response_encoder = C00Response { json_response }.encode();
-                    client.send_packet(&response_encoder);
+                    self.clients[client_index].send_packet(&response_encoder);
                 }
-                0x01 => {
-                    let packet = S01Ping::decode(decoder);
+                S01Ping::ID => {
+                    let packet = S01Ping::decode(decoder)?;
                     let pong_encoder = C01Pong {
                         payload: packet.payload,
                     }
@@ -198,15 +686,25 @@ impl Server {
                 _ => Server::unknown_packet(decoder.packet_id),
             },
             NetworkState::LOGIN => match decoder.packet_id {
-                0x00 => {
-                    let packet = S00LoginStart::decode(decoder);
+                S00LoginStart::ID => {
+                    let packet = S00LoginStart::decode(decoder)?;
+                    // Banned addresses don't get to authenticate
+                    let ip = client.address.ip().to_string();
+                    if let Some(ban) = self.user_lists.ip_ban(&ip) {
+                        client
+                            .disconnect(ban_message("multiplayer.disconnect.banned_ip", &ban.ban));
+                        return Ok(());
+                    }
+                    if !self.config.online_mode {
+                        let uuid = utils::offline_uuid(&packet.name);
+                        self.login(client_index, uuid, packet.name);
+                        return Ok(());
+                    }
                     let public_key = self.key_pair.public_key_to_der().unwrap();
                     let verify_token = rand::thread_rng().gen::<[u8; 4]>().to_vec();
                     let request_encoder = C01EcryptionRequest {
                         server_id: "".to_string(),
-                        public_key_length: public_key.len() as i32,
                         public_key,
-                        verify_token_length: 4,
                         verify_token: verify_token.clone(),
                     }
                     .encode();
@@ -214,10 +712,11 @@ impl Server {
                     client.username = Some(packet.name);
                     client.send_packet(&request_encoder);
                 }
-                0x01 => {
-                    let packet = S01EncryptionResponse::decode(decoder);
-                    let mut received_verify_token = vec![0u8; packet.verify_token_length as usize];
+                S01EncryptionResponse::ID => {
+                    let packet = S01EncryptionResponse::decode(decoder)?;
+                    let mut received_verify_token = vec![0u8; self.key_pair.size() as usize];
 
+                    // A token that can't be decrypted is just as wrong as an incorrect one
                     let length_decrypted = self
                         .key_pair
                         .private_decrypt(
@@ -225,30 +724,970 @@ impl Server {
                             received_verify_token.as_mut(),
                             Padding::PKCS1,
                         )
-                        .unwrap();
-                    received_verify_token.drain(length_decrypted..received_verify_token.len());
-                    if &received_verify_token == client.verify_token.as_ref().unwrap() {
-                        // Start login process
-                        println!("Starting login process");
-                        /*self.mojang.send_has_joined(
-                            &clients[client].username.unwrap(),
-                            clients[client].id
-                        );*/
+                        .unwrap_or(0);
+                    received_verify_token.truncate(length_decrypted);
+                    if length_decrypted > 0
+                        && Some(&received_verify_token) == client.verify_token.as_ref()
+                    {
+                        debug!("Starting login process");
+                        if let Some(username) = &client.username {
+                            self.mojang.send_has_joined(username, client.id);
+                        }
                     } else {
-                        println!("Verify token incorrent!!");
+                        client.disconnect(ChatComponent::new("Invalid verify token".to_string()));
                     }
                 }
                 _ => Server::unknown_packet(decoder.packet_id),
             },
-            NetworkState::PLAY => match decoder.packet_id {
+            NetworkState::PLAY => match client.protocol.serverbound_id(state, decoder.packet_id) {
+                S00TeleportConfirm::ID => {
+                    let packet = S00TeleportConfirm::decode(decoder)?;
+                    if packet.teleport_id == client.teleport_id {
+                        client.teleport_pending = false;
+                    }
+                }
+                S03ChatMessage::ID => {
+                    let packet = S03ChatMessage::decode(decoder)?;
+                    self.handle_chat_message(client_index, packet)?;
+                }
+                S06TabComplete::ID => {
+                    let packet = S06TabComplete::decode(decoder)?;
+                    self.handle_tab_complete(client_index, packet);
+                }
+                S09ClickWindow::ID => {
+                    let packet = match S09ClickWindow::decode(decoder) {
+                        Ok(packet) => packet,
+                        Err(ProtocolError::UnknownItem(id)) => {
+                            debug!("Client {} clicked with unknown item {}", client.id, id);
+                            client.resend_inventory();
+                            return Ok(());
+                        }
+                        Err(err) => return Err(err),
+                    };
+                    let player = match &mut client.player {
+                        Some(player) => player,
+                        None => return Ok(()),
+                    };
+                    let inventory = &mut player.inventory;
+                    let accepted = packet.mode == 0
+                        && inventory.click(
+                            packet.window_id,
+                            packet.slot,
+                            packet.button,
+                            &self.recipes,
+                        );
+                    let result = inventory
+                        .crafting_grid(packet.window_id)
+                        .map(|grid| grid.result.clone());
+                    let slot_data = inventory.window_slots(packet.window_id);
+                    let cursor = inventory.cursor.clone();
+
+                    let confirmation_encoder = C13WindowConfirmation {
+                        window_id: packet.window_id as i8,
+                        action_number: packet.action_number,
+                        accepted,
+                    }
+                    .encode();
+                    client.send_packet(&confirmation_encoder);
+                    if accepted {
+                        if let Some(result) = result {
+                            let result_encoder = C17SetSlot {
+                                window_id: packet.window_id as i8,
+                                slot: 0,
+                                slot_data: result,
+                            }
+                            .encode(client.protocol);
+                            client.send_packet(&result_encoder);
+                        }
+                    } else {
+                        // TODO: Shift clicking, dragging and the other click modes
+                        let items_encoder = C15WindowItems {
+                            window_id: packet.window_id,
+                            slot_data,
+                        }
+                        .encode(client.protocol);
+                        client.send_packet(&items_encoder);
+                        let cursor_encoder = C17SetSlot {
+                            window_id: -1,
+                            slot: -1,
+                            slot_data: cursor,
+                        }
+                        .encode(client.protocol);
+                        client.send_packet(&cursor_encoder);
+                    }
+                }
+                S0ACloseWindow::ID => {
+                    let packet = S0ACloseWindow::decode(decoder)?;
+                    let player = match &mut client.player {
+                        Some(player) => player,
+                        None => return Ok(()),
+                    };
+                    let leftover = player.inventory.close_window(packet.window_id);
+                    if !leftover.is_empty() {
+                        // TODO: Drop items that don't fit back into the inventory
+                        warn!(
+                            "{} items didn't fit back into the inventory",
+                            leftover.len()
+                        );
+                    }
+                    let items_encoder = C15WindowItems {
+                        window_id: 0,
+                        slot_data: player.inventory.window_slots(0),
+                    }
+                    .encode(client.protocol);
+                    client.send_packet(&items_encoder);
+                }
+                S0BPluginMessage::ID => {
+                    let packet = S0BPluginMessage::decode(decoder)?;
+                    self.handle_plugin_message(client_index, packet);
+                }
+                S11PlayerPosition::ID => {
+                    let packet = S11PlayerPosition::decode(decoder)?;
+                    if client.teleport_pending {
+                        return Ok(());
+                    }
+                    if let Some(player) = &client.player {
+                        let mut to = player.position;
+                        to.x = packet.x;
+                        to.y = packet.feet_y;
+                        to.z = packet.z;
+                        self.handle_move(client_index, to);
+                    }
+                }
+                S12PlayerPositionAndRotation::ID => {
+                    let packet = S12PlayerPositionAndRotation::decode(decoder)?;
+                    if client.teleport_pending {
+                        return Ok(());
+                    }
+                    let to =
+                        Location::new(packet.x, packet.feet_y, packet.z, packet.pitch, packet.yaw);
+                    self.handle_move(client_index, to);
+                }
+                S13PlayerRotation::ID => {
+                    let packet = S13PlayerRotation::decode(decoder)?;
+                    if client.teleport_pending {
+                        return Ok(());
+                    }
+                    if let Some(player) = &client.player {
+                        let mut to = player.position;
+                        to.yaw = packet.yaw;
+                        to.pitch = packet.pitch;
+                        self.handle_move(client_index, to);
+                    }
+                }
+                S1APlayerDigging::ID => {
+                    let packet = S1APlayerDigging::decode(decoder)?;
+                    self.handle_player_digging(client_index, packet);
+                }
+                S23HeldItemChange::ID => {
+                    let packet = S23HeldItemChange::decode(decoder)?;
+                    if let Some(player) = &mut client.player {
+                        if packet.slot >= 0 && packet.slot < 9 {
+                            player.selected_slot = packet.slot as usize;
+                        }
+                    }
+                }
+                S26CreativeInventoryAction::ID => {
+                    // The registries may not have every item the creative menu has
+                    let packet = match S26CreativeInventoryAction::decode(decoder) {
+                        Ok(packet) => packet,
+                        Err(ProtocolError::UnknownItem(id)) => {
+                            debug!("Client {} took unknown item {}", client.id, id);
+                            client.resend_inventory();
+                            return Ok(());
+                        }
+                        Err(err) => return Err(err),
+                    };
+                    if let Some(player) = &mut client.player {
+                        if player.gamemode == Gamemode::Creative {
+                            player.inventory.set_slot(packet.slot, packet.clicked_item);
+                        }
+                    }
+                }
+                S2CPlayerBlockPlacement::ID => {
+                    let packet = S2CPlayerBlockPlacement::decode(decoder)?;
+                    self.handle_block_placement(client_index, packet);
+                }
                 _ => Server::unknown_packet(decoder.packet_id),
             },
         }
+        Ok(())
+    }
+
+    fn handle_plugin_message(&mut self, client_index: usize, packet: S0BPluginMessage) {
+        let client = &mut self.clients[client_index];
+        if packet.data.len() > channels::MAX_SERVERBOUND_SIZE {
+            client.disconnect(ChatComponent::new("Plugin message is too long".to_string()));
+            return;
+        }
+        // Handlers listen on the name a channel is sent as, which the legacy BungeeCord name isn't
+        let channel = match channels::channel_name(&packet.channel) {
+            Some(channel) => channel,
+            None => {
+                debug!(
+                    "Client {} sent a message on the invalid channel {}",
+                    client.id, packet.channel
+                );
+                return;
+            }
+        };
+        match channel {
+            channels::REGISTER => {
+                let registered = channels::register_channels(&mut client.channels, &packet.data);
+                if !registered {
+                    warn!("Client {} registered too many channels", client.id);
+                }
+            }
+            channels::UNREGISTER => {
+                channels::unregister_channels(&mut client.channels, &packet.data)
+            }
+            channels::BRAND => {
+                if let Some(brand) = channels::decode_brand(&packet.data) {
+                    debug!("Client {} is running {}", client.id, brand);
+                    client.brand = Some(brand);
+                }
+            }
+            _ => {}
+        }
+        let client_id = client.id;
+        for handler in self.channels.handlers(channel) {
+            handler(self, client_id, &packet.data);
+        }
+    }
+
+    fn handle_chat_message(
+        &mut self,
+        client_index: usize,
+        packet: S03ChatMessage,
+    ) -> ProtocolResult<()> {
+        let length = packet.message.chars().count();
+        if length > chat::MAX_MESSAGE_LENGTH {
+            return Err(ProtocolError::StringTooLong {
+                length,
+                max: chat::MAX_MESSAGE_LENGTH,
+            });
+        }
+        let message = chat::sanitize_message(&packet.message);
+        let client = &self.clients[client_index];
+        let username = match &client.player {
+            Some(player) => player.username.clone(),
+            None => return Ok(()),
+        };
+        if message.is_empty() {
+            return Ok(());
+        }
+        if let Some(command) = message.strip_prefix('/') {
+            self.execute_command(CommandSender::Player(client.id), command);
+            return Ok(());
+        }
+        let mut event = ChatEvent {
+            client_id: client.id,
+            message,
+            cancelled: false,
+        };
+        if !self.fire_event(&mut event) {
+            return Ok(());
+        }
+        let message = event.message;
+        let name = ChatComponent::new(username.clone())
+            .insertion(&username)
+            .click_event(
+                ClickEventType::SuggestCommand,
+                &format!("/tell {} ", username),
+            );
+        let message = ChatComponent::format(
+            &self.config.chat_format,
+            &[("name", name), ("message", ChatComponent::new(message))],
+        );
+        self.broadcast_chat(message, ChatPosition::Chat);
+        Ok(())
+    }
+
+    /// Tells everyone which commands they can use, after changes that can affect anyone
+    pub(crate) fn resend_commands(&mut self) {
+        for index in 0..self.clients.len() {
+            if self.clients[index].state == NetworkState::PLAY {
+                self.send_commands(index);
+            }
+        }
+    }
+
+    /// The UUID of a player running a command, None for the console
+    fn sender_uuid(&self, sender: &CommandSender) -> Option<String> {
+        match sender {
+            CommandSender::Console => None,
+            CommandSender::Player(client_id) => self
+                .client_index(*client_id)
+                .and_then(|index| self.clients[index].uuid)
+                .map(utils::format_uuid),
+        }
+    }
+
+    fn permission_level(&self, sender: &CommandSender) -> u8 {
+        match sender {
+            CommandSender::Console => commands::MAX_PERMISSION_LEVEL,
+            CommandSender::Player(_) => self
+                .sender_uuid(sender)
+                .and_then(|uuid| self.user_lists.operator(&uuid))
+                .map_or(0, |op| op.level),
+        }
+    }
+
+    pub(crate) fn sender_permissions(&self, sender: &CommandSender) -> SenderPermissions<'_> {
+        SenderPermissions {
+            level: self.permission_level(sender),
+            uuid: self.sender_uuid(sender),
+            permissions: &self.permissions,
+        }
+    }
+
+    pub(crate) fn client_index(&self, client_id: u32) -> Option<usize> {
+        self.clients
+            .iter()
+            .position(|client| client.id == client_id)
+    }
+
+    /// Runs a command without the leading slash and tells the sender if it failed
+    pub fn execute_command(&mut self, sender: CommandSender, command: &str) {
+        if let CommandSender::Player(client_id) = sender {
+            let player = self
+                .client_index(client_id)
+                .and_then(|index| self.clients[index].player.as_ref());
+            if let Some(player) = player {
+                info!("{} issued server command: /{}", player.username, command);
+            }
+        }
+        let parsed =
+            self.commands
+                .parse(sender.clone(), command, &self.sender_permissions(&sender));
+        let result = parsed.and_then(|(executor, context)| executor(self, &context));
+        if let Err(err) = result {
+            let message = ChatComponent::new(err.to_string()).color(ChatColor::Red);
+            self.send_message(&sender, message);
+        }
+    }
+
+    /// Sends feedback to whoever ran a command
+    pub fn send_message(&mut self, sender: &CommandSender, message: ChatComponent) {
+        match sender {
+            CommandSender::Console => info!("{}", message.to_legacy()),
+            CommandSender::Player(client_id) => {
+                if let Some(index) = self.client_index(*client_id) {
+                    let chat_encoder = C0FChatMessage {
+                        json_data: message.to_json(),
+                        position: ChatPosition::System,
+                    }
+                    .encode();
+                    self.clients[index].send_packet(&chat_encoder);
+                }
+            }
+        }
+    }
+
+    /// Sends the commands a client can use so it can complete them
+    pub(crate) fn send_commands(&mut self, client_index: usize) {
+        let sender = CommandSender::Player(self.clients[client_index].id);
+        let (nodes, root_index) = self.commands.declare(&self.sender_permissions(&sender));
+        let commands_encoder = C12DeclareCommands { nodes, root_index }.encode();
+        self.clients[client_index].send_packet(&commands_encoder);
+    }
+
+    fn handle_tab_complete(&mut self, client_index: usize, packet: S06TabComplete) {
+        let client = &self.clients[client_index];
+        if !packet.text.starts_with('/') {
+            return;
+        }
+        let input = &packet.text[1..];
+        let permissions = self.sender_permissions(&CommandSender::Player(client.id));
+        let player_names: Vec<String> = self
+            .clients
+            .iter()
+            .filter_map(|client| client.player.as_ref())
+            .map(|player| player.username.clone())
+            .collect();
+        let (start, suggestions) = self.commands.suggest(input, &permissions, &player_names);
+        // The client counts in UTF-16 code units, the start is after the slash
+        let tab_complete_encoder = C11TabComplete {
+            id: packet.transaction_id,
+            start: input[..start].encode_utf16().count() as i32 + 1,
+            length: input[start..].encode_utf16().count() as i32,
+            matches: suggestions
+                .into_iter()
+                .map(|text| TabCompleteMatch {
+                    text,
+                    tooltip: None,
+                })
+                .collect(),
+        }
+        .encode();
+        self.clients[client_index].send_packet(&tab_complete_encoder);
+    }
+
+    /// Sends a message to every player and the console
+    pub fn broadcast_chat(&mut self, message: ChatComponent, position: ChatPosition) {
+        info!("{}", message.to_legacy());
+        let chat_encoder = C0FChatMessage {
+            json_data: message.to_json(),
+            position,
+        }
+        .encode();
+        for client in &mut self.clients {
+            if client.state == NetworkState::PLAY {
+                client.send_packet(&chat_encoder);
+            }
+        }
+    }
+
+    /// Called once a client has logged in and spawned as a player
+    fn on_player_join(&mut self, client_index: usize) {
+        let username = match &self.clients[client_index].player {
+            Some(player) => player.username.clone(),
+            None => return,
+        };
+        let message = ChatComponent::translate(
+            "multiplayer.player.joined",
+            vec![ChatComponent::new(username)],
+        )
+        .color(ChatColor::Yellow);
+        let mut event = PlayerJoinEvent {
+            client_id: self.clients[client_index].id,
+            join_message: Some(message),
+        };
+        self.fire_event(&mut event);
+        if let Some(message) = event.join_message {
+            self.broadcast_chat(message, ChatPosition::System);
+        }
+        self.send_commands(client_index);
+        self.send_recipes(client_index);
+        let client = &mut self.clients[client_index];
+        client.send_plugin_message(
+            channels::BRAND,
+            channels::encode_brand(channels::SERVER_BRAND),
+        );
+        let listened = self.channels.channels();
+        if !listened.is_empty() {
+            client
+                .send_plugin_message(channels::REGISTER, channels::encode_channel_list(&listened));
+        }
+        let time_encoder = self.time_packet();
+        self.clients[client_index].send_packet(&time_encoder);
+        for weather_encoder in self.weather_packets() {
+            self.clients[client_index].send_packet(&weather_encoder);
+        }
+    }
+
+    fn time_packet(&self) -> PacketEncoder {
+        C4FTimeUpdate {
+            world_age: self.world.age,
+            time_of_day: self.world.time,
+        }
+        .encode()
+    }
+
+    fn weather_packets(&self) -> Vec<PacketEncoder> {
+        let (reason, rain, thunder) = match self.world.weather {
+            Weather::Clear => (GameStateReason::EndRaining, 0.0, 0.0),
+            Weather::Rain => (GameStateReason::BeginRaining, 1.0, 0.0),
+            Weather::Thunder => (GameStateReason::BeginRaining, 1.0, 1.0),
+        };
+        vec![
+            C1FChangeGameState { reason, value: 0.0 }.encode(),
+            C1FChangeGameState {
+                reason: GameStateReason::RainLevel,
+                value: rain,
+            }
+            .encode(),
+            C1FChangeGameState {
+                reason: GameStateReason::ThunderLevel,
+                value: thunder,
+            }
+            .encode(),
+        ]
+    }
+
+    pub(crate) fn broadcast_time(&mut self) {
+        let time_encoder = self.time_packet();
+        for client in &mut self.clients {
+            if client.state == NetworkState::PLAY {
+                client.send_packet(&time_encoder);
+            }
+        }
+    }
+
+    pub(crate) fn broadcast_weather(&mut self) {
+        let weather_encoders = self.weather_packets();
+        for client in &mut self.clients {
+            if client.state == NetworkState::PLAY {
+                for weather_encoder in &weather_encoders {
+                    client.send_packet(weather_encoder);
+                }
+            }
+        }
+    }
+
+    fn tick_world(&mut self) {
+        // Keep the chunks around players loaded, wherever they moved or were teleported to
+        for client in &self.clients {
+            if let Some(player) = &client.player {
+                self.world
+                    .load_chunks_around(&player.position, VIEW_DISTANCE);
+            }
+        }
+        if self.world.tick() {
+            self.broadcast_weather();
+        }
+        // Clients advance the time themselves, so they only need to be corrected once a second
+        if self.world.age % 20 == 0 {
+            self.broadcast_time();
+        }
+    }
+
+    /// Moves a player where the client says it went, unless a handler objects
+    fn handle_move(&mut self, client_index: usize, to: Location) {
+        let client = &self.clients[client_index];
+        let from = match &client.player {
+            Some(player) => player.position,
+            None => return,
+        };
+        let mut event = MoveEvent {
+            client_id: client.id,
+            from,
+            to,
+            cancelled: false,
+        };
+        let moved = self.fire_event(&mut event);
+        let client = &mut self.clients[client_index];
+        if !moved {
+            client.teleport(from);
+        } else if event.to != to {
+            client.teleport(event.to);
+        } else if let Some(player) = &mut client.player {
+            player.position = to;
+        }
+        self.update_view(client_index, from);
+    }
+
+    /// Moves a player and sends the chunks around where they end up, returns false if they're offline
+    pub fn teleport(&mut self, client_id: u32, location: Location) -> bool {
+        match self.client_index(client_id) {
+            Some(client_index) => self.teleport_client(client_index, location),
+            None => false,
+        }
+    }
+
+    pub(crate) fn teleport_client(&mut self, client_index: usize, location: Location) -> bool {
+        let client = &mut self.clients[client_index];
+        let from = match &client.player {
+            Some(player) => player.position,
+            None => return false,
+        };
+        client.teleport(location);
+        self.update_view(client_index, from);
+        true
+    }
+
+    fn handle_player_digging(&mut self, client_index: usize, packet: S1APlayerDigging) {
+        let client = &mut self.clients[client_index];
+        let client_id = client.id;
+        let player = match &mut client.player {
+            Some(player) => player,
+            None => return,
+        };
+        let position = packet.location;
+        // Clients can send any position, so nothing out of reach is looked up
+        if !player.can_reach(position) {
+            return;
+        }
+        let block = self.world.get_block(position).block();
+        let can_dig = match player.gamemode {
+            Gamemode::Survival | Gamemode::Creative => true,
+            Gamemode::Adventure | Gamemode::Spectator => false,
+        };
+
+        let (mut successful, broken) = match packet.status {
+            DiggingStatus::Started if can_dig => {
+                let min_ticks = block.and_then(|block| block.min_break_ticks());
+                // Clients that break a block instantly don't send a finish for it
+                if player.gamemode == Gamemode::Creative || min_ticks == Some(0) {
+                    (true, true)
+                } else if min_ticks.is_some() {
+                    player.digging = Some(Digging {
+                        position,
+                        started: Instant::now(),
+                    });
+                    (true, false)
+                } else {
+                    (false, false)
+                }
+            }
+            DiggingStatus::Cancelled => {
+                player.digging = None;
+                (true, false)
+            }
+            DiggingStatus::Finished if can_dig => match player.digging.take() {
+                Some(digging) if digging.position == position => {
+                    let ticks = digging.started.elapsed().as_millis() / 50;
+                    match block.and_then(|block| block.min_break_ticks()) {
+                        Some(min_ticks) if ticks >= min_ticks as u128 => (true, true),
+                        _ => (false, false),
+                    }
+                }
+                _ => (false, false),
+            },
+            DiggingStatus::Started | DiggingStatus::Finished => (false, false),
+            // TODO: Dropping items, eating and swapping items between hands
+            _ => return,
+        };
+
+        if broken {
+            let mut event = BlockBreakEvent {
+                client_id,
+                position,
+                block: self.world.get_block(position),
+                cancelled: false,
+            };
+            if self.fire_event(&mut event) {
+                // TODO: Drop the block as an item in survival
+                self.world.set_block(position, BlockState::AIR);
+            } else {
+                // The client puts the block back if digging wasn't successful
+                successful = false;
+            }
+        }
+        let ack_encoder = C08AcknowledgePlayerDigging {
+            location: position,
+            block: self.world.get_block(position).id() as i32,
+            status: packet.status,
+            successful,
+        }
+        .encode();
+        self.clients[client_index].send_packet(&ack_encoder);
+    }
+
+    fn handle_block_placement(&mut self, client_index: usize, packet: S2CPlayerBlockPlacement) {
+        let client = &mut self.clients[client_index];
+        let player = match &mut client.player {
+            Some(player) => player,
+            None => return,
+        };
+        if player.gamemode == Gamemode::Spectator {
+            return;
+        }
+        let clicked = packet.location;
+        // Clients can send any position, so nothing out of reach is looked up
+        if !player.can_reach(clicked) {
+            return;
+        }
+        let clicked_state = self.world.get_block(clicked);
+
+        // TODO: Don't open the crafting table while sneaking
+        let is_crafting_table = clicked_state
+            .block()
+            .is_some_and(|block| block.name == "minecraft:crafting_table");
+        if packet.hand == 0 && is_crafting_table {
+            let window_id = player.inventory.open_crafting_window();
+            let open_encoder = C2FOpenWindow {
+                window_id: window_id as i32,
+                window_type: Menu::Crafting.id(),
+                window_title: ChatComponent::translate("container.crafting", vec![]).to_json(),
+            }
+            .encode();
+            client.send_packet(&open_encoder);
+            return;
+        }
+
+        let target = if clicked_state.is_replaceable() {
+            clicked
+        } else {
+            clicked.offset(packet.face)
+        };
+        let can_place = player.gamemode != Gamemode::Adventure
+            && player.can_reach(target)
+            && target.y >= 0
+            && target.y < world::WORLD_HEIGHT
+            && self.world.get_block(target).is_replaceable()
+            && !player.intersects(target);
+        let selected_slot = player.selected_slot;
+        let held = match packet.hand {
+            0 => player.inventory.hotbar(selected_slot),
+            _ => &player.inventory.offhand,
+        };
+        let block = held
+            .as_ref()
+            .and_then(|stack| Block::from_name(stack.item.name()));
+        let state = match block {
+            Some(block) if can_place => {
+                // TODO: Facing of stairs, furnaces and other directional blocks
+                let mut state = block.default_state();
+                if state.axis().is_some() {
+                    let axis = match packet.face {
+                        0 | 1 => "y",
+                        2 | 3 => "z",
+                        _ => "x",
+                    };
+                    state = state.with_property("axis", axis).unwrap_or(state);
+                }
+                let mut event = BlockPlaceEvent {
+                    client_id: client.id,
+                    position: target,
+                    block: state,
+                    cancelled: false,
+                };
+                if self.fire_event(&mut event) {
+                    Some(event.block)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+
+        // The handlers could have done anything to the player
+        let client = &mut self.clients[client_index];
+        let player = match &mut client.player {
+            Some(player) => player,
+            None => return,
+        };
+        let held = match packet.hand {
+            0 => player.inventory.hotbar_mut(selected_slot),
+            _ => &mut player.inventory.offhand,
+        };
+        match state {
+            Some(state) => {
+                self.world.set_block(target, state);
+                if player.gamemode != Gamemode::Creative {
+                    if let Some(stack) = held {
+                        stack.count -= 1;
+                        if stack.count <= 0 {
+                            *held = None;
+                        }
+                    }
+                }
+            }
+            None => {
+                // Revert the client's prediction
+                let held_slot = match packet.hand {
+                    0 => 36 + selected_slot as i16,
+                    _ => 45,
+                };
+                let held = held.clone();
+                for position in &[clicked, target] {
+                    let block_encoder = C0CBlockChange {
+                        location: *position,
+                        block_id: self.world.get_block(*position).id() as i32,
+                    }
+                    .encode();
+                    client.send_packet(&block_encoder);
+                }
+                let slot_encoder = C17SetSlot {
+                    window_id: 0,
+                    slot: held_slot,
+                    slot_data: held,
+                }
+                .encode(client.protocol);
+                client.send_packet(&slot_encoder);
+            }
+        }
+    }
+
+    /// Sends every block change since the last call to the clients viewing the chunk
+    fn broadcast_block_changes(&mut self) {
+        for ((chunk_x, chunk_z), changes) in self.world.take_block_changes() {
+            let encoder = if changes.len() == 1 {
+                C0CBlockChange {
+                    location: changes[0].position,
+                    block_id: changes[0].state.id() as i32,
+                }
+                .encode()
+            } else {
+                C10MultiBlockChange {
+                    chunk_x,
+                    chunk_z,
+                    records: changes
+                        .iter()
+                        .map(|change| MultiBlockChangeRecord {
+                            x: (change.position.x & 15) as u8,
+                            y: change.position.y as u8,
+                            z: (change.position.z & 15) as u8,
+                            block_id: change.state.id() as i32,
+                        })
+                        .collect(),
+                }
+                .encode()
+            };
+            for client in &mut self.clients {
+                if client.is_viewing_chunk(chunk_x, chunk_z) {
+                    client.send_packet(&encoder);
+                }
+            }
+        }
+    }
+
+    /// Sends the chunks within the view distance of the player
+    fn send_chunks(&mut self, client_index: usize) {
+        let client = &mut self.clients[client_index];
+        let position = match &client.player {
+            Some(player) => player.position.block_position(),
+            None => return,
+        };
+        let (center_x, center_z) = (position.x >> 4, position.z >> 4);
+        client.send_packet(
+            &C41UpdateViewPosition {
+                chunk_x: center_x,
+                chunk_z: center_z,
+            }
+            .encode(),
+        );
+        for chunk_x in center_x - VIEW_DISTANCE..=center_x + VIEW_DISTANCE {
+            for chunk_z in center_z - VIEW_DISTANCE..=center_z + VIEW_DISTANCE {
+                Server::send_chunk(&mut self.world, client, chunk_x, chunk_z);
+            }
+        }
+    }
+
+    fn send_chunk(world: &mut World, client: &mut Client, chunk_x: i32, chunk_z: i32) {
+        let chunk = world.chunk(chunk_x, chunk_z);
+        let chunk_encoder = C22ChunkData {
+            chunk_x,
+            chunk_z,
+            sections: chunk.sections(),
+            biomes: chunk.biomes(),
+        }
+        .encode(client.protocol);
+        client.send_packet(&chunk_encoder);
+    }
+
+    /// Sends the chunks that came into view after the player went into another chunk,
+    /// and unloads the ones that are out of view now
+    fn update_view(&mut self, client_index: usize, from: Location) {
+        let client = &mut self.clients[client_index];
+        let to = match &client.player {
+            Some(player) => player.position.block_position(),
+            None => return,
+        };
+        let from = from.block_position();
+        let (from_x, from_z) = (from.x >> 4, from.z >> 4);
+        let (to_x, to_z) = (to.x >> 4, to.z >> 4);
+        if (from_x, from_z) == (to_x, to_z) {
+            return;
+        }
+        client.send_packet(
+            &C41UpdateViewPosition {
+                chunk_x: to_x,
+                chunk_z: to_z,
+            }
+            .encode(),
+        );
+        let in_view = |chunk_x: i32, chunk_z: i32, center_x: i32, center_z: i32| {
+            (chunk_x - center_x).abs() <= VIEW_DISTANCE
+                && (chunk_z - center_z).abs() <= VIEW_DISTANCE
+        };
+        for chunk_x in from_x - VIEW_DISTANCE..=from_x + VIEW_DISTANCE {
+            for chunk_z in from_z - VIEW_DISTANCE..=from_z + VIEW_DISTANCE {
+                if !in_view(chunk_x, chunk_z, to_x, to_z) {
+                    client.send_packet(&C1EUnloadChunk { chunk_x, chunk_z }.encode());
+                }
+            }
+        }
+        for chunk_x in to_x - VIEW_DISTANCE..=to_x + VIEW_DISTANCE {
+            for chunk_z in to_z - VIEW_DISTANCE..=to_z + VIEW_DISTANCE {
+                if !in_view(chunk_x, chunk_z, from_x, from_z) {
+                    Server::send_chunk(&mut self.world, client, chunk_x, chunk_z);
+                }
+            }
+        }
+    }
+
+    fn send_recipes(&mut self, client: usize) {
+        let client = &mut self.clients[client];
+        let declare_encoder = C5BDeclareRecipes {
+            recipes: &self.recipes.recipes,
+        }
+        .encode(client.protocol);
+        client.send_packet(&declare_encoder);
+
+        let recipe_ids: Vec<String> = self
+            .recipes
+            .recipes
+            .iter()
+            .filter(|recipe| !matches!(recipe.kind, RecipeKind::Special))
+            .map(|recipe| recipe.id.clone())
+            .collect();
+        let unlock_encoder = C37UnlockRecipes {
+            action: UnlockRecipesAction::Init,
+            crafting_recipe_book_open: false,
+            crafting_recipe_book_filter_active: false,
+            smelting_recipe_book_open: false,
+            smelting_recipe_book_filter_active: false,
+            recipe_ids: recipe_ids.clone(),
+            recipe_ids_init: recipe_ids,
+        }
+        .encode();
+        client.send_packet(&unlock_encoder);
     }
 
     fn on_mojang_has_joined_response(&mut self, client_id: u32, result: MojangHasJoinedResponse) {
-        let client = self.get_client(client_id);
+        let index = match self.client_index(client_id) {
+            Some(index) => index,
+            None => return,
+        };
+        let uuid = match u128::from_str_radix(&result.id, 16) {
+            Ok(uuid) => uuid,
+            Err(_) => {
+                let reason =
+                    ChatComponent::translate("multiplayer.disconnect.unverified_username", vec![]);
+                self.clients[index].disconnect(reason);
+                return;
+            }
+        };
+        self.login(index, uuid, result.name);
+    }
+
+    /// Finishes logging in a client once its UUID is known, in online or offline mode
+    fn login(&mut self, client_index: usize, uuid: u128, username: String) {
+        if !utils::is_valid_username(&username) {
+            let reason = ChatComponent::new("Invalid characters in username".to_string());
+            self.clients[client_index].disconnect(reason);
+            return;
+        }
+        let client = &mut self.clients[client_index];
+        client.uuid = Some(uuid);
+        client.username = Some(username.clone());
+        if let Some(reason) = self.login_refusal(uuid) {
+            self.clients[client_index].disconnect(reason);
+            return;
+        }
+        // The player logged in again from somewhere else, so the old connection goes
+        for (index, client) in self.clients.iter_mut().enumerate() {
+            if index != client_index && client.uuid == Some(uuid) {
+                client.disconnect(ChatComponent::translate(
+                    "multiplayer.disconnect.duplicate_login",
+                    vec![],
+                ));
+            }
+        }
+        let player = self.world.load_player(username);
+        let max_players = self.config.max_players;
+        self.clients[client_index].join(uuid, player, max_players);
+        self.send_chunks(client_index);
+        self.on_player_join(client_index);
+    }
 
+    /// Why an authenticated player may not join, None if they may
+    fn login_refusal(&self, uuid: u128) -> Option<ChatComponent> {
+        let uuid = utils::format_uuid(uuid);
+        if let Some(ban) = self.user_lists.player_ban(&uuid) {
+            return Some(ban_message("multiplayer.disconnect.banned", &ban.ban));
+        }
+        let whitelisted =
+            self.user_lists.is_whitelisted(&uuid) || self.user_lists.operator(&uuid).is_some();
+        if self.config.whitelist && !whitelisted {
+            return Some(ChatComponent::translate(
+                "multiplayer.disconnect.not_whitelisted",
+                vec![],
+            ));
+        }
+        None
     }
 
     fn receive_packets(&mut self) {
@@ -257,18 +1696,74 @@ impl Server {
             let mut packets = self.clients[client]
                 .connection
                 .receive_packets();
-            for packet_batch in packets.drain(..) {
-                for packet in PacketDecoder::new_batch(packet_batch, &self.clients[client]) {
-                    println!("{}", utils::to_hex_string(&packet.buffer));
-                    self.handle_packet(client, packet.buffer);
+            for packet in packets.drain(..) {
+                if !self.clients[client].connection.alive {
+                    break;
+                }
+                if self.config.log_packets {
+                    debug!("{}", utils::to_hex_string(&packet));
                 }
+                if let Err(err) = self.handle_packet(client, packet) {
+                    warn!("Error handling packet: {}", err);
+                    let reason = ChatComponent::new(err.to_string());
+                    self.clients[client].disconnect(ChatComponent::translate(
+                        "disconnect.genericReason",
+                        vec![reason],
+                    ));
+                }
+            }
+        }
+        self.remove_disconnected_clients();
+    }
+
+    fn flush_clients(&mut self) {
+        for client in &mut self.clients {
+            client.connection.flush();
+        }
+        self.remove_disconnected_clients();
+    }
+
+    /// Removes the clients with closed connections and fires the quit event for each of them
+    fn remove_disconnected_clients(&mut self) {
+        let mut i = 0;
+        while i < self.clients.len() {
+            if self.clients[i].connection.alive {
+                i += 1;
+            } else {
+                let client = self.clients.remove(i);
+                self.on_client_quit(client);
+            }
+        }
+    }
+
+    fn on_client_quit(&mut self, client: Client) {
+        // TODO: Despawn the player for the other clients
+        if let (NetworkState::PLAY, Some(player)) = (client.state, client.player) {
+            let message = ChatComponent::translate(
+                "multiplayer.player.left",
+                vec![ChatComponent::new(player.username.clone())],
+            )
+            .color(ChatColor::Yellow);
+            let mut event = PlayerQuitEvent {
+                client_id: client.id,
+                username: player.username,
+                quit_message: Some(message),
+            };
+            self.fire_event(&mut event);
+            if let Some(message) = event.quit_message {
+                self.broadcast_chat(message, ChatPosition::System);
             }
         }
     }
 
+    fn receive_console_commands(&mut self) {
+        for command in self.console.receive_commands() {
+            self.execute_command(CommandSender::Console, &command);
+        }
+    }
+
     fn receive_clients(&mut self) {
-        let result = self.client_receiver.try_recv();
-        if let Ok(client) = result {
+        while let Ok(client) = self.client_receiver.try_recv() {
             self.clients.push(client);
         }
     }
@@ -280,33 +1775,685 @@ impl Server {
                 finished_indicies.push(i);
             }
         }
-        for index in finished_indicies {
+        // Removing from the back keeps the other indices valid
+        for index in finished_indicies.into_iter().rev() {
             let response = self.mojang.has_joined_pending.remove(index);
             self.on_mojang_has_joined_response(response.client_id, response.result.unwrap());
         }
         self.mojang.clean();
     }
 
-    fn start(mut self) {
-        println!("Listening for connections...");
-        //let mut last_tick_time = SystemTime::now();
-        loop {
-            /*let  now = SystemTime::now();
-            let time_since = now.duration_since(last_tick_time).unwrap().as_millis();
-            if time_since > 50 {
-                last_tick_time = now;
+    pub fn clients(&self) -> &[Client] {
+        &self.clients
+    }
+
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.world
+    }
+
+    pub fn client(&self, client_id: u32) -> Option<&Client> {
+        self.clients.iter().find(|client| client.id == client_id)
+    }
+
+    pub fn client_mut(&mut self, client_id: u32) -> Option<&mut Client> {
+        self.clients
+            .iter_mut()
+            .find(|client| client.id == client_id)
+    }
+
+    /// Runs the handlers of an event, returns false if one of them cancelled it
+    pub fn fire_event<E: Event>(&mut self, event: &mut E) -> bool {
+        for handler in self.events.handlers::<E>() {
+            handler(self, event);
+        }
+        !event.is_cancelled()
+    }
+
+    pub fn register_event_handler<E, F>(
+        &mut self,
+        plugin: &str,
+        priority: EventPriority,
+        handler: F,
+    ) where
+        E: Event,
+        F: Fn(&mut Server, &mut E) + 'static,
+    {
+        self.events.register(plugin, priority, handler);
+    }
+
+    /// Handles the messages clients send on a channel, and tells them the server listens on it.
+    /// Returns false if the channel name isn't valid.
+    pub fn listen_on_channel<F>(&mut self, plugin: &str, channel: &str, handler: F) -> bool
+    where
+        F: Fn(&mut Server, u32, &[u8]) + 'static,
+    {
+        let channel = match channels::channel_name(channel) {
+            Some(channel) if channel != channels::REGISTER && channel != channels::UNREGISTER => {
+                channel
+            }
+            _ => return false,
+        };
+        if self.channels.listen(plugin, channel, handler) {
+            self.broadcast_plugin_message(channels::REGISTER, channel.as_bytes().to_vec());
+        }
+        true
+    }
+
+    /// Sends a packet to a client that's playing, returns false if there's no such client
+    pub fn send_packet(&mut self, client_id: u32, encoder: &PacketEncoder) -> bool {
+        match self.client_mut(client_id) {
+            Some(client) if client.state == NetworkState::PLAY => {
+                client.send_packet(encoder);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Sends a plugin message to a client, returns false if the client doesn't listen on the channel
+    pub fn send_plugin_message(&mut self, client_id: u32, channel: &str, data: Vec<u8>) -> bool {
+        let channel = match channels::channel_name(channel) {
+            Some(channel) => channel,
+            None => return false,
+        };
+        if data.len() > channels::MAX_CLIENTBOUND_SIZE {
+            return false;
+        }
+        match self.client_mut(client_id) {
+            Some(client) if client.state == NetworkState::PLAY && client.is_listening(channel) => {
+                client.send_plugin_message(channel, data);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Sends a request to the proxy through any player, as the proxy only sees messages sent
+    /// through a player's connection. Returns false if nobody is online or the request is too long.
+    pub fn send_bungee_request(&mut self, request: &BungeeRequest) -> bool {
+        let data = match request.encode() {
+            Some(data) => data,
+            None => return false,
+        };
+        let client = self.clients.iter_mut().find(|client| {
+            client.state == NetworkState::PLAY && client.is_listening(channels::BUNGEECORD)
+        });
+        match client {
+            Some(client) => {
+                client.send_plugin_message(channels::BUNGEECORD, data);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn broadcast_plugin_message(&mut self, channel: &str, data: Vec<u8>) {
+        for client in &mut self.clients {
+            if client.state == NetworkState::PLAY && client.is_listening(channel) {
+                client.send_plugin_message(channel, data.clone());
             }
-            */
+        }
+    }
+
+    /// Adds a command that's removed again when the plugin is disabled
+    pub fn register_plugin_command(&mut self, plugin: &str, command: CommandBuilder<Server>) {
+        if let Some(name) = command.name() {
+            self.plugins.add_command(plugin, name);
+        }
+        self.commands.register(command);
+        self.resend_commands();
+    }
+
+    pub fn run_later<F>(&mut self, plugin: &str, ticks: u64, task: F) -> TaskId
+    where
+        F: FnOnce(&mut Server) + 'static,
+    {
+        self.scheduler
+            .run_later(Some(plugin), ticks, Box::new(task))
+    }
+
+    pub fn run_repeating<F>(&mut self, plugin: &str, delay: u64, period: u64, task: F) -> TaskId
+    where
+        F: FnMut(&mut Server) + 'static,
+    {
+        self.scheduler
+            .run_repeating(Some(plugin), delay, period, Box::new(task))
+    }
+
+    /// Runs a job on a worker thread, `then` gets its result on the main thread in a later tick.
+    /// Jobs without a plugin belong to the server and aren't cancelled when a plugin is disabled.
+    pub fn run_async<T, J, F>(&mut self, plugin: Option<&str>, job: J, then: F) -> TaskId
+    where
+        T: Send + 'static,
+        J: FnOnce() -> T + Send + 'static,
+        F: FnOnce(&mut Server, T) + 'static,
+    {
+        self.scheduler.run_async(plugin, job, then)
+    }
+
+    /// Cancels a task or job, returns false if it already finished
+    pub fn cancel_task(&mut self, id: TaskId) -> bool {
+        self.scheduler.cancel(id)
+    }
+
+    /// Adds a plugin to be enabled when the server starts
+    pub fn add_plugin(&mut self, plugin: Box<dyn Plugin>) {
+        self.plugins.add(plugin, None);
+    }
+
+    /// Loads the plugins in the plugins directory, plugins that can't be loaded are skipped
+    fn load_plugins(&mut self) {
+        let paths = match plugin_loader::plugin_files() {
+            Ok(paths) => paths,
+            Err(err) => {
+                error!(
+                    "Couldn't read the {} directory: {}",
+                    plugin_loader::PLUGINS_DIR,
+                    err
+                );
+                return;
+            }
+        };
+        for path in paths {
+            let result = plugin_loader::load(&path).and_then(|plugin| {
+                if self.plugins.index_of(plugin.name()).is_some() {
+                    Err(LoadError::Duplicate(plugin.name().to_string()))
+                } else {
+                    Ok(plugin)
+                }
+            });
+            match result {
+                Ok(plugin) => {
+                    info!(
+                        "Loaded {}",
+                        plugin::describe(plugin.name(), plugin.version())
+                    );
+                    self.plugins.add(Box::new(plugin), Some(path));
+                }
+                Err(err) => error!("Couldn't load {}: {}", path.display(), err),
+            }
+        }
+        self.load_changed_scripts();
+    }
+
+    /// Loads the scripts that are new or were edited and unloads the ones that were removed.
+    /// Scripts that can't be loaded keep running as they were. Returns the names of the new plugins.
+    fn load_changed_scripts(&mut self) -> Vec<String> {
+        let mut loaded = Vec::new();
+        for change in self.scripts.changes() {
+            let (path, script) = match change {
+                ScriptChange::Changed(path) => {
+                    let script = scripting::load(&path);
+                    (path, Some(script))
+                }
+                ScriptChange::Removed(path) => (path, None),
+            };
+            if let Some(Err(err)) = &script {
+                error!("Couldn't load {}: {}", path.display(), err);
+                continue;
+            }
+            if let Some(index) = self.plugins.index_of_path(&path) {
+                self.disable_plugin(index);
+                self.plugins.remove(index);
+            }
+            if let Some(Ok(script)) = script {
+                let name = script.name().to_string();
+                if self.plugins.index_of(&name).is_some() {
+                    error!(
+                        "Couldn't load {}: {}",
+                        path.display(),
+                        LoadError::Duplicate(name)
+                    );
+                    continue;
+                }
+                info!("Loaded script {}", name);
+                self.plugins.add(Box::new(script), Some(path));
+                loaded.push(name);
+            }
+        }
+        loaded
+    }
+
+    /// Disables every plugin, loads the plugins directory again and enables them all
+    pub(crate) fn reload_plugins(&mut self) {
+        for index in (0..self.plugins.len()).rev() {
+            self.disable_plugin(index);
+        }
+        // Dropping the plugins unloads their libraries
+        self.plugins.remove_loaded();
+        self.scripts.reset();
+        self.load_plugins();
+        for index in 0..self.plugins.len() {
+            self.enable_plugin(index);
+        }
+    }
+
+    /// Disables a plugin that failed at the end of the tick, as it can fail in the middle of anything
+    pub fn fail_plugin(&mut self, name: &str) {
+        self.plugins.fail(name);
+    }
+
+    fn enable_plugin(&mut self, index: usize) {
+        let mut plugin = match self.plugins.take(index) {
+            Some(plugin) => plugin,
+            None => return,
+        };
+        let name = plugin.name().to_string();
+        info!("Enabling {}", plugin::describe(&name, plugin.version()));
+        plugin.on_enable(&mut PluginContext::new(self, &name));
+        self.plugins.put_back(index, plugin, true);
+    }
+
+    /// Lets the plugin clean up, then removes its event handlers, channels, commands and tasks
+    fn disable_plugin(&mut self, index: usize) {
+        if !self.plugins.is_enabled(index) {
+            return;
+        }
+        let mut plugin = match self.plugins.take(index) {
+            Some(plugin) => plugin,
+            None => return,
+        };
+        let name = plugin.name().to_string();
+        info!("Disabling {}", plugin::describe(&name, plugin.version()));
+        plugin.on_disable(self);
+        self.events.unregister_plugin(&name);
+        let unused = self.channels.unregister_plugin(&name);
+        if !unused.is_empty() {
+            let unused: Vec<&str> = unused.iter().map(String::as_str).collect();
+            self.broadcast_plugin_message(
+                channels::UNREGISTER,
+                channels::encode_channel_list(&unused),
+            );
+        }
+        for command in self.plugins.take_commands(index) {
+            self.commands.unregister(&command);
+        }
+        self.scheduler.cancel_plugin(&name);
+        self.plugins.put_back(index, plugin, false);
+        self.resend_commands();
+    }
+
+    /// Runs the scheduled tasks that are due and disables the plugins that failed
+    pub(crate) fn run_tasks(&mut self) {
+        self.scheduler.tick();
+        while let Some(mut task) = self.scheduler.next_due() {
+            task.run(self);
+            self.scheduler.finish(task);
+        }
+        for name in self.plugins.take_failed() {
+            if let Some(index) = self.plugins.index_of(&name) {
+                self.disable_plugin(index);
+            }
+        }
+    }
+
+    fn reload_changed_files(&mut self) {
+        let ops_changed = self.user_lists.reload_changed();
+        if self.permissions.reload_if_changed() || ops_changed {
+            self.resend_commands();
+        }
+        for name in self.load_changed_scripts() {
+            if let Some(index) = self.plugins.index_of(&name) {
+                self.enable_plugin(index);
+            }
+        }
+    }
+
+    /// Writes the changed chunks on a worker thread, unless the last save is still being written
+    fn autosave(&mut self) {
+        if self.saving_world {
+            return;
+        }
+        self.saving_world = true;
+        let save = self.world.background_save();
+        self.run_async(
+            None,
+            move || save.write(),
+            |server: &mut Server, result: io::Result<()>| {
+                server.saving_world = false;
+                server.world.finish_background_save();
+                if let Err(err) = result {
+                    error!("Couldn't save the world: {}", err);
+                }
+            },
+        );
+    }
+
+    fn start(mut self) {
+        for index in 0..self.plugins.len() {
+            self.enable_plugin(index);
+        }
+        // Edits to the user lists, permissions and scripts take effect within a second
+        self.scheduler
+            .run_repeating(None, 0, 20, Box::new(Server::reload_changed_files));
+        self.scheduler.run_repeating(
+            None,
+            AUTOSAVE_PERIOD,
+            AUTOSAVE_PERIOD,
+            Box::new(Server::autosave),
+        );
+        info!("Listening for connections...");
+        while self.running {
+            let tick_start = Instant::now();
             self.receive_clients();
             self.receive_packets();
+            self.receive_console_commands();
+            self.tick_world();
+            self.run_tasks();
+            self.broadcast_block_changes();
             self.poll_mojang();
-            thread::sleep(Duration::from_millis(1));
+            self.flush_clients();
+            if let Some(remaining) = TICK_DURATION.checked_sub(tick_start.elapsed()) {
+                thread::sleep(remaining);
+            }
+        }
+        self.shutdown();
+    }
+
+    /// Disconnects everyone, waits for their last packets to be written and saves the world
+    fn shutdown(&mut self) {
+        info!("Stopping server");
+        for index in (0..self.plugins.len()).rev() {
+            self.disable_plugin(index);
         }
+        for client in &mut self.clients {
+            client.disconnect(ChatComponent::translate(
+                "multiplayer.disconnect.server_shutdown",
+                vec![],
+            ));
+        }
+        let shutdown_start = Instant::now();
+        while shutdown_start.elapsed() < SHUTDOWN_TIMEOUT
+            && !self
+                .clients
+                .iter()
+                .all(|client| client.connection.is_written())
+        {
+            thread::sleep(TICK_DURATION);
+        }
+        self.clients.clear();
+        // This also writes the chunks of a background save that may not have finished
+        info!("Saving the world");
+        if let Err(err) = self.world.save() {
+            error!("Couldn't save the world: {}", err);
+        }
+        self.console.stop();
     }
 }
 
-pub fn start_server() {
-    println!("Starting server...");
-    let server = Server::new();
+pub fn start_server(plugins: Vec<Box<dyn Plugin>>) {
+    // The error is logged once the logger is set up, which needs the config
+    let (config, config_error) = match ServerConfig::load(CONFIG_FILE) {
+        Ok(config) => (config, None),
+        Err(err) => (ServerConfig::default(), Some(err)),
+    };
+    let console = Console::start();
+    logging::init(&config.log_filter, console.writer());
+    if let Some(err) = config_error {
+        error!(
+            "Couldn't load {}, using the default config: {}",
+            CONFIG_FILE, err
+        );
+    }
+    if config.online_mode {
+        // Logging in would stop at the encryption response, so nobody could join
+        error!(
+            "online_mode isn't supported yet, as connections can't be encrypted. Set it to false in {} to start the server",
+            CONFIG_FILE
+        );
+        log::logger().flush();
+        return;
+    }
+    warn!("The server is in offline mode, so players aren't authenticated and anyone can join with any name");
+    warn!("Ops, the whitelist, bans and permissions go by offline UUIDs, which come from the name alone, and don't protect the server");
+    info!("Starting server...");
+    let mut server = Server::new(config, console);
+    for plugin in plugins {
+        server.add_plugin(plugin);
+    }
+    server.load_plugins();
     server.start();
+    log::logger().flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory::{Inventory, ItemStack};
+    use crate::registry::Item;
+    use std::io::Read;
+    use std::net;
+
+    /// A client connected over loopback, with the other end of its socket
+    fn connect(runtime: &Runtime) -> (Client, net::TcpStream) {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let remote = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, address) = listener.accept().unwrap();
+        let client = runtime.enter(|| {
+            let stream = TcpStream::from_std(stream).unwrap();
+            Client::new(stream, address, 7, 1024 * 1024)
+        });
+        remote
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        (client, remote)
+    }
+
+    fn frame(encoder: PacketEncoder, state: NetworkState, protocol: ProtocolVersion) -> Vec<u8> {
+        encoder.finalize(false, &None, state, protocol)
+    }
+
+    #[test]
+    fn login() {
+        let runtime = Runtime::new().unwrap();
+        let (mut client, mut remote) = connect(&runtime);
+        client.state = NetworkState::LOGIN;
+        let uuid = utils::offline_uuid("Notch");
+        let location = Location::new(0.5, 4.0, 0.5, 90.0, 0.0);
+        let player = Player {
+            username: "Notch".to_string(),
+            position: location,
+            inventory: Inventory::new(),
+            gamemode: Gamemode::Creative,
+            selected_slot: 0,
+            digging: None,
+        };
+        client.join(uuid, player, 300);
+        client.connection.flush();
+
+        assert_eq!(client.state, NetworkState::PLAY);
+        assert!(client.teleport_pending);
+        assert_eq!(client.player().unwrap().username, "Notch");
+
+        let protocol = ProtocolVersion::LATEST;
+        let mut expected = frame(
+            C02LoginSuccess {
+                uuid: "b50ad385-829d-3141-a216-7e7d7539ba7f".to_string(),
+                username: "Notch".to_string(),
+            }
+            .encode(),
+            NetworkState::LOGIN,
+            protocol,
+        );
+        let join_game = C26JoinGame {
+            entity_id: 7,
+            gamemode: 1,
+            dimension: 0,
+            hashed_seed: 0,
+            max_players: 255,
+            level_type: "flat".to_string(),
+            view_distance: VIEW_DISTANCE,
+            reduced_debug_info: false,
+            enable_respawn_screen: true,
+        };
+        let abilities = C32PlayerAbilities {
+            flags: Gamemode::Creative.ability_flags(),
+            flying_speed: 0.05,
+            field_of_view_modifier: 0.1,
+        };
+        let position = C36PlayerPositionAndLook {
+            x: location.x,
+            y: location.y,
+            z: location.z,
+            yaw: location.yaw,
+            pitch: location.pitch,
+            flags: 0,
+            teleport_id: 1,
+        };
+        for encoder in [
+            join_game.encode(protocol),
+            abilities.encode(),
+            position.encode(),
+        ] {
+            expected.extend(frame(encoder, NetworkState::PLAY, protocol));
+        }
+
+        let mut received = vec![0; expected.len()];
+        remote.read_exact(&mut received).unwrap();
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn resend_inventory() {
+        let runtime = Runtime::new().unwrap();
+        let (mut client, mut remote) = connect(&runtime);
+        client.state = NetworkState::PLAY;
+        let mut inventory = Inventory::new();
+        inventory.cursor = Some(ItemStack::new(Item::Stone, 3));
+        client.player = Some(Player {
+            username: "Notch".to_string(),
+            position: Location::new(0.5, 4.0, 0.5, 0.0, 0.0),
+            inventory,
+            gamemode: Gamemode::Creative,
+            selected_slot: 0,
+            digging: None,
+        });
+        client.resend_inventory();
+        client.connection.flush();
+
+        let protocol = ProtocolVersion::LATEST;
+        let mut expected = frame(
+            C15WindowItems {
+                window_id: 0,
+                slot_data: Inventory::new().window_slots(0),
+            }
+            .encode(protocol),
+            NetworkState::PLAY,
+            protocol,
+        );
+        expected.extend(frame(
+            C17SetSlot {
+                window_id: -1,
+                slot: -1,
+                slot_data: Some(ItemStack::new(Item::Stone, 3)),
+            }
+            .encode(protocol),
+            NetworkState::PLAY,
+            protocol,
+        ));
+
+        let mut received = vec![0; expected.len()];
+        remote.read_exact(&mut received).unwrap();
+        assert_eq!(received, expected);
+    }
+
+    /// Reads the next packet the server sent, as its id and data
+    fn read_packet(remote: &mut net::TcpStream) -> (u8, Vec<u8>) {
+        let mut length = 0;
+        for shift in (0..).step_by(7) {
+            let mut byte = [0];
+            remote.read_exact(&mut byte).unwrap();
+            length |= ((byte[0] & 0x7F) as usize) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut packet = vec![0; length];
+        remote.read_exact(&mut packet).unwrap();
+        (packet[0], packet.split_off(1))
+    }
+
+    /// Adds a client that's playing at the spawn to the server
+    fn join(server: &mut Server) -> net::TcpStream {
+        let (mut client, remote) = connect(&server.runtime);
+        client.state = NetworkState::PLAY;
+        client.player = Some(Player {
+            username: "Notch".to_string(),
+            position: Location::new(0.5, 4.0, 0.5, 0.0, 0.0),
+            inventory: Inventory::new(),
+            gamemode: Gamemode::Creative,
+            selected_slot: 0,
+            digging: None,
+        });
+        server.clients.push(client);
+        remote
+    }
+
+    #[test]
+    fn teleport_sends_chunks() {
+        let mut server = Server::unstarted();
+        let mut remote = join(&mut server);
+        assert!(server.teleport(7, Location::new(16.5, 4.0, 0.5, 0.0, 0.0)));
+        assert!(!server.teleport(8, Location::new(16.5, 4.0, 0.5, 0.0, 0.0)));
+        server.clients[0].connection.flush();
+
+        assert_eq!(read_packet(&mut remote).0, 0x36);
+        assert_eq!(read_packet(&mut remote), (0x41, vec![1, 0]));
+        // The column of chunks left behind is unloaded and the one ahead is sent
+        for chunk_z in -VIEW_DISTANCE..=VIEW_DISTANCE {
+            let (id, data) = read_packet(&mut remote);
+            assert_eq!(id, 0x1E);
+            assert_eq!(data[..4], (-VIEW_DISTANCE).to_be_bytes());
+            assert_eq!(data[4..], chunk_z.to_be_bytes());
+        }
+        for chunk_z in -VIEW_DISTANCE..=VIEW_DISTANCE {
+            let (id, data) = read_packet(&mut remote);
+            assert_eq!(id, 0x22);
+            assert_eq!(data[..4], (VIEW_DISTANCE + 1).to_be_bytes());
+            assert_eq!(data[4..8], chunk_z.to_be_bytes());
+        }
+    }
+
+    #[test]
+    fn tab_complete_counts_utf16() {
+        let mut server = Server::unstarted();
+        server
+            .commands
+            .register(commands::literal("grüße").then(commands::argument(
+                "targets",
+                commands::ArgumentType::GameProfile,
+            )));
+        let mut remote = join(&mut server);
+        server.handle_tab_complete(
+            0,
+            S06TabComplete {
+                transaction_id: 3,
+                text: "/grüße N".to_string(),
+            },
+        );
+        server.clients[0].connection.flush();
+        let (id, data) = read_packet(&mut remote);
+        assert_eq!(id, 0x11);
+        // "grüße " is 8 bytes but 6 UTF-16 code units, and the slash comes before it
+        assert_eq!(data[..3], [3, 7, 1]);
+    }
+
+    #[test]
+    fn status_counts_players() {
+        let mut server = Server::unstarted();
+        join(&mut server);
+        join(&mut server);
+        server.clients[1].state = NetworkState::LOGIN;
+        let (mut client, mut remote) = connect(&server.runtime);
+        client.state = NetworkState::STATUS;
+        server.clients.push(client);
+        // A Request packet, which is just its id
+        server.handle_packet(2, vec![1, 0]).unwrap();
+        server.clients[2].connection.flush();
+        let (id, data) = read_packet(&mut remote);
+        assert_eq!(id, 0x00);
+        // The JSON is prefixed with its length as a two byte VarInt
+        let response: serde_json::Value = serde_json::from_slice(&data[2..]).unwrap();
+        assert_eq!(response["players"]["online"], 1);
+    }
 }
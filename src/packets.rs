@@ -1,5 +1,15 @@
+use crate::commands::{ArgumentType, DeclaredNode, StringKind};
+use crate::inventory::{ItemStack, Slot};
 use crate::network::Client;
+use crate::protocol::ProtocolVersion;
+use crate::recipes::{Ingredient, Recipe, RecipeKind};
+use crate::registry::Item;
+use crate::utils::BlockPosition;
+use packet_derive::Packet;
 use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+use std::string::FromUtf8Error;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum NetworkState {
@@ -20,185 +30,403 @@ type Long = i64;
 type Float = f32;
 type Double = f64;
 type Chat = String; // Max length of 32767
-type Indentifier = String; // Max length of 32767
+type Identifier = String; // Max length of 32767
 type VarInt = i32;
-type VarLong = i32;
+type VarLong = i64;
 type UUID = u128;
+/// In degrees, sent as steps of 1/256 of a full turn
+type Angle = f32;
 type ByteArray = Vec<u8>;
+type Position = BlockPosition;
+/// The rest of the packet, without a length prefix
+type RemainingBytes = Vec<u8>;
+
+/// The longest string the protocol allows, in UTF-16 code units
+const MAX_STRING_LENGTH: usize = 32767;
+/// The largest packet length that fits into a three byte VarInt
+const MAX_PACKET_LENGTH: i32 = 2097151;
+/// How deep NBT compounds and lists can be nested, same as vanilla
+const MAX_NBT_DEPTH: u32 = 512;
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    /// The packet ended before the value being read
+    Truncated,
+    VarIntTooLong,
+    InvalidUtf8(FromUtf8Error),
+    StringTooLong {
+        length: usize,
+        max: usize,
+    },
+    /// A negative length, or one longer than the rest of the packet
+    InvalidLength(i64),
+    InvalidNbt,
+    InvalidIdentifier(String),
+    /// A value that doesn't belong to an enum like `NetworkState`
+    InvalidEnum {
+        name: &'static str,
+        value: i32,
+    },
+    /// An item id that isn't in the item registry
+    UnknownItem(i32),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Truncated => write!(f, "Packet is truncated"),
+            ProtocolError::VarIntTooLong => write!(f, "VarInt is too big"),
+            ProtocolError::InvalidUtf8(err) => write!(f, "String is not valid UTF-8: {}", err),
+            ProtocolError::StringTooLong { length, max } => {
+                write!(f, "String is too long ({} > {})", length, max)
+            }
+            ProtocolError::InvalidLength(length) => write!(f, "Invalid length: {}", length),
+            ProtocolError::InvalidNbt => write!(f, "Invalid NBT"),
+            ProtocolError::InvalidIdentifier(identifier) => {
+                write!(f, "Invalid identifier: {}", identifier)
+            }
+            ProtocolError::InvalidEnum { name, value } => write!(f, "Invalid {}: {}", name, value),
+            ProtocolError::UnknownItem(id) => write!(f, "Unknown item id: {}", id),
+        }
+    }
+}
+
+impl Error for ProtocolError {}
+
+impl From<FromUtf8Error> for ProtocolError {
+    fn from(err: FromUtf8Error) -> ProtocolError {
+        ProtocolError::InvalidUtf8(err)
+    }
+}
+
+pub type ProtocolResult<T> = Result<T, ProtocolError>;
+
+/// Whether a string is a `namespace:path` identifier, the namespace can be left out
+pub fn is_valid_identifier(identifier: &str) -> bool {
+    let (namespace, path) = match identifier.find(':') {
+        Some(index) => (&identifier[..index], &identifier[index + 1..]),
+        None => ("minecraft", identifier),
+    };
+    let valid_namespace = namespace
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "_-.".contains(c));
+    let valid_path = path
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "_-./".contains(c));
+    valid_namespace && valid_path
+}
 
 pub struct PacketDecoder {
     pub buffer: PacketBuffer,
     pub packet_id: i32,
     pub length: i32,
     i: usize,
+    /// The version the packet was sent with, for the ids in it
+    protocol: ProtocolVersion,
 }
 
 impl PacketDecoder {
-    pub fn new_batch(buffer: PacketBuffer, client: &Client) -> Vec<PacketDecoder> {
-        let mut decoders = Vec::new();
-        let mut next = buffer;
-        loop {
-            if client.shared_secret.is_some() {
-                // TODO: Protocol Encryption
-            }
-            let mut decoder = PacketDecoder {
-                buffer: next,
-                i: 0,
-                length: 0,
-                packet_id: 0,
-            };
-            decoder.length = decoder.read_varint();
-            let length_of_length = decoder.i;
-
-            decoder.packet_id = decoder.read_varint();
-            let packet_id_length = decoder.i - length_of_length;
-
-            if (decoder.buffer.len() - length_of_length) > decoder.length as usize {
-                let buffer_clone = decoder.buffer.clone();
-                let (new_buffer, other_packets) =
-                    buffer_clone.split_at(decoder.length as usize + decoder.i - packet_id_length);
-                decoder.buffer = Vec::from(new_buffer);
-                decoders.push(decoder);
-                next = other_packets.to_vec();
-            } else {
-                decoders.push(decoder);
-                break;
-            }
+    /// The length of the first packet in the buffer including its length prefix,
+    /// or None if the buffer doesn't hold the complete packet yet
+    pub fn frame_length(buffer: &[u8]) -> ProtocolResult<Option<usize>> {
+        let mut decoder = PacketDecoder {
+            // TODO: Avoid copying the prefix
+            buffer: buffer[..buffer.len().min(3)].to_vec(),
+            i: 0,
+            length: 0,
+            packet_id: 0,
+            protocol: ProtocolVersion::LATEST,
+        };
+        let length = match decoder.read_varint() {
+            Ok(length) => length,
+            Err(ProtocolError::Truncated) if buffer.len() < 3 => return Ok(None),
+            // Packets can't be longer than a three byte VarInt
+            Err(ProtocolError::Truncated) => return Err(ProtocolError::VarIntTooLong),
+            Err(err) => return Err(err),
+        };
+        if length <= 0 || length > MAX_PACKET_LENGTH {
+            return Err(ProtocolError::InvalidLength(length as i64));
+        }
+        let frame_length = decoder.i + length as usize;
+        if buffer.len() < frame_length {
+            Ok(None)
+        } else {
+            Ok(Some(frame_length))
         }
-        decoders
     }
 
-    pub fn new(buffer: PacketBuffer, client: &Client) -> PacketDecoder {
+    pub fn new(buffer: PacketBuffer, client: &Client) -> ProtocolResult<PacketDecoder> {
         let mut decoder = PacketDecoder {
             buffer,
             i: 0,
             length: 0,
             packet_id: 0,
+            protocol: client.protocol,
         };
 
-        decoder.length = decoder.read_varint();
+        decoder.length = decoder.read_varint()?;
 
         // TODO: compression
-        decoder.packet_id = decoder.read_varint();
+        decoder.packet_id = decoder.read_varint()?;
 
         if client.shared_secret.is_some() {
             // TODO: Protocol Encryption
         }
 
-        decoder
+        Ok(decoder)
     }
 
-    fn read_ubyte(&mut self) -> u8 {
-        self.i += 1;
-        self.buffer[self.i - 1]
+    /// Makes sure a length read from the packet fits into the rest of the packet
+    fn check_length(&self, length: i64) -> ProtocolResult<usize> {
+        if length < 0 || length as usize > self.buffer.len() - self.i {
+            Err(ProtocolError::InvalidLength(length))
+        } else {
+            Ok(length as usize)
+        }
     }
 
-    fn read_byte(&mut self) -> i8 {
-        self.i += 1;
-        self.buffer[self.i - 1] as i8
+    fn take(&mut self, bytes: usize) -> ProtocolResult<&[u8]> {
+        if bytes > self.buffer.len() - self.i {
+            return Err(ProtocolError::Truncated);
+        }
+        self.i += bytes;
+        Ok(&self.buffer[self.i - bytes..self.i])
     }
 
-    fn read_bytes(&mut self, bytes: usize) -> Vec<u8> {
-        let out = &self.buffer[self.i..self.i + bytes];
-        self.i += bytes;
-        out.to_vec()
+    fn read_ubyte(&mut self) -> ProtocolResult<u8> {
+        Ok(self.take(1)?[0])
     }
 
-    fn read_long(&mut self) -> i64 {
-        let mut arr = [0; 8];
-        arr.copy_from_slice(&self.buffer[self.i..self.i + 8]);
-        let out = i64::from_be_bytes(arr);
-        self.i += 8;
-        out
+    fn read_byte(&mut self) -> ProtocolResult<i8> {
+        Ok(self.read_ubyte()? as i8)
     }
 
-    fn read_int(&mut self) -> i32 {
-        let mut arr = [0; 4];
-        arr.copy_from_slice(&self.buffer[self.i..self.i + 4]);
-        let out = i32::from_be_bytes(arr);
-        self.i += 4;
-        out
+    fn read_bytes(&mut self, bytes: usize) -> ProtocolResult<Vec<u8>> {
+        Ok(self.take(bytes)?.to_vec())
     }
 
-    fn read_bool(&mut self) -> bool {
-        let out;
-        if self.buffer[self.i] == 1 {
-            out = true;
-        } else {
-            out = false;
-        }
-        self.i += 1;
-        out
+    fn read_remaining_bytes(&mut self) -> ProtocolResult<Vec<u8>> {
+        self.read_bytes(self.buffer.len() - self.i)
+    }
+
+    fn read_long(&mut self) -> ProtocolResult<i64> {
+        Ok(i64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn read_int(&mut self) -> ProtocolResult<i32> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into().unwrap()))
     }
 
-    fn read_varint(&mut self) -> i32 {
+    fn read_bool(&mut self) -> ProtocolResult<bool> {
+        Ok(self.read_ubyte()? == 1)
+    }
+
+    fn read_varint(&mut self) -> ProtocolResult<i32> {
         let mut num_read = 0;
         let mut result = 0i32;
         let mut read;
         loop {
-            read = self.read_byte() as u8;
+            if num_read == 5 {
+                return Err(ProtocolError::VarIntTooLong);
+            }
+            read = self.read_ubyte()?;
             let value = (read & 0b01111111) as i32;
             result |= value << (7 * num_read);
 
             num_read += 1;
-            if num_read > 5 {
-                panic!("VarInt is too big!");
-            }
             if read & 0b10000000 == 0 {
                 break;
             }
         }
-        return result;
+        Ok(result)
     }
 
-    fn read_varlong(&mut self) -> i64 {
+    fn read_varlong(&mut self) -> ProtocolResult<i64> {
         let mut num_read = 0;
         let mut result = 0i64;
         let mut read;
         loop {
-            read = self.read_byte() as u8;
+            if num_read == 10 {
+                return Err(ProtocolError::VarIntTooLong);
+            }
+            read = self.read_ubyte()?;
             let value = (read & 0b01111111) as i64;
             result |= value << (7 * num_read);
 
             num_read += 1;
-            if num_read > 5 {
-                panic!("VarInt is too big!");
-            }
             if read & 0b10000000 == 0 {
                 break;
             }
         }
-        return result;
+        Ok(result)
     }
 
-    fn read_string(&mut self) -> String {
-        let length = self.read_varint();
-        String::from_utf8(self.read_bytes(length as usize)).unwrap()
+    fn read_string(&mut self) -> ProtocolResult<String> {
+        let length = self.read_varint()?;
+        // Every UTF-16 code unit takes at most 3 bytes of UTF-8
+        if length as usize > MAX_STRING_LENGTH * 3 {
+            return Err(ProtocolError::StringTooLong {
+                length: length as usize,
+                max: MAX_STRING_LENGTH,
+            });
+        }
+        let length = self.check_length(length as i64)?;
+        let string = String::from_utf8(self.read_bytes(length)?)?;
+        let length = string.encode_utf16().count();
+        if length > MAX_STRING_LENGTH {
+            return Err(ProtocolError::StringTooLong {
+                length,
+                max: MAX_STRING_LENGTH,
+            });
+        }
+        Ok(string)
+    }
+
+    /// A string in the form of `namespace:path`, where the namespace defaults to `minecraft`
+    fn read_identifier(&mut self) -> ProtocolResult<String> {
+        let identifier = self.read_string()?;
+        if is_valid_identifier(&identifier) {
+            Ok(identifier)
+        } else {
+            Err(ProtocolError::InvalidIdentifier(identifier))
+        }
     }
 
-    fn read_ushort(&mut self) -> u16 {
-        let mut arr = [0; 2];
-        arr.copy_from_slice(&self.buffer[self.i..self.i + 2]);
-        let out = u16::from_be_bytes(arr);
-        self.i += 2;
-        out
+    fn read_ushort(&mut self) -> ProtocolResult<u16> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn read_short(&mut self) -> ProtocolResult<i16> {
+        Ok(self.read_ushort()? as i16)
+    }
+
+    fn read_float(&mut self) -> ProtocolResult<f32> {
+        Ok(f32::from_bits(self.read_int()? as u32))
+    }
+
+    fn read_double(&mut self) -> ProtocolResult<f64> {
+        Ok(f64::from_bits(self.read_long()? as u64))
+    }
+
+    fn read_uuid(&mut self) -> ProtocolResult<u128> {
+        Ok(u128::from_be_bytes(self.take(16)?.try_into().unwrap()))
+    }
+
+    fn read_angle(&mut self) -> ProtocolResult<f32> {
+        Ok(self.read_ubyte()? as f32 * 360.0 / 256.0)
+    }
+
+    fn read_position(&mut self) -> ProtocolResult<BlockPosition> {
+        let val = self.read_long()?;
+        Ok(BlockPosition {
+            x: (val >> 38) as i32,
+            y: (val << 52 >> 52) as i32,
+            z: (val << 26 >> 38) as i32,
+        })
+    }
+
+    fn read_slot(&mut self) -> ProtocolResult<Slot> {
+        if !self.read_bool()? {
+            return Ok(None);
+        }
+        let item_id = self.read_varint()?;
+        let item = Item::from_protocol_id(item_id, self.protocol)
+            .ok_or(ProtocolError::UnknownItem(item_id))?;
+        let count = self.read_byte()?;
+        let nbt_start = self.i;
+        let nbt = if self.read_ubyte()? == 0 {
+            None
+        } else {
+            self.skip_string_nbt()?;
+            self.skip_nbt_payload(10, 0)?;
+            Some(self.buffer[nbt_start..self.i].to_vec())
+        };
+        Ok(Some(ItemStack { item, count, nbt }))
+    }
+
+    fn skip_string_nbt(&mut self) -> ProtocolResult<()> {
+        let length = self.read_ushort()? as usize;
+        self.take(length)?;
+        Ok(())
+    }
+
+    /// Skips over the payload of an NBT tag, leaving the raw bytes in the buffer
+    fn skip_nbt_payload(&mut self, tag_type: u8, depth: u32) -> ProtocolResult<()> {
+        if depth > MAX_NBT_DEPTH {
+            return Err(ProtocolError::InvalidNbt);
+        }
+        match tag_type {
+            1 => self.skip(1)?,
+            2 => self.skip(2)?,
+            3 | 5 => self.skip(4)?,
+            4 | 6 => self.skip(8)?,
+            7 => {
+                let length = self.read_int()? as i64;
+                self.skip(self.check_length(length)?)?;
+            }
+            8 => self.skip_string_nbt()?,
+            9 => {
+                let list_type = self.read_ubyte()?;
+                let length = self.read_int()?;
+                for _ in 0..length {
+                    self.skip_nbt_payload(list_type, depth + 1)?;
+                }
+            }
+            10 => loop {
+                let entry_type = self.read_ubyte()?;
+                if entry_type == 0 {
+                    break;
+                }
+                self.skip_string_nbt()?;
+                self.skip_nbt_payload(entry_type, depth + 1)?;
+            },
+            11 => {
+                let length = self.read_int()? as i64;
+                self.skip(self.check_length(length * 4)?)?;
+            }
+            12 => {
+                let length = self.read_int()? as i64;
+                self.skip(self.check_length(length * 8)?)?;
+            }
+            _ => return Err(ProtocolError::InvalidNbt),
+        }
+        Ok(())
+    }
+
+    fn skip(&mut self, bytes: usize) -> ProtocolResult<()> {
+        self.take(bytes)?;
+        Ok(())
     }
 }
 
 pub struct PacketEncoder {
     buffer: PacketBuffer,
-    packet_id: u8,
+    packet_id: i32,
 }
 
 impl PacketEncoder {
-    fn new(packet_id: u8) -> PacketEncoder {
+    fn new(packet_id: i32) -> PacketEncoder {
         PacketEncoder {
             buffer: PacketBuffer::new(),
             packet_id,
         }
     }
 
-    pub fn finalize(&self, compressed: bool, encryption_key: &Option<Vec<u8>>) -> Vec<u8> {
+    /// A packet with data that's already encoded, like one a plugin built. The id is the
+    /// one the latest version uses, it's mapped for older clients like every other packet.
+    pub fn raw(packet_id: i32, data: Vec<u8>) -> PacketEncoder {
+        let mut encoder = PacketEncoder::new(packet_id);
+        encoder.write_remaining_bytes(data);
+        encoder
+    }
+
+    pub fn finalize(
+        &self,
+        compressed: bool,
+        encryption_key: &Option<Vec<u8>>,
+        state: NetworkState,
+        protocol: ProtocolVersion,
+    ) -> Vec<u8> {
         let mut dummy_encoder = PacketEncoder::new(0);
         let mut out;
 
@@ -206,7 +434,7 @@ impl PacketEncoder {
             out = vec![];
         } else {
             let mut packet_id_encoder = PacketEncoder::new(0);
-            packet_id_encoder.write_varint(self.packet_id as i32);
+            packet_id_encoder.write_varint(protocol.clientbound_id(state, self.packet_id));
             dummy_encoder
                 .write_varint(self.buffer.len() as i32 + packet_id_encoder.buffer.len() as i32);
             out = dummy_encoder.buffer.clone();
@@ -232,6 +460,10 @@ impl PacketEncoder {
         self.buffer.append(bytes);
     }
 
+    fn write_remaining_bytes(&mut self, mut bytes: Vec<u8>) {
+        self.write_bytes(&mut bytes);
+    }
+
     fn write_long(&mut self, long: i64) {
         self.write_bytes(&mut long.to_be_bytes().to_vec());
     }
@@ -255,10 +487,12 @@ impl PacketEncoder {
         self.write_bytes(&mut ushort.to_be_bytes().to_vec());
     }
 
-    fn write_varint(&mut self, mut value: i32) {
+    fn write_varint(&mut self, value: i32) {
+        // Shift the bits of negative numbers like unsigned ones
+        let mut value = value as u32;
         loop {
-            let mut temp = (value & 0b11111111) as u8;
-            value = value >> 7;
+            let mut temp = (value & 0b01111111) as u8;
+            value >>= 7;
             if value != 0 {
                 temp |= 0b10000000;
             }
@@ -269,10 +503,11 @@ impl PacketEncoder {
         }
     }
 
-    fn write_varlong(&mut self, mut value: i64) {
+    fn write_varlong(&mut self, value: i64) {
+        let mut value = value as u64;
         loop {
-            let mut temp = (value & 0b11111111) as u8;
-            value = value >> 7;
+            let mut temp = (value & 0b01111111) as u8;
+            value >>= 7;
             if value != 0 {
                 temp |= 0b10000000;
             }
@@ -287,139 +522,1274 @@ impl PacketEncoder {
         self.write_varint(string.len().try_into().unwrap());
         self.write_bytes(&mut string.as_bytes().to_vec());
     }
+
+    fn write_float(&mut self, float: f32) {
+        self.write_bytes(&mut float.to_be_bytes().to_vec());
+    }
+
+    fn write_identifier(&mut self, identifier: String) {
+        self.write_string(identifier);
+    }
+
+    fn write_angle(&mut self, angle: f32) {
+        // Wraps around like vanilla, so -90 degrees becomes 192
+        self.write_ubyte((angle * 256.0 / 360.0).floor() as i32 as u8);
+    }
+
+    fn write_double(&mut self, double: f64) {
+        self.write_bytes(&mut double.to_be_bytes().to_vec());
+    }
+
+    fn write_uuid(&mut self, uuid: u128) {
+        self.write_bytes(&mut uuid.to_be_bytes().to_vec());
+    }
+
+    fn write_position(&mut self, position: &BlockPosition) {
+        self.write_long(
+            ((position.x as i64 & 0x3FFFFFF) << 38)
+                | ((position.z as i64 & 0x3FFFFFF) << 12)
+                | (position.y as i64 & 0xFFF),
+        );
+    }
+
+    /// Items the client's version doesn't have are sent as empty slots
+    fn write_slot(&mut self, slot: &Slot, protocol: ProtocolVersion) {
+        let stack_and_id = slot
+            .as_ref()
+            .and_then(|stack| Some((stack, stack.item.protocol_id(protocol)?)));
+        match stack_and_id {
+            Some((stack, item_id)) => {
+                self.write_bool(true);
+                self.write_varint(item_id);
+                self.write_byte(stack.count);
+                match &stack.nbt {
+                    Some(nbt) => self.write_bytes(&mut nbt.clone()),
+                    None => self.write_ubyte(0),
+                }
+            }
+            None => self.write_bool(false),
+        }
+    }
+
+    fn write_argument_type(&mut self, parser: &ArgumentType) {
+        self.write_identifier(parser.identifier().to_string());
+        match parser {
+            ArgumentType::Integer { min, max } => {
+                self.write_byte(min.is_some() as i8 | (max.is_some() as i8) << 1);
+                if let Some(min) = min {
+                    self.write_int(*min);
+                }
+                if let Some(max) = max {
+                    self.write_int(*max);
+                }
+            }
+            ArgumentType::String(kind) => self.write_varint(match kind {
+                StringKind::Word => 0,
+                StringKind::Phrase => 1,
+                StringKind::Greedy => 2,
+            }),
+            ArgumentType::Entity {
+                single,
+                players_only,
+            } => self.write_byte(*single as i8 | (*players_only as i8) << 1),
+            _ => {}
+        }
+    }
+
+    fn write_ingredient(&mut self, ingredient: &Ingredient, protocol: ProtocolVersion) {
+        let items: Vec<Item> = ingredient
+            .items
+            .iter()
+            .copied()
+            .filter(|item| item.protocol_id(protocol).is_some())
+            .collect();
+        self.write_varint(items.len() as i32);
+        for item in items {
+            self.write_slot(&Some(ItemStack::new(item, 1)), protocol);
+        }
+    }
+
+    fn write_recipe(&mut self, recipe: &Recipe, protocol: ProtocolVersion) {
+        self.write_identifier(recipe.recipe_type.clone());
+        self.write_identifier(recipe.id.clone());
+        match &recipe.kind {
+            RecipeKind::Shaped {
+                width,
+                height,
+                ingredients,
+                result,
+            } => {
+                self.write_varint(*width as i32);
+                self.write_varint(*height as i32);
+                self.write_string(recipe.group.clone());
+                for ingredient in ingredients {
+                    self.write_ingredient(ingredient, protocol);
+                }
+                self.write_slot(&Some(result.clone()), protocol);
+            }
+            RecipeKind::Shapeless {
+                ingredients,
+                result,
+            } => {
+                self.write_string(recipe.group.clone());
+                self.write_varint(ingredients.len() as i32);
+                for ingredient in ingredients {
+                    self.write_ingredient(ingredient, protocol);
+                }
+                self.write_slot(&Some(result.clone()), protocol);
+            }
+            RecipeKind::Cooking {
+                ingredient,
+                result,
+                experience,
+                cooking_time,
+            } => {
+                self.write_string(recipe.group.clone());
+                self.write_ingredient(ingredient, protocol);
+                self.write_slot(&Some(result.clone()), protocol);
+                self.write_float(*experience);
+                self.write_varint(*cooking_time);
+            }
+            RecipeKind::Stonecutting { ingredient, result } => {
+                self.write_string(recipe.group.clone());
+                self.write_ingredient(ingredient, protocol);
+                self.write_slot(&Some(result.clone()), protocol);
+            }
+            RecipeKind::Special => {}
+        }
+    }
+}
+
+/// Implemented by `#[derive(Packet)]`
+pub trait Packet {
+    /// The id of the packet in the latest protocol version
+    const ID: i32;
+    const STATE: NetworkState;
+}
+
+/// A field type without a type alias that derived packets can read and write
+pub trait PacketField: Sized {
+    fn write(self, encoder: &mut PacketEncoder);
+    fn read(decoder: &mut PacketDecoder) -> ProtocolResult<Self>;
+}
+
+impl PacketField for NetworkState {
+    fn write(self, encoder: &mut PacketEncoder) {
+        encoder.write_varint(match self {
+            NetworkState::HANDSHAKING => 0,
+            NetworkState::STATUS => 1,
+            NetworkState::LOGIN => 2,
+            NetworkState::PLAY => 3,
+        });
+    }
+
+    fn read(decoder: &mut PacketDecoder) -> ProtocolResult<NetworkState> {
+        match decoder.read_varint()? {
+            1 => Ok(NetworkState::STATUS),
+            2 => Ok(NetworkState::LOGIN),
+            value => Err(ProtocolError::InvalidEnum {
+                name: "next network state",
+                value,
+            }),
+        }
+    }
 }
 
 // CLIENT BOUND
 
+#[derive(Packet)]
+#[packet(id = 0x00, state = Status, bound = Client)]
 pub struct C00Response {
     pub json_response: String,
 }
 
-impl C00Response {
+#[derive(Packet)]
+#[packet(id = 0x01, state = Status, bound = Client)]
+pub struct C01Pong {
+    pub payload: Long,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x00, state = Login, bound = Client)]
+pub struct C00Disconnect {
+    pub reason: Chat,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x01, state = Login, bound = Client)]
+pub struct C01EcryptionRequest {
+    pub server_id: String,
+    pub public_key: ByteArray,
+    pub verify_token: ByteArray,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x02, state = Login, bound = Client)]
+pub struct C02LoginSuccess {
+    /// With dashes, unlike other UUIDs in the protocol
+    pub uuid: String,
+    pub username: String,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DiggingStatus {
+    Started,
+    Cancelled,
+    Finished,
+    DropItemStack,
+    DropItem,
+    ShootArrowOrFinishEating,
+    SwapItemInHand,
+}
+
+impl PacketField for DiggingStatus {
+    fn write(self, encoder: &mut PacketEncoder) {
+        encoder.write_varint(self as i32);
+    }
+
+    fn read(decoder: &mut PacketDecoder) -> ProtocolResult<DiggingStatus> {
+        Ok(match decoder.read_varint()? {
+            0 => DiggingStatus::Started,
+            1 => DiggingStatus::Cancelled,
+            2 => DiggingStatus::Finished,
+            3 => DiggingStatus::DropItemStack,
+            4 => DiggingStatus::DropItem,
+            5 => DiggingStatus::ShootArrowOrFinishEating,
+            6 => DiggingStatus::SwapItemInHand,
+            value => {
+                return Err(ProtocolError::InvalidEnum {
+                    name: "digging status",
+                    value,
+                })
+            }
+        })
+    }
+}
+
+#[derive(Packet)]
+#[packet(id = 0x08, state = Play, bound = Client)]
+pub struct C08AcknowledgePlayerDigging {
+    pub location: Position,
+    pub block: VarInt,
+    pub status: DiggingStatus,
+    pub successful: Boolean,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x0C, state = Play, bound = Client)]
+pub struct C0CBlockChange {
+    pub location: Position,
+    pub block_id: VarInt,
+}
+
+/// Where a chat message is shown
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ChatPosition {
+    Chat,
+    System,
+    /// Above the hotbar
+    GameInfo,
+}
+
+impl PacketField for ChatPosition {
+    fn write(self, encoder: &mut PacketEncoder) {
+        encoder.write_byte(self as i8);
+    }
+
+    fn read(decoder: &mut PacketDecoder) -> ProtocolResult<ChatPosition> {
+        Ok(match decoder.read_byte()? {
+            0 => ChatPosition::Chat,
+            1 => ChatPosition::System,
+            2 => ChatPosition::GameInfo,
+            value => {
+                return Err(ProtocolError::InvalidEnum {
+                    name: "chat position",
+                    value: value as i32,
+                })
+            }
+        })
+    }
+}
+
+#[derive(Packet)]
+#[packet(id = 0x0F, state = Play, bound = Client)]
+pub struct C0FChatMessage {
+    pub json_data: Chat,
+    pub position: ChatPosition,
+}
+
+pub struct MultiBlockChangeRecord {
+    /// Relative to the chunk, from 0 to 15
+    pub x: UnsignedByte,
+    pub y: UnsignedByte,
+    /// Relative to the chunk, from 0 to 15
+    pub z: UnsignedByte,
+    pub block_id: VarInt,
+}
+
+impl PacketField for MultiBlockChangeRecord {
+    fn write(self, encoder: &mut PacketEncoder) {
+        encoder.write_ubyte(self.x << 4 | self.z & 0x0F);
+        encoder.write_ubyte(self.y);
+        encoder.write_varint(self.block_id);
+    }
+
+    fn read(decoder: &mut PacketDecoder) -> ProtocolResult<MultiBlockChangeRecord> {
+        let horizontal = decoder.read_ubyte()?;
+        Ok(MultiBlockChangeRecord {
+            x: horizontal >> 4,
+            y: decoder.read_ubyte()?,
+            z: horizontal & 0x0F,
+            block_id: decoder.read_varint()?,
+        })
+    }
+}
+
+#[derive(Packet)]
+#[packet(id = 0x10, state = Play, bound = Client)]
+pub struct C10MultiBlockChange {
+    pub chunk_x: Int,
+    pub chunk_z: Int,
+    pub records: Vec<MultiBlockChangeRecord>,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x11, state = Play, bound = Client)]
+pub struct C11TabComplete {
+    pub id: VarInt,
+    pub start: VarInt,
+    pub length: VarInt,
+    pub matches: Vec<TabCompleteMatch>,
+}
+
+pub struct TabCompleteMatch {
+    pub text: String,
+    pub tooltip: Option<Chat>,
+}
+
+impl PacketField for TabCompleteMatch {
+    fn write(self, encoder: &mut PacketEncoder) {
+        encoder.write_string(self.text);
+        encoder.write_bool(self.tooltip.is_some());
+        if let Some(tooltip) = self.tooltip {
+            encoder.write_string(tooltip);
+        }
+    }
+
+    fn read(decoder: &mut PacketDecoder) -> ProtocolResult<TabCompleteMatch> {
+        let text = decoder.read_string()?;
+        let tooltip = if decoder.read_bool()? {
+            Some(decoder.read_string()?)
+        } else {
+            None
+        };
+        Ok(TabCompleteMatch { text, tooltip })
+    }
+}
+
+pub struct C12DeclareCommands<'a> {
+    pub nodes: Vec<DeclaredNode<'a>>,
+    pub root_index: VarInt,
+}
+
+impl<'a> C12DeclareCommands<'a> {
     pub fn encode(self) -> PacketEncoder {
-        let mut encoder = PacketEncoder::new(0x00);
-        encoder.write_string(self.json_response);
+        let mut encoder = PacketEncoder::new(0x12);
+        encoder.write_varint(self.nodes.len() as i32);
+        for node in self.nodes {
+            let mut flags = match (node.name, node.parser) {
+                (None, _) => 0,
+                (Some(_), None) => 1,
+                (Some(_), Some(_)) => 2,
+            };
+            if node.executable {
+                flags |= 0x04;
+            }
+            if node.ask_server {
+                flags |= 0x10;
+            }
+            encoder.write_byte(flags);
+            encoder.write_varint(node.children.len() as i32);
+            for child in node.children {
+                encoder.write_varint(child);
+            }
+            if let Some(name) = node.name {
+                encoder.write_string(name.to_string());
+            }
+            if let Some(parser) = node.parser {
+                encoder.write_argument_type(parser);
+            }
+            if node.ask_server {
+                encoder.write_identifier("minecraft:ask_server".to_string());
+            }
+        }
+        encoder.write_varint(self.root_index);
         encoder
     }
 }
 
-pub struct C01Pong {
-    pub payload: Long,
+#[derive(Packet)]
+#[packet(id = 0x13, state = Play, bound = Client)]
+pub struct C13WindowConfirmation {
+    pub window_id: Byte,
+    pub action_number: Short,
+    pub accepted: Boolean,
 }
 
-impl C01Pong {
-    pub fn encode(self) -> PacketEncoder {
-        let mut encoder = PacketEncoder::new(0x01);
-        encoder.write_long(self.payload);
+pub struct C15WindowItems {
+    pub window_id: UnsignedByte,
+    pub slot_data: Vec<Slot>,
+}
+
+impl C15WindowItems {
+    pub fn encode(self, protocol: ProtocolVersion) -> PacketEncoder {
+        let mut encoder = PacketEncoder::new(0x15);
+        encoder.write_ubyte(self.window_id);
+        encoder.write_short(self.slot_data.len() as i16);
+        for slot in &self.slot_data {
+            encoder.write_slot(slot, protocol);
+        }
         encoder
     }
 }
 
-pub struct C00Disconnect {
-    pub reason: Chat,
+pub struct C17SetSlot {
+    pub window_id: Byte,
+    pub slot: Short,
+    pub slot_data: Slot,
 }
 
-impl C00Disconnect {
-    pub fn encode(self) -> PacketEncoder {
-        let mut encoder = PacketEncoder::new(0x00);
-        encoder.write_string(self.reason);
+impl C17SetSlot {
+    pub fn encode(self, protocol: ProtocolVersion) -> PacketEncoder {
+        let mut encoder = PacketEncoder::new(0x17);
+        encoder.write_byte(self.window_id);
+        encoder.write_short(self.slot);
+        encoder.write_slot(&self.slot_data, protocol);
         encoder
     }
 }
 
-pub struct C01EcryptionRequest {
-    pub server_id: String,
-    pub public_key_length: VarInt,
-    pub public_key: ByteArray,
-    pub verify_token_length: VarInt,
-    pub verify_token: ByteArray,
+#[derive(Packet)]
+#[packet(id = 0x19, state = Play, bound = Client)]
+pub struct C19PluginMessage {
+    pub channel: Identifier,
+    pub data: RemainingBytes,
 }
 
-impl C01EcryptionRequest {
-    pub fn encode(mut self) -> PacketEncoder {
-        let mut encoder = PacketEncoder::new(0x01);
-        encoder.write_string(self.server_id);
-        encoder.write_varint(self.public_key_length);
-        encoder.write_bytes(&mut self.public_key);
-        encoder.write_varint(self.verify_token_length);
-        encoder.write_bytes(&mut self.verify_token);
+#[derive(Packet)]
+#[packet(id = 0x1B, state = Play, bound = Client)]
+pub struct C1BDisconnect {
+    pub reason: Chat,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x1E, state = Play, bound = Client)]
+pub struct C1EUnloadChunk {
+    pub chunk_x: Int,
+    pub chunk_z: Int,
+}
+
+/// What a Change Game State packet changes
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GameStateReason {
+    EndRaining = 1,
+    BeginRaining = 2,
+    /// The value is the id of the new gamemode
+    ChangeGamemode = 3,
+    /// The value is from 0 to 1
+    RainLevel = 7,
+    /// The value is from 0 to 1
+    ThunderLevel = 8,
+}
+
+impl PacketField for GameStateReason {
+    fn write(self, encoder: &mut PacketEncoder) {
+        encoder.write_ubyte(self as u8);
+    }
+
+    fn read(decoder: &mut PacketDecoder) -> ProtocolResult<GameStateReason> {
+        Ok(match decoder.read_ubyte()? {
+            1 => GameStateReason::EndRaining,
+            2 => GameStateReason::BeginRaining,
+            3 => GameStateReason::ChangeGamemode,
+            7 => GameStateReason::RainLevel,
+            8 => GameStateReason::ThunderLevel,
+            value => {
+                return Err(ProtocolError::InvalidEnum {
+                    name: "game state reason",
+                    value: value as i32,
+                })
+            }
+        })
+    }
+}
+
+#[derive(Packet)]
+#[packet(id = 0x1F, state = Play, bound = Client)]
+pub struct C1FChangeGameState {
+    pub reason: GameStateReason,
+    pub value: Float,
+}
+
+/// A full chunk column, the client unloads it first if it had it already
+pub struct C22ChunkData<'a> {
+    pub chunk_x: Int,
+    pub chunk_z: Int,
+    /// The global palette ids of the blocks in each of the 16 sections from the bottom up,
+    /// None for sections that are all air
+    pub sections: Vec<Option<&'a [u32]>>,
+    /// The biome ids of 4x4x4 cells from the bottom up, with x changing fastest and then z
+    pub biomes: Vec<Int>,
+}
+
+impl<'a> C22ChunkData<'a> {
+    /// Enough for every block state, sent without a section palette
+    const BITS_PER_BLOCK: u32 = 14;
+
+    pub fn encode(self, protocol: ProtocolVersion) -> PacketEncoder {
+        let mut encoder = PacketEncoder::new(0x22);
+        encoder.write_int(self.chunk_x);
+        encoder.write_int(self.chunk_z);
+        // Full chunk
+        encoder.write_bool(true);
+        let mut primary_bit_mask = 0;
+        for (y, section) in self.sections.iter().enumerate() {
+            if section.is_some() {
+                primary_bit_mask |= 1 << y;
+            }
+        }
+        encoder.write_varint(primary_bit_mask);
+        // An empty compound for the heightmaps, which the client can do without
+        encoder.write_bytes(&mut vec![0x0a, 0, 0, 0]);
+        // 1.15 sends biomes in 4x4x4 cells before the sections,
+        // before that they were sent per column after the sections
+        let cells = protocol >= ProtocolVersion::V1_15_1;
+        if cells {
+            for biome in &self.biomes {
+                encoder.write_int(*biome);
+            }
+        }
+        let mut data = PacketEncoder::new(0);
+        for blocks in self.sections.iter().flatten() {
+            let block_count = blocks.iter().filter(|block| **block != 0).count();
+            data.write_short(block_count as i16);
+            data.write_ubyte(Self::BITS_PER_BLOCK as u8);
+            let longs = C22ChunkData::pack_blocks(blocks);
+            data.write_varint(longs.len() as i32);
+            for long in longs {
+                data.write_long(long as i64);
+            }
+        }
+        if !cells {
+            for z in 0..16 {
+                for x in 0..16 {
+                    data.write_int(self.biomes.get(z / 4 * 4 + x / 4).copied().unwrap_or(0));
+                }
+            }
+        }
+        encoder.write_varint(data.buffer.len() as i32);
+        encoder.write_bytes(&mut data.buffer);
+        // Block entities
+        encoder.write_varint(0);
         encoder
     }
+
+    /// Packs the ids into longs from the lowest bits up, an id can be split between two longs
+    fn pack_blocks(blocks: &[u32]) -> Vec<u64> {
+        let bits = Self::BITS_PER_BLOCK as usize;
+        let mut longs = vec![0u64; (blocks.len() * bits).div_ceil(64)];
+        for (i, block) in blocks.iter().enumerate() {
+            let value = *block as u64 & ((1 << bits) - 1);
+            let (index, offset) = (i * bits / 64, i * bits % 64);
+            longs[index] |= value << offset;
+            if offset + bits > 64 {
+                longs[index + 1] |= value >> (64 - offset);
+            }
+        }
+        longs
+    }
 }
 
-// SERVER BOUND
+#[derive(PartialEq)]
+pub enum UnlockRecipesAction {
+    Init,
+    Add,
+    Remove,
+}
 
-pub struct S01Ping {
-    pub payload: Long,
+pub struct C26JoinGame {
+    pub entity_id: Int,
+    /// The gamemode id, with 0x8 set for hardcore
+    pub gamemode: UnsignedByte,
+    /// -1 for the nether, 0 for the overworld and 1 for the end
+    pub dimension: Int,
+    /// The first 8 bytes of the SHA-256 hash of the seed, for biome noise on the client
+    pub hashed_seed: Long,
+    /// Ignored by the client
+    pub max_players: UnsignedByte,
+    pub level_type: String,
+    pub view_distance: VarInt,
+    pub reduced_debug_info: Boolean,
+    pub enable_respawn_screen: Boolean,
 }
 
-impl S01Ping {
-    pub fn decode(mut decoder: PacketDecoder) -> S01Ping {
-        S01Ping {
-            payload: decoder.read_long(),
+impl C26JoinGame {
+    pub fn encode(self, protocol: ProtocolVersion) -> PacketEncoder {
+        let mut encoder = PacketEncoder::new(0x26);
+        encoder.write_int(self.entity_id);
+        encoder.write_ubyte(self.gamemode);
+        encoder.write_int(self.dimension);
+        // The hashed seed and the respawn screen were added in 1.15
+        if protocol >= ProtocolVersion::V1_15_1 {
+            encoder.write_long(self.hashed_seed);
+        }
+        encoder.write_ubyte(self.max_players);
+        encoder.write_string(self.level_type);
+        encoder.write_varint(self.view_distance);
+        encoder.write_bool(self.reduced_debug_info);
+        if protocol >= ProtocolVersion::V1_15_1 {
+            encoder.write_bool(self.enable_respawn_screen);
         }
+        encoder
     }
 }
 
-pub struct S00Handshake {
-    pub protocol_version: VarInt,
-    pub server_address: String,
-    pub server_port: UnsignedShort,
-    pub next_state: NetworkState,
+#[derive(Packet)]
+#[packet(id = 0x2F, state = Play, bound = Client)]
+pub struct C2FOpenWindow {
+    pub window_id: VarInt,
+    pub window_type: VarInt,
+    pub window_title: Chat,
 }
 
-impl S00Handshake {
-    pub fn decode(mut decoder: PacketDecoder) -> S00Handshake {
-        S00Handshake {
-            protocol_version: decoder.read_varint(),
-            server_address: decoder.read_string(),
-            server_port: decoder.read_ushort(),
-            next_state: {
-                let next_state = decoder.read_varint();
-
-                match next_state {
-                    1 => NetworkState::STATUS,
-                    2 => NetworkState::LOGIN,
-                    _ => {
-                        println!("Invalid next network state: {}", next_state);
-                        NetworkState::HANDSHAKING
-                    }
-                }
-            },
+#[derive(Packet)]
+#[packet(id = 0x32, state = Play, bound = Client)]
+pub struct C32PlayerAbilities {
+    /// Invulnerable, flying, allow flying and instant break from the lowest bit
+    pub flags: Byte,
+    pub flying_speed: Float,
+    pub field_of_view_modifier: Float,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x36, state = Play, bound = Client)]
+pub struct C36PlayerPositionAndLook {
+    pub x: Double,
+    pub y: Double,
+    pub z: Double,
+    pub yaw: Float,
+    pub pitch: Float,
+    /// Which of the fields are relative, all are absolute if 0
+    pub flags: Byte,
+    pub teleport_id: VarInt,
+}
+
+pub struct C37UnlockRecipes {
+    pub action: UnlockRecipesAction,
+    pub crafting_recipe_book_open: Boolean,
+    pub crafting_recipe_book_filter_active: Boolean,
+    pub smelting_recipe_book_open: Boolean,
+    pub smelting_recipe_book_filter_active: Boolean,
+    pub recipe_ids: Vec<Identifier>,
+    /// Only sent with `UnlockRecipesAction::Init`
+    pub recipe_ids_init: Vec<Identifier>,
+}
+
+impl C37UnlockRecipes {
+    pub fn encode(self) -> PacketEncoder {
+        let mut encoder = PacketEncoder::new(0x37);
+        let init = self.action == UnlockRecipesAction::Init;
+        encoder.write_varint(match self.action {
+            UnlockRecipesAction::Init => 0,
+            UnlockRecipesAction::Add => 1,
+            UnlockRecipesAction::Remove => 2,
+        });
+        encoder.write_bool(self.crafting_recipe_book_open);
+        encoder.write_bool(self.crafting_recipe_book_filter_active);
+        encoder.write_bool(self.smelting_recipe_book_open);
+        encoder.write_bool(self.smelting_recipe_book_filter_active);
+        encoder.write_varint(self.recipe_ids.len() as i32);
+        for id in self.recipe_ids {
+            encoder.write_identifier(id);
+        }
+        if init {
+            encoder.write_varint(self.recipe_ids_init.len() as i32);
+            for id in self.recipe_ids_init {
+                encoder.write_identifier(id);
+            }
         }
+        encoder
     }
 }
 
-pub struct S00LoginStart {
-    pub name: String,
+/// The chunk the player is in, the client only shows the chunks within view distance of it
+#[derive(Packet)]
+#[packet(id = 0x41, state = Play, bound = Client)]
+pub struct C41UpdateViewPosition {
+    pub chunk_x: VarInt,
+    pub chunk_z: VarInt,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x4F, state = Play, bound = Client)]
+pub struct C4FTimeUpdate {
+    pub world_age: Long,
+    /// Negative to stop the client from advancing the time itself
+    pub time_of_day: Long,
+}
+
+pub struct C5BDeclareRecipes<'a> {
+    pub recipes: &'a [Recipe],
 }
 
-impl S00LoginStart {
-    pub fn decode(mut decoder: PacketDecoder) -> S00LoginStart {
-        S00LoginStart {
-            name: decoder.read_string(),
+impl<'a> C5BDeclareRecipes<'a> {
+    pub fn encode(self, protocol: ProtocolVersion) -> PacketEncoder {
+        let mut encoder = PacketEncoder::new(0x5B);
+        encoder.write_varint(self.recipes.len() as i32);
+        for recipe in self.recipes {
+            encoder.write_recipe(recipe, protocol);
         }
+        encoder
     }
 }
 
+// SERVER BOUND
+
+#[derive(Packet)]
+#[packet(id = 0x00, state = Status, bound = Server)]
+pub struct S00Request;
+
+#[derive(Packet)]
+#[packet(id = 0x01, state = Status, bound = Server)]
+pub struct S01Ping {
+    pub payload: Long,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x00, state = Handshaking, bound = Server)]
+pub struct S00Handshake {
+    pub protocol_version: VarInt,
+    pub server_address: String,
+    pub server_port: UnsignedShort,
+    pub next_state: NetworkState,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x00, state = Login, bound = Server)]
+pub struct S00LoginStart {
+    pub name: String,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x01, state = Login, bound = Server)]
 pub struct S01EncryptionResponse {
-    pub shared_secret_length: VarInt,
     pub shared_secret: ByteArray,
-    pub verify_token_length: VarInt,
     pub verify_token: ByteArray,
 }
 
-impl S01EncryptionResponse {
-    pub fn decode(mut decoder: PacketDecoder) -> S01EncryptionResponse {
-        let shared_secret_length = decoder.read_varint();
-        let shared_secret = decoder.read_bytes(shared_secret_length.clone() as usize);
-        let verify_token_length = decoder.read_varint();
-        let verify_token = decoder.read_bytes(verify_token_length.clone() as usize);
-        S01EncryptionResponse {
-            shared_secret_length,
-            shared_secret,
-            verify_token_length,
-            verify_token,
+#[derive(Packet)]
+#[packet(id = 0x00, state = Play, bound = Server)]
+pub struct S00TeleportConfirm {
+    pub teleport_id: VarInt,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x03, state = Play, bound = Server)]
+pub struct S03ChatMessage {
+    pub message: String,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x06, state = Play, bound = Server)]
+pub struct S06TabComplete {
+    pub transaction_id: VarInt,
+    /// Everything before the cursor, including the slash
+    pub text: String,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x09, state = Play, bound = Server)]
+pub struct S09ClickWindow {
+    pub window_id: UnsignedByte,
+    pub slot: Short,
+    pub button: Byte,
+    pub action_number: Short,
+    pub mode: VarInt,
+    pub clicked_item: Slot,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x0A, state = Play, bound = Server)]
+pub struct S0ACloseWindow {
+    pub window_id: UnsignedByte,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x0B, state = Play, bound = Server)]
+pub struct S0BPluginMessage {
+    pub channel: Identifier,
+    pub data: RemainingBytes,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x11, state = Play, bound = Server)]
+pub struct S11PlayerPosition {
+    pub x: Double,
+    pub feet_y: Double,
+    pub z: Double,
+    pub on_ground: Boolean,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x12, state = Play, bound = Server)]
+pub struct S12PlayerPositionAndRotation {
+    pub x: Double,
+    pub feet_y: Double,
+    pub z: Double,
+    pub yaw: Float,
+    pub pitch: Float,
+    pub on_ground: Boolean,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x13, state = Play, bound = Server)]
+pub struct S13PlayerRotation {
+    pub yaw: Float,
+    pub pitch: Float,
+    pub on_ground: Boolean,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x1A, state = Play, bound = Server)]
+pub struct S1APlayerDigging {
+    pub status: DiggingStatus,
+    pub location: Position,
+    pub face: Byte,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x23, state = Play, bound = Server)]
+pub struct S23HeldItemChange {
+    pub slot: Short,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x26, state = Play, bound = Server)]
+pub struct S26CreativeInventoryAction {
+    pub slot: Short,
+    pub clicked_item: Slot,
+}
+
+#[derive(Packet)]
+#[packet(id = 0x2C, state = Play, bound = Server)]
+pub struct S2CPlayerBlockPlacement {
+    pub hand: VarInt,
+    pub location: Position,
+    pub face: VarInt,
+    pub cursor_position_x: Float,
+    pub cursor_position_y: Float,
+    pub cursor_position_z: Float,
+    pub inside_block: Boolean,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoder(buffer: &[u8]) -> PacketDecoder {
+        PacketDecoder {
+            buffer: buffer.to_vec(),
+            packet_id: 0,
+            length: 0,
+            i: 0,
+            protocol: ProtocolVersion::LATEST,
+        }
+    }
+
+    fn encoded(write: impl FnOnce(&mut PacketEncoder)) -> Vec<u8> {
+        let mut encoder = PacketEncoder::new(0);
+        write(&mut encoder);
+        encoder.buffer
+    }
+
+    /// A xorshift generator, so the round trip tests cover a spread of values reproducibly
+    fn random_values(count: usize) -> Vec<u64> {
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        (0..count)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state
+            })
+            .collect()
+    }
+
+    // Known values from https://wiki.vg/Protocol#VarInt_and_VarLong
+    const VARINTS: &[(i32, &[u8])] = &[
+        (0, &[0x00]),
+        (1, &[0x01]),
+        (2, &[0x02]),
+        (127, &[0x7f]),
+        (128, &[0x80, 0x01]),
+        (255, &[0xff, 0x01]),
+        (25565, &[0xdd, 0xc7, 0x01]),
+        (2097151, &[0xff, 0xff, 0x7f]),
+        (2147483647, &[0xff, 0xff, 0xff, 0xff, 0x07]),
+        (-1, &[0xff, 0xff, 0xff, 0xff, 0x0f]),
+        (-2147483648, &[0x80, 0x80, 0x80, 0x80, 0x08]),
+    ];
+
+    const VARLONGS: &[(i64, &[u8])] = &[
+        (0, &[0x00]),
+        (1, &[0x01]),
+        (2, &[0x02]),
+        (127, &[0x7f]),
+        (128, &[0x80, 0x01]),
+        (255, &[0xff, 0x01]),
+        (2147483647, &[0xff, 0xff, 0xff, 0xff, 0x07]),
+        (
+            9223372036854775807,
+            &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f],
+        ),
+        (
+            -1,
+            &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01],
+        ),
+        (
+            -2147483648,
+            &[0x80, 0x80, 0x80, 0x80, 0xf8, 0xff, 0xff, 0xff, 0xff, 0x01],
+        ),
+        (
+            -9223372036854775808,
+            &[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01],
+        ),
+    ];
+
+    #[test]
+    fn varint_known_values() {
+        for (value, bytes) in VARINTS {
+            assert_eq!(encoded(|e| e.write_varint(*value)), *bytes, "{}", value);
+            assert_eq!(decoder(bytes).read_varint().unwrap(), *value);
+        }
+    }
+
+    #[test]
+    fn varlong_known_values() {
+        for (value, bytes) in VARLONGS {
+            assert_eq!(encoded(|e| e.write_varlong(*value)), *bytes, "{}", value);
+            assert_eq!(decoder(bytes).read_varlong().unwrap(), *value);
+        }
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        for value in random_values(10000) {
+            let value = value as i32;
+            let bytes = encoded(|e| e.write_varint(value));
+            assert!(bytes.len() <= 5);
+            let mut decoder = decoder(&bytes);
+            assert_eq!(decoder.read_varint().unwrap(), value);
+            assert_eq!(decoder.i, bytes.len());
+        }
+    }
+
+    #[test]
+    fn varlong_round_trip() {
+        for value in random_values(10000) {
+            let value = value as i64;
+            let bytes = encoded(|e| e.write_varlong(value));
+            assert!(bytes.len() <= 10);
+            let mut decoder = decoder(&bytes);
+            assert_eq!(decoder.read_varlong().unwrap(), value);
+            assert_eq!(decoder.i, bytes.len());
+        }
+    }
+
+    #[test]
+    fn varint_too_long() {
+        match decoder(&[0xff, 0xff, 0xff, 0xff, 0xff, 0x01]).read_varint() {
+            Err(ProtocolError::VarIntTooLong) => {}
+            other => panic!("Expected VarIntTooLong, got {:?}", other),
+        }
+        match decoder(&[0xff; 11]).read_varlong() {
+            Err(ProtocolError::VarIntTooLong) => {}
+            other => panic!("Expected VarIntTooLong, got {:?}", other),
+        }
+        match decoder(&[0x80]).read_varint() {
+            Err(ProtocolError::Truncated) => {}
+            other => panic!("Expected Truncated, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn position_known_value() {
+        // The example from https://wiki.vg/Protocol#Position
+        let position = BlockPosition::new(18357644, 831, -20882616);
+        let bytes = 0x4607_632c_15b4_833fu64.to_be_bytes();
+        assert_eq!(encoded(|e| e.write_position(&position)), bytes);
+        assert_eq!(decoder(&bytes).read_position().unwrap(), position);
+    }
+
+    #[test]
+    fn position_round_trip() {
+        let values = random_values(3000);
+        for chunk in values.chunks(3) {
+            // 26 bits for x and z, 12 bits for y, all signed
+            let x = (chunk[0] as i32) << 6 >> 6;
+            let y = (chunk[1] as i32) << 20 >> 20;
+            let z = (chunk[2] as i32) << 6 >> 6;
+            let position = BlockPosition::new(x, y, z);
+            let bytes = encoded(|e| e.write_position(&position));
+            assert_eq!(decoder(&bytes).read_position().unwrap(), position);
+        }
+    }
+
+    #[test]
+    fn uuid_round_trip() {
+        let uuid = 0x069a_79f4_44e9_4726_a5be_fca9_0e38_aaf5u128;
+        let bytes = encoded(|e| e.write_uuid(uuid));
+        assert_eq!(
+            bytes,
+            [
+                0x06, 0x9a, 0x79, 0xf4, 0x44, 0xe9, 0x47, 0x26, 0xa5, 0xbe, 0xfc, 0xa9, 0x0e, 0x38,
+                0xaa, 0xf5
+            ]
+        );
+        assert_eq!(decoder(&bytes).read_uuid().unwrap(), uuid);
+        let values = random_values(2000);
+        for pair in values.chunks(2) {
+            let uuid = (pair[0] as u128) << 64 | pair[1] as u128;
+            let bytes = encoded(|e| e.write_uuid(uuid));
+            assert_eq!(decoder(&bytes).read_uuid().unwrap(), uuid);
+        }
+    }
+
+    #[test]
+    fn float_and_double() {
+        assert_eq!(encoded(|e| e.write_float(1.5)), [0x3f, 0xc0, 0x00, 0x00]);
+        assert_eq!(
+            decoder(&[0x3f, 0xc0, 0x00, 0x00]).read_float().unwrap(),
+            1.5
+        );
+        let double = [0xc0, 0x02, 0, 0, 0, 0, 0, 0];
+        assert_eq!(encoded(|e| e.write_double(-2.25)), double);
+        assert_eq!(decoder(&double).read_double().unwrap(), -2.25);
+        for value in random_values(2000) {
+            let float = f32::from_bits(value as u32);
+            let bytes = encoded(|e| e.write_float(float));
+            let read = decoder(&bytes).read_float().unwrap();
+            assert_eq!(read.to_bits(), float.to_bits());
+
+            let double = f64::from_bits(value);
+            let bytes = encoded(|e| e.write_double(double));
+            let read = decoder(&bytes).read_double().unwrap();
+            assert_eq!(read.to_bits(), double.to_bits());
+        }
+    }
+
+    #[test]
+    fn angle() {
+        assert_eq!(encoded(|e| e.write_angle(0.0)), [0]);
+        assert_eq!(encoded(|e| e.write_angle(90.0)), [64]);
+        assert_eq!(encoded(|e| e.write_angle(-90.0)), [192]);
+        assert_eq!(encoded(|e| e.write_angle(450.0)), [64]);
+        assert_eq!(decoder(&[128]).read_angle().unwrap(), 180.0);
+        for step in 0..=255u8 {
+            let angle = decoder(&[step]).read_angle().unwrap();
+            assert_eq!(encoded(|e| e.write_angle(angle)), [step]);
+        }
+    }
+
+    #[test]
+    fn identifier() {
+        for valid in &["minecraft:stone", "stone", "my_mod:blocks/fancy.stone-2"] {
+            let bytes = encoded(|e| e.write_identifier(valid.to_string()));
+            assert_eq!(decoder(&bytes).read_identifier().unwrap(), *valid);
+        }
+        for invalid in &[
+            "Minecraft:stone",
+            "minecraft:stone block",
+            "a:b:c",
+            "my/mod:stone",
+        ] {
+            let bytes = encoded(|e| e.write_string(invalid.to_string()));
+            match decoder(&bytes).read_identifier() {
+                Err(ProtocolError::InvalidIdentifier(_)) => {}
+                other => panic!("Expected {} to be invalid, got {:?}", invalid, other),
+            }
+        }
+    }
+
+    #[test]
+    fn remaining_bytes() {
+        let bytes = encoded(|e| {
+            e.write_identifier("minecraft:brand".to_string());
+            e.write_remaining_bytes(vec![1, 2, 3]);
+        });
+        let mut decoder = decoder(&bytes);
+        assert_eq!(decoder.read_identifier().unwrap(), "minecraft:brand");
+        assert_eq!(decoder.read_remaining_bytes().unwrap(), [1, 2, 3]);
+        assert!(decoder.read_remaining_bytes().unwrap().is_empty());
+    }
+
+    #[test]
+    fn string_round_trip() {
+        for string in &["", "Hello World!", "§aGreen ✓", "日本語"] {
+            let bytes = encoded(|e| e.write_string(string.to_string()));
+            let mut decoder = decoder(&bytes);
+            assert_eq!(decoder.read_string().unwrap(), *string);
+            assert_eq!(decoder.i, bytes.len());
+        }
+        let too_long = "a".repeat(MAX_STRING_LENGTH + 1);
+        let bytes = encoded(|e| e.write_string(too_long));
+        match decoder(&bytes).read_string() {
+            Err(ProtocolError::StringTooLong { .. }) => {}
+            other => panic!("Expected StringTooLong, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn slot_round_trip() {
+        let stone = Item::from_name("minecraft:stone").unwrap();
+        // A compound with an int named `a`
+        let nbt = vec![0x0a, 0, 0, 0x03, 0, 1, b'a', 0, 0, 0, 5, 0];
+        let slots = [
+            None,
+            Some(ItemStack::new(stone, 64)),
+            Some(ItemStack {
+                item: stone,
+                count: 1,
+                nbt: Some(nbt),
+            }),
+        ];
+        for protocol in &[ProtocolVersion::LATEST, ProtocolVersion::V1_14_4] {
+            for slot in &slots {
+                let bytes = encoded(|e| e.write_slot(slot, *protocol));
+                let mut decoder = decoder(&bytes);
+                decoder.protocol = *protocol;
+                assert_eq!(decoder.read_slot().unwrap(), *slot);
+                assert_eq!(decoder.i, bytes.len());
+            }
         }
     }
+
+    #[test]
+    fn unknown_item() {
+        let bytes = encoded(|e| {
+            e.write_bool(true);
+            e.write_varint(100_000);
+            e.write_byte(1);
+            e.write_ubyte(0);
+        });
+        match decoder(&bytes).read_slot() {
+            Err(ProtocolError::UnknownItem(100_000)) => {}
+            other => panic!("Expected UnknownItem, got {:?}", other),
+        }
+    }
+
+    fn join_game() -> C26JoinGame {
+        C26JoinGame {
+            entity_id: 7,
+            gamemode: 1,
+            dimension: -1,
+            hashed_seed: 0x0102_0304_0506_0708,
+            max_players: 20,
+            level_type: "flat".to_string(),
+            view_distance: 10,
+            reduced_debug_info: false,
+            enable_respawn_screen: true,
+        }
+    }
+
+    #[test]
+    fn join_game_versions() {
+        let start = [0, 0, 0, 7, 1, 0xff, 0xff, 0xff, 0xff];
+        let end = [20, 4, b'f', b'l', b'a', b't', 10, 0];
+        let seed = [1, 2, 3, 4, 5, 6, 7, 8];
+        let expected_1_15 = [&start[..], &seed, &end, &[1]].concat();
+        for protocol in &[ProtocolVersion::V1_15_1, ProtocolVersion::V1_15_2] {
+            let encoder = join_game().encode(*protocol);
+            assert_eq!(encoder.packet_id, 0x26);
+            assert_eq!(encoder.buffer, expected_1_15);
+        }
+        let encoder = join_game().encode(ProtocolVersion::V1_14_4);
+        assert_eq!(encoder.buffer, [&start[..], &end].concat());
+        // Only the id is remapped when it's sent
+        let sent = encoder.finalize(false, &None, NetworkState::PLAY, ProtocolVersion::V1_14_4);
+        assert_eq!(sent[..2], [encoder.buffer.len() as u8 + 1, 0x25]);
+    }
+
+    #[test]
+    fn packed_blocks() {
+        let mut blocks = vec![0; 4096];
+        blocks[0] = 1;
+        blocks[1] = 0x3fff;
+        // The fifth id starts at bit 56 and ends in the next long
+        blocks[4] = 0x2c3;
+        let longs = C22ChunkData::pack_blocks(&blocks);
+        assert_eq!(longs.len(), 896);
+        assert_eq!(longs[0], 1 | 0x3fff << 14 | 0xc3 << 56);
+        assert_eq!(longs[1], 0x2);
+        assert!(longs[2..].iter().all(|long| *long == 0));
+    }
+
+    fn chunk_data(sections: Vec<Option<&[u32]>>, protocol: ProtocolVersion) -> Vec<u8> {
+        let biomes = (0..1024).map(|i| i % 16).collect();
+        let packet = C22ChunkData {
+            chunk_x: -2,
+            chunk_z: 3,
+            sections,
+            biomes,
+        };
+        packet.encode(protocol).buffer
+    }
+
+    #[test]
+    fn chunk_data_versions() {
+        let stone = vec![1; 4096];
+        let mut sections = vec![None; 16];
+        sections[2] = Some(stone.as_slice());
+        let header = [0xff, 0xff, 0xff, 0xfe, 0, 0, 0, 3, 1, 0x04, 0x0a, 0, 0, 0];
+        // Block count, bits per block and the length of the array of longs
+        let section_header = [0x10, 0x00, 14, 0x80, 0x07];
+        let section_length = section_header.len() + 896 * 8;
+
+        let bytes = chunk_data(sections.clone(), ProtocolVersion::V1_15_2);
+        assert_eq!(bytes[..header.len()], header);
+        let mut reader = decoder(&bytes[header.len()..]);
+        for i in 0..1024 {
+            assert_eq!(reader.read_int().unwrap(), i % 16);
+        }
+        assert_eq!(reader.read_varint().unwrap(), section_length as i32);
+        assert_eq!(reader.read_bytes(5).unwrap(), section_header);
+        reader.read_bytes(896 * 8).unwrap();
+        assert_eq!(reader.read_varint().unwrap(), 0);
+        assert_eq!(reader.i, reader.buffer.len());
+
+        // 1.14 has one biome per column at the end of the data
+        let bytes = chunk_data(sections, ProtocolVersion::V1_14_4);
+        assert_eq!(bytes[..header.len()], header);
+        let mut reader = decoder(&bytes[header.len()..]);
+        assert_eq!(
+            reader.read_varint().unwrap(),
+            (section_length + 256 * 4) as i32
+        );
+        assert_eq!(reader.read_bytes(5).unwrap(), section_header);
+        reader.read_bytes(896 * 8).unwrap();
+        for z in 0..16 {
+            for x in 0..16 {
+                assert_eq!(reader.read_int().unwrap(), (z / 4 * 4 + x / 4) % 16);
+            }
+        }
+        assert_eq!(reader.read_varint().unwrap(), 0);
+        assert_eq!(reader.i, reader.buffer.len());
+
+        // Without sections only the 1.14 biomes are in the data
+        let empty = chunk_data(vec![None; 16], ProtocolVersion::V1_15_2);
+        assert_eq!(empty.len(), header.len() + 1024 * 4 + 2);
+        assert_eq!(empty[9], 0);
+        let empty = chunk_data(vec![None; 16], ProtocolVersion::V1_14_4);
+        assert_eq!(empty.len(), header.len() + 2 + 256 * 4 + 1);
+    }
 }
@@ -0,0 +1,514 @@
+//! Permission nodes of players, granted to them directly or through groups.
+//! Nodes are dotted like `minecraft.command.kick`, and `minecraft.command.*` or `*` match everything below them.
+//! Everyone is in the `default` group. The nodes are stored in `permissions.json`:
+//!
+//! ```json
+//! {
+//!   "groups": {
+//!     "default": { "permissions": { "minecraft.command.list": true } },
+//!     "moderator": { "inherits": ["default"], "permissions": { "minecraft.command.kick": true } }
+//!   },
+//!   "players": {
+//!     "069a79f4-44e9-4726-a5be-fca90e38aaf5": {
+//!       "name": "Notch",
+//!       "groups": ["moderator"],
+//!       "permissions": { "minecraft.command.*": false }
+//!     }
+//!   }
+//! }
+//! ```
+
+use crate::commands::Permissible;
+use crate::userlist::UserListError;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+pub const PERMISSIONS_FILE: &str = "permissions.json";
+/// The group every player is in
+pub const DEFAULT_GROUP: &str = "default";
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Group {
+    /// Groups whose permissions this group has unless it sets them itself
+    pub inherits: Vec<String>,
+    pub permissions: BTreeMap<String, bool>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlayerPermissions {
+    /// The last known name, to make the file readable
+    pub name: String,
+    pub groups: Vec<String>,
+    pub permissions: BTreeMap<String, bool>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct PermissionData {
+    groups: BTreeMap<String, Group>,
+    /// By UUID with dashes
+    players: BTreeMap<String, PlayerPermissions>,
+}
+
+/// The value of the most specific node that matches, `a.b.c` is matched by itself, `a.b.*`, `a.*` and `*`
+fn lookup(permissions: &BTreeMap<String, bool>, node: &str) -> Option<bool> {
+    if let Some(&value) = permissions.get(node) {
+        return Some(value);
+    }
+    let mut prefix = node;
+    while let Some(index) = prefix.rfind('.') {
+        prefix = &prefix[..index];
+        if let Some(&value) = permissions.get(&format!("{}.*", prefix)) {
+            return Some(value);
+        }
+    }
+    permissions.get("*").copied()
+}
+
+/// Whether a node can be stored, like `a.b`, `a.*` or `*`
+pub fn is_valid_node(node: &str) -> bool {
+    !node.is_empty()
+        && node.split('.').all(|part| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                || part == "*"
+        })
+}
+
+pub struct Permissions {
+    path: PathBuf,
+    data: PermissionData,
+    /// When the file was last read or written, to notice when it's edited
+    modified: Option<SystemTime>,
+}
+
+impl Permissions {
+    /// Permissions with just the default group that aren't backed by a file, for servers in tests
+    #[cfg(test)]
+    pub fn unsaved() -> Permissions {
+        let mut permissions = Permissions {
+            path: PathBuf::new(),
+            data: PermissionData::default(),
+            modified: None,
+        };
+        permissions.create_group(DEFAULT_GROUP);
+        permissions
+    }
+
+    /// Loads the permissions from the working directory, or creates the file with an empty default group
+    pub fn load() -> Permissions {
+        let mut permissions = Permissions {
+            path: PathBuf::from(PERMISSIONS_FILE),
+            data: PermissionData::default(),
+            modified: None,
+        };
+        if permissions.path.exists() {
+            if let Err(err) = permissions.reload() {
+                warn!("Couldn't load {}: {}", PERMISSIONS_FILE, err);
+            }
+        } else {
+            permissions.create_group(DEFAULT_GROUP);
+            permissions.save();
+        }
+        permissions
+    }
+
+    fn modified_time(&self) -> Option<SystemTime> {
+        fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// Reads the file again, the permissions stay the same if it can't be read
+    pub fn reload(&mut self) -> Result<(), UserListError> {
+        self.modified = self.modified_time();
+        let data = fs::read_to_string(&self.path)?;
+        self.data = serde_json::from_str(&data)?;
+        Ok(())
+    }
+
+    /// Writes the permissions to the file, changes stay in memory if that fails
+    pub fn save(&mut self) {
+        let result = serde_json::to_string_pretty(&self.data)
+            .map_err(UserListError::from)
+            .and_then(|data| fs::write(&self.path, data).map_err(UserListError::from));
+        match result {
+            Ok(()) => self.modified = self.modified_time(),
+            Err(err) => error!("Couldn't save {}: {}", PERMISSIONS_FILE, err),
+        }
+    }
+
+    /// Reloads the file if it was changed since it was last read or written, returns whether it was
+    pub fn reload_if_changed(&mut self) -> bool {
+        let modified = self.modified_time();
+        if modified.is_none() || modified == self.modified {
+            return false;
+        }
+        match self.reload() {
+            Ok(()) => {
+                info!("Reloaded {}", PERMISSIONS_FILE);
+                true
+            }
+            Err(err) => {
+                warn!("Couldn't reload {}: {}", PERMISSIONS_FILE, err);
+                false
+            }
+        }
+    }
+
+    /// Whether a player has a node granted or denied, directly or through their groups.
+    /// None if it isn't set anywhere.
+    pub fn check(&self, uuid: &str, node: &str) -> Option<bool> {
+        let node = node.to_ascii_lowercase();
+        let player = self.data.players.get(&uuid.to_ascii_lowercase());
+        if let Some(value) = player.and_then(|player| lookup(&player.permissions, &node)) {
+            return Some(value);
+        }
+        let mut visited = HashSet::new();
+        player
+            .into_iter()
+            .flat_map(|player| player.groups.iter().map(String::as_str))
+            .chain(Some(DEFAULT_GROUP))
+            .find_map(|group| self.check_group(group, &node, &mut visited))
+    }
+
+    /// Whether a group has a node set, itself or through the groups it inherits from
+    fn check_group<'a>(
+        &'a self,
+        name: &'a str,
+        node: &str,
+        visited: &mut HashSet<&'a str>,
+    ) -> Option<bool> {
+        // Inheritance can go in circles
+        if !visited.insert(name) {
+            return None;
+        }
+        let group = self.data.groups.get(name)?;
+        if let Some(value) = lookup(&group.permissions, node) {
+            return Some(value);
+        }
+        group
+            .inherits
+            .iter()
+            .find_map(|parent| self.check_group(parent, node, visited))
+    }
+
+    /// Whether a player is granted a node. Unlike the command dispatcher, this doesn't fall back
+    /// to the op level, so nodes that aren't set for the player or their groups are denied.
+    pub fn has(&self, uuid: &str, node: &str) -> bool {
+        self.check(uuid, node).unwrap_or(false)
+    }
+
+    pub fn player(&self, uuid: &str) -> Option<&PlayerPermissions> {
+        self.data.players.get(&uuid.to_ascii_lowercase())
+    }
+
+    fn player_mut(&mut self, uuid: &str, name: &str) -> &mut PlayerPermissions {
+        let player = self
+            .data
+            .players
+            .entry(uuid.to_ascii_lowercase())
+            .or_default();
+        player.name = name.to_string();
+        player
+    }
+
+    /// Players without any groups or nodes are removed from the file
+    fn remove_if_empty(&mut self, uuid: &str) {
+        let uuid = uuid.to_ascii_lowercase();
+        let empty = self
+            .data
+            .players
+            .get(&uuid)
+            .is_some_and(|player| player.groups.is_empty() && player.permissions.is_empty());
+        if empty {
+            self.data.players.remove(&uuid);
+        }
+    }
+
+    /// Grants or denies a node, or unsets it with None. Returns whether anything changed.
+    pub fn set_player(&mut self, uuid: &str, name: &str, node: &str, value: Option<bool>) -> bool {
+        let node = node.to_ascii_lowercase();
+        let permissions = &mut self.player_mut(uuid, name).permissions;
+        let changed = match value {
+            Some(value) => permissions.insert(node, value) != Some(value),
+            None => permissions.remove(&node).is_some(),
+        };
+        self.remove_if_empty(uuid);
+        changed
+    }
+
+    /// Returns false if the player is already in the group
+    pub fn add_player_group(&mut self, uuid: &str, name: &str, group: &str) -> bool {
+        let groups = &mut self.player_mut(uuid, name).groups;
+        if groups.iter().any(|existing| existing == group) {
+            return false;
+        }
+        groups.push(group.to_string());
+        true
+    }
+
+    /// Returns false if the player isn't in the group
+    pub fn remove_player_group(&mut self, uuid: &str, group: &str) -> bool {
+        let player = match self.data.players.get_mut(&uuid.to_ascii_lowercase()) {
+            Some(player) => player,
+            None => return false,
+        };
+        let len = player.groups.len();
+        player.groups.retain(|existing| existing != group);
+        let changed = player.groups.len() != len;
+        self.remove_if_empty(uuid);
+        changed
+    }
+
+    pub fn group(&self, name: &str) -> Option<&Group> {
+        self.data.groups.get(name)
+    }
+
+    pub fn group_names(&self) -> Vec<&str> {
+        self.data.groups.keys().map(String::as_str).collect()
+    }
+
+    /// Returns false if the group already exists
+    pub fn create_group(&mut self, name: &str) -> bool {
+        if self.data.groups.contains_key(name) {
+            return false;
+        }
+        self.data.groups.insert(name.to_string(), Group::default());
+        true
+    }
+
+    /// Removes the group and takes it away from the players and groups that had it.
+    /// Returns false if it doesn't exist or is the default group, which every player has.
+    pub fn delete_group(&mut self, name: &str) -> bool {
+        if name == DEFAULT_GROUP || self.data.groups.remove(name).is_none() {
+            return false;
+        }
+        for group in self.data.groups.values_mut() {
+            group.inherits.retain(|parent| parent != name);
+        }
+        let uuids: Vec<String> = self.data.players.keys().cloned().collect();
+        for uuid in uuids {
+            self.remove_player_group(&uuid, name);
+        }
+        true
+    }
+
+    /// Like `set_player` for a group, returns None if the group doesn't exist
+    pub fn set_group(&mut self, name: &str, node: &str, value: Option<bool>) -> Option<bool> {
+        let node = node.to_ascii_lowercase();
+        let permissions = &mut self.data.groups.get_mut(name)?.permissions;
+        Some(match value {
+            Some(value) => permissions.insert(node, value) != Some(value),
+            None => permissions.remove(&node).is_some(),
+        })
+    }
+
+    /// Makes a group inherit from another, returns None if either doesn't exist
+    pub fn add_parent(&mut self, name: &str, parent: &str) -> Option<bool> {
+        if !self.data.groups.contains_key(parent) {
+            return None;
+        }
+        let inherits = &mut self.data.groups.get_mut(name)?.inherits;
+        if name == parent || inherits.iter().any(|existing| existing == parent) {
+            return Some(false);
+        }
+        inherits.push(parent.to_string());
+        Some(true)
+    }
+
+    /// Returns None if the group doesn't exist
+    pub fn remove_parent(&mut self, name: &str, parent: &str) -> Option<bool> {
+        let inherits = &mut self.data.groups.get_mut(name)?.inherits;
+        let len = inherits.len();
+        inherits.retain(|existing| existing != parent);
+        Some(inherits.len() != len)
+    }
+}
+
+/// The op level and permission nodes of a command sender, for the command dispatcher
+pub struct SenderPermissions<'a> {
+    pub level: u8,
+    /// None for the console
+    pub uuid: Option<String>,
+    pub permissions: &'a Permissions,
+}
+
+impl Permissible for SenderPermissions<'_> {
+    fn permission_level(&self) -> u8 {
+        self.level
+    }
+
+    fn permission(&self, node: &str) -> Option<bool> {
+        self.uuid
+            .as_ref()
+            .and_then(|uuid| self.permissions.check(uuid, node))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{self, literal, CommandDispatcher};
+
+    const NOTCH: &str = "069a79f4-44e9-4726-a5be-fca90e38aaf5";
+    const JEB: &str = "853c80ef-3c37-49fd-aa49-938b674adae6";
+
+    fn permissions(path: PathBuf) -> Permissions {
+        let mut permissions = Permissions {
+            path,
+            data: PermissionData::default(),
+            modified: None,
+        };
+        permissions.create_group(DEFAULT_GROUP);
+        permissions
+    }
+
+    fn empty() -> Permissions {
+        permissions(PathBuf::from(PERMISSIONS_FILE))
+    }
+
+    #[test]
+    fn wildcards() {
+        let mut permissions = empty();
+        permissions.set_player(NOTCH, "Notch", "minecraft.command.*", Some(true));
+        permissions.set_player(NOTCH, "Notch", "minecraft.command.ban", Some(false));
+        permissions.set_player(NOTCH, "Notch", "plugin.*", Some(false));
+        permissions.set_player(NOTCH, "Notch", "*", Some(true));
+        assert_eq!(
+            permissions.check(NOTCH, "minecraft.command.kick"),
+            Some(true)
+        );
+        // The most specific node wins
+        assert_eq!(
+            permissions.check(NOTCH, "minecraft.command.ban"),
+            Some(false)
+        );
+        assert_eq!(permissions.check(NOTCH, "plugin.home.set"), Some(false));
+        assert_eq!(permissions.check(NOTCH, "other"), Some(true));
+        // Nodes are case insensitive
+        assert_eq!(
+            permissions.check(NOTCH, "Minecraft.Command.Ban"),
+            Some(false)
+        );
+        assert_eq!(permissions.check(JEB, "minecraft.command.kick"), None);
+
+        assert!(is_valid_node("minecraft.command.kick"));
+        assert!(is_valid_node("minecraft.*"));
+        assert!(is_valid_node("*"));
+        assert!(!is_valid_node(""));
+        assert!(!is_valid_node("minecraft..kick"));
+        assert!(!is_valid_node("minecraft.kick!"));
+    }
+
+    #[test]
+    fn negated_nodes() {
+        let mut permissions = empty();
+        permissions.set_group(DEFAULT_GROUP, "minecraft.command.list", Some(true));
+        permissions.set_group(DEFAULT_GROUP, "minecraft.command.me", Some(false));
+        // Nodes set on the player override their groups, in both directions
+        permissions.set_player(NOTCH, "Notch", "minecraft.command.list", Some(false));
+        permissions.set_player(NOTCH, "Notch", "minecraft.command.me", Some(true));
+        assert!(!permissions.has(NOTCH, "minecraft.command.list"));
+        assert!(permissions.has(NOTCH, "minecraft.command.me"));
+        assert!(permissions.has(JEB, "minecraft.command.list"));
+        assert!(!permissions.has(JEB, "minecraft.command.me"));
+        assert_eq!(permissions.check(JEB, "minecraft.command.me"), Some(false));
+        assert!(!permissions.has(JEB, "minecraft.command.kick"));
+
+        // Unsetting the nodes brings back the group's, and drops the empty player
+        permissions.set_player(NOTCH, "Notch", "minecraft.command.list", None);
+        permissions.set_player(NOTCH, "Notch", "minecraft.command.me", None);
+        assert!(permissions.has(NOTCH, "minecraft.command.list"));
+        assert!(permissions.player(NOTCH).is_none());
+    }
+
+    #[test]
+    fn group_inheritance() {
+        let mut permissions = empty();
+        permissions.create_group("moderator");
+        permissions.create_group("admin");
+        permissions.set_group(DEFAULT_GROUP, "minecraft.command.list", Some(true));
+        permissions.set_group("moderator", "minecraft.command.kick", Some(true));
+        permissions.set_group("moderator", "minecraft.command.ban", Some(true));
+        permissions.set_group("admin", "minecraft.command.ban", Some(false));
+        assert_eq!(
+            permissions.add_parent("moderator", DEFAULT_GROUP),
+            Some(true)
+        );
+        assert_eq!(permissions.add_parent("admin", "moderator"), Some(true));
+        assert_eq!(permissions.add_parent("admin", "moderator"), Some(false));
+        assert_eq!(permissions.add_parent("admin", "missing"), None);
+        // Going in circles doesn't loop forever
+        assert_eq!(permissions.add_parent("moderator", "admin"), Some(true));
+        permissions.add_player_group(NOTCH, "Notch", "admin");
+
+        assert!(permissions.has(NOTCH, "minecraft.command.kick"));
+        assert!(permissions.has(NOTCH, "minecraft.command.list"));
+        // The group's own node wins over the inherited one
+        assert!(!permissions.has(NOTCH, "minecraft.command.ban"));
+        assert_eq!(permissions.check(NOTCH, "minecraft.command.stop"), None);
+
+        assert!(permissions.delete_group("moderator"));
+        // Every player has the default group, so it can't go
+        assert!(!permissions.delete_group(DEFAULT_GROUP));
+        assert!(permissions.group(DEFAULT_GROUP).is_some());
+        assert!(!permissions.has(NOTCH, "minecraft.command.kick"));
+        assert!(permissions.group("admin").unwrap().inherits.is_empty());
+        assert!(permissions.remove_player_group(NOTCH, "admin"));
+        assert!(permissions.player(NOTCH).is_none());
+    }
+
+    #[test]
+    fn op_level_fallback() {
+        let mut permissions = empty();
+        permissions.set_player(NOTCH, "Notch", "minecraft.command.ban", Some(false));
+        permissions.set_player(JEB, "jeb_", "minecraft.command.ban", Some(true));
+        let sender = |level, uuid: Option<&str>| SenderPermissions {
+            level,
+            uuid: uuid.map(String::from),
+            permissions: &permissions,
+        };
+        // Nodes that aren't set fall back to the op level, set nodes override it
+        let mut dispatcher: CommandDispatcher<()> = CommandDispatcher::new();
+        dispatcher.register(literal("ban").requires(commands::MODERATOR_PERMISSION_LEVEL));
+        dispatcher.register(literal("kick").requires(commands::MODERATOR_PERMISSION_LEVEL));
+        let op = sender(commands::MODERATOR_PERMISSION_LEVEL, Some(NOTCH));
+        assert_eq!(dispatcher.command_names(&op), ["kick"]);
+        assert_eq!(dispatcher.command_names(&sender(0, Some(JEB))), ["ban"]);
+        assert!(dispatcher.command_names(&sender(0, Some(NOTCH))).is_empty());
+        let owner = sender(commands::MAX_PERMISSION_LEVEL, Some(NOTCH));
+        assert_eq!(dispatcher.command_names(&owner), ["kick"]);
+        let console = sender(commands::MAX_PERMISSION_LEVEL, None);
+        assert_eq!(dispatcher.command_names(&console), ["ban", "kick"]);
+    }
+
+    #[test]
+    fn save_and_reload() {
+        let path = std::env::temp_dir().join(format!(
+            "rustmcserver-permissions-{}.json",
+            std::process::id()
+        ));
+        let mut saved = permissions(path.clone());
+        saved.create_group("moderator");
+        saved.set_group("moderator", "minecraft.command.kick", Some(true));
+        saved.add_player_group(NOTCH, "Notch", "moderator");
+        saved.save();
+        assert!(!saved.reload_if_changed());
+
+        let mut loaded = permissions(path.clone());
+        loaded.reload().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.group_names(), [DEFAULT_GROUP, "moderator"]);
+        assert_eq!(loaded.player(NOTCH).unwrap().name, "Notch");
+        assert!(loaded.has(NOTCH, "minecraft.command.kick"));
+    }
+}
@@ -1,8 +1,86 @@
-use crate::utils::Location;
+use crate::inventory::Inventory;
+use crate::utils::{BlockPosition, Location};
+use std::time::Instant;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Gamemode {
+    Survival,
+    Creative,
+    Adventure,
+    Spectator,
+}
+
+impl Gamemode {
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_name(name: &str) -> Option<Gamemode> {
+        match name {
+            "survival" => Some(Gamemode::Survival),
+            "creative" => Some(Gamemode::Creative),
+            "adventure" => Some(Gamemode::Adventure),
+            "spectator" => Some(Gamemode::Spectator),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Gamemode::Survival => "survival",
+            Gamemode::Creative => "creative",
+            Gamemode::Adventure => "adventure",
+            Gamemode::Spectator => "spectator",
+        }
+    }
+
+    /// The flags of the Player Abilities packet for this gamemode
+    pub fn ability_flags(self) -> i8 {
+        match self {
+            // Invulnerable, allowed to fly and breaking blocks instantly
+            Gamemode::Creative => 0x01 | 0x04 | 0x08,
+            // Invulnerable, flying and allowed to fly
+            Gamemode::Spectator => 0x01 | 0x02 | 0x04,
+            Gamemode::Survival | Gamemode::Adventure => 0,
+        }
+    }
+}
+
+pub struct Digging {
+    pub position: BlockPosition,
+    pub started: Instant,
+}
 
 pub struct Player {
     pub username: String,
     pub position: Location,
+    pub inventory: Inventory,
+    pub gamemode: Gamemode,
+    /// Selected hotbar slot from 0 to 8
+    pub selected_slot: usize,
+    pub digging: Option<Digging>,
 }
 
-impl Player {}
+impl Player {
+    /// Whether the player's bounding box intersects a block
+    pub fn intersects(&self, block: BlockPosition) -> bool {
+        let (x, y, z) = (block.x as f64, block.y as f64, block.z as f64);
+        self.position.x + 0.3 > x
+            && self.position.x - 0.3 < x + 1.0
+            && self.position.y + 1.8 > y
+            && self.position.y < y + 1.0
+            && self.position.z + 0.3 > z
+            && self.position.z - 0.3 < z + 1.0
+    }
+
+    pub fn can_reach(&self, block: BlockPosition) -> bool {
+        let eyes = Location::new(
+            self.position.x,
+            self.position.y + 1.62,
+            self.position.z,
+            0.0,
+            0.0,
+        );
+        block.distance_squared(&eyes) <= 36.0
+    }
+}
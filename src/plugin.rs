@@ -0,0 +1,232 @@
+//! Plugins extend the server without changing it. They handle events, add commands and
+//! schedule tasks when they're enabled, and all of that is removed again when they're disabled.
+
+use crate::channels::{self, BungeeRequest, BungeeResponse};
+use crate::commands::CommandBuilder;
+use crate::events::{Event, EventPriority};
+use crate::network::Server;
+use crate::scheduler::TaskId;
+use log::debug;
+use std::path::{Path, PathBuf};
+
+pub trait Plugin {
+    fn name(&self) -> &str;
+    fn version(&self) -> &str;
+    /// Called when the server starts, to register event handlers, commands and tasks
+    fn on_enable(&mut self, context: &mut PluginContext);
+    /// Called when the server stops, the plugin's handlers, commands and tasks are removed afterwards
+    fn on_disable(&mut self, _server: &mut Server) {}
+}
+
+/// The name and version of a plugin for the log, scripts don't have a version
+pub fn describe(name: &str, version: &str) -> String {
+    if version.is_empty() {
+        name.to_string()
+    } else {
+        format!("{} v{}", name, version)
+    }
+}
+
+/// What a plugin gets to set itself up with, everything registered through it belongs to the plugin
+pub struct PluginContext<'a> {
+    server: &'a mut Server,
+    plugin: String,
+}
+
+impl<'a> PluginContext<'a> {
+    pub fn new(server: &'a mut Server, plugin: &str) -> PluginContext<'a> {
+        PluginContext {
+            server,
+            plugin: plugin.to_string(),
+        }
+    }
+
+    pub fn server(&mut self) -> &mut Server {
+        self.server
+    }
+
+    pub fn on<E, F>(&mut self, priority: EventPriority, handler: F)
+    where
+        E: Event,
+        F: Fn(&mut Server, &mut E) + 'static,
+    {
+        self.server
+            .register_event_handler(&self.plugin, priority, handler);
+    }
+
+    /// Handles plugin messages on a channel, the handler gets the id of the client that sent it.
+    /// Returns false if the channel name isn't valid.
+    pub fn listen<F>(&mut self, channel: &str, handler: F) -> bool
+    where
+        F: Fn(&mut Server, u32, &[u8]) + 'static,
+    {
+        self.server
+            .listen_on_channel(&self.plugin, channel, handler)
+    }
+
+    /// Sends a plugin message to a client, returns false if it doesn't listen on the channel
+    pub fn send_plugin_message(&mut self, client_id: u32, channel: &str, data: Vec<u8>) -> bool {
+        self.server.send_plugin_message(client_id, channel, data)
+    }
+
+    /// Sends a request to the proxy, returns false if nobody is online to send it through
+    pub fn bungee_request(&mut self, request: &BungeeRequest) -> bool {
+        self.server.send_bungee_request(request)
+    }
+
+    /// Handles the proxy's answers to requests and data forwarded from other servers
+    pub fn on_bungee_response<F>(&mut self, handler: F)
+    where
+        F: Fn(&mut Server, BungeeResponse) + 'static,
+    {
+        self.listen(
+            channels::BUNGEECORD,
+            move |server, _, data| match BungeeResponse::decode(data) {
+                Some(response) => handler(server, response),
+                None => debug!("The proxy sent a malformed message"),
+            },
+        );
+    }
+
+    pub fn register_command(&mut self, command: CommandBuilder<Server>) {
+        self.server.register_plugin_command(&self.plugin, command);
+    }
+
+    /// Runs a task on the main thread after a number of ticks
+    pub fn run_later<F>(&mut self, ticks: u64, task: F) -> TaskId
+    where
+        F: FnOnce(&mut Server) + 'static,
+    {
+        self.server.run_later(&self.plugin, ticks, task)
+    }
+
+    /// Runs a task on the main thread after `delay` ticks and then every `period` ticks
+    pub fn run_repeating<F>(&mut self, delay: u64, period: u64, task: F) -> TaskId
+    where
+        F: FnMut(&mut Server) + 'static,
+    {
+        self.server.run_repeating(&self.plugin, delay, period, task)
+    }
+
+    /// Runs a job off the main thread, and then `then` with its result on the main thread
+    pub fn run_async<T, J, F>(&mut self, job: J, then: F) -> TaskId
+    where
+        T: Send + 'static,
+        J: FnOnce() -> T + Send + 'static,
+        F: FnOnce(&mut Server, T) + 'static,
+    {
+        self.server.run_async(Some(&self.plugin), job, then)
+    }
+
+    /// Cancels a task or job, returns false if it already finished
+    pub fn cancel_task(&mut self, id: TaskId) -> bool {
+        self.server.cancel_task(id)
+    }
+}
+
+struct PluginEntry {
+    /// None while one of the plugin's hooks runs
+    plugin: Option<Box<dyn Plugin>>,
+    name: String,
+    version: String,
+    enabled: bool,
+    /// The file the plugin was loaded from, None for plugins built into the server
+    path: Option<PathBuf>,
+    /// The commands the plugin registered, to remove them when it's disabled
+    commands: Vec<String>,
+}
+
+pub struct PluginManager {
+    plugins: Vec<PluginEntry>,
+    /// Plugins that failed while they were running, disabled at the end of the tick
+    failed: Vec<String>,
+}
+
+impl PluginManager {
+    pub fn new() -> PluginManager {
+        PluginManager {
+            plugins: Vec::new(),
+            failed: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, plugin: Box<dyn Plugin>, path: Option<PathBuf>) {
+        self.plugins.push(PluginEntry {
+            name: plugin.name().to_string(),
+            version: plugin.version().to_string(),
+            plugin: Some(plugin),
+            enabled: false,
+            path,
+            commands: Vec::new(),
+        });
+    }
+
+    /// Removes the plugins that were loaded from files, they have to be disabled first
+    pub fn remove_loaded(&mut self) {
+        self.plugins.retain(|entry| entry.path.is_none());
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.plugins.iter().position(|entry| entry.name == name)
+    }
+
+    pub fn index_of_path(&self, path: &Path) -> Option<usize> {
+        self.plugins
+            .iter()
+            .position(|entry| entry.path.as_deref() == Some(path))
+    }
+
+    /// Removes a plugin, it has to be disabled first
+    pub fn remove(&mut self, index: usize) {
+        self.plugins.remove(index);
+    }
+
+    pub fn len(&self) -> usize {
+        self.plugins.len()
+    }
+
+    /// The name, version and whether it's enabled of every plugin
+    pub fn list(&self) -> Vec<(&str, &str, bool)> {
+        self.plugins
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.version.as_str(), entry.enabled))
+            .collect()
+    }
+
+    pub fn is_enabled(&self, index: usize) -> bool {
+        self.plugins[index].enabled
+    }
+
+    /// Takes a plugin out to run one of its hooks, it has to be put back with `put_back`
+    pub fn take(&mut self, index: usize) -> Option<Box<dyn Plugin>> {
+        self.plugins[index].plugin.take()
+    }
+
+    pub fn put_back(&mut self, index: usize, plugin: Box<dyn Plugin>, enabled: bool) {
+        let entry = &mut self.plugins[index];
+        entry.plugin = Some(plugin);
+        entry.enabled = enabled;
+    }
+
+    pub fn add_command(&mut self, plugin: &str, command: &str) {
+        if let Some(entry) = self.plugins.iter_mut().find(|entry| entry.name == plugin) {
+            entry.commands.push(command.to_string());
+        }
+    }
+
+    /// The commands a plugin registered, they're forgotten as they'll be removed
+    pub fn take_commands(&mut self, index: usize) -> Vec<String> {
+        std::mem::take(&mut self.plugins[index].commands)
+    }
+
+    /// Disables a plugin once whatever it's doing has finished
+    pub fn fail(&mut self, name: &str) {
+        if !self.failed.iter().any(|failed| failed == name) {
+            self.failed.push(name.to_string());
+        }
+    }
+
+    pub fn take_failed(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.failed)
+    }
+}
@@ -0,0 +1,1363 @@
+//! Loads plugins from the `plugins` directory. A plugin is either a shared library (`.so`, `.dll`
+//! or `.dylib`) with a C ABI, or a WebAssembly module (`.wasm`). Both get the same small API:
+//! they subscribe to events, register commands, send messages, packets and plugin messages and
+//! schedule tasks through host functions, and the server calls them back when any of that happens.
+//!
+//! A shared library exports `rustmc_plugin_declare`, returning a pointer to a `PluginDeclaration`
+//! that lives as long as the library. Its callbacks return 0 on success. They must not unwind,
+//! a plugin written in Rust catches its panics and returns something else instead.
+//!
+//! A WebAssembly module exports its `memory` and these functions, strings are passed as a
+//! pointer and a length in its memory:
+//!
+//! - `rustmc_plugin_declare() -> i32`, the address of five little endian u32s: the API version,
+//!   then the pointer and length of the name and of the version
+//! - `rustmc_alloc(len: i32) -> i32`, memory for strings the server passes to the plugin, which
+//!   the plugin owns afterwards
+//! - `rustmc_on_enable() -> i32` and `rustmc_on_disable() -> i32`
+//! - `rustmc_on_command(name, name_len, sender: i64, args, args_len) -> i32`
+//! - `rustmc_on_event(event, client_id: i64, text, text_len, x: f64, y: f64, z: f64) -> i32`,
+//!   which returns 1 to cancel the event
+//! - `rustmc_on_task(task: i64) -> i32`
+//! - `rustmc_on_plugin_message(channel, channel_len, client_id: i64, data, data_len) -> i32`,
+//!   only needed by plugins that listen on a channel
+//!
+//! The host functions are imported from the `rustmc` module, with the same arguments as the
+//! ones in `HostApi`. Lists of strings are passed as one string separated by null bytes. A plugin that fails, panics or traps is disabled.
+
+use crate::channels::{self, BungeeRequest};
+use crate::chat::ChatComponent;
+use crate::commands::{argument, literal, ArgumentType, CommandContext, CommandSender, StringKind};
+use crate::events::{
+    BlockBreakEvent, BlockPlaceEvent, ChatEvent, DynamicEvent, EventData, EventPriority, MoveEvent,
+    PlayerJoinEvent, PlayerQuitEvent, EVENT_BLOCK_BREAK, EVENT_BLOCK_PLACE, EVENT_CHAT, EVENT_MOVE,
+    EVENT_PLAYER_JOIN, EVENT_PLAYER_QUIT,
+};
+use crate::network::Server;
+use crate::packets::{ChatPosition, PacketEncoder};
+use crate::plugin::{Plugin, PluginContext};
+use libloading::{Library, Symbol};
+use log::{error, log, warn, Level};
+use std::cell::{Cell, RefCell};
+use std::error::Error;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::raw::{c_char, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::slice;
+use wasmi::{
+    Externals, FuncInstance, FuncRef, ImportsBuilder, MemoryRef, ModuleImportResolver,
+    ModuleInstance, ModuleRef, NopExternals, RuntimeArgs, RuntimeValue, Signature, Trap, TrapKind,
+    ValueType,
+};
+
+pub const PLUGINS_DIR: &str = "plugins";
+/// Changes whenever the ABI does, plugins built for another version aren't loaded
+pub const PLUGIN_API_VERSION: u32 = 3;
+const DECLARE_SYMBOL: &[u8] = b"rustmc_plugin_declare\0";
+/// Where the host functions of WebAssembly plugins are imported from
+const WASM_HOST_MODULE: &str = "rustmc";
+
+/// The sender of commands run from the console
+const CONSOLE_SENDER: i64 = -1;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Wasm(wasmi::Error),
+    /// Something the plugin has to export is missing
+    Missing(&'static str),
+    /// The declaration can't be read
+    InvalidDeclaration,
+    /// The plugin was built for another version of the API
+    IncompatibleVersion(u32),
+    /// Another plugin with the same name is loaded already
+    Duplicate(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "{}", err),
+            LoadError::Wasm(err) => write!(f, "{}", err),
+            LoadError::Missing(name) => write!(f, "it doesn't export {}", name),
+            LoadError::InvalidDeclaration => write!(f, "its declaration is invalid"),
+            LoadError::IncompatibleVersion(version) => write!(
+                f,
+                "it was built for plugin API version {}, the server has version {}",
+                version, PLUGIN_API_VERSION
+            ),
+            LoadError::Duplicate(name) => write!(f, "a plugin named {} is already loaded", name),
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> LoadError {
+        LoadError::Io(err)
+    }
+}
+
+impl From<wasmi::Error> for LoadError {
+    fn from(err: wasmi::Error) -> LoadError {
+        LoadError::Wasm(err)
+    }
+}
+
+/// What a shared library declares about itself. Strings are nul terminated UTF-8.
+#[repr(C)]
+pub struct PluginDeclaration {
+    /// `PLUGIN_API_VERSION`, nothing else is read if it's different
+    pub api_version: u32,
+    pub name: *const c_char,
+    pub version: *const c_char,
+    pub on_enable: extern "C" fn(host: *const HostApi) -> i32,
+    pub on_disable: extern "C" fn(host: *const HostApi) -> i32,
+    /// The sender is a client id, or -1 for the console
+    pub on_command: extern "C" fn(
+        host: *const HostApi,
+        name: *const c_char,
+        sender: i64,
+        args: *const c_char,
+    ) -> i32,
+    pub on_event: extern "C" fn(host: *const HostApi, event: *mut RawEvent) -> i32,
+    pub on_task: extern "C" fn(host: *const HostApi, task: u64) -> i32,
+    /// A client or the proxy sent a message on a channel the plugin listens on
+    pub on_plugin_message: extern "C" fn(
+        host: *const HostApi,
+        channel: *const c_char,
+        client_id: i64,
+        data: *const u8,
+        len: usize,
+    ) -> i32,
+}
+
+/// An event as shared libraries see it
+#[repr(C)]
+pub struct RawEvent {
+    pub kind: u32,
+    pub client_id: i64,
+    /// The chat message or the name of the player that quit, empty otherwise
+    pub text: *const c_char,
+    /// The block of block events, or where the player moves to
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    /// Set to 1 to cancel the event, if it can be cancelled
+    pub cancelled: i32,
+}
+
+/// The host functions shared libraries can call while one of their callbacks runs
+#[repr(C)]
+pub struct HostApi {
+    /// Passed back to every host function
+    pub context: *mut c_void,
+    /// Levels go from 1 for errors to 5 for trace messages
+    pub log: extern "C" fn(context: *mut c_void, level: u32, message: *const c_char),
+    /// Priorities go from 0 for the lowest to 5 for monitor, returns 0 if the event doesn't exist
+    pub subscribe: extern "C" fn(context: *mut c_void, event: u32, priority: u32) -> i32,
+    /// Returns 0 if the name can't be used
+    pub register_command: extern "C" fn(context: *mut c_void, name: *const c_char) -> i32,
+    /// Messages are chat components as JSON or text with legacy color codes.
+    /// Returns 0 if the client doesn't exist.
+    pub send_message:
+        extern "C" fn(context: *mut c_void, client_id: i64, message: *const c_char) -> i32,
+    pub broadcast: extern "C" fn(context: *mut c_void, message: *const c_char),
+    /// Runs a command as the console
+    pub execute_command: extern "C" fn(context: *mut c_void, command: *const c_char),
+    /// Calls `on_task` with the task after a number of ticks
+    pub run_later: extern "C" fn(context: *mut c_void, ticks: u64, task: u64),
+    /// Sends a packet to a client that's playing. The id and data are the ones of the latest
+    /// version, without the length and id. Returns 0 if the client doesn't exist.
+    pub send_packet: extern "C" fn(
+        context: *mut c_void,
+        client_id: i64,
+        packet_id: i32,
+        data: *const u8,
+        len: usize,
+    ) -> i32,
+    /// Calls `on_plugin_message` with the messages sent on a channel, returns 0 if the channel
+    /// name isn't valid
+    pub listen: extern "C" fn(context: *mut c_void, channel: *const c_char) -> i32,
+    /// Returns 0 if the client doesn't exist or doesn't listen on the channel
+    pub send_plugin_message: extern "C" fn(
+        context: *mut c_void,
+        client_id: i64,
+        channel: *const c_char,
+        data: *const u8,
+        len: usize,
+    ) -> i32,
+    /// Sends a request to the proxy, with the arguments after the subchannel in the order
+    /// BungeeCord reads them. `data` is what `Forward` and `ForwardToPlayer` send. Returns 0 if
+    /// the request isn't valid or nobody is online to send it through. The answers are plugin
+    /// messages on `bungeecord:main`.
+    pub bungee_request: extern "C" fn(
+        context: *mut c_void,
+        subchannel: *const c_char,
+        args: *const *const c_char,
+        args_len: usize,
+        data: *const u8,
+        len: usize,
+    ) -> i32,
+}
+
+/// The code of a plugin, callbacks return an error if the plugin failed
+trait Module {
+    fn enable(&mut self, host: &mut Host) -> Result<(), String>;
+    fn disable(&mut self, host: &mut Host) -> Result<(), String>;
+    fn command(
+        &mut self,
+        host: &mut Host,
+        name: &str,
+        sender: i64,
+        args: &str,
+    ) -> Result<(), String>;
+    fn event(&mut self, host: &mut Host, event: &mut EventData) -> Result<(), String>;
+    fn task(&mut self, host: &mut Host, task: u64) -> Result<(), String>;
+    fn plugin_message(
+        &mut self,
+        host: &mut Host,
+        channel: &str,
+        client_id: u32,
+        data: &[u8],
+    ) -> Result<(), String>;
+}
+
+/// A loaded plugin, shared with the event handlers, commands and tasks it registered
+struct Shared {
+    name: String,
+    module: RefCell<Box<dyn Module>>,
+    /// Set once the plugin failed, it isn't called anymore
+    failed: Cell<bool>,
+}
+
+/// Calls the plugin, returns false if it failed or couldn't be called.
+/// A plugin that fails is disabled at the end of the tick.
+fn call<F>(server: &mut Server, plugin: &Rc<Shared>, callback: F) -> bool
+where
+    F: FnOnce(&mut dyn Module, &mut Host) -> Result<(), String>,
+{
+    if plugin.failed.get() {
+        return false;
+    }
+    let mut module = match plugin.module.try_borrow_mut() {
+        Ok(module) => module,
+        Err(_) => {
+            // Like a plugin running its own command
+            warn!("{} can't be called while it's running", plugin.name);
+            return false;
+        }
+    };
+    let mut host = Host {
+        server: &mut *server,
+        plugin: plugin.clone(),
+    };
+    let result = panic::catch_unwind(AssertUnwindSafe(|| callback(&mut **module, &mut host)));
+    let err = match result {
+        Ok(Ok(())) => return true,
+        Ok(Err(err)) => err,
+        Err(_) => "it panicked".to_string(),
+    };
+    error!("{} failed and will be disabled: {}", plugin.name, err);
+    plugin.failed.set(true);
+    server.fail_plugin(&plugin.name);
+    false
+}
+
+/// A message from a plugin, either a chat component as JSON or text with legacy color codes
+fn parse_message(message: &str) -> ChatComponent {
+    if message.starts_with('{') {
+        if let Ok(component) = ChatComponent::from_json(message) {
+            return component;
+        }
+    }
+    ChatComponent::from_legacy(message, '§')
+}
+
+/// What plugins can do to the server while they're called
+pub struct Host<'a> {
+    server: &'a mut Server,
+    plugin: Rc<Shared>,
+}
+
+impl<'a> Host<'a> {
+    fn log(&mut self, level: u32, message: &str) {
+        let level = match level {
+            1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            _ => Level::Trace,
+        };
+        log!(level, "[{}] {}", self.plugin.name, message);
+    }
+
+    fn subscribe(&mut self, event: u32, priority: u32) -> bool {
+        let priority = match priority {
+            0 => EventPriority::Lowest,
+            1 => EventPriority::Low,
+            2 => EventPriority::Normal,
+            3 => EventPriority::High,
+            4 => EventPriority::Highest,
+            5 => EventPriority::Monitor,
+            _ => return false,
+        };
+        match event {
+            EVENT_PLAYER_JOIN => self.subscribe_to::<PlayerJoinEvent>(priority),
+            EVENT_PLAYER_QUIT => self.subscribe_to::<PlayerQuitEvent>(priority),
+            EVENT_CHAT => self.subscribe_to::<ChatEvent>(priority),
+            EVENT_BLOCK_BREAK => self.subscribe_to::<BlockBreakEvent>(priority),
+            EVENT_BLOCK_PLACE => self.subscribe_to::<BlockPlaceEvent>(priority),
+            EVENT_MOVE => self.subscribe_to::<MoveEvent>(priority),
+            _ => return false,
+        }
+        true
+    }
+
+    fn subscribe_to<E: DynamicEvent>(&mut self, priority: EventPriority) {
+        let plugin = self.plugin.clone();
+        let name = plugin.name.clone();
+        PluginContext::new(self.server, &name).on(
+            priority,
+            move |server: &mut Server, event: &mut E| {
+                let mut data = event.data();
+                if call(server, &plugin, |module, host| {
+                    module.event(host, &mut data)
+                }) {
+                    event.update(&data);
+                }
+            },
+        );
+    }
+
+    fn register_command(&mut self, name: &str) -> bool {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+        if !valid {
+            return false;
+        }
+        let plugin = self.plugin.clone();
+        let command = name.to_string();
+        let execute = Rc::new(move |server: &mut Server, context: &CommandContext| {
+            let sender = match context.sender {
+                CommandSender::Console => CONSOLE_SENDER,
+                CommandSender::Player(client_id) => client_id as i64,
+            };
+            let args = context.get_string("args").unwrap_or("");
+            call(server, &plugin, |module, host| {
+                module.command(host, &command, sender, args)
+            });
+            Ok(1)
+        });
+        let execute_args = execute.clone();
+        let command = literal(name)
+            .executes(move |server: &mut Server, context: &CommandContext| execute(server, context))
+            .then(
+                argument("args", ArgumentType::String(StringKind::Greedy)).executes(
+                    move |server: &mut Server, context: &CommandContext| {
+                        execute_args(server, context)
+                    },
+                ),
+            );
+        let plugin = self.plugin.name.clone();
+        PluginContext::new(self.server, &plugin).register_command(command);
+        true
+    }
+
+    fn send_message(&mut self, client_id: i64, message: &str) -> bool {
+        let sender = if client_id == CONSOLE_SENDER {
+            CommandSender::Console
+        } else if client_id >= 0 && self.server.client(client_id as u32).is_some() {
+            CommandSender::Player(client_id as u32)
+        } else {
+            return false;
+        };
+        self.server.send_message(&sender, parse_message(message));
+        true
+    }
+
+    fn broadcast(&mut self, message: &str) {
+        self.server
+            .broadcast_chat(parse_message(message), ChatPosition::System);
+    }
+
+    fn execute_command(&mut self, command: &str) {
+        let command = command.trim_start_matches('/');
+        self.server.execute_command(CommandSender::Console, command);
+    }
+
+    fn run_later(&mut self, ticks: u64, task: u64) {
+        let plugin = self.plugin.clone();
+        let name = plugin.name.clone();
+        PluginContext::new(self.server, &name).run_later(ticks, move |server: &mut Server| {
+            call(server, &plugin, |module, host| module.task(host, task));
+        });
+    }
+
+    fn send_packet(&mut self, client_id: i64, packet_id: i32, data: Vec<u8>) -> bool {
+        client_id >= 0
+            && self
+                .server
+                .send_packet(client_id as u32, &PacketEncoder::raw(packet_id, data))
+    }
+
+    fn listen(&mut self, channel: &str) -> bool {
+        let plugin = self.plugin.clone();
+        let name = plugin.name.clone();
+        // Plugins get the name the channel is sent as, like `bungeecord:main` for `BungeeCord`
+        let sent_as = match channels::channel_name(channel) {
+            Some(sent_as) => sent_as.to_string(),
+            None => return false,
+        };
+        PluginContext::new(self.server, &name).listen(
+            channel,
+            move |server: &mut Server, client_id, data: &[u8]| {
+                call(server, &plugin, |module, host| {
+                    module.plugin_message(host, &sent_as, client_id, data)
+                });
+            },
+        )
+    }
+
+    fn send_plugin_message(&mut self, client_id: i64, channel: &str, data: Vec<u8>) -> bool {
+        let name = self.plugin.name.clone();
+        client_id >= 0
+            && PluginContext::new(self.server, &name).send_plugin_message(
+                client_id as u32,
+                channel,
+                data,
+            )
+    }
+
+    fn bungee_request(&mut self, subchannel: &str, args: &[String], data: Vec<u8>) -> bool {
+        let name = self.plugin.name.clone();
+        match BungeeRequest::from_parts(subchannel, args, data) {
+            Some(request) => PluginContext::new(self.server, &name).bungee_request(&request),
+            None => false,
+        }
+    }
+}
+
+/// Turns a status returned by a plugin into a result
+fn status(code: i32) -> Result<(), String> {
+    match code {
+        0 => Ok(()),
+        code => Err(format!("it returned {}", code)),
+    }
+}
+
+/// Reads a string from a shared library, null is the empty string
+unsafe fn read_c_str(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(ptr).to_string_lossy().into_owned()
+}
+
+/// Reads bytes from a shared library, null is no bytes
+unsafe fn read_c_bytes(ptr: *const u8, len: usize) -> Vec<u8> {
+    if ptr.is_null() {
+        return Vec::new();
+    }
+    slice::from_raw_parts(ptr, len).to_vec()
+}
+
+/// Strings for shared libraries, nul bytes can't be passed so they're dropped
+fn to_c_string(string: &str) -> CString {
+    CString::new(string.replace('\0', "")).unwrap()
+}
+
+/// Runs a host function called by a shared library, server panics can't unwind into the plugin
+fn with_host<T, F>(context: *mut c_void, default: T, function: F) -> T
+where
+    F: FnOnce(&mut Host) -> T,
+{
+    let host = unsafe { &mut *(context as *mut Host) };
+    panic::catch_unwind(AssertUnwindSafe(|| function(host))).unwrap_or_else(|_| {
+        error!("A host function panicked while it was called by a plugin");
+        default
+    })
+}
+
+extern "C" fn host_log(context: *mut c_void, level: u32, message: *const c_char) {
+    let message = unsafe { read_c_str(message) };
+    with_host(context, (), |host| host.log(level, &message))
+}
+
+extern "C" fn host_subscribe(context: *mut c_void, event: u32, priority: u32) -> i32 {
+    with_host(context, false, |host| host.subscribe(event, priority)) as i32
+}
+
+extern "C" fn host_register_command(context: *mut c_void, name: *const c_char) -> i32 {
+    let name = unsafe { read_c_str(name) };
+    with_host(context, false, |host| host.register_command(&name)) as i32
+}
+
+extern "C" fn host_send_message(
+    context: *mut c_void,
+    client_id: i64,
+    message: *const c_char,
+) -> i32 {
+    let message = unsafe { read_c_str(message) };
+    with_host(context, false, |host| {
+        host.send_message(client_id, &message)
+    }) as i32
+}
+
+extern "C" fn host_broadcast(context: *mut c_void, message: *const c_char) {
+    let message = unsafe { read_c_str(message) };
+    with_host(context, (), |host| host.broadcast(&message))
+}
+
+extern "C" fn host_execute_command(context: *mut c_void, command: *const c_char) {
+    let command = unsafe { read_c_str(command) };
+    with_host(context, (), |host| host.execute_command(&command))
+}
+
+extern "C" fn host_run_later(context: *mut c_void, ticks: u64, task: u64) {
+    with_host(context, (), |host| host.run_later(ticks, task))
+}
+
+extern "C" fn host_send_packet(
+    context: *mut c_void,
+    client_id: i64,
+    packet_id: i32,
+    data: *const u8,
+    len: usize,
+) -> i32 {
+    let data = unsafe { read_c_bytes(data, len) };
+    with_host(context, false, |host| {
+        host.send_packet(client_id, packet_id, data)
+    }) as i32
+}
+
+extern "C" fn host_listen(context: *mut c_void, channel: *const c_char) -> i32 {
+    let channel = unsafe { read_c_str(channel) };
+    with_host(context, false, |host| host.listen(&channel)) as i32
+}
+
+extern "C" fn host_send_plugin_message(
+    context: *mut c_void,
+    client_id: i64,
+    channel: *const c_char,
+    data: *const u8,
+    len: usize,
+) -> i32 {
+    let channel = unsafe { read_c_str(channel) };
+    let data = unsafe { read_c_bytes(data, len) };
+    with_host(context, false, |host| {
+        host.send_plugin_message(client_id, &channel, data)
+    }) as i32
+}
+
+extern "C" fn host_bungee_request(
+    context: *mut c_void,
+    subchannel: *const c_char,
+    args: *const *const c_char,
+    args_len: usize,
+    data: *const u8,
+    len: usize,
+) -> i32 {
+    let subchannel = unsafe { read_c_str(subchannel) };
+    let args: Vec<String> = if args.is_null() {
+        Vec::new()
+    } else {
+        unsafe { slice::from_raw_parts(args, args_len) }
+            .iter()
+            .map(|arg| unsafe { read_c_str(*arg) })
+            .collect()
+    };
+    let data = unsafe { read_c_bytes(data, len) };
+    with_host(context, false, |host| {
+        host.bungee_request(&subchannel, &args, data)
+    }) as i32
+}
+
+fn host_api(host: &mut Host) -> HostApi {
+    HostApi {
+        context: host as *mut Host as *mut c_void,
+        log: host_log,
+        subscribe: host_subscribe,
+        register_command: host_register_command,
+        send_message: host_send_message,
+        broadcast: host_broadcast,
+        execute_command: host_execute_command,
+        run_later: host_run_later,
+        send_packet: host_send_packet,
+        listen: host_listen,
+        send_plugin_message: host_send_plugin_message,
+        bungee_request: host_bungee_request,
+    }
+}
+
+/// A plugin in a shared library
+struct NativeModule {
+    /// Points into the library, which is unloaded after this is dropped
+    declaration: *const PluginDeclaration,
+    _library: Library,
+}
+
+impl NativeModule {
+    fn declaration(&self) -> &PluginDeclaration {
+        unsafe { &*self.declaration }
+    }
+}
+
+impl Module for NativeModule {
+    fn enable(&mut self, host: &mut Host) -> Result<(), String> {
+        let api = host_api(host);
+        status((self.declaration().on_enable)(&api))
+    }
+
+    fn disable(&mut self, host: &mut Host) -> Result<(), String> {
+        let api = host_api(host);
+        status((self.declaration().on_disable)(&api))
+    }
+
+    fn command(
+        &mut self,
+        host: &mut Host,
+        name: &str,
+        sender: i64,
+        args: &str,
+    ) -> Result<(), String> {
+        let api = host_api(host);
+        let name = to_c_string(name);
+        let args = to_c_string(args);
+        status((self.declaration().on_command)(
+            &api,
+            name.as_ptr(),
+            sender,
+            args.as_ptr(),
+        ))
+    }
+
+    fn event(&mut self, host: &mut Host, event: &mut EventData) -> Result<(), String> {
+        let api = host_api(host);
+        let text = to_c_string(&event.text);
+        let (x, y, z) = event.position;
+        let mut raw = RawEvent {
+            kind: event.kind,
+            client_id: event.client_id as i64,
+            text: text.as_ptr(),
+            x,
+            y,
+            z,
+            cancelled: event.cancelled as i32,
+        };
+        status((self.declaration().on_event)(&api, &mut raw))?;
+        event.cancelled = raw.cancelled != 0;
+        Ok(())
+    }
+
+    fn task(&mut self, host: &mut Host, task: u64) -> Result<(), String> {
+        let api = host_api(host);
+        status((self.declaration().on_task)(&api, task))
+    }
+
+    fn plugin_message(
+        &mut self,
+        host: &mut Host,
+        channel: &str,
+        client_id: u32,
+        data: &[u8],
+    ) -> Result<(), String> {
+        let api = host_api(host);
+        let channel = to_c_string(channel);
+        status((self.declaration().on_plugin_message)(
+            &api,
+            channel.as_ptr(),
+            client_id as i64,
+            data.as_ptr(),
+            data.len(),
+        ))
+    }
+}
+
+/// The host functions of WebAssembly plugins, by the index they're resolved to
+const WASM_HOST_FUNCTIONS: &[(&str, &[ValueType], Option<ValueType>)] = &[
+    (
+        "log",
+        &[ValueType::I32, ValueType::I32, ValueType::I32],
+        None,
+    ),
+    (
+        "subscribe",
+        &[ValueType::I32, ValueType::I32],
+        Some(ValueType::I32),
+    ),
+    (
+        "register_command",
+        &[ValueType::I32, ValueType::I32],
+        Some(ValueType::I32),
+    ),
+    (
+        "send_message",
+        &[ValueType::I64, ValueType::I32, ValueType::I32],
+        Some(ValueType::I32),
+    ),
+    ("broadcast", &[ValueType::I32, ValueType::I32], None),
+    ("execute_command", &[ValueType::I32, ValueType::I32], None),
+    ("run_later", &[ValueType::I64, ValueType::I64], None),
+    (
+        "send_packet",
+        &[
+            ValueType::I64,
+            ValueType::I32,
+            ValueType::I32,
+            ValueType::I32,
+        ],
+        Some(ValueType::I32),
+    ),
+    (
+        "listen",
+        &[ValueType::I32, ValueType::I32],
+        Some(ValueType::I32),
+    ),
+    (
+        "send_plugin_message",
+        &[
+            ValueType::I64,
+            ValueType::I32,
+            ValueType::I32,
+            ValueType::I32,
+            ValueType::I32,
+        ],
+        Some(ValueType::I32),
+    ),
+    (
+        "bungee_request",
+        &[
+            ValueType::I32,
+            ValueType::I32,
+            ValueType::I32,
+            ValueType::I32,
+            ValueType::I32,
+            ValueType::I32,
+        ],
+        Some(ValueType::I32),
+    ),
+];
+
+struct WasmHostResolver;
+
+impl ModuleImportResolver for WasmHostResolver {
+    fn resolve_func(&self, name: &str, signature: &Signature) -> Result<FuncRef, wasmi::Error> {
+        let index = WASM_HOST_FUNCTIONS
+            .iter()
+            .position(|(function, _, _)| *function == name)
+            .ok_or_else(|| {
+                wasmi::Error::Instantiation(format!("There's no host function {}", name))
+            })?;
+        let (_, params, return_type) = WASM_HOST_FUNCTIONS[index];
+        if signature.params() != params || signature.return_type() != return_type {
+            return Err(wasmi::Error::Instantiation(format!(
+                "The host function {} has another signature",
+                name
+            )));
+        }
+        Ok(FuncInstance::alloc_host(
+            Signature::new(params, return_type),
+            index,
+        ))
+    }
+}
+
+fn read_wasm_bytes(memory: &MemoryRef, ptr: u32, len: u32) -> Result<Vec<u8>, Trap> {
+    memory
+        .get(ptr, len as usize)
+        .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))
+}
+
+fn read_wasm_string(memory: &MemoryRef, ptr: u32, len: u32) -> Result<String, Trap> {
+    let bytes = read_wasm_bytes(memory, ptr, len)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Gives a WebAssembly plugin the host functions while it's called
+struct WasmExternals<'a, 'b> {
+    host: &'a mut Host<'b>,
+    memory: &'a MemoryRef,
+}
+
+impl<'a, 'b> WasmExternals<'a, 'b> {
+    fn bytes(&self, args: &RuntimeArgs, index: usize) -> Result<Vec<u8>, Trap> {
+        let ptr: u32 = args.nth_checked(index)?;
+        let len: u32 = args.nth_checked(index + 1)?;
+        read_wasm_bytes(self.memory, ptr, len)
+    }
+
+    fn string(&self, args: &RuntimeArgs, index: usize) -> Result<String, Trap> {
+        let ptr: u32 = args.nth_checked(index)?;
+        let len: u32 = args.nth_checked(index + 1)?;
+        read_wasm_string(self.memory, ptr, len)
+    }
+}
+
+impl<'a, 'b> Externals for WasmExternals<'a, 'b> {
+    fn invoke_index(
+        &mut self,
+        index: usize,
+        args: RuntimeArgs,
+    ) -> Result<Option<RuntimeValue>, Trap> {
+        let result = match WASM_HOST_FUNCTIONS[index].0 {
+            "log" => {
+                let message = self.string(&args, 1)?;
+                self.host.log(args.nth_checked(0)?, &message);
+                None
+            }
+            "subscribe" => {
+                let subscribed = self
+                    .host
+                    .subscribe(args.nth_checked(0)?, args.nth_checked(1)?);
+                Some(RuntimeValue::I32(subscribed as i32))
+            }
+            "register_command" => {
+                let name = self.string(&args, 0)?;
+                Some(RuntimeValue::I32(self.host.register_command(&name) as i32))
+            }
+            "send_message" => {
+                let message = self.string(&args, 1)?;
+                let sent = self.host.send_message(args.nth_checked(0)?, &message);
+                Some(RuntimeValue::I32(sent as i32))
+            }
+            "broadcast" => {
+                let message = self.string(&args, 0)?;
+                self.host.broadcast(&message);
+                None
+            }
+            "execute_command" => {
+                let command = self.string(&args, 0)?;
+                self.host.execute_command(&command);
+                None
+            }
+            "run_later" => {
+                self.host
+                    .run_later(args.nth_checked(0)?, args.nth_checked(1)?);
+                None
+            }
+            "send_packet" => {
+                let data = self.bytes(&args, 2)?;
+                let sent = self
+                    .host
+                    .send_packet(args.nth_checked(0)?, args.nth_checked(1)?, data);
+                Some(RuntimeValue::I32(sent as i32))
+            }
+            "listen" => {
+                let channel = self.string(&args, 0)?;
+                Some(RuntimeValue::I32(self.host.listen(&channel) as i32))
+            }
+            "send_plugin_message" => {
+                let channel = self.string(&args, 1)?;
+                let data = self.bytes(&args, 3)?;
+                let sent = self
+                    .host
+                    .send_plugin_message(args.nth_checked(0)?, &channel, data);
+                Some(RuntimeValue::I32(sent as i32))
+            }
+            _ => {
+                let subchannel = self.string(&args, 0)?;
+                let args_string = self.string(&args, 2)?;
+                let request_args: Vec<String> = if args_string.is_empty() {
+                    Vec::new()
+                } else {
+                    args_string.split('\0').map(str::to_string).collect()
+                };
+                let data = self.bytes(&args, 4)?;
+                let sent = self.host.bungee_request(&subchannel, &request_args, data);
+                Some(RuntimeValue::I32(sent as i32))
+            }
+        };
+        Ok(result)
+    }
+}
+
+/// A plugin in a WebAssembly module
+struct WasmModule {
+    instance: ModuleRef,
+    memory: MemoryRef,
+}
+
+impl WasmModule {
+    /// Calls an export that returns a status
+    fn invoke(&self, host: &mut Host, name: &str, args: &[RuntimeValue]) -> Result<i32, String> {
+        let mut externals = WasmExternals {
+            host,
+            memory: &self.memory,
+        };
+        match self.instance.invoke_export(name, args, &mut externals) {
+            Ok(Some(RuntimeValue::I32(code))) => Ok(code),
+            Ok(_) => Err(format!("{} didn't return a status", name)),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    /// Copies a string into the plugin's memory
+    fn write_string(&self, string: &str) -> Result<(RuntimeValue, RuntimeValue), String> {
+        self.write_bytes(string.as_bytes())
+    }
+
+    fn write_bytes(&self, bytes: &[u8]) -> Result<(RuntimeValue, RuntimeValue), String> {
+        let len = bytes.len() as i32;
+        let ptr = match self.instance.invoke_export(
+            "rustmc_alloc",
+            &[RuntimeValue::I32(len)],
+            &mut NopExternals,
+        ) {
+            Ok(Some(RuntimeValue::I32(ptr))) => ptr,
+            Ok(_) => return Err("rustmc_alloc didn't return a pointer".to_string()),
+            Err(err) => return Err(err.to_string()),
+        };
+        self.memory
+            .set(ptr as u32, bytes)
+            .map_err(|err| err.to_string())?;
+        Ok((RuntimeValue::I32(ptr), RuntimeValue::I32(len)))
+    }
+}
+
+impl Module for WasmModule {
+    fn enable(&mut self, host: &mut Host) -> Result<(), String> {
+        status(self.invoke(host, "rustmc_on_enable", &[])?)
+    }
+
+    fn disable(&mut self, host: &mut Host) -> Result<(), String> {
+        status(self.invoke(host, "rustmc_on_disable", &[])?)
+    }
+
+    fn command(
+        &mut self,
+        host: &mut Host,
+        name: &str,
+        sender: i64,
+        args: &str,
+    ) -> Result<(), String> {
+        let (name_ptr, name_len) = self.write_string(name)?;
+        let (args_ptr, args_len) = self.write_string(args)?;
+        let args = [
+            name_ptr,
+            name_len,
+            RuntimeValue::I64(sender),
+            args_ptr,
+            args_len,
+        ];
+        status(self.invoke(host, "rustmc_on_command", &args)?)
+    }
+
+    fn event(&mut self, host: &mut Host, event: &mut EventData) -> Result<(), String> {
+        let (text_ptr, text_len) = self.write_string(&event.text)?;
+        let (x, y, z) = event.position;
+        let args = [
+            RuntimeValue::I32(event.kind as i32),
+            RuntimeValue::I64(event.client_id as i64),
+            text_ptr,
+            text_len,
+            RuntimeValue::F64(x.into()),
+            RuntimeValue::F64(y.into()),
+            RuntimeValue::F64(z.into()),
+        ];
+        match self.invoke(host, "rustmc_on_event", &args)? {
+            1 => event.cancelled = true,
+            code => status(code)?,
+        }
+        Ok(())
+    }
+
+    fn task(&mut self, host: &mut Host, task: u64) -> Result<(), String> {
+        status(self.invoke(host, "rustmc_on_task", &[RuntimeValue::I64(task as i64)])?)
+    }
+
+    fn plugin_message(
+        &mut self,
+        host: &mut Host,
+        channel: &str,
+        client_id: u32,
+        data: &[u8],
+    ) -> Result<(), String> {
+        let (channel_ptr, channel_len) = self.write_string(channel)?;
+        let (data_ptr, data_len) = self.write_bytes(data)?;
+        let args = [
+            channel_ptr,
+            channel_len,
+            RuntimeValue::I64(client_id as i64),
+            data_ptr,
+            data_len,
+        ];
+        status(self.invoke(host, "rustmc_on_plugin_message", &args)?)
+    }
+}
+
+/// A plugin loaded from the plugins directory
+pub struct DynamicPlugin {
+    plugin: Rc<Shared>,
+    version: String,
+}
+
+impl Plugin for DynamicPlugin {
+    fn name(&self) -> &str {
+        &self.plugin.name
+    }
+
+    fn version(&self) -> &str {
+        &self.version
+    }
+
+    fn on_enable(&mut self, context: &mut PluginContext) {
+        call(context.server(), &self.plugin, |module, host| {
+            module.enable(host)
+        });
+    }
+
+    fn on_disable(&mut self, server: &mut Server) {
+        call(server, &self.plugin, |module, host| module.disable(host));
+    }
+}
+
+fn dynamic_plugin(name: String, version: String, module: Box<dyn Module>) -> DynamicPlugin {
+    DynamicPlugin {
+        plugin: Rc::new(Shared {
+            name,
+            module: RefCell::new(module),
+            failed: Cell::new(false),
+        }),
+        version,
+    }
+}
+
+fn load_native(path: &Path) -> Result<DynamicPlugin, LoadError> {
+    let library = Library::new(path)?;
+    let declaration = unsafe {
+        let declare: Symbol<unsafe extern "C" fn() -> *const PluginDeclaration> = library
+            .get(DECLARE_SYMBOL)
+            .map_err(|_| LoadError::Missing("rustmc_plugin_declare"))?;
+        declare()
+    };
+    if declaration.is_null() {
+        return Err(LoadError::InvalidDeclaration);
+    }
+    let api_version = unsafe { (*declaration).api_version };
+    if api_version != PLUGIN_API_VERSION {
+        return Err(LoadError::IncompatibleVersion(api_version));
+    }
+    let (name, version) = unsafe {
+        (
+            read_c_str((*declaration).name),
+            read_c_str((*declaration).version),
+        )
+    };
+    if name.is_empty() {
+        return Err(LoadError::InvalidDeclaration);
+    }
+    let module = NativeModule {
+        declaration,
+        _library: library,
+    };
+    Ok(dynamic_plugin(name, version, Box::new(module)))
+}
+
+fn load_wasm(path: &Path) -> Result<DynamicPlugin, LoadError> {
+    wasm_plugin(&fs::read(path)?)
+}
+
+fn wasm_plugin(buffer: &[u8]) -> Result<DynamicPlugin, LoadError> {
+    let module = wasmi::Module::from_buffer(buffer)?;
+    let imports = ImportsBuilder::new().with_resolver(WASM_HOST_MODULE, &WasmHostResolver);
+    let instance = ModuleInstance::new(&module, &imports)?
+        .run_start(&mut NopExternals)
+        .map_err(wasmi::Error::from)?;
+    let memory = instance
+        .export_by_name("memory")
+        .and_then(|export| export.as_memory().cloned())
+        .ok_or(LoadError::Missing("memory"))?;
+    let declaration = match instance.invoke_export("rustmc_plugin_declare", &[], &mut NopExternals)
+    {
+        Ok(Some(RuntimeValue::I32(ptr))) => ptr as u32,
+        Ok(_) => return Err(LoadError::InvalidDeclaration),
+        Err(wasmi::Error::Function(_)) => return Err(LoadError::Missing("rustmc_plugin_declare")),
+        Err(err) => return Err(err.into()),
+    };
+    let field = |index: u32| -> Result<u32, LoadError> {
+        memory
+            .get_value(declaration + index * 4)
+            .map_err(|_| LoadError::InvalidDeclaration)
+    };
+    let api_version = field(0)?;
+    if api_version != PLUGIN_API_VERSION {
+        return Err(LoadError::IncompatibleVersion(api_version));
+    }
+    let name = read_wasm_string(&memory, field(1)?, field(2)?)
+        .map_err(|_| LoadError::InvalidDeclaration)?;
+    let version = read_wasm_string(&memory, field(3)?, field(4)?)
+        .map_err(|_| LoadError::InvalidDeclaration)?;
+    if name.is_empty() {
+        return Err(LoadError::InvalidDeclaration);
+    }
+    let module = WasmModule { instance, memory };
+    Ok(dynamic_plugin(name, version, Box::new(module)))
+}
+
+/// Whether a file in the plugins directory is a plugin, by its extension
+fn is_plugin_file(path: &Path) -> bool {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => ["so", "dll", "dylib", "wasm"].contains(&extension),
+        None => false,
+    }
+}
+
+/// Loads a plugin from a shared library or a WebAssembly module
+pub fn load(path: &Path) -> Result<DynamicPlugin, LoadError> {
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "wasm")
+    {
+        load_wasm(path)
+    } else {
+        load_native(path)
+    }
+}
+
+/// The plugin files in the plugins directory in alphabetical order, the directory is created if
+/// it doesn't exist
+pub fn plugin_files() -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(PLUGINS_DIR)?;
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(PLUGINS_DIR)? {
+        let path = entry?.path();
+        if path.is_file() && is_plugin_file(&path) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leb128(mut value: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(byte);
+                return bytes;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+
+    /// Vectors and sections are prefixed with their length
+    fn sized(contents: Vec<u8>) -> Vec<u8> {
+        let mut bytes = leb128(contents.len() as u32);
+        bytes.extend(contents);
+        bytes
+    }
+
+    fn vector(items: Vec<Vec<u8>>) -> Vec<u8> {
+        let mut bytes = leb128(items.len() as u32);
+        bytes.extend(items.into_iter().flatten());
+        bytes
+    }
+
+    fn name(name: &str) -> Vec<u8> {
+        sized(name.as_bytes().to_vec())
+    }
+
+    fn value_type(value_type: ValueType) -> u8 {
+        match value_type {
+            ValueType::I32 => 0x7F,
+            ValueType::I64 => 0x7E,
+            ValueType::F32 => 0x7D,
+            ValueType::F64 => 0x7C,
+        }
+    }
+
+    fn function_type(params: &[ValueType], result: Option<ValueType>) -> Vec<u8> {
+        let mut bytes = vec![0x60];
+        bytes.extend(sized(
+            params.iter().map(|param| value_type(*param)).collect(),
+        ));
+        bytes.extend(sized(result.into_iter().map(value_type).collect()));
+        bytes
+    }
+
+    /// A plugin with the name `test` and version `1.0`, its declaration is at address 16
+    struct TestModule {
+        imports: Vec<(&'static str, &'static [ValueType], Option<ValueType>)>,
+        /// Exported functions without arguments that return an i32, by their code
+        functions: Vec<(&'static str, Vec<u8>)>,
+        declaration: [u32; 5],
+    }
+
+    impl TestModule {
+        fn new() -> TestModule {
+            TestModule {
+                imports: Vec::new(),
+                functions: vec![
+                    // i32.const 16
+                    ("rustmc_plugin_declare", vec![0x41, 16]),
+                    // i32.const 0
+                    ("rustmc_on_enable", vec![0x41, 0]),
+                ],
+                declaration: [PLUGIN_API_VERSION, 0, 4, 8, 3],
+            }
+        }
+
+        fn function(mut self, name: &'static str, code: Vec<u8>) -> TestModule {
+            self.functions.retain(|(function, _)| *function != name);
+            self.functions.push((name, code));
+            self
+        }
+
+        fn assemble(&self) -> Vec<u8> {
+            let mut types = vec![function_type(&[], Some(ValueType::I32))];
+            let mut imports = Vec::new();
+            for (i, (function, params, result)) in self.imports.iter().enumerate() {
+                types.push(function_type(params, *result));
+                let mut import = name(WASM_HOST_MODULE);
+                import.extend(name(function));
+                import.push(0x00);
+                import.extend(leb128(i as u32 + 1));
+                imports.push(import);
+            }
+            let mut exports = vec![[name("memory"), vec![0x02, 0x00]].concat()];
+            let mut bodies = Vec::new();
+            for (i, (function, code)) in self.functions.iter().enumerate() {
+                let index = (self.imports.len() + i) as u32;
+                exports.push([name(function), vec![0x00], leb128(index)].concat());
+                bodies.push(sized([&[0x00][..], code, &[0x0B]].concat()));
+            }
+            let mut data = [&b"test"[..], &[0; 4], b"1.0", &[0; 5]].concat();
+            for field in &self.declaration {
+                data.extend_from_slice(&field.to_le_bytes());
+            }
+            let segment = [vec![0x00, 0x41, 0x00, 0x0B], sized(data)].concat();
+
+            let mut module = b"\0asm\x01\0\0\0".to_vec();
+            let sections = vec![
+                (1, vector(types)),
+                (2, vector(imports)),
+                (3, vector(vec![vec![0x00]; self.functions.len()])),
+                // One page of memory
+                (5, vec![0x01, 0x00, 0x01]),
+                (7, vector(exports)),
+                (10, vector(bodies)),
+                (11, vector(vec![segment])),
+            ];
+            for (id, contents) in sections {
+                module.push(id);
+                module.extend(sized(contents));
+            }
+            module
+        }
+
+        fn load(&self) -> Result<DynamicPlugin, LoadError> {
+            wasm_plugin(&self.assemble())
+        }
+    }
+
+    #[test]
+    fn declaration() {
+        let plugin = TestModule::new().load().unwrap();
+        assert_eq!(plugin.name(), "test");
+        assert_eq!(plugin.version(), "1.0");
+    }
+
+    #[test]
+    fn incompatible_version() {
+        let mut module = TestModule::new();
+        module.declaration[0] = PLUGIN_API_VERSION + 1;
+        match module.load() {
+            Err(LoadError::IncompatibleVersion(version)) => {
+                assert_eq!(version, PLUGIN_API_VERSION + 1)
+            }
+            _ => panic!("Expected the version to be rejected"),
+        }
+    }
+
+    #[test]
+    fn invalid_declaration() {
+        let mut empty_name = TestModule::new();
+        empty_name.declaration[2] = 0;
+        let mut name_out_of_bounds = TestModule::new();
+        name_out_of_bounds.declaration[1] = 65535;
+        // i32.const -4, past the end of the memory
+        let declaration_out_of_bounds =
+            TestModule::new().function("rustmc_plugin_declare", vec![0x41, 0x7C]);
+        for module in &[empty_name, name_out_of_bounds, declaration_out_of_bounds] {
+            assert!(matches!(module.load(), Err(LoadError::InvalidDeclaration)));
+        }
+
+        let mut undeclared = TestModule::new();
+        undeclared
+            .functions
+            .retain(|(function, _)| *function != "rustmc_plugin_declare");
+        assert!(matches!(
+            undeclared.load(),
+            Err(LoadError::Missing("rustmc_plugin_declare"))
+        ));
+        assert!(matches!(wasm_plugin(b"not wasm"), Err(LoadError::Wasm(_))));
+    }
+
+    #[test]
+    fn host_function_signatures() {
+        let mut module = TestModule::new();
+        module.imports = vec![
+            (
+                "log",
+                &[ValueType::I32, ValueType::I32, ValueType::I32],
+                None,
+            ),
+            (
+                "listen",
+                &[ValueType::I32, ValueType::I32],
+                Some(ValueType::I32),
+            ),
+        ];
+        assert!(module.load().is_ok());
+
+        for import in &[
+            ("log", &[ValueType::I32, ValueType::I32][..], None),
+            ("listen", &[ValueType::I32, ValueType::I32], None),
+            ("run_later", &[ValueType::I32, ValueType::I64], None),
+            ("teleport", &[], None),
+        ] {
+            let mut module = TestModule::new();
+            module.imports = vec![*import];
+            assert!(
+                matches!(module.load(), Err(LoadError::Wasm(_))),
+                "{} shouldn't resolve",
+                import.0
+            );
+        }
+    }
+
+    #[test]
+    fn traps_disable_the_plugin() {
+        let mut server = Server::unstarted();
+        // unreachable
+        let module = TestModule::new().function("rustmc_on_enable", vec![0x00]);
+        let mut plugin = module.load().unwrap();
+        plugin.on_enable(&mut PluginContext::new(&mut server, "test"));
+        assert!(plugin.plugin.failed.get());
+        assert_eq!(server.plugins.take_failed(), ["test"]);
+
+        // Plugins that failed aren't called again
+        plugin.on_enable(&mut PluginContext::new(&mut server, "test"));
+        assert!(server.plugins.take_failed().is_empty());
+
+        let mut plugin = TestModule::new().load().unwrap();
+        plugin.on_enable(&mut PluginContext::new(&mut server, "test"));
+        assert!(!plugin.plugin.failed.get());
+        assert!(server.plugins.take_failed().is_empty());
+    }
+}
@@ -0,0 +1,59 @@
+use crate::packets::NetworkState;
+
+/// A protocol version the server can talk to.
+/// Packets in packets.rs are named after and encoded with their ids in the latest version,
+/// which get mapped to the ids of the client's version when sent or received.
+/// Packets whose fields differ between versions take the version to encode with,
+/// like Join Game and Chunk Data which changed in 1.15.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ProtocolVersion {
+    V1_14_4 = 498,
+    V1_15_1 = 575,
+    V1_15_2 = 578,
+}
+
+impl ProtocolVersion {
+    pub const LATEST: ProtocolVersion = ProtocolVersion::V1_15_2;
+    pub const OLDEST: ProtocolVersion = ProtocolVersion::V1_14_4;
+
+    pub fn id(self) -> i32 {
+        self as i32
+    }
+
+    /// Returns None if the version isn't supported
+    pub fn from_id(id: i32) -> Option<ProtocolVersion> {
+        match id {
+            498 => Some(ProtocolVersion::V1_14_4),
+            575 => Some(ProtocolVersion::V1_15_1),
+            578 => Some(ProtocolVersion::V1_15_2),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ProtocolVersion::V1_14_4 => "1.14.4",
+            ProtocolVersion::V1_15_1 => "1.15.1",
+            ProtocolVersion::V1_15_2 => "1.15.2",
+        }
+    }
+
+    /// Maps the id of a clientbound packet in the latest version to the id used by this version
+    pub fn clientbound_id(self, state: NetworkState, packet_id: i32) -> i32 {
+        match (self, state) {
+            (ProtocolVersion::V1_14_4, NetworkState::PLAY) => match packet_id {
+                // Acknowledge Player Digging was moved from the end of the list in 1.15
+                0x08 => 0x5C,
+                0x09..=0x5C => packet_id - 1,
+                _ => packet_id,
+            },
+            _ => packet_id,
+        }
+    }
+
+    /// Maps the id of a serverbound packet sent by this version to the id used by the latest version
+    pub fn serverbound_id(self, _state: NetworkState, packet_id: i32) -> i32 {
+        // The serverbound packets haven't changed between 1.14.4 and 1.15.2
+        packet_id
+    }
+}
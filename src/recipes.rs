@@ -0,0 +1,593 @@
+use crate::inventory::{CraftingGrid, ItemStack};
+use crate::registry::Item;
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum RecipeError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    UnknownItem(String),
+    UnknownTag(String),
+    InvalidPattern(String),
+    UnknownType(String),
+}
+
+impl fmt::Display for RecipeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecipeError::Io(err) => write!(f, "{}", err),
+            RecipeError::Json(err) => write!(f, "{}", err),
+            RecipeError::UnknownItem(item) => write!(f, "Unknown item '{}'", item),
+            RecipeError::UnknownTag(tag) => write!(f, "Unknown item tag '{}'", tag),
+            RecipeError::InvalidPattern(reason) => write!(f, "Invalid pattern: {}", reason),
+            RecipeError::UnknownType(recipe_type) => {
+                write!(f, "Invalid or unsupported recipe type '{}'", recipe_type)
+            }
+        }
+    }
+}
+
+impl Error for RecipeError {}
+
+impl From<io::Error> for RecipeError {
+    fn from(err: io::Error) -> RecipeError {
+        RecipeError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for RecipeError {
+    fn from(err: serde_json::Error) -> RecipeError {
+        RecipeError::Json(err)
+    }
+}
+
+type RecipeResult<T> = Result<T, RecipeError>;
+
+/// A set of item ids any of which satisfy a recipe slot. An empty ingredient only matches an empty slot.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ingredient {
+    pub items: Vec<Item>,
+}
+
+impl Ingredient {
+    fn empty() -> Ingredient {
+        Ingredient { items: Vec::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn test(&self, stack: &Option<ItemStack>) -> bool {
+        match stack {
+            Some(stack) => self.items.contains(&stack.item),
+            None => self.is_empty(),
+        }
+    }
+}
+
+pub enum RecipeKind {
+    Shaped {
+        width: usize,
+        height: usize,
+        /// Row major, `width * height` long
+        ingredients: Vec<Ingredient>,
+        result: ItemStack,
+    },
+    Shapeless {
+        ingredients: Vec<Ingredient>,
+        result: ItemStack,
+    },
+    /// Smelting, blasting, smoking and campfire cooking
+    Cooking {
+        ingredient: Ingredient,
+        result: ItemStack,
+        experience: f32,
+        cooking_time: i32,
+    },
+    Stonecutting {
+        ingredient: Ingredient,
+        result: ItemStack,
+    },
+    /// Recipes like `crafting_special_firework_rocket` which are hardcoded in the client
+    Special,
+}
+
+pub struct Recipe {
+    pub id: String,
+    pub recipe_type: String,
+    pub group: String,
+    pub kind: RecipeKind,
+}
+
+impl Recipe {
+    pub fn crafting_result(&self) -> Option<&ItemStack> {
+        match &self.kind {
+            RecipeKind::Shaped { result, .. } | RecipeKind::Shapeless { result, .. } => {
+                Some(result)
+            }
+            _ => None,
+        }
+    }
+
+    fn matches(&self, grid: &CraftingGrid) -> bool {
+        match &self.kind {
+            RecipeKind::Shaped {
+                width,
+                height,
+                ingredients,
+                ..
+            } => {
+                if *width > grid.size || *height > grid.size {
+                    return false;
+                }
+                for x in 0..=(grid.size - width) {
+                    for y in 0..=(grid.size - height) {
+                        if Recipe::matches_shaped(grid, *width, *height, ingredients, x, y, true)
+                            || Recipe::matches_shaped(
+                                grid,
+                                *width,
+                                *height,
+                                ingredients,
+                                x,
+                                y,
+                                false,
+                            )
+                        {
+                            return true;
+                        }
+                    }
+                }
+                false
+            }
+            RecipeKind::Shapeless { ingredients, .. } => {
+                let stacks: Vec<&ItemStack> =
+                    grid.slots.iter().filter_map(|s| s.as_ref()).collect();
+                if stacks.len() != ingredients.len() {
+                    return false;
+                }
+                let mut used = vec![false; ingredients.len()];
+                Recipe::matches_shapeless(&stacks, ingredients, &mut used)
+            }
+            _ => false,
+        }
+    }
+
+    fn matches_shaped(
+        grid: &CraftingGrid,
+        width: usize,
+        height: usize,
+        ingredients: &[Ingredient],
+        offset_x: usize,
+        offset_y: usize,
+        mirrored: bool,
+    ) -> bool {
+        for x in 0..grid.size {
+            for y in 0..grid.size {
+                let ingredient = if x >= offset_x
+                    && y >= offset_y
+                    && x - offset_x < width
+                    && y - offset_y < height
+                {
+                    let pattern_x = if mirrored {
+                        width - (x - offset_x) - 1
+                    } else {
+                        x - offset_x
+                    };
+                    &ingredients[pattern_x + (y - offset_y) * width]
+                } else {
+                    &EMPTY_INGREDIENT
+                };
+                if !ingredient.test(grid.get(x, y)) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Tries to assign every stack to a distinct ingredient
+    fn matches_shapeless(
+        stacks: &[&ItemStack],
+        ingredients: &[Ingredient],
+        used: &mut Vec<bool>,
+    ) -> bool {
+        let (stack, rest) = match stacks.split_first() {
+            Some(split) => split,
+            None => return true,
+        };
+        for (i, ingredient) in ingredients.iter().enumerate() {
+            if !used[i] && ingredient.items.contains(&stack.item) {
+                used[i] = true;
+                if Recipe::matches_shapeless(rest, ingredients, used) {
+                    return true;
+                }
+                used[i] = false;
+            }
+        }
+        false
+    }
+}
+
+static EMPTY_INGREDIENT: Ingredient = Ingredient { items: Vec::new() };
+
+#[derive(Deserialize)]
+struct TagJson {
+    values: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum IngredientJson {
+    Single(IngredientEntryJson),
+    List(Vec<IngredientEntryJson>),
+}
+
+#[derive(Deserialize)]
+struct IngredientEntryJson {
+    item: Option<String>,
+    tag: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CraftingResultJson {
+    item: String,
+    #[serde(default = "default_count")]
+    count: i8,
+}
+
+fn default_count() -> i8 {
+    1
+}
+
+#[derive(Deserialize)]
+struct ShapedJson {
+    #[serde(default)]
+    group: String,
+    pattern: Vec<String>,
+    key: HashMap<String, IngredientJson>,
+    result: CraftingResultJson,
+}
+
+#[derive(Deserialize)]
+struct ShapelessJson {
+    #[serde(default)]
+    group: String,
+    ingredients: Vec<IngredientJson>,
+    result: CraftingResultJson,
+}
+
+#[derive(Deserialize)]
+struct CookingJson {
+    #[serde(default)]
+    group: String,
+    ingredient: IngredientJson,
+    result: String,
+    #[serde(default)]
+    experience: f32,
+    cookingtime: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct StonecuttingJson {
+    #[serde(default)]
+    group: String,
+    ingredient: IngredientJson,
+    result: String,
+    count: i8,
+}
+
+pub struct RecipeRegistry {
+    pub recipes: Vec<Recipe>,
+}
+
+impl RecipeRegistry {
+    /// Loads every recipe in a directory of vanilla format recipe files.
+    /// Ingredient tags are resolved against the item tags in `tags_dir`.
+    pub fn load(recipes_dir: &str, tags_dir: &str) -> RecipeRegistry {
+        let tags = match RecipeRegistry::load_tags(tags_dir) {
+            Ok(tags) => tags,
+            Err(err) => {
+                warn!("Couldn't load item tags: {}", err);
+                HashMap::new()
+            }
+        };
+        let loader = RecipeLoader { tags };
+
+        let mut recipes = Vec::new();
+        let mut paths: Vec<_> = match fs::read_dir(recipes_dir) {
+            Ok(dir) => dir
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .collect(),
+            Err(err) => {
+                warn!("Couldn't read recipes from {}: {}", recipes_dir, err);
+                Vec::new()
+            }
+        };
+        paths.sort();
+        for path in paths {
+            let id = match path.file_stem() {
+                Some(stem) => format!("minecraft:{}", stem.to_string_lossy()),
+                None => continue,
+            };
+            match loader.load_recipe(&path, id.clone()) {
+                Ok(recipe) => recipes.push(recipe),
+                Err(err) => warn!("Parsing error loading recipe {}: {}", id, err),
+            }
+        }
+        info!("Loaded {} recipes", recipes.len());
+        RecipeRegistry { recipes }
+    }
+
+    fn load_tags(tags_dir: &str) -> RecipeResult<HashMap<String, TagJson>> {
+        let mut tags = HashMap::new();
+        for entry in fs::read_dir(tags_dir)? {
+            let path = entry?.path();
+            if let Some(stem) = path.file_stem() {
+                let tag: TagJson = serde_json::from_str(&fs::read_to_string(&path)?)?;
+                tags.insert(format!("minecraft:{}", stem.to_string_lossy()), tag);
+            }
+        }
+        Ok(tags)
+    }
+
+    /// Finds the crafting recipe matching the contents of a 2x2 or 3x3 grid.
+    pub fn match_crafting(&self, grid: &CraftingGrid) -> Option<&Recipe> {
+        if grid.slots.iter().all(|slot| slot.is_none()) {
+            return None;
+        }
+        self.recipes.iter().find(|recipe| recipe.matches(grid))
+    }
+}
+
+struct RecipeLoader {
+    tags: HashMap<String, TagJson>,
+}
+
+impl RecipeLoader {
+    fn load_recipe(&self, path: &Path, id: String) -> RecipeResult<Recipe> {
+        let json: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let recipe_type = json["type"].as_str().unwrap_or("").to_string();
+        let (group, kind) = match recipe_type.as_str() {
+            "minecraft:crafting_shaped" => {
+                let shaped: ShapedJson = serde_json::from_value(json)?;
+                let height = shaped.pattern.len();
+                let width = shaped
+                    .pattern
+                    .first()
+                    .map(|row| row.chars().count())
+                    .unwrap_or(0);
+                if width == 0 || width > 3 || height > 3 {
+                    return Err(RecipeError::InvalidPattern(format!(
+                        "{}x{} is not a valid size",
+                        width, height
+                    )));
+                }
+                let mut ingredients = Vec::with_capacity(width * height);
+                for row in &shaped.pattern {
+                    if row.chars().count() != width {
+                        return Err(RecipeError::InvalidPattern(
+                            "Rows differ in width".to_string(),
+                        ));
+                    }
+                    for key in row.chars() {
+                        if key == ' ' {
+                            ingredients.push(Ingredient::empty());
+                            continue;
+                        }
+                        match shaped.key.get(&key.to_string()) {
+                            Some(ingredient) => ingredients.push(self.ingredient(ingredient)?),
+                            None => {
+                                return Err(RecipeError::InvalidPattern(format!(
+                                    "Undefined symbol '{}'",
+                                    key
+                                )))
+                            }
+                        }
+                    }
+                }
+                let result = self.crafting_result(&shaped.result)?;
+                (
+                    shaped.group,
+                    RecipeKind::Shaped {
+                        width,
+                        height,
+                        ingredients,
+                        result,
+                    },
+                )
+            }
+            "minecraft:crafting_shapeless" => {
+                let shapeless: ShapelessJson = serde_json::from_value(json)?;
+                let mut ingredients = Vec::new();
+                for ingredient in &shapeless.ingredients {
+                    ingredients.push(self.ingredient(ingredient)?);
+                }
+                let result = self.crafting_result(&shapeless.result)?;
+                (
+                    shapeless.group,
+                    RecipeKind::Shapeless {
+                        ingredients,
+                        result,
+                    },
+                )
+            }
+            "minecraft:smelting"
+            | "minecraft:blasting"
+            | "minecraft:smoking"
+            | "minecraft:campfire_cooking" => {
+                let cooking: CookingJson = serde_json::from_value(json)?;
+                let default_time = match recipe_type.as_str() {
+                    "minecraft:smelting" => 200,
+                    "minecraft:blasting" | "minecraft:smoking" => 100,
+                    _ => 600,
+                };
+                (
+                    cooking.group,
+                    RecipeKind::Cooking {
+                        ingredient: self.ingredient(&cooking.ingredient)?,
+                        result: ItemStack::new(self.item(&cooking.result)?, 1),
+                        experience: cooking.experience,
+                        cooking_time: cooking.cookingtime.unwrap_or(default_time),
+                    },
+                )
+            }
+            "minecraft:stonecutting" => {
+                let stonecutting: StonecuttingJson = serde_json::from_value(json)?;
+                (
+                    stonecutting.group,
+                    RecipeKind::Stonecutting {
+                        ingredient: self.ingredient(&stonecutting.ingredient)?,
+                        result: ItemStack::new(
+                            self.item(&stonecutting.result)?,
+                            stonecutting.count,
+                        ),
+                    },
+                )
+            }
+            special if special.starts_with("minecraft:crafting_special_") => {
+                (String::new(), RecipeKind::Special)
+            }
+            other => return Err(RecipeError::UnknownType(other.to_string())),
+        };
+        Ok(Recipe {
+            id,
+            recipe_type,
+            group,
+            kind,
+        })
+    }
+
+    fn item(&self, name: &str) -> RecipeResult<Item> {
+        Item::from_name(name).ok_or_else(|| RecipeError::UnknownItem(name.to_string()))
+    }
+
+    fn crafting_result(&self, result: &CraftingResultJson) -> RecipeResult<ItemStack> {
+        Ok(ItemStack::new(self.item(&result.item)?, result.count))
+    }
+
+    fn ingredient(&self, json: &IngredientJson) -> RecipeResult<Ingredient> {
+        let entries = match json {
+            IngredientJson::Single(entry) => std::slice::from_ref(entry),
+            IngredientJson::List(entries) => entries.as_slice(),
+        };
+        let mut items = Vec::new();
+        for entry in entries {
+            if let Some(item) = &entry.item {
+                items.push(self.item(item)?);
+            } else if let Some(tag) = &entry.tag {
+                self.tag_items(tag, &mut items)?;
+            }
+        }
+        Ok(Ingredient { items })
+    }
+
+    fn tag_items(&self, tag: &str, items: &mut Vec<Item>) -> RecipeResult<()> {
+        let tag_json = self
+            .tags
+            .get(tag)
+            .ok_or_else(|| RecipeError::UnknownTag(tag.to_string()))?;
+        for value in &tag_json.values {
+            if let Some(nested) = value.strip_prefix('#') {
+                self.tag_items(nested, items)?;
+            } else {
+                let item = self.item(value)?;
+                if !items.contains(&item) {
+                    items.push(item);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> RecipeRegistry {
+        RecipeRegistry::load("res/recipes", "res/tags/items")
+    }
+
+    fn grid(size: usize, items: &[Option<Item>]) -> CraftingGrid {
+        let mut grid = CraftingGrid::new(size);
+        for (slot, item) in grid.slots.iter_mut().zip(items) {
+            *slot = item.map(|item| ItemStack::new(item, 1));
+        }
+        grid
+    }
+
+    fn crafted(registry: &RecipeRegistry, grid: &CraftingGrid) -> Option<Item> {
+        registry
+            .match_crafting(grid)
+            .and_then(|recipe| recipe.crafting_result())
+            .map(|result| result.item)
+    }
+
+    #[test]
+    fn shaped() {
+        let registry = registry();
+        let (d, g, p) = (Some(Item::Dirt), Some(Item::Gravel), Some(Item::OakPlanks));
+        let coarse_dirt = Some(Item::CoarseDirt);
+        assert_eq!(crafted(&registry, &grid(2, &[d, g, g, d])), coarse_dirt);
+        // Mirrored horizontally
+        assert_eq!(crafted(&registry, &grid(2, &[g, d, d, g])), coarse_dirt);
+        // Anywhere in a bigger grid
+        let shifted = grid(3, &[None, None, None, None, d, g, None, g, d]);
+        assert_eq!(crafted(&registry, &shifted), coarse_dirt);
+        assert_eq!(crafted(&registry, &grid(2, &[d, g, d, g])), None);
+        assert_eq!(crafted(&registry, &grid(2, &[d, g, g, None])), None);
+
+        let stairs = Some(Item::OakStairs);
+        let pattern = [p, None, None, p, p, None, p, p, p];
+        let mirrored = [None, None, p, None, p, p, p, p, p];
+        let flipped = [p, p, p, p, p, None, p, None, None];
+        assert_eq!(crafted(&registry, &grid(3, &pattern)), stairs);
+        assert_eq!(crafted(&registry, &grid(3, &mirrored)), stairs);
+        assert_eq!(crafted(&registry, &grid(3, &flipped)), None);
+        // Too big for the player's grid
+        assert_eq!(crafted(&registry, &grid(2, &[p, None, p, p])), None);
+    }
+
+    #[test]
+    fn shapeless() {
+        let dirt = Ingredient {
+            items: vec![Item::Dirt],
+        };
+        let gravel = Ingredient {
+            items: vec![Item::Gravel, Item::Sand],
+        };
+        let registry = RecipeRegistry {
+            recipes: vec![Recipe {
+                id: "test:coarse_dirt".to_string(),
+                recipe_type: "minecraft:crafting_shapeless".to_string(),
+                group: String::new(),
+                kind: RecipeKind::Shapeless {
+                    ingredients: vec![dirt.clone(), gravel, dirt],
+                    result: ItemStack::new(Item::CoarseDirt, 3),
+                },
+            }],
+        };
+        let (d, g, s) = (Some(Item::Dirt), Some(Item::Gravel), Some(Item::Sand));
+        let coarse_dirt = Some(Item::CoarseDirt);
+        assert_eq!(crafted(&registry, &grid(2, &[d, d, g, None])), coarse_dirt);
+        assert_eq!(crafted(&registry, &grid(2, &[None, s, d, d])), coarse_dirt);
+        let scattered = grid(3, &[d, None, None, None, g, None, None, None, d]);
+        assert_eq!(crafted(&registry, &scattered), coarse_dirt);
+        // Every ingredient needs its own stack
+        assert_eq!(crafted(&registry, &grid(2, &[d, g, None, None])), None);
+        assert_eq!(crafted(&registry, &grid(2, &[d, g, g, None])), None);
+        assert_eq!(crafted(&registry, &grid(2, &[d, d, d, None])), None);
+        assert_eq!(crafted(&registry, &grid(2, &[d, d, g, g])), None);
+        assert_eq!(crafted(&registry, &grid(2, &[None; 4])), None);
+    }
+}
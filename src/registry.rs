@@ -0,0 +1,71 @@
+//! Typed registries generated by build.rs from res/registries.json.
+//! Every registry is an enum with `id`, `from_id`, `name` and `from_name` lookups,
+//! e.g. `Item::OakPlanks`, `EntityType::Player` or `Menu::Crafting`.
+//! The ids are the latest version's, `protocol_id` and `from_protocol_id` map them for older
+//! clients, using the entries res/registries_added_1_15.json lists as missing from 1.14.4.
+
+use crate::protocol::ProtocolVersion;
+
+include!(concat!(env!("OUT_DIR"), "/registries.rs"));
+
+/// Maps an id of the latest version to `protocol`, given the sorted ids of the entries added
+/// in 1.15 which moved the ones after them. None if the entry is one of those.
+fn protocol_id(id: i32, added_in_1_15: &[i32], protocol: ProtocolVersion) -> Option<i32> {
+    if protocol >= ProtocolVersion::V1_15_1 {
+        return Some(id);
+    }
+    if added_in_1_15.contains(&id) {
+        return None;
+    }
+    Some(id - added_in_1_15.iter().filter(|&&added| added < id).count() as i32)
+}
+
+/// The inverse of `protocol_id`
+fn latest_id(id: i32, added_in_1_15: &[i32], protocol: ProtocolVersion) -> i32 {
+    if protocol >= ProtocolVersion::V1_15_1 {
+        return id;
+    }
+    added_in_1_15
+        .iter()
+        .fold(id, |id, &added| if added <= id { id + 1 } else { id })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn older_protocol_ids() {
+        // The bee was added at 4 in 1.15
+        let v1_14_4 = ProtocolVersion::V1_14_4;
+        assert_eq!(EntityType::Bee.protocol_id(v1_14_4), None);
+        assert_eq!(EntityType::Bat.protocol_id(v1_14_4), Some(3));
+        assert_eq!(EntityType::Blaze.protocol_id(v1_14_4), Some(4));
+        assert_eq!(
+            EntityType::Blaze.protocol_id(ProtocolVersion::LATEST),
+            Some(5)
+        );
+        assert_eq!(
+            EntityType::from_protocol_id(4, v1_14_4),
+            Some(EntityType::Blaze)
+        );
+        assert_eq!(
+            EntityType::from_protocol_id(3, v1_14_4),
+            Some(EntityType::Bat)
+        );
+        assert_eq!(
+            EntityType::from_protocol_id(4, ProtocolVersion::LATEST),
+            Some(EntityType::Bee)
+        );
+        for id in 0..200 {
+            if let Some(entity) = EntityType::from_protocol_id(id, v1_14_4) {
+                assert_eq!(entity.protocol_id(v1_14_4), Some(id));
+            }
+        }
+
+        assert_eq!(ParticleType::DrippingHoney.protocol_id(v1_14_4), None);
+        assert_eq!(Item::Stone.protocol_id(v1_14_4), Some(1));
+        assert_eq!(latest_id(5, &[4, 5], v1_14_4), 7);
+        assert_eq!(protocol_id(7, &[4, 5], v1_14_4), Some(5));
+    }
+}
@@ -0,0 +1,494 @@
+//! Runs tasks on the main thread a number of ticks later or repeatedly, and jobs on a pool of
+//! worker threads whose results are handed back to the main thread.
+
+use log::error;
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// How many jobs can run at the same time
+const WORKER_THREADS: usize = 4;
+
+pub type Task<S> = Box<dyn FnOnce(&mut S)>;
+pub type RepeatingTask<S> = Box<dyn FnMut(&mut S)>;
+type Job = Box<dyn FnOnce() -> Box<dyn Any + Send> + Send>;
+type Completion<S> = Box<dyn FnOnce(&mut S, Box<dyn Any + Send>)>;
+/// The result of a job, or None if it panicked
+type JobResult = (TaskId, Option<Box<dyn Any + Send>>);
+
+/// Identifies a scheduled task or job to cancel it
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct TaskId(u64);
+
+struct ScheduledTask<S> {
+    id: TaskId,
+    /// The plugin that scheduled the task, to cancel it when the plugin is disabled.
+    /// None for the server's own tasks.
+    plugin: Option<String>,
+    /// The tick to run on
+    tick: u64,
+    /// How many ticks until it runs again, None if it only runs once
+    period: Option<u64>,
+    task: RepeatingTask<S>,
+}
+
+/// A task that's due, it's run by the server and handed back with `Scheduler::finish`
+pub struct DueTask<S> {
+    scheduled: ScheduledTask<S>,
+}
+
+impl<S> DueTask<S> {
+    pub fn run(&mut self, state: &mut S) {
+        (self.scheduled.task)(state);
+    }
+}
+
+struct PendingJob<S> {
+    plugin: Option<String>,
+    completion: Completion<S>,
+}
+
+/// Threads that run jobs off the main thread and send back what they return
+struct WorkerPool {
+    jobs: Sender<(TaskId, Job)>,
+    results: Receiver<JobResult>,
+}
+
+impl WorkerPool {
+    fn new() -> WorkerPool {
+        let (job_sender, job_receiver) = mpsc::channel::<(TaskId, Job)>();
+        let (result_sender, result_receiver) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        for i in 0..WORKER_THREADS {
+            let jobs = job_receiver.clone();
+            let results = result_sender.clone();
+            thread::Builder::new()
+                .name(format!("Worker #{}", i + 1))
+                .spawn(move || loop {
+                    // The lock is only held while waiting, so the other workers can run jobs
+                    let job = jobs.lock().unwrap().recv();
+                    let (id, job) = match job {
+                        Ok(job) => job,
+                        // The scheduler was dropped
+                        Err(_) => break,
+                    };
+                    let result = panic::catch_unwind(AssertUnwindSafe(job)).ok();
+                    if results.send((id, result)).is_err() {
+                        break;
+                    }
+                })
+                .unwrap();
+        }
+        WorkerPool {
+            jobs: job_sender,
+            results: result_receiver,
+        }
+    }
+}
+
+pub struct Scheduler<S> {
+    /// How many ticks have passed
+    tick: u64,
+    next_id: u64,
+    tasks: Vec<ScheduledTask<S>>,
+    /// The tasks that are due this tick, in the order they were scheduled
+    due: VecDeque<ScheduledTask<S>>,
+    /// The task being run, and whether it was cancelled while running
+    running: Option<(TaskId, Option<String>, bool)>,
+    jobs: HashMap<TaskId, PendingJob<S>>,
+    workers: WorkerPool,
+}
+
+impl<S: 'static> Scheduler<S> {
+    pub fn new() -> Scheduler<S> {
+        Scheduler {
+            tick: 0,
+            next_id: 0,
+            tasks: Vec::new(),
+            due: VecDeque::new(),
+            running: None,
+            jobs: HashMap::new(),
+            workers: WorkerPool::new(),
+        }
+    }
+
+    fn next_id(&mut self) -> TaskId {
+        self.next_id += 1;
+        TaskId(self.next_id)
+    }
+
+    fn schedule(
+        &mut self,
+        plugin: Option<&str>,
+        delay: u64,
+        period: Option<u64>,
+        task: RepeatingTask<S>,
+    ) -> TaskId {
+        let id = self.next_id();
+        self.tasks.push(ScheduledTask {
+            id,
+            plugin: plugin.map(str::to_string),
+            tick: self.tick + delay,
+            period,
+            task,
+        });
+        id
+    }
+
+    /// Runs a task after a number of ticks, 0 runs it at the end of the current tick
+    pub fn run_later(&mut self, plugin: Option<&str>, ticks: u64, task: Task<S>) -> TaskId {
+        let mut task = Some(task);
+        self.schedule(
+            plugin,
+            ticks,
+            None,
+            Box::new(move |state| {
+                if let Some(task) = task.take() {
+                    task(state);
+                }
+            }),
+        )
+    }
+
+    /// Runs a task after a number of ticks and then every `period` ticks until it's cancelled
+    pub fn run_repeating(
+        &mut self,
+        plugin: Option<&str>,
+        delay: u64,
+        period: u64,
+        task: RepeatingTask<S>,
+    ) -> TaskId {
+        // Running every 0 ticks would never let the tick end
+        self.schedule(plugin, delay, Some(period.max(1)), task)
+    }
+
+    /// Runs a job on a worker thread, and then passes what it returns to `then` on the main thread
+    pub fn run_async<T, J, F>(&mut self, plugin: Option<&str>, job: J, then: F) -> TaskId
+    where
+        T: Send + 'static,
+        J: FnOnce() -> T + Send + 'static,
+        F: FnOnce(&mut S, T) + 'static,
+    {
+        let id = self.next_id();
+        let completion: Completion<S> = Box::new(move |state, result| {
+            // The worker boxed exactly what the job returned
+            then(state, *result.downcast::<T>().unwrap());
+        });
+        let job: Job = Box::new(move || Box::new(job()) as Box<dyn Any + Send>);
+        // The workers only stop once the scheduler is dropped, but the job is dropped rather
+        // than taking down the main thread if they're gone anyway
+        if self.workers.jobs.send((id, job)).is_err() {
+            let owner = plugin.unwrap_or("the server");
+            error!(
+                "Couldn't run a job scheduled by {}, the workers stopped",
+                owner
+            );
+            return id;
+        }
+        self.jobs.insert(
+            id,
+            PendingJob {
+                plugin: plugin.map(str::to_string),
+                completion,
+            },
+        );
+        id
+    }
+
+    /// Stops a task from running again, or drops the result of a job.
+    /// Returns false if it already finished or was cancelled.
+    pub fn cancel(&mut self, id: TaskId) -> bool {
+        if let Some((running, _, cancelled)) = &mut self.running {
+            if *running == id && !*cancelled {
+                *cancelled = true;
+                return true;
+            }
+        }
+        let tasks = self.tasks.len() + self.due.len();
+        self.tasks.retain(|task| task.id != id);
+        self.due.retain(|task| task.id != id);
+        tasks != self.tasks.len() + self.due.len() || self.jobs.remove(&id).is_some()
+    }
+
+    /// Cancels everything a plugin scheduled
+    pub fn cancel_plugin(&mut self, plugin: &str) {
+        let plugin = Some(plugin);
+        self.tasks.retain(|task| task.plugin.as_deref() != plugin);
+        self.due.retain(|task| task.plugin.as_deref() != plugin);
+        self.jobs.retain(|_, job| job.plugin.as_deref() != plugin);
+        if let Some((_, owner, cancelled)) = &mut self.running {
+            if owner.as_deref() == plugin {
+                *cancelled = true;
+            }
+        }
+    }
+
+    /// Queues the tasks that are due and the results of finished jobs, and advances to the next tick
+    pub fn tick(&mut self) {
+        let tick = self.tick;
+        self.tick += 1;
+        let (due, pending): (Vec<_>, Vec<_>) =
+            self.tasks.drain(..).partition(|task| task.tick <= tick);
+        self.tasks = pending;
+        self.due.extend(due);
+        while let Ok((id, result)) = self.workers.results.try_recv() {
+            // Cancelled jobs are dropped
+            let job = match self.jobs.remove(&id) {
+                Some(job) => job,
+                None => continue,
+            };
+            let result = match result {
+                Some(result) => result,
+                None => {
+                    let owner = job.plugin.as_deref().unwrap_or("the server");
+                    error!("A job scheduled by {} panicked", owner);
+                    continue;
+                }
+            };
+            let mut completion = Some((job.completion, result));
+            self.due.push_back(ScheduledTask {
+                id,
+                plugin: job.plugin,
+                tick,
+                period: None,
+                task: Box::new(move |state| {
+                    if let Some((completion, result)) = completion.take() {
+                        completion(state, result);
+                    }
+                }),
+            });
+        }
+    }
+
+    /// Takes the next task that's due this tick, it has to be handed back with `finish` after it ran
+    pub fn next_due(&mut self) -> Option<DueTask<S>> {
+        let scheduled = self.due.pop_front()?;
+        self.running = Some((scheduled.id, scheduled.plugin.clone(), false));
+        Some(DueTask { scheduled })
+    }
+
+    /// Schedules a repeating task to run again, unless it was cancelled while running
+    pub fn finish(&mut self, task: DueTask<S>) {
+        let cancelled = match self.running.take() {
+            Some((_, _, cancelled)) => cancelled,
+            None => false,
+        };
+        let mut scheduled = task.scheduled;
+        if let (Some(period), false) = (scheduled.period, cancelled) {
+            // Counted from when it was due, so it doesn't drift
+            scheduled.tick += period;
+            self.tasks.push(scheduled);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    struct State {
+        scheduler: Scheduler<State>,
+        ran: Vec<&'static str>,
+    }
+
+    impl State {
+        fn new() -> State {
+            State {
+                scheduler: Scheduler::new(),
+                ran: Vec::new(),
+            }
+        }
+
+        /// Runs the tasks due this tick like the server does, returns what ran
+        fn tick(&mut self) -> Vec<&'static str> {
+            self.ran.clear();
+            self.scheduler.tick();
+            while let Some(mut task) = self.scheduler.next_due() {
+                task.run(self);
+                self.scheduler.finish(task);
+            }
+            self.ran.clone()
+        }
+
+        /// Ticks until something runs or a second passes, for the results of jobs
+        fn tick_until_ran(&mut self) -> Vec<&'static str> {
+            let start = Instant::now();
+            loop {
+                let ran = self.tick();
+                if !ran.is_empty() || start.elapsed() > Duration::from_secs(1) {
+                    return ran;
+                }
+                thread::sleep(Duration::from_millis(5));
+            }
+        }
+    }
+
+    #[test]
+    fn delay_and_period() {
+        let mut state = State::new();
+        state.scheduler.run_later(
+            None,
+            2,
+            Box::new(|state: &mut State| state.ran.push("later")),
+        );
+        state.scheduler.run_repeating(
+            None,
+            1,
+            2,
+            Box::new(|state: &mut State| state.ran.push("repeating")),
+        );
+        state
+            .scheduler
+            .run_later(None, 0, Box::new(|state: &mut State| state.ran.push("now")));
+        assert_eq!(state.tick(), ["now"]);
+        assert_eq!(state.tick(), ["repeating"]);
+        assert_eq!(state.tick(), ["later"]);
+        assert_eq!(state.tick(), ["repeating"]);
+        assert!(state.tick().is_empty());
+        assert_eq!(state.tick(), ["repeating"]);
+    }
+
+    #[test]
+    fn tasks_due_on_the_same_tick_run_in_order() {
+        let mut state = State::new();
+        for name in &["a", "b", "c"] {
+            state.scheduler.run_later(
+                None,
+                1,
+                Box::new(move |state: &mut State| state.ran.push(name)),
+            );
+        }
+        assert!(state.tick().is_empty());
+        assert_eq!(state.tick(), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn cancel_from_inside_the_task() {
+        let mut state = State::new();
+        let mut runs = 0;
+        let id = Rc::new(Cell::new(None));
+        let task_id = id.clone();
+        id.set(Some(state.scheduler.run_repeating(
+            None,
+            0,
+            1,
+            Box::new(move |state: &mut State| {
+                runs += 1;
+                state.ran.push("repeating");
+                if runs == 2 {
+                    assert!(state.scheduler.cancel(task_id.get().unwrap()));
+                }
+            }),
+        )));
+        assert_eq!(state.tick(), ["repeating"]);
+        assert_eq!(state.tick(), ["repeating"]);
+        assert!(state.tick().is_empty());
+        assert!(!state.scheduler.cancel(id.get().unwrap()));
+    }
+
+    #[test]
+    fn cancel_a_task_due_later_in_the_same_tick() {
+        let mut state = State::new();
+        let later = Rc::new(Cell::new(None));
+        let task_later = later.clone();
+        state.scheduler.run_later(
+            None,
+            0,
+            Box::new(move |state: &mut State| {
+                state.ran.push("first");
+                assert!(state.scheduler.cancel(task_later.get().unwrap()));
+            }),
+        );
+        later.set(Some(state.scheduler.run_later(
+            None,
+            0,
+            Box::new(|state: &mut State| state.ran.push("second")),
+        )));
+        assert_eq!(state.tick(), ["first"]);
+        assert!(state.tick().is_empty());
+    }
+
+    #[test]
+    fn cancel_plugin() {
+        let mut state = State::new();
+        state.scheduler.run_repeating(
+            Some("test"),
+            0,
+            1,
+            Box::new(|state: &mut State| state.ran.push("plugin")),
+        );
+        state.scheduler.run_later(
+            None,
+            1,
+            Box::new(|state: &mut State| state.ran.push("server")),
+        );
+        assert_eq!(state.tick(), ["plugin"]);
+        state.scheduler.cancel_plugin("test");
+        assert_eq!(state.tick(), ["server"]);
+    }
+
+    #[test]
+    fn job_results_run_on_the_main_thread() {
+        let mut state = State::new();
+        let main_thread = thread::current().id();
+        state.scheduler.run_async(
+            None,
+            move || thread::current().id() != main_thread,
+            move |state: &mut State, off_main_thread| {
+                assert!(off_main_thread);
+                assert_eq!(thread::current().id(), main_thread);
+                state.ran.push("job");
+            },
+        );
+        assert_eq!(state.tick_until_ran(), ["job"]);
+    }
+
+    #[test]
+    fn panicking_jobs_are_dropped() {
+        let mut state = State::new();
+        for _ in 0..WORKER_THREADS {
+            state.scheduler.run_async(
+                None,
+                || -> u32 { panic!("job panicked") },
+                |state: &mut State, _| state.ran.push("panicked"),
+            );
+        }
+        // Every worker caught a panic and is still there to run this
+        state.scheduler.run_async(
+            None,
+            || 1,
+            |state: &mut State, result| {
+                assert_eq!(result, 1);
+                state.ran.push("job");
+            },
+        );
+        // The panicked results can arrive after the job's, so wait for all of them
+        let start = Instant::now();
+        let mut ran = Vec::new();
+        while !state.scheduler.jobs.is_empty() && start.elapsed() < Duration::from_secs(1) {
+            ran.extend(state.tick());
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(state.scheduler.jobs.is_empty());
+        assert_eq!(ran, ["job"]);
+    }
+
+    #[test]
+    fn cancelled_jobs_are_dropped() {
+        let mut state = State::new();
+        let id = state.scheduler.run_async(
+            None,
+            || thread::sleep(Duration::from_millis(50)),
+            |state: &mut State, _| state.ran.push("cancelled"),
+        );
+        assert!(state.scheduler.cancel(id));
+        thread::sleep(Duration::from_millis(100));
+        assert!(state.tick().is_empty());
+    }
+}
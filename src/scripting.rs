@@ -0,0 +1,1326 @@
+//! Rhai scripts in the `scripts` directory. Each script runs as a plugin named after its file,
+//! and is reloaded when the file changes. Scripts have these functions:
+//!
+//! - `on(event, handler)` and `on(event, priority, handler)` call `handler(event)` for
+//!   `"join"`, `"quit"`, `"chat"`, `"block_break"`, `"block_place"` and `"move"` events, with
+//!   priorities from `"lowest"` to `"monitor"`
+//! - `command(name, handler)` adds a command that calls `handler(sender, args)`
+//! - `run_later(ticks, function)`, `execute(command)` and `broadcast(message)`
+//! - `run_repeating(delay, period, function)` calls `function()` every `period` ticks until it
+//!   returns `false`
+//! - `listen(channel, handler)` calls `handler(player, data)` with the plugin messages sent on a
+//!   channel, and `player.send_plugin_message(channel, data)` sends one, the data is a blob
+//! - `bungee(subchannel, args)` and `bungee(subchannel, args, data)` send a request to the proxy,
+//!   and `on_bungee(handler)` calls `handler(response)` with its answers. They're maps of the
+//!   `subchannel` and the fields, like `count` for `PlayerCount`.
+//! - `players()`, `player(name)`, `block_at(x, y, z)`, `set_block(x, y, z, block)`, `time()` and
+//!   `weather()` to look at and change the world
+//! - `text(string)` and `translate(key)` to build chat components with `color`, `bold`, `italic`,
+//!   `underline`, `strikethrough` and `append`
+//!
+//! Messages are chat components or strings with markup like `<red>Hello</red>`. A script can
+//! define `on_disable()` to be called before it's unloaded.
+//!
+//! Scripts change blocks right away, but commands, messages and teleports happen once the
+//! handler returns. Players are seen as they were when the handler started.
+
+use crate::blocks::Block;
+use crate::channels::{self, BungeeRequest, BungeeResponse};
+use crate::chat::{ChatColor, ChatComponent};
+use crate::commands::{argument, literal, ArgumentType, CommandContext, CommandSender, StringKind};
+use crate::events::{
+    BlockBreakEvent, BlockPlaceEvent, ChatEvent, DynamicEvent, EventData, EventPriority, MoveEvent,
+    PlayerJoinEvent, PlayerQuitEvent, EVENT_BLOCK_BREAK, EVENT_BLOCK_PLACE, EVENT_CHAT,
+    EVENT_PLAYER_JOIN, EVENT_PLAYER_QUIT,
+};
+use crate::network::Server;
+use crate::packets::ChatPosition;
+use crate::player::Gamemode;
+use crate::plugin::{Plugin, PluginContext};
+use crate::utils::{self, BlockPosition, Location};
+use crate::world::World;
+use log::{error, info, warn};
+use rhai::{
+    Array, Blob, CallFnOptions, Dynamic, Engine, EvalAltResult, FnPtr, Map, ParseError, Scope, AST,
+};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
+
+pub const SCRIPTS_DIR: &str = "scripts";
+/// Stops scripts that run too long, like ones stuck in a loop
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// The names scripts use for events, by their `EVENT_` constant
+const EVENT_NAMES: &[&str] = &["join", "quit", "chat", "block_break", "block_place", "move"];
+
+#[derive(Debug)]
+pub enum ScriptError {
+    Io(io::Error),
+    Parse(ParseError),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptError::Io(err) => write!(f, "{}", err),
+            ScriptError::Parse(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for ScriptError {}
+
+impl From<io::Error> for ScriptError {
+    fn from(err: io::Error) -> ScriptError {
+        ScriptError::Io(err)
+    }
+}
+
+impl From<ParseError> for ScriptError {
+    fn from(err: ParseError) -> ScriptError {
+        ScriptError::Parse(err)
+    }
+}
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Something a script did that needs the server, it's done once the script returns
+enum Action {
+    Event {
+        kind: u32,
+        priority: EventPriority,
+        handler: FnPtr,
+    },
+    Command {
+        name: String,
+        handler: FnPtr,
+    },
+    Task {
+        ticks: u64,
+        task: FnPtr,
+    },
+    RepeatingTask {
+        delay: u64,
+        period: u64,
+        task: FnPtr,
+    },
+    Execute(String),
+    Broadcast(ChatComponent),
+    /// A message to a player, or to the console if there's no client
+    Send {
+        client_id: Option<u32>,
+        message: ChatComponent,
+    },
+    Teleport {
+        client_id: u32,
+        location: Location,
+    },
+    Listen {
+        channel: String,
+        handler: FnPtr,
+    },
+    PluginMessage {
+        client_id: u32,
+        channel: String,
+        data: Vec<u8>,
+    },
+    Bungee(BungeeRequest),
+    BungeeListener(FnPtr),
+}
+
+/// A player as they were when the script started running
+struct PlayerState {
+    client_id: u32,
+    name: String,
+    uuid: String,
+    gamemode: Gamemode,
+    location: Location,
+}
+
+/// What a script sees of the server while it runs. The world is moved out of the server for
+/// that long, so scripts change it right away.
+struct ServerState {
+    world: World,
+    players: Vec<PlayerState>,
+}
+
+impl ServerState {
+    fn take(server: &mut Server) -> ServerState {
+        let players = server
+            .clients()
+            .iter()
+            .filter_map(|client| {
+                let player = client.player()?;
+                Some(PlayerState {
+                    client_id: client.id(),
+                    name: player.username.clone(),
+                    uuid: client.uuid().map(utils::format_uuid).unwrap_or_default(),
+                    gamemode: player.gamemode,
+                    location: player.position,
+                })
+            })
+            .collect();
+        let world = mem::replace(server.world_mut(), World::empty());
+        ServerState { world, players }
+    }
+
+    fn restore(self, server: &mut Server) {
+        *server.world_mut() = self.world;
+    }
+
+    fn player(&self, client_id: u32) -> Option<&PlayerState> {
+        self.players
+            .iter()
+            .find(|player| player.client_id == client_id)
+    }
+}
+
+/// What the functions registered with the engine share
+struct ScriptContext {
+    name: String,
+    /// Set while the script runs
+    state: RefCell<Option<ServerState>>,
+    pending: RefCell<Vec<Action>>,
+}
+
+impl ScriptContext {
+    fn with_state<T, F>(&self, function: F) -> ScriptResult<T>
+    where
+        F: FnOnce(&mut ServerState) -> T,
+    {
+        match self.state.borrow_mut().as_mut() {
+            Some(state) => Ok(function(state)),
+            None => Err("The server can only be used while the script runs".into()),
+        }
+    }
+
+    fn queue(&self, action: Action) {
+        self.pending.borrow_mut().push(action);
+    }
+
+    /// A property of a player that's online, or the default if they aren't
+    fn player<T, F>(&self, client_id: u32, property: F) -> ScriptResult<T>
+    where
+        T: Default,
+        F: FnOnce(&PlayerState) -> T,
+    {
+        self.with_state(|state| state.player(client_id).map(property).unwrap_or_default())
+    }
+}
+
+struct Script {
+    engine: Engine,
+    ast: AST,
+    context: Rc<ScriptContext>,
+}
+
+/// Runs part of a script, then does what it queued with the server.
+/// Errors are logged, they don't stop the script.
+fn run<T, F>(server: &mut Server, script: &Rc<Script>, function: F) -> Option<T>
+where
+    F: FnOnce(&Script) -> ScriptResult<T>,
+{
+    let context = &script.context;
+    // Scripts can't get to the server while they run, so they never run inside each other
+    *context.state.borrow_mut() = Some(ServerState::take(server));
+    let result = function(script);
+    if let Some(state) = context.state.borrow_mut().take() {
+        state.restore(server);
+    }
+    let pending = context.pending.replace(Vec::new());
+    for action in pending {
+        apply(server, script, action);
+    }
+    match result {
+        Ok(value) => Some(value),
+        Err(err) => {
+            error!("Error in script {}: {}", context.name, err);
+            None
+        }
+    }
+}
+
+fn apply(server: &mut Server, script: &Rc<Script>, action: Action) {
+    match action {
+        Action::Event {
+            kind,
+            priority,
+            handler,
+        } => match kind {
+            EVENT_PLAYER_JOIN => subscribe::<PlayerJoinEvent>(server, script, priority, handler),
+            EVENT_PLAYER_QUIT => subscribe::<PlayerQuitEvent>(server, script, priority, handler),
+            EVENT_CHAT => subscribe::<ChatEvent>(server, script, priority, handler),
+            EVENT_BLOCK_BREAK => subscribe::<BlockBreakEvent>(server, script, priority, handler),
+            EVENT_BLOCK_PLACE => subscribe::<BlockPlaceEvent>(server, script, priority, handler),
+            _ => subscribe::<MoveEvent>(server, script, priority, handler),
+        },
+        Action::Command { name, handler } => {
+            let plugin = script.context.name.clone();
+            let script = script.clone();
+            let execute = Rc::new(move |server: &mut Server, context: &CommandContext| {
+                let sender = ScriptSender {
+                    context: script.context.clone(),
+                    client_id: match context.sender {
+                        CommandSender::Console => None,
+                        CommandSender::Player(client_id) => Some(client_id),
+                    },
+                };
+                let args = context.get_string("args").unwrap_or("").to_string();
+                run(server, &script, |script| {
+                    handler.call::<Dynamic>(&script.engine, &script.ast, (sender, args))
+                });
+                Ok(1)
+            });
+            let execute_args = execute.clone();
+            let command = literal(&name)
+                .executes(move |server: &mut Server, context: &CommandContext| {
+                    execute(server, context)
+                })
+                .then(
+                    argument("args", ArgumentType::String(StringKind::Greedy)).executes(
+                        move |server: &mut Server, context: &CommandContext| {
+                            execute_args(server, context)
+                        },
+                    ),
+                );
+            PluginContext::new(server, &plugin).register_command(command);
+        }
+        Action::Task { ticks, task } => {
+            let plugin = script.context.name.clone();
+            let script = script.clone();
+            PluginContext::new(server, &plugin).run_later(ticks, move |server: &mut Server| {
+                run(server, &script, |script| {
+                    task.call::<Dynamic>(&script.engine, &script.ast, ())
+                });
+            });
+        }
+        Action::RepeatingTask {
+            delay,
+            period,
+            task,
+        } => {
+            let plugin = script.context.name.clone();
+            let script = script.clone();
+            // The task needs its own id to cancel itself, which is only known once it's scheduled
+            let id = Rc::new(Cell::new(None));
+            let task_id = id.clone();
+            let repeat = move |server: &mut Server| {
+                let result = run(server, &script, |script| {
+                    task.call::<Dynamic>(&script.engine, &script.ast, ())
+                });
+                if result.and_then(|value| value.as_bool().ok()) == Some(false) {
+                    if let Some(id) = task_id.get() {
+                        PluginContext::new(server, &script.context.name).cancel_task(id);
+                    }
+                }
+            };
+            id.set(Some(
+                PluginContext::new(server, &plugin).run_repeating(delay, period, repeat),
+            ));
+        }
+        Action::Execute(command) => server.execute_command(CommandSender::Console, &command),
+        Action::Broadcast(message) => server.broadcast_chat(message, ChatPosition::System),
+        Action::Send { client_id, message } => {
+            let sender = match client_id {
+                Some(client_id) => CommandSender::Player(client_id),
+                None => CommandSender::Console,
+            };
+            server.send_message(&sender, message);
+        }
+        Action::Teleport {
+            client_id,
+            location,
+        } => {
+            server.teleport(client_id, location);
+        }
+        Action::Listen { channel, handler } => {
+            let plugin = script.context.name.clone();
+            let script = script.clone();
+            let listening = PluginContext::new(server, &plugin).listen(
+                &channel,
+                move |server: &mut Server, client_id, data: &[u8]| {
+                    let player = ScriptPlayer {
+                        context: script.context.clone(),
+                        client_id,
+                    };
+                    let data: Blob = data.to_vec();
+                    run(server, &script, |script| {
+                        handler.call::<Dynamic>(&script.engine, &script.ast, (player, data))
+                    });
+                },
+            );
+            if !listening {
+                warn!("Script {} can't listen on {}", plugin, channel);
+            }
+        }
+        Action::PluginMessage {
+            client_id,
+            channel,
+            data,
+        } => {
+            let plugin = &script.context.name;
+            PluginContext::new(server, plugin).send_plugin_message(client_id, &channel, data);
+        }
+        Action::Bungee(request) => {
+            let plugin = &script.context.name;
+            if !PluginContext::new(server, plugin).bungee_request(&request) {
+                warn!(
+                    "Script {} sent a request to the proxy while nobody was online",
+                    plugin
+                );
+            }
+        }
+        Action::BungeeListener(handler) => {
+            let plugin = script.context.name.clone();
+            let script = script.clone();
+            PluginContext::new(server, &plugin).on_bungee_response(
+                move |server: &mut Server, response| {
+                    let response = bungee_response_map(response);
+                    run(server, &script, |script| {
+                        handler.call::<Dynamic>(&script.engine, &script.ast, (response,))
+                    });
+                },
+            );
+        }
+    }
+}
+
+fn subscribe<E: DynamicEvent>(
+    server: &mut Server,
+    script: &Rc<Script>,
+    priority: EventPriority,
+    handler: FnPtr,
+) {
+    let plugin = script.context.name.clone();
+    let script = script.clone();
+    PluginContext::new(server, &plugin).on(priority, move |server: &mut Server, event: &mut E| {
+        let script_event = ScriptEvent {
+            context: script.context.clone(),
+            data: Rc::new(RefCell::new(event.data())),
+        };
+        let data = script_event.data.clone();
+        run(server, &script, |script| {
+            handler.call::<Dynamic>(&script.engine, &script.ast, (script_event,))
+        });
+        event.update(&data.borrow());
+    });
+}
+
+/// A player as scripts see them, their state is looked up whenever it's used
+#[derive(Clone)]
+struct ScriptPlayer {
+    context: Rc<ScriptContext>,
+    client_id: u32,
+}
+
+impl ScriptPlayer {
+    fn location(&self) -> ScriptResult<Location> {
+        self.context
+            .with_state(|state| state.player(self.client_id).map(|player| player.location))?
+            .ok_or_else(|| "The player is offline".into())
+    }
+}
+
+/// Whoever ran a script's command
+#[derive(Clone)]
+struct ScriptSender {
+    context: Rc<ScriptContext>,
+    /// None for the console
+    client_id: Option<u32>,
+}
+
+/// An event passed to a handler, handlers share it so they can cancel it
+#[derive(Clone)]
+struct ScriptEvent {
+    context: Rc<ScriptContext>,
+    data: Rc<RefCell<EventData>>,
+}
+
+fn parse_priority(priority: &str) -> ScriptResult<EventPriority> {
+    Ok(match priority {
+        "lowest" => EventPriority::Lowest,
+        "low" => EventPriority::Low,
+        "normal" => EventPriority::Normal,
+        "high" => EventPriority::High,
+        "highest" => EventPriority::Highest,
+        "monitor" => EventPriority::Monitor,
+        _ => return Err(format!("Unknown priority {}", priority).into()),
+    })
+}
+
+fn register_event(
+    context: &ScriptContext,
+    event: &str,
+    priority: &str,
+    handler: FnPtr,
+) -> ScriptResult<()> {
+    let kind = EVENT_NAMES
+        .iter()
+        .position(|name| *name == event)
+        .ok_or_else(|| format!("Unknown event {}", event))?;
+    context.queue(Action::Event {
+        kind: kind as u32,
+        priority: parse_priority(priority)?,
+        handler,
+    });
+    Ok(())
+}
+
+fn queue_bungee_request(
+    context: &ScriptContext,
+    subchannel: &str,
+    args: Array,
+    data: Blob,
+) -> ScriptResult<()> {
+    let args: Vec<String> = args.iter().map(Dynamic::to_string).collect();
+    let request = BungeeRequest::from_parts(subchannel, &args, data)
+        .ok_or_else(|| format!("Invalid request to the proxy {}", subchannel))?;
+    context.queue(Action::Bungee(request));
+    Ok(())
+}
+
+/// The proxy's answer as a map of the subchannel and its fields. Data forwarded from another
+/// server has the subchannel the sender chose.
+fn bungee_response_map(response: BungeeResponse) -> Map {
+    let mut map = Map::new();
+    let mut set = |key: &str, value: Dynamic| {
+        map.insert(key.into(), value);
+    };
+    let list =
+        |list: Vec<String>| Dynamic::from_array(list.into_iter().map(Dynamic::from).collect());
+    match response {
+        BungeeResponse::Ip { ip, port } => {
+            set("subchannel", Dynamic::from("IP"));
+            set("ip", Dynamic::from(ip));
+            set("port", Dynamic::from(port as i64));
+        }
+        BungeeResponse::IpOther { player, ip, port } => {
+            set("subchannel", Dynamic::from("IPOther"));
+            set("player", Dynamic::from(player));
+            set("ip", Dynamic::from(ip));
+            set("port", Dynamic::from(port as i64));
+        }
+        BungeeResponse::PlayerCount { server, count } => {
+            set("subchannel", Dynamic::from("PlayerCount"));
+            set("server", Dynamic::from(server));
+            set("count", Dynamic::from(count as i64));
+        }
+        BungeeResponse::PlayerList { server, players } => {
+            set("subchannel", Dynamic::from("PlayerList"));
+            set("server", Dynamic::from(server));
+            set("players", list(players));
+        }
+        BungeeResponse::GetServers { servers } => {
+            set("subchannel", Dynamic::from("GetServers"));
+            set("servers", list(servers));
+        }
+        BungeeResponse::GetServer { server } => {
+            set("subchannel", Dynamic::from("GetServer"));
+            set("server", Dynamic::from(server));
+        }
+        BungeeResponse::Uuid { uuid } => {
+            set("subchannel", Dynamic::from("UUID"));
+            set("uuid", Dynamic::from(uuid));
+        }
+        BungeeResponse::UuidOther { player, uuid } => {
+            set("subchannel", Dynamic::from("UUIDOther"));
+            set("player", Dynamic::from(player));
+            set("uuid", Dynamic::from(uuid));
+        }
+        BungeeResponse::ServerIp { server, ip, port } => {
+            set("subchannel", Dynamic::from("ServerIP"));
+            set("server", Dynamic::from(server));
+            set("ip", Dynamic::from(ip));
+            set("port", Dynamic::from(port as i64));
+        }
+        BungeeResponse::Forwarded { subchannel, data } => {
+            set("subchannel", Dynamic::from(subchannel));
+            set("data", Dynamic::from_blob(data));
+        }
+    }
+    map
+}
+
+/// The players that are logged in
+fn players(context: &Rc<ScriptContext>) -> ScriptResult<Array> {
+    context.with_state(|state| {
+        state
+            .players
+            .iter()
+            .map(|player| {
+                Dynamic::from(ScriptPlayer {
+                    context: context.clone(),
+                    client_id: player.client_id,
+                })
+            })
+            .collect()
+    })
+}
+
+fn create_engine(context: &Rc<ScriptContext>) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    let name = context.name.clone();
+    engine.on_print(move |text| info!("[{}] {}", name, text));
+    let name = context.name.clone();
+    engine.on_debug(move |text, _, position| info!("[{}] {} {}", name, position, text));
+
+    let ctx = context.clone();
+    engine.register_fn(
+        "on",
+        move |event: &str, priority: &str, handler: FnPtr| -> ScriptResult<()> {
+            register_event(&ctx, event, priority, handler)
+        },
+    );
+    let ctx = context.clone();
+    engine.register_fn(
+        "on",
+        move |event: &str, handler: FnPtr| -> ScriptResult<()> {
+            register_event(&ctx, event, "normal", handler)
+        },
+    );
+    let ctx = context.clone();
+    engine.register_fn(
+        "command",
+        move |name: &str, handler: FnPtr| -> ScriptResult<()> {
+            let valid = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+            if !valid {
+                return Err(format!("Invalid command name {}", name).into());
+            }
+            ctx.queue(Action::Command {
+                name: name.to_string(),
+                handler,
+            });
+            Ok(())
+        },
+    );
+    let ctx = context.clone();
+    engine.register_fn("run_later", move |ticks: i64, task: FnPtr| {
+        ctx.queue(Action::Task {
+            ticks: ticks.max(0) as u64,
+            task,
+        });
+    });
+    let ctx = context.clone();
+    engine.register_fn(
+        "run_repeating",
+        move |delay: i64, period: i64, task: FnPtr| {
+            ctx.queue(Action::RepeatingTask {
+                delay: delay.max(0) as u64,
+                period: period.max(1) as u64,
+                task,
+            });
+        },
+    );
+    let ctx = context.clone();
+    engine.register_fn("execute", move |command: &str| {
+        let command = command.trim_start_matches('/').to_string();
+        ctx.queue(Action::Execute(command));
+    });
+    let ctx = context.clone();
+    engine.register_fn("broadcast", move |message: ChatComponent| {
+        ctx.queue(Action::Broadcast(message));
+    });
+    let ctx = context.clone();
+    engine.register_fn("broadcast", move |message: &str| {
+        ctx.queue(Action::Broadcast(ChatComponent::from_markup(message)));
+    });
+
+    // Plugin messages
+    let ctx = context.clone();
+    engine.register_fn(
+        "listen",
+        move |channel: &str, handler: FnPtr| -> ScriptResult<()> {
+            let channel = channels::channel_name(channel)
+                .ok_or_else(|| format!("Invalid channel {}", channel))?;
+            ctx.queue(Action::Listen {
+                channel: channel.to_string(),
+                handler,
+            });
+            Ok(())
+        },
+    );
+    let ctx = context.clone();
+    engine.register_fn("bungee", move |subchannel: &str, args: Array| {
+        queue_bungee_request(&ctx, subchannel, args, Blob::new())
+    });
+    let ctx = context.clone();
+    engine.register_fn(
+        "bungee",
+        move |subchannel: &str, args: Array, data: Blob| {
+            queue_bungee_request(&ctx, subchannel, args, data)
+        },
+    );
+    let ctx = context.clone();
+    engine.register_fn("on_bungee", move |handler: FnPtr| {
+        ctx.queue(Action::BungeeListener(handler));
+    });
+
+    // The world
+    let ctx = context.clone();
+    engine.register_fn("players", move || players(&ctx));
+    let ctx = context.clone();
+    engine.register_fn("player", move |name: &str| -> ScriptResult<Dynamic> {
+        let client_id = ctx.with_state(|state| {
+            state
+                .players
+                .iter()
+                .find(|player| player.name.eq_ignore_ascii_case(name))
+                .map(|player| player.client_id)
+        })?;
+        Ok(match client_id {
+            Some(client_id) => Dynamic::from(ScriptPlayer {
+                context: ctx.clone(),
+                client_id,
+            }),
+            None => Dynamic::UNIT,
+        })
+    });
+    let ctx = context.clone();
+    engine.register_fn("block_at", move |x: i64, y: i64, z: i64| {
+        ctx.with_state(|state| {
+            let position = BlockPosition::new(x as i32, y as i32, z as i32);
+            let block = state.world.get_block(position).block();
+            block
+                .map_or("minecraft:air", |block| block.name)
+                .to_string()
+        })
+    });
+    let ctx = context.clone();
+    engine.register_fn(
+        "set_block",
+        move |x: i64, y: i64, z: i64, name: &str| -> ScriptResult<()> {
+            let block = Block::from_name(name).ok_or_else(|| format!("Unknown block {}", name))?;
+            ctx.with_state(|state| {
+                let position = BlockPosition::new(x as i32, y as i32, z as i32);
+                state.world.set_block(position, block.default_state());
+            })
+        },
+    );
+    let ctx = context.clone();
+    engine.register_fn("time", move || ctx.with_state(|state| state.world.time));
+    let ctx = context.clone();
+    engine.register_fn("weather", move || {
+        ctx.with_state(|state| state.world.weather.name().to_string())
+    });
+
+    // Chat components
+    engine.register_type_with_name::<ChatComponent>("Chat");
+    engine.register_fn("text", |text: &str| ChatComponent::new(text.to_string()));
+    engine.register_fn("translate", |key: &str| {
+        ChatComponent::translate(key, vec![])
+    });
+    engine.register_fn(
+        "color",
+        |component: ChatComponent, color: &str| -> ScriptResult<ChatComponent> {
+            let color =
+                ChatColor::from_name(color).ok_or_else(|| format!("Unknown color {}", color))?;
+            Ok(component.color(color))
+        },
+    );
+    engine.register_fn("bold", |component: ChatComponent, bold: bool| {
+        component.bold(bold)
+    });
+    engine.register_fn("italic", |component: ChatComponent, italic: bool| {
+        component.italic(italic)
+    });
+    engine.register_fn("underline", |component: ChatComponent, underline: bool| {
+        component.underline(underline)
+    });
+    engine.register_fn(
+        "strikethrough",
+        |component: ChatComponent, strikethrough: bool| component.strikethrough(strikethrough),
+    );
+    engine.register_fn(
+        "append",
+        |component: ChatComponent, child: ChatComponent| component.append(child),
+    );
+    engine.register_fn("append", |component: ChatComponent, child: &str| {
+        component.append(ChatComponent::from_markup(child))
+    });
+    engine.register_fn("to_string", |component: &mut ChatComponent| {
+        component.to_legacy()
+    });
+
+    // Players
+    engine.register_type_with_name::<ScriptPlayer>("Player");
+    engine.register_get(
+        "name",
+        |player: &mut ScriptPlayer| -> ScriptResult<String> {
+            player
+                .context
+                .player(player.client_id, |player| player.name.clone())
+        },
+    );
+    engine.register_get(
+        "uuid",
+        |player: &mut ScriptPlayer| -> ScriptResult<String> {
+            player
+                .context
+                .player(player.client_id, |player| player.uuid.clone())
+        },
+    );
+    engine.register_get(
+        "online",
+        |player: &mut ScriptPlayer| -> ScriptResult<bool> {
+            player.context.player(player.client_id, |_| true)
+        },
+    );
+    engine.register_get(
+        "gamemode",
+        |player: &mut ScriptPlayer| -> ScriptResult<String> {
+            player.context.player(player.client_id, |player| {
+                player.gamemode.name().to_string()
+            })
+        },
+    );
+    engine.register_get("x", |player: &mut ScriptPlayer| -> ScriptResult<f64> {
+        Ok(player.location()?.x)
+    });
+    engine.register_get("y", |player: &mut ScriptPlayer| -> ScriptResult<f64> {
+        Ok(player.location()?.y)
+    });
+    engine.register_get("z", |player: &mut ScriptPlayer| -> ScriptResult<f64> {
+        Ok(player.location()?.z)
+    });
+    engine.register_get("yaw", |player: &mut ScriptPlayer| -> ScriptResult<f64> {
+        Ok(player.location()?.yaw as f64)
+    });
+    engine.register_get("pitch", |player: &mut ScriptPlayer| -> ScriptResult<f64> {
+        Ok(player.location()?.pitch as f64)
+    });
+    engine.register_fn(
+        "send",
+        |player: &mut ScriptPlayer, message: ChatComponent| {
+            player.context.queue(Action::Send {
+                client_id: Some(player.client_id),
+                message,
+            });
+        },
+    );
+    engine.register_fn("send", |player: &mut ScriptPlayer, message: &str| {
+        player.context.queue(Action::Send {
+            client_id: Some(player.client_id),
+            message: ChatComponent::from_markup(message),
+        });
+    });
+    engine.register_fn(
+        "teleport",
+        |player: &mut ScriptPlayer, x: f64, y: f64, z: f64| -> ScriptResult<()> {
+            let mut location = player.location()?;
+            location.x = x;
+            location.y = y;
+            location.z = z;
+            player.context.queue(Action::Teleport {
+                client_id: player.client_id,
+                location,
+            });
+            Ok(())
+        },
+    );
+    engine.register_fn(
+        "send_plugin_message",
+        |player: &mut ScriptPlayer, channel: &str, data: Blob| -> ScriptResult<()> {
+            let channel = channels::channel_name(channel)
+                .ok_or_else(|| format!("Invalid channel {}", channel))?;
+            player.context.queue(Action::PluginMessage {
+                client_id: player.client_id,
+                channel: channel.to_string(),
+                data,
+            });
+            Ok(())
+        },
+    );
+    engine.register_fn("to_string", |player: &mut ScriptPlayer| {
+        player
+            .context
+            .player(player.client_id, |player| player.name.clone())
+    });
+
+    // Command senders
+    engine.register_type_with_name::<ScriptSender>("Sender");
+    engine.register_get("is_console", |sender: &mut ScriptSender| {
+        sender.client_id.is_none()
+    });
+    engine.register_get("player", |sender: &mut ScriptSender| {
+        match sender.client_id {
+            Some(client_id) => Dynamic::from(ScriptPlayer {
+                context: sender.context.clone(),
+                client_id,
+            }),
+            None => Dynamic::UNIT,
+        }
+    });
+    engine.register_get(
+        "name",
+        |sender: &mut ScriptSender| -> ScriptResult<String> {
+            let client_id = match sender.client_id {
+                Some(client_id) => client_id,
+                None => return Ok("Server".to_string()),
+            };
+            sender
+                .context
+                .player(client_id, |player| player.name.clone())
+        },
+    );
+    engine.register_fn(
+        "send",
+        |sender: &mut ScriptSender, message: ChatComponent| {
+            sender.context.queue(Action::Send {
+                client_id: sender.client_id,
+                message,
+            });
+        },
+    );
+    engine.register_fn("send", |sender: &mut ScriptSender, message: &str| {
+        sender.context.queue(Action::Send {
+            client_id: sender.client_id,
+            message: ChatComponent::from_markup(message),
+        });
+    });
+
+    // Events
+    engine.register_type_with_name::<ScriptEvent>("Event");
+    engine.register_get("type", |event: &mut ScriptEvent| {
+        EVENT_NAMES[event.data.borrow().kind as usize].to_string()
+    });
+    engine.register_get("player", |event: &mut ScriptEvent| ScriptPlayer {
+        context: event.context.clone(),
+        client_id: event.data.borrow().client_id,
+    });
+    engine.register_get("message", |event: &mut ScriptEvent| {
+        event.data.borrow().text.clone()
+    });
+    engine.register_get("x", |event: &mut ScriptEvent| {
+        event.data.borrow().position.0
+    });
+    engine.register_get("y", |event: &mut ScriptEvent| {
+        event.data.borrow().position.1
+    });
+    engine.register_get("z", |event: &mut ScriptEvent| {
+        event.data.borrow().position.2
+    });
+    engine.register_get("cancelled", |event: &mut ScriptEvent| {
+        event.data.borrow().cancelled
+    });
+    engine.register_set("cancelled", |event: &mut ScriptEvent, cancelled: bool| {
+        event.data.borrow_mut().cancelled = cancelled;
+    });
+    engine.register_fn("cancel", |event: &mut ScriptEvent| {
+        event.data.borrow_mut().cancelled = true;
+    });
+
+    engine
+}
+
+/// A script running as a plugin
+pub struct ScriptPlugin {
+    script: Rc<Script>,
+}
+
+impl Plugin for ScriptPlugin {
+    fn name(&self) -> &str {
+        &self.script.context.name
+    }
+
+    fn version(&self) -> &str {
+        // Scripts aren't versioned, they're reloaded whenever they change
+        ""
+    }
+
+    /// Runs the script, which registers its handlers and commands
+    fn on_enable(&mut self, context: &mut PluginContext) {
+        run(context.server(), &self.script, |script| {
+            script.engine.run_ast(&script.ast)
+        });
+    }
+
+    fn on_disable(&mut self, server: &mut Server) {
+        let has_on_disable = self
+            .script
+            .ast
+            .iter_functions()
+            .any(|function| function.name == "on_disable" && function.params.is_empty());
+        if has_on_disable {
+            run(server, &self.script, |script| {
+                script.engine.call_fn_with_options::<Dynamic>(
+                    CallFnOptions::new().eval_ast(false),
+                    &mut Scope::new(),
+                    &script.ast,
+                    "on_disable",
+                    (),
+                )
+            });
+        }
+    }
+}
+
+/// Compiles a script, it's run when the plugin is enabled
+pub fn load(path: &Path) -> Result<ScriptPlugin, ScriptError> {
+    let source = fs::read_to_string(path)?;
+    let name = path
+        .file_stem()
+        .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+    Ok(compile(name, &source)?)
+}
+
+fn compile(name: String, source: &str) -> Result<ScriptPlugin, ParseError> {
+    let context = Rc::new(ScriptContext {
+        name,
+        state: RefCell::new(None),
+        pending: RefCell::new(Vec::new()),
+    });
+    let engine = create_engine(&context);
+    let ast = engine.compile(source)?;
+    Ok(ScriptPlugin {
+        script: Rc::new(Script {
+            engine,
+            ast,
+            context,
+        }),
+    })
+}
+
+pub enum ScriptChange {
+    /// The script is new or was edited
+    Changed(PathBuf),
+    Removed(PathBuf),
+}
+
+/// Notices when scripts are added, edited or removed
+pub struct ScriptWatcher {
+    /// When each script was last modified
+    modified: HashMap<PathBuf, SystemTime>,
+}
+
+impl ScriptWatcher {
+    pub fn new() -> ScriptWatcher {
+        ScriptWatcher {
+            modified: HashMap::new(),
+        }
+    }
+
+    /// Forgets every script, so they're all new on the next check
+    pub fn reset(&mut self) {
+        self.modified.clear();
+    }
+
+    /// The changes since the last check, the directory is created if it doesn't exist
+    pub fn changes(&mut self) -> Vec<ScriptChange> {
+        let scripts = match script_files() {
+            Ok(scripts) => scripts,
+            Err(err) => {
+                warn!("Couldn't read the {} directory: {}", SCRIPTS_DIR, err);
+                return Vec::new();
+            }
+        };
+        let mut changes = Vec::new();
+        let removed: Vec<PathBuf> = self
+            .modified
+            .keys()
+            .filter(|path| !scripts.iter().any(|(script, _)| script == *path))
+            .cloned()
+            .collect();
+        for path in removed {
+            self.modified.remove(&path);
+            changes.push(ScriptChange::Removed(path));
+        }
+        for (path, modified) in scripts {
+            if self.modified.get(&path) != Some(&modified) {
+                self.modified.insert(path.clone(), modified);
+                changes.push(ScriptChange::Changed(path));
+            }
+        }
+        changes
+    }
+}
+
+/// The scripts in alphabetical order with when they were last modified
+fn script_files() -> io::Result<Vec<(PathBuf, SystemTime)>> {
+    fs::create_dir_all(SCRIPTS_DIR)?;
+    let mut scripts = Vec::new();
+    for entry in fs::read_dir(SCRIPTS_DIR)? {
+        let entry = entry?;
+        let path = entry.path();
+        let is_script = path
+            .extension()
+            .is_some_and(|extension| extension == "rhai");
+        if is_script && path.is_file() {
+            scripts.push((path, entry.metadata()?.modified()?));
+        }
+    }
+    scripts.sort();
+    Ok(scripts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Compiles and runs a script without a server, returning what it queued
+    fn run_script(source: &str) -> (Rc<Script>, ScriptResult<()>) {
+        let script = compile("test".to_string(), source).unwrap().script;
+        let result = script.engine.run_ast(&script.ast);
+        (script, result)
+    }
+
+    fn take_pending(script: &Script) -> Vec<Action> {
+        script.context.pending.replace(Vec::new())
+    }
+
+    #[test]
+    fn registrations() {
+        let (script, result) = run_script(
+            r#"
+            on("join", |event| {});
+            on("chat", "highest", |event| {});
+            command("hello", |sender, args| {});
+            run_later(20, || {});
+            run_later(-5, || {});
+            "#,
+        );
+        result.unwrap();
+        let pending = take_pending(&script);
+        assert_eq!(pending.len(), 5);
+        match &pending[0] {
+            Action::Event { kind, priority, .. } => {
+                assert_eq!(*kind, EVENT_PLAYER_JOIN);
+                assert_eq!(*priority, EventPriority::Normal);
+            }
+            _ => panic!("Expected an event handler"),
+        }
+        match &pending[1] {
+            Action::Event { kind, priority, .. } => {
+                assert_eq!(*kind, EVENT_CHAT);
+                assert_eq!(*priority, EventPriority::Highest);
+            }
+            _ => panic!("Expected an event handler"),
+        }
+        match &pending[2] {
+            Action::Command { name, .. } => assert_eq!(name, "hello"),
+            _ => panic!("Expected a command"),
+        }
+        match (&pending[3], &pending[4]) {
+            (Action::Task { ticks: 20, .. }, Action::Task { ticks: 0, .. }) => {}
+            _ => panic!("Expected two tasks"),
+        }
+    }
+
+    #[test]
+    fn invalid_registrations() {
+        for source in &[
+            r#"on("jump", |event| {});"#,
+            r#"on("chat", "urgent", |event| {});"#,
+            r#"command("Hello", |sender, args| {});"#,
+            r#"command("", |sender, args| {});"#,
+            r#"listen("data", |player, data| {});"#,
+            r#"bungee("Connect", []);"#,
+            r#"bungee("Teleport", ["lobby"]);"#,
+        ] {
+            let (script, result) = run_script(source);
+            assert!(result.is_err(), "{} should fail", source);
+            assert!(take_pending(&script).is_empty());
+        }
+        assert!(compile("test".to_string(), "on(").is_err());
+    }
+
+    #[test]
+    fn plugin_messages() {
+        let (script, result) = run_script(
+            r#"
+            listen("BungeeCord", |player, data| {});
+            bungee("PlayerCount", ["ALL"]);
+            bungee("Forward", ["ALL", "test"], "hi".to_blob());
+            on_bungee(|response| {});
+            "#,
+        );
+        result.unwrap();
+        match take_pending(&script).as_slice() {
+            [Action::Listen { channel, .. }, Action::Bungee(count), Action::Bungee(forward), Action::BungeeListener(_)] =>
+            {
+                assert_eq!(channel, channels::BUNGEECORD);
+                assert_eq!(count.encode().unwrap(), b"\0\x0bPlayerCount\0\x03ALL");
+                assert_eq!(
+                    forward.encode().unwrap(),
+                    b"\0\x07Forward\0\x03ALL\0\x04test\0\x02hi"
+                );
+            }
+            _ => panic!("Expected a listener, two requests and a response handler"),
+        }
+    }
+
+    #[test]
+    fn bungee_responses() {
+        let engine = Engine::new();
+        let mut scope = Scope::new();
+        scope.push(
+            "list",
+            bungee_response_map(BungeeResponse::PlayerList {
+                server: "hub".to_string(),
+                players: vec!["Alex".to_string(), "Steve".to_string()],
+            }),
+        );
+        scope.push(
+            "forwarded",
+            bungee_response_map(BungeeResponse::Forwarded {
+                subchannel: "test".to_string(),
+                data: vec![1, 2],
+            }),
+        );
+        let result: String = engine
+            .eval_with_scope(
+                &mut scope,
+                r#"`${list.subchannel} ${list.server} ${list.players[1]} ${forwarded.subchannel} ${forwarded.data.len()}`"#,
+            )
+            .unwrap();
+        assert_eq!(result, "PlayerList hub Steve test 2");
+    }
+
+    #[test]
+    fn priorities() {
+        let names = ["lowest", "low", "normal", "high", "highest", "monitor"];
+        let priorities: Vec<EventPriority> = names
+            .iter()
+            .map(|name| parse_priority(name).unwrap())
+            .collect();
+        assert_eq!(
+            priorities,
+            [
+                EventPriority::Lowest,
+                EventPriority::Low,
+                EventPriority::Normal,
+                EventPriority::High,
+                EventPriority::Highest,
+                EventPriority::Monitor,
+            ]
+        );
+        assert!(parse_priority("Normal").is_err());
+        assert!(parse_priority("").is_err());
+    }
+
+    /// Calls a script's chat handler with a chat event, like the event bus would
+    fn chat(script: &Rc<Script>, handler: &FnPtr, message: &str) -> ChatEvent {
+        let mut event = ChatEvent {
+            client_id: 3,
+            message: message.to_string(),
+            cancelled: false,
+        };
+        let script_event = ScriptEvent {
+            context: script.context.clone(),
+            data: Rc::new(RefCell::new(event.data())),
+        };
+        let data = script_event.data.clone();
+        handler
+            .call::<()>(&script.engine, &script.ast, (script_event,))
+            .unwrap();
+        event.update(&data.borrow());
+        event
+    }
+
+    #[test]
+    fn cancelling_events() {
+        let (script, result) = run_script(
+            r#"
+            on("chat", |event| {
+                if event.message == "spam" {
+                    event.cancel();
+                } else if event.message.contains("spam") {
+                    event.cancelled = true;
+                }
+            });
+            "#,
+        );
+        result.unwrap();
+        let handler = match take_pending(&script).pop() {
+            Some(Action::Event { handler, .. }) => handler,
+            _ => panic!("Expected an event handler"),
+        };
+        assert!(chat(&script, &handler, "spam").cancelled);
+        assert!(chat(&script, &handler, "more spam").cancelled);
+        assert!(!chat(&script, &handler, "hello").cancelled);
+    }
+
+    #[test]
+    fn repeating_tasks() {
+        let mut server = Server::unstarted();
+        let (script, result) = run_script(
+            r#"
+            let runs = 0;
+            run_repeating(2, 1, || {
+                runs += 1;
+                set_block(runs, 0, 0, "minecraft:stone");
+                runs < 3
+            });
+            "#,
+        );
+        result.unwrap();
+        for action in take_pending(&script) {
+            apply(&mut server, &script, action);
+        }
+        for _ in 0..10 {
+            server.run_tasks();
+        }
+        let stone = Block::from_name("minecraft:stone").unwrap().default_state();
+        for x in 1..=3 {
+            assert_eq!(server.world.get_block(BlockPosition::new(x, 0, 0)), stone);
+        }
+        // It stopped once it returned false
+        assert_ne!(server.world.get_block(BlockPosition::new(4, 0, 0)), stone);
+    }
+
+    #[test]
+    fn server_state() {
+        let (script, result) = run_script("block_at(0, 0, 0);");
+        assert!(result.is_err(), "The world can't be used without a server");
+
+        let mut world = World::empty();
+        world.time = 6000;
+        *script.context.state.borrow_mut() = Some(ServerState {
+            world,
+            players: vec![PlayerState {
+                client_id: 3,
+                name: "Notch".to_string(),
+                uuid: "b50ad385-829d-3141-a216-7e7d7539ba7f".to_string(),
+                gamemode: Gamemode::Creative,
+                location: Location::new(1.0, 2.0, 3.0, 0.0, 0.0),
+            }],
+        });
+        let result: String = script
+            .engine
+            .eval(
+                r#"
+                set_block(1, 2, 3, "minecraft:stone");
+                let player = player("notch");
+                player.send("Hi");
+                player.teleport(5.0, 6.0, 7.0);
+                execute("/say hi");
+                `${block_at(1, 2, 3)} ${player.name} ${player.gamemode} ${player.x} ${time()} ${players().len()}`
+                "#,
+            )
+            .unwrap();
+        assert_eq!(result, "minecraft:stone Notch creative 1.0 6000 1");
+        match take_pending(&script).as_slice() {
+            [Action::Send {
+                client_id: Some(3), ..
+            }, Action::Teleport {
+                client_id: 3,
+                location,
+            }, Action::Execute(command)] => {
+                assert_eq!((location.x, location.y, location.z), (5.0, 6.0, 7.0));
+                assert_eq!(command, "say hi");
+            }
+            _ => panic!("Expected a message, a teleport and a command"),
+        }
+    }
+}
@@ -0,0 +1,453 @@
+//! Operators, the whitelist and bans, stored in the same files and formats as vanilla.
+//! Each file is reloaded when it's changed while the server is running.
+
+use chrono::{DateTime, FixedOffset, Local};
+use log::{error, info, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+pub const OPS_FILE: &str = "ops.json";
+pub const WHITELIST_FILE: &str = "whitelist.json";
+pub const BANNED_PLAYERS_FILE: &str = "banned-players.json";
+pub const BANNED_IPS_FILE: &str = "banned-ips.json";
+/// How `created` and `expires` are written, like `2019-12-24 18:30:00 +0100`
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S %z";
+/// `expires` of bans that never expire
+const FOREVER: &str = "forever";
+pub const DEFAULT_BAN_REASON: &str = "Banned by an operator.";
+
+#[derive(Debug)]
+pub enum UserListError {
+    Io(io::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for UserListError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UserListError::Io(err) => write!(f, "{}", err),
+            UserListError::Json(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for UserListError {}
+
+impl From<io::Error> for UserListError {
+    fn from(err: io::Error) -> UserListError {
+        UserListError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for UserListError {
+    fn from(err: serde_json::Error) -> UserListError {
+        UserListError::Json(err)
+    }
+}
+
+type UserListResult<T> = Result<T, UserListError>;
+
+/// A player the lists refer to, who doesn't have to be online
+#[derive(Clone)]
+pub struct Profile {
+    pub uuid: String,
+    pub name: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Operator {
+    /// With dashes, like `069a79f4-44e9-4726-a5be-fca90e38aaf5`
+    pub uuid: String,
+    pub name: String,
+    /// The permission level from 1 to 4
+    pub level: u8,
+    pub bypasses_player_limit: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WhitelistEntry {
+    pub uuid: String,
+    pub name: String,
+}
+
+/// The details both kinds of bans have
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Ban {
+    pub created: String,
+    /// The name of whoever banned the player, or `Server` for the console
+    pub source: String,
+    /// A date like `created` or `forever`
+    pub expires: String,
+    pub reason: String,
+}
+
+impl Ban {
+    pub fn new(source: String, reason: Option<String>) -> Ban {
+        Ban {
+            created: Local::now().format(DATE_FORMAT).to_string(),
+            source,
+            expires: FOREVER.to_string(),
+            reason: reason.unwrap_or_else(|| DEFAULT_BAN_REASON.to_string()),
+        }
+    }
+
+    /// When the ban expires, None if it's permanent or the date can't be read
+    pub fn expiration(&self) -> Option<DateTime<FixedOffset>> {
+        if self.expires == FOREVER {
+            return None;
+        }
+        DateTime::parse_from_str(&self.expires, DATE_FORMAT).ok()
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expiration()
+            .is_some_and(|expiration| expiration < Local::now())
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PlayerBan {
+    pub uuid: String,
+    pub name: String,
+    #[serde(flatten)]
+    pub ban: Ban,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct IpBan {
+    pub ip: String,
+    #[serde(flatten)]
+    pub ban: Ban,
+}
+
+/// The entries of one of the json files
+pub struct UserList<T> {
+    path: PathBuf,
+    pub entries: Vec<T>,
+    /// When the file was last read or written, to notice when it's edited
+    modified: Option<SystemTime>,
+}
+
+impl<T: Serialize + DeserializeOwned> UserList<T> {
+    /// Loads the list, or creates an empty one if the file doesn't exist
+    fn load(path: PathBuf) -> UserList<T> {
+        let mut list = UserList {
+            path,
+            entries: Vec::new(),
+            modified: None,
+        };
+        let result = if list.path.exists() {
+            list.reload()
+        } else {
+            list.write()
+        };
+        if let Err(err) = result {
+            warn!("Couldn't load {}: {}", list.path.display(), err);
+        }
+        list
+    }
+
+    fn modified_time(&self) -> Option<SystemTime> {
+        fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// Reads the file again, the entries stay the same if it can't be read
+    pub fn reload(&mut self) -> UserListResult<()> {
+        self.modified = self.modified_time();
+        let data = fs::read_to_string(&self.path)?;
+        self.entries = serde_json::from_str(&data)?;
+        Ok(())
+    }
+
+    fn write(&mut self) -> UserListResult<()> {
+        fs::write(&self.path, serde_json::to_string_pretty(&self.entries)?)?;
+        self.modified = self.modified_time();
+        Ok(())
+    }
+
+    /// Writes the entries to the file, changes stay in memory if that fails
+    pub fn save(&mut self) {
+        if let Err(err) = self.write() {
+            error!("Couldn't save {}: {}", self.path.display(), err);
+        }
+    }
+
+    /// Reloads the file if it was changed since it was last read or written, returns whether it was
+    fn reload_if_changed(&mut self) -> bool {
+        let modified = self.modified_time();
+        if modified.is_none() || modified == self.modified {
+            return false;
+        }
+        match self.reload() {
+            Ok(()) => {
+                info!("Reloaded {}", self.path.display());
+                true
+            }
+            Err(err) => {
+                warn!("Couldn't reload {}: {}", self.path.display(), err);
+                false
+            }
+        }
+    }
+
+    pub fn find<P: Fn(&T) -> bool>(&self, predicate: P) -> Option<&T> {
+        self.entries.iter().find(|entry| predicate(entry))
+    }
+
+    /// Removes the entries matching the predicate, returns whether there were any
+    pub fn remove<P: Fn(&T) -> bool>(&mut self, predicate: P) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|entry| !predicate(entry));
+        self.entries.len() != len
+    }
+}
+
+pub struct UserLists {
+    pub ops: UserList<Operator>,
+    pub whitelist: UserList<WhitelistEntry>,
+    pub banned_players: UserList<PlayerBan>,
+    pub banned_ips: UserList<IpBan>,
+}
+
+impl UserLists {
+    /// Loads the lists from the working directory
+    pub fn load() -> UserLists {
+        UserLists::load_in(Path::new(""))
+    }
+
+    /// Empty lists that aren't backed by files, for servers in tests
+    #[cfg(test)]
+    pub fn unsaved() -> UserLists {
+        fn list<T>() -> UserList<T> {
+            UserList {
+                path: PathBuf::new(),
+                entries: Vec::new(),
+                modified: None,
+            }
+        }
+        UserLists {
+            ops: list(),
+            whitelist: list(),
+            banned_players: list(),
+            banned_ips: list(),
+        }
+    }
+
+    fn load_in(directory: &Path) -> UserLists {
+        UserLists {
+            ops: UserList::load(directory.join(OPS_FILE)),
+            whitelist: UserList::load(directory.join(WHITELIST_FILE)),
+            banned_players: UserList::load(directory.join(BANNED_PLAYERS_FILE)),
+            banned_ips: UserList::load(directory.join(BANNED_IPS_FILE)),
+        }
+    }
+
+    /// Reloads the files that were edited since they were last read, returns whether the ops changed
+    pub fn reload_changed(&mut self) -> bool {
+        self.whitelist.reload_if_changed();
+        self.banned_players.reload_if_changed();
+        self.banned_ips.reload_if_changed();
+        self.ops.reload_if_changed()
+    }
+
+    pub fn operator(&self, uuid: &str) -> Option<&Operator> {
+        self.ops.find(|op| op.uuid.eq_ignore_ascii_case(uuid))
+    }
+
+    pub fn is_whitelisted(&self, uuid: &str) -> bool {
+        self.whitelist
+            .find(|entry| entry.uuid.eq_ignore_ascii_case(uuid))
+            .is_some()
+    }
+
+    /// The player's ban, unless it has expired
+    pub fn player_ban(&self, uuid: &str) -> Option<&PlayerBan> {
+        self.banned_players
+            .find(|ban| ban.uuid.eq_ignore_ascii_case(uuid) && !ban.ban.is_expired())
+    }
+
+    /// The ban of an IP address, unless it has expired
+    pub fn ip_ban(&self, ip: &str) -> Option<&IpBan> {
+        self.banned_ips
+            .find(|ban| ban.ip == ip && !ban.ban.is_expired())
+    }
+
+    /// A player with this name in any of the lists, for players that aren't online
+    pub fn find_profile(&self, name: &str) -> Option<Profile> {
+        let matches = |entry_name: &str| entry_name.eq_ignore_ascii_case(name);
+        let profile = |uuid: &str, name: &str| Profile {
+            uuid: uuid.to_string(),
+            name: name.to_string(),
+        };
+        self.ops
+            .find(|op| matches(&op.name))
+            .map(|op| profile(&op.uuid, &op.name))
+            .or_else(|| {
+                self.whitelist
+                    .find(|entry| matches(&entry.name))
+                    .map(|entry| profile(&entry.uuid, &entry.name))
+            })
+            .or_else(|| {
+                self.banned_players
+                    .find(|ban| matches(&ban.name))
+                    .map(|ban| profile(&ban.uuid, &ban.name))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTCH: &str = "069a79f4-44e9-4726-a5be-fca90e38aaf5";
+    const JEB: &str = "853c80ef-3c37-49fd-aa49-938b674adae6";
+
+    /// Lists in a temporary directory that's removed when dropped
+    struct TestLists {
+        directory: PathBuf,
+        lists: UserLists,
+    }
+
+    impl TestLists {
+        fn new(name: &str) -> TestLists {
+            let directory = std::env::temp_dir().join(format!(
+                "rustmcserver-userlist-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&directory);
+            fs::create_dir_all(&directory).unwrap();
+            TestLists {
+                lists: UserLists::load_in(&directory),
+                directory,
+            }
+        }
+    }
+
+    impl Drop for TestLists {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.directory);
+        }
+    }
+
+    fn player_ban(uuid: &str, name: &str, expires: &str) -> PlayerBan {
+        let mut ban = Ban::new("Server".to_string(), None);
+        ban.expires = expires.to_string();
+        PlayerBan {
+            uuid: uuid.to_string(),
+            name: name.to_string(),
+            ban,
+        }
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut test = TestLists::new("save-and-load");
+        let lists = &mut test.lists;
+        lists.ops.entries.push(Operator {
+            uuid: NOTCH.to_string(),
+            name: "Notch".to_string(),
+            level: 4,
+            bypasses_player_limit: false,
+        });
+        lists.ops.save();
+        lists
+            .banned_players
+            .entries
+            .push(player_ban(JEB, "jeb_", FOREVER));
+        lists.banned_players.save();
+
+        let data = fs::read_to_string(test.directory.join(OPS_FILE)).unwrap();
+        assert!(data.contains("\"bypassesPlayerLimit\": false"));
+
+        let loaded = UserLists::load_in(&test.directory);
+        let op = loaded.operator(NOTCH).unwrap();
+        assert_eq!((op.name.as_str(), op.level), ("Notch", 4));
+        let ban = loaded.player_ban(JEB).unwrap();
+        assert_eq!(ban.ban.reason, DEFAULT_BAN_REASON);
+        assert_eq!(ban.ban.expiration(), None);
+        assert!(loaded.whitelist.entries.is_empty());
+        assert!(loaded.banned_ips.entries.is_empty());
+    }
+
+    #[test]
+    fn reloads_edited_files() {
+        let mut test = TestLists::new("reload");
+        assert!(!test.lists.reload_changed());
+        let entries = format!(r#"[{{"uuid": "{}", "name": "Notch"}}]"#, NOTCH);
+        fs::write(test.directory.join(WHITELIST_FILE), entries).unwrap();
+        // Make sure the modification time differs even on coarse file systems
+        test.lists.whitelist.modified = Some(SystemTime::UNIX_EPOCH);
+        test.lists.reload_changed();
+        assert!(test.lists.is_whitelisted(NOTCH));
+    }
+
+    #[test]
+    fn expired_bans() {
+        let mut test = TestLists::new("expiry");
+        let lists = &mut test.lists;
+        let past = "2019-12-24 18:30:00 +0100";
+        let future = "2999-12-24 18:30:00 +0100";
+        lists
+            .banned_players
+            .entries
+            .push(player_ban(NOTCH, "Notch", past));
+        lists
+            .banned_players
+            .entries
+            .push(player_ban(JEB, "jeb_", future));
+        assert!(lists.banned_players.entries[0].ban.is_expired());
+        assert!(lists.player_ban(NOTCH).is_none());
+        assert!(lists.player_ban(JEB).is_some());
+
+        for (ip, expires) in &[("127.0.0.1", past), ("10.0.0.1", FOREVER)] {
+            let mut ban = Ban::new("Server".to_string(), Some("Spam".to_string()));
+            ban.expires = expires.to_string();
+            lists.banned_ips.entries.push(IpBan {
+                ip: ip.to_string(),
+                ban,
+            });
+        }
+        assert!(lists.ip_ban("127.0.0.1").is_none());
+        assert_eq!(lists.ip_ban("10.0.0.1").unwrap().ban.reason, "Spam");
+        assert!(lists.ip_ban("10.0.0.2").is_none());
+    }
+
+    #[test]
+    fn lookups() {
+        let mut test = TestLists::new("lookups");
+        let lists = &mut test.lists;
+        lists.whitelist.entries.push(WhitelistEntry {
+            uuid: NOTCH.to_string(),
+            name: "Notch".to_string(),
+        });
+        lists
+            .banned_players
+            .entries
+            .push(player_ban(JEB, "jeb_", FOREVER));
+        assert!(lists.is_whitelisted(&NOTCH.to_uppercase()));
+        assert!(!lists.is_whitelisted(JEB));
+
+        let profile = lists.find_profile("NOTCH").unwrap();
+        assert_eq!(
+            (profile.uuid.as_str(), profile.name.as_str()),
+            (NOTCH, "Notch")
+        );
+        assert_eq!(lists.find_profile("Jeb_").unwrap().uuid, JEB);
+        assert!(lists.find_profile("Dinnerbone").is_none());
+
+        assert!(lists
+            .banned_players
+            .remove(|ban| ban.name.eq_ignore_ascii_case("JEB_")));
+        assert!(lists.player_ban(JEB).is_none());
+    }
+}
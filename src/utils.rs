@@ -1,6 +1,6 @@
 use crate::world::World;
+use openssl::hash::{hash, MessageDigest};
 use openssl::sha::Sha1;
-use serde_json::Map;
 
 pub struct Vec3 {
     x: i32,
@@ -14,16 +14,17 @@ impl Vec3 {
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Location {
-    x: i32,
-    y: i32,
-    z: i32,
-    pitch: i8,
-    yaw: i8,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub pitch: f32,
+    pub yaw: f32,
 }
 
 impl Location {
-    pub fn new(x: i32, y: i32, z: i32, pitch: i8, yaw: i8) -> Self {
+    pub fn new(x: f64, y: f64, z: f64, pitch: f32, yaw: f32) -> Self {
         Self {
             x,
             y,
@@ -32,33 +33,46 @@ impl Location {
             yaw,
         }
     }
+
+    pub fn block_position(&self) -> BlockPosition {
+        BlockPosition::new(
+            self.x.floor() as i32,
+            self.y.floor() as i32,
+            self.z.floor() as i32,
+        )
+    }
 }
 
-pub struct ChatComponent {
-    text: String,
-    children: Vec<String>,
-    color: String,
-    bold: bool,
-    underline: bool,
-    italics: bool,
-    obfuscated: bool,
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BlockPosition {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
 }
 
-impl ChatComponent {
-    fn new(text: String) -> Self {
-        Self {
-            text,
-            children: Vec::new(),
-            color: "none".to_string(),
-            bold: false,
-            underline: false,
-            italics: false,
-            obfuscated: false,
+impl BlockPosition {
+    pub fn new(x: i32, y: i32, z: i32) -> Self {
+        Self { x, y, z }
+    }
+
+    /// The neighbouring position in the direction of a block face as sent by the client
+    pub fn offset(&self, face: i32) -> Self {
+        match face {
+            0 => Self::new(self.x, self.y - 1, self.z),
+            1 => Self::new(self.x, self.y + 1, self.z),
+            2 => Self::new(self.x, self.y, self.z - 1),
+            3 => Self::new(self.x, self.y, self.z + 1),
+            4 => Self::new(self.x - 1, self.y, self.z),
+            5 => Self::new(self.x + 1, self.y, self.z),
+            _ => *self,
         }
     }
 
-    pub fn build() -> Option<Map<String, bool>> {
-        return None;
+    pub fn distance_squared(&self, location: &Location) -> f64 {
+        let dx = self.x as f64 + 0.5 - location.x;
+        let dy = self.y as f64 + 0.5 - location.y;
+        let dz = self.z as f64 + 0.5 - location.z;
+        dx * dx + dy * dy + dz * dz
     }
 }
 
@@ -67,6 +81,37 @@ pub fn to_hex_string(bytes: &Vec<u8>) -> String {
     strs.join("")
 }
 
+/// Formats a UUID with dashes, like `069a79f4-44e9-4726-a5be-fca90e38aaf5`
+pub fn format_uuid(uuid: u128) -> String {
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        uuid >> 96,
+        (uuid >> 80) & 0xffff,
+        (uuid >> 64) & 0xffff,
+        (uuid >> 48) & 0xffff,
+        uuid & 0xffff_ffff_ffff
+    )
+}
+
+/// Whether a name is 1 to 16 letters, digits and underscores, which is what Mojang allows
+pub fn is_valid_username(name: &str) -> bool {
+    (1..=16).contains(&name.len()) && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The UUID of a player in offline mode, a version 3 UUID of `OfflinePlayer:<name>` like vanilla
+pub fn offline_uuid(name: &str) -> u128 {
+    let digest = hash(
+        MessageDigest::md5(),
+        format!("OfflinePlayer:{}", name).as_bytes(),
+    )
+    .unwrap();
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest);
+    bytes[6] = (bytes[6] & 0x0f) | 0x30;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    u128::from_be_bytes(bytes)
+}
+
 fn mc_twos_comp(bytes: &mut Vec<u8>) {
     let mut carry = true;
     for i in (0..bytes.len()).rev() {
@@ -93,3 +138,28 @@ pub fn mc_hex_digest(name: &str) -> String {
         String::from(to_hex_string(&bytes).trim_start_matches("0"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offline_uuids() {
+        assert_eq!(
+            format_uuid(offline_uuid("Notch")),
+            "b50ad385-829d-3141-a216-7e7d7539ba7f"
+        );
+    }
+
+    #[test]
+    fn usernames() {
+        assert!(is_valid_username("Notch"));
+        assert!(is_valid_username("a"));
+        assert!(is_valid_username("Player_123456789"));
+        assert!(!is_valid_username(""));
+        assert!(!is_valid_username("Player_1234567890"));
+        assert!(!is_valid_username("Not ch"));
+        assert!(!is_valid_username("Notch§c"));
+        assert!(!is_valid_username("Nötch"));
+    }
+}
@@ -1,17 +1,591 @@
-use crate::player::Player;
-use crate::utils::Location;
+use crate::blocks::{Block, BlockState};
+use crate::inventory::Inventory;
+use crate::player::{Gamemode, Player};
+use crate::registry::Biome;
+use crate::utils::{BlockPosition, Location};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
 
-pub struct World {}
+pub const WORLD_HEIGHT: i32 = 256;
+pub const TICKS_PER_DAY: i64 = 24000;
+const WORLD_DIRECTORY: &str = "world";
+const SECTION_SIZE: usize = 4096;
+
+struct ChunkSection {
+    blocks: Vec<u32>,
+}
+
+impl ChunkSection {
+    fn new() -> ChunkSection {
+        ChunkSection {
+            blocks: vec![BlockState::AIR.id(); SECTION_SIZE],
+        }
+    }
+
+    fn index(x: i32, y: i32, z: i32) -> usize {
+        ((y & 15) << 8 | (z & 15) << 4 | (x & 15)) as usize
+    }
+}
+
+pub struct Chunk {
+    sections: Vec<Option<ChunkSection>>,
+}
+
+impl Chunk {
+    fn new() -> Chunk {
+        let mut sections = Vec::new();
+        sections.resize_with(16, || None);
+        Chunk { sections }
+    }
+
+    /// A flat chunk with bedrock, two layers of dirt and grass on top
+    fn generate_flat() -> Chunk {
+        let state = |name| Block::from_name(name).unwrap().default_state();
+        let layers = [
+            state("minecraft:bedrock"),
+            state("minecraft:dirt"),
+            state("minecraft:dirt"),
+            state("minecraft:grass_block"),
+        ];
+        let mut chunk = Chunk::new();
+        for x in 0..16 {
+            for z in 0..16 {
+                for (y, block) in layers.iter().enumerate() {
+                    chunk.set_block(x, y as i32, z, *block);
+                }
+            }
+        }
+        chunk
+    }
+
+    /// Reads a chunk written by `write`
+    fn read(data: &[u8]) -> io::Result<Chunk> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "truncated chunk");
+        let mut chunk = Chunk::new();
+        let mut data = data;
+        for section in chunk.sections.iter_mut() {
+            let (&present, rest) = data.split_first().ok_or_else(invalid)?;
+            data = rest;
+            if present == 0 {
+                continue;
+            }
+            if data.len() < SECTION_SIZE * 4 {
+                return Err(invalid());
+            }
+            let blocks = data[..SECTION_SIZE * 4]
+                .chunks(4)
+                .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .collect();
+            data = &data[SECTION_SIZE * 4..];
+            *section = Some(ChunkSection { blocks });
+        }
+        Ok(chunk)
+    }
+
+    /// Writes each section as a flag for whether it exists followed by its block states
+    fn write(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for section in &self.sections {
+            match section {
+                Some(section) => {
+                    data.push(1);
+                    for block in &section.blocks {
+                        data.extend_from_slice(&block.to_be_bytes());
+                    }
+                }
+                None => data.push(0),
+            }
+        }
+        data
+    }
+
+    /// The block states of each section from the bottom up, None for sections that are all air
+    pub fn sections(&self) -> Vec<Option<&[u32]>> {
+        self.sections
+            .iter()
+            .map(|section| section.as_ref().map(|section| section.blocks.as_slice()))
+            .collect()
+    }
+
+    /// The biome ids of the chunk's 4x4x4 cells from the bottom up
+    pub fn biomes(&self) -> Vec<i32> {
+        // TODO: Generate and store biomes
+        vec![Biome::Plains.id(); 1024]
+    }
+
+    pub fn get_block(&self, x: i32, y: i32, z: i32) -> BlockState {
+        match &self.sections[(y >> 4) as usize] {
+            Some(section) => BlockState(section.blocks[ChunkSection::index(x, y, z)]),
+            None => BlockState::AIR,
+        }
+    }
+
+    pub fn set_block(&mut self, x: i32, y: i32, z: i32, state: BlockState) {
+        let section = &mut self.sections[(y >> 4) as usize];
+        if section.is_none() {
+            if state == BlockState::AIR {
+                return;
+            }
+            *section = Some(ChunkSection::new());
+        }
+        if let Some(section) = section {
+            section.blocks[ChunkSection::index(x, y, z)] = state.id();
+        }
+    }
+}
+
+pub struct BlockChange {
+    pub position: BlockPosition,
+    pub state: BlockState,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Weather {
+    Clear,
+    Rain,
+    Thunder,
+}
+
+impl Weather {
+    pub fn name(self) -> &'static str {
+        match self {
+            Weather::Clear => "clear",
+            Weather::Rain => "rain",
+            Weather::Thunder => "thunder",
+        }
+    }
+}
+
+/// Everything about the world that isn't stored in chunks
+#[derive(Serialize, Deserialize)]
+struct LevelData {
+    age: i64,
+    time: i64,
+    weather: Weather,
+    weather_duration: i32,
+}
+
+impl Default for LevelData {
+    fn default() -> LevelData {
+        LevelData {
+            age: 0,
+            time: 0,
+            weather: Weather::Clear,
+            weather_duration: 0,
+        }
+    }
+}
+
+/// The files of a save, which can be written on another thread
+pub struct WorldSave {
+    generation: u64,
+    chunk_directory: PathBuf,
+    files: Vec<(PathBuf, Vec<u8>)>,
+    written: Arc<Mutex<HashMap<PathBuf, u64>>>,
+}
+
+impl WorldSave {
+    /// Writes the files, except the ones a newer save already wrote
+    pub fn write(self) -> io::Result<()> {
+        fs::create_dir_all(&self.chunk_directory)?;
+        let generation = self.generation;
+        let mut written = self.written.lock().unwrap_or_else(PoisonError::into_inner);
+        for (path, data) in self.files {
+            if written.get(&path).is_some_and(|&newer| newer > generation) {
+                continue;
+            }
+            fs::write(&path, data)?;
+            written.insert(path, generation);
+        }
+        Ok(())
+    }
+}
+
+pub struct World {
+    directory: PathBuf,
+    chunks: HashMap<(i32, i32), Chunk>,
+    /// Chunks with changes that haven't been saved yet
+    unsaved_chunks: HashSet<(i32, i32)>,
+    /// Chunks a background save is writing, saved again by `save` in case it doesn't finish
+    saving_chunks: HashSet<(i32, i32)>,
+    /// Counts saves so a slow background save can't overwrite newer data
+    save_generation: u64,
+    /// The generation each file was last written by
+    written: Arc<Mutex<HashMap<PathBuf, u64>>>,
+    /// Block changes since the last call to `take_block_changes`, grouped by chunk
+    block_changes: HashMap<(i32, i32), Vec<BlockChange>>,
+    /// Ticks since the world was created
+    pub age: i64,
+    /// The time of day, which keeps counting up past the first day
+    pub time: i64,
+    pub weather: Weather,
+    /// Ticks until the weather clears up
+    pub weather_duration: i32,
+}
 
 impl World {
     pub fn load_world() -> World {
-        World {}
+        World::load(PathBuf::from(WORLD_DIRECTORY))
+    }
+
+    fn load(directory: PathBuf) -> World {
+        let level = match fs::read_to_string(directory.join("level.json")) {
+            Ok(data) => serde_json::from_str(&data).unwrap(),
+            Err(_) => LevelData::default(),
+        };
+        World::new(directory, level)
+    }
+
+    /// A world with nothing loaded that's never saved, to stand in while the real one is lent out
+    pub fn empty() -> World {
+        World::new(PathBuf::new(), LevelData::default())
+    }
+
+    fn new(directory: PathBuf, level: LevelData) -> World {
+        World {
+            directory,
+            chunks: HashMap::new(),
+            unsaved_chunks: HashSet::new(),
+            saving_chunks: HashSet::new(),
+            save_generation: 0,
+            written: Arc::new(Mutex::new(HashMap::new())),
+            block_changes: HashMap::new(),
+            age: level.age,
+            time: level.time,
+            weather: level.weather,
+            weather_duration: level.weather_duration,
+        }
+    }
+
+    /// Writes the level data and every changed chunk to the world directory
+    pub fn save(&mut self) -> io::Result<()> {
+        let mut chunks: HashSet<_> = self.unsaved_chunks.drain().collect();
+        chunks.extend(self.saving_chunks.drain());
+        self.prepare_save(chunks).write()
+    }
+
+    /// Takes the level data and the changed chunks to be written on another thread,
+    /// `finish_background_save` has to be called once it's written
+    pub fn background_save(&mut self) -> WorldSave {
+        let chunks: HashSet<_> = self.unsaved_chunks.drain().collect();
+        self.saving_chunks.extend(&chunks);
+        self.prepare_save(chunks)
+    }
+
+    pub fn finish_background_save(&mut self) {
+        self.saving_chunks.clear();
+    }
+
+    fn prepare_save(&mut self, chunks: HashSet<(i32, i32)>) -> WorldSave {
+        self.save_generation += 1;
+        let level = LevelData {
+            age: self.age,
+            time: self.time,
+            weather: self.weather,
+            weather_duration: self.weather_duration,
+        };
+        let chunk_directory = self.directory.join("chunks");
+        let mut files = vec![(
+            self.directory.join("level.json"),
+            serde_json::to_string_pretty(&level).unwrap().into_bytes(),
+        )];
+        for (chunk_x, chunk_z) in chunks {
+            let data = self.chunks[&(chunk_x, chunk_z)].write();
+            files.push((World::chunk_path(&chunk_directory, chunk_x, chunk_z), data));
+        }
+        WorldSave {
+            generation: self.save_generation,
+            chunk_directory,
+            files,
+            written: self.written.clone(),
+        }
+    }
+
+    fn chunk_path(chunk_directory: &Path, chunk_x: i32, chunk_z: i32) -> PathBuf {
+        chunk_directory.join(format!("{}.{}.bin", chunk_x, chunk_z))
+    }
+
+    /// Advances the time and weather by a tick, returns whether the weather changed
+    pub fn tick(&mut self) -> bool {
+        self.age += 1;
+        self.time += 1;
+        if self.weather != Weather::Clear {
+            self.weather_duration -= 1;
+            if self.weather_duration <= 0 {
+                self.weather = Weather::Clear;
+                self.weather_duration = 0;
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn set_weather(&mut self, weather: Weather, duration: i32) {
+        self.weather = weather;
+        self.weather_duration = duration;
+    }
+
+    pub fn spawn_location(&self) -> Location {
+        Location::new(0.0, 4.0, 0.0, 0.0, 0.0)
     }
 
     pub fn load_player(&self, username: String) -> Player {
+        // TODO: Save and load players
         Player {
             username,
-            position: Location::new(0, 0, 0, 0, 0),
+            position: self.spawn_location(),
+            inventory: Inventory::new(),
+            gamemode: Gamemode::Survival,
+            selected_slot: 0,
+            digging: None,
+        }
+    }
+
+    /// Loads a chunk from the world directory, or generates it if it doesn't exist yet
+    pub fn chunk(&mut self, chunk_x: i32, chunk_z: i32) -> &mut Chunk {
+        let directory = &self.directory;
+        self.chunks.entry((chunk_x, chunk_z)).or_insert_with(|| {
+            let path = World::chunk_path(&directory.join("chunks"), chunk_x, chunk_z);
+            match fs::read(&path) {
+                Ok(data) => Chunk::read(&data).unwrap_or_else(|err| {
+                    warn!("Couldn't read chunk {}: {}", path.display(), err);
+                    Chunk::generate_flat()
+                }),
+                Err(_) => Chunk::generate_flat(),
+            }
+        })
+    }
+
+    /// Loads the chunks within a distance in chunks around a location
+    pub fn load_chunks_around(&mut self, location: &Location, distance: i32) {
+        let center = location.block_position();
+        let (center_x, center_z) = (center.x >> 4, center.z >> 4);
+        for chunk_x in center_x - distance..=center_x + distance {
+            for chunk_z in center_z - distance..=center_z + distance {
+                self.chunk(chunk_x, chunk_z);
+            }
+        }
+    }
+
+    /// The block at a position, air if its chunk isn't loaded
+    pub fn get_block(&self, position: BlockPosition) -> BlockState {
+        if position.y < 0 || position.y >= WORLD_HEIGHT {
+            return BlockState::AIR;
+        }
+        match self.chunks.get(&(position.x >> 4, position.z >> 4)) {
+            Some(chunk) => chunk.get_block(position.x, position.y, position.z),
+            None => BlockState::AIR,
+        }
+    }
+
+    pub fn set_block(&mut self, position: BlockPosition, state: BlockState) {
+        if position.y < 0 || position.y >= WORLD_HEIGHT {
+            return;
         }
+        let chunk_pos = (position.x >> 4, position.z >> 4);
+        self.chunk(chunk_pos.0, chunk_pos.1)
+            .set_block(position.x, position.y, position.z, state);
+        self.unsaved_chunks.insert(chunk_pos);
+        let changes = self.block_changes.entry(chunk_pos).or_default();
+        changes.retain(|change| change.position != position);
+        changes.push(BlockChange { position, state });
+    }
+
+    pub fn take_block_changes(&mut self) -> HashMap<(i32, i32), Vec<BlockChange>> {
+        std::mem::take(&mut self.block_changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A world in a temporary directory that's removed when dropped
+    struct TestWorld {
+        world: World,
+    }
+
+    impl TestWorld {
+        fn new(name: &str) -> TestWorld {
+            let directory = std::env::temp_dir().join(format!(
+                "rustmcserver-world-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&directory);
+            TestWorld {
+                world: World::load(directory),
+            }
+        }
+    }
+
+    impl Drop for TestWorld {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.world.directory);
+        }
+    }
+
+    fn state(name: &str) -> BlockState {
+        Block::from_name(name).unwrap().default_state()
+    }
+
+    #[test]
+    fn get_and_set() {
+        let mut test = TestWorld::new("get-and-set");
+        let world = &mut test.world;
+        let stone = state("minecraft:stone");
+        let position = BlockPosition::new(-1, 70, 17);
+        world.set_block(position, stone);
+        assert_eq!(world.get_block(position), stone);
+        assert_eq!(
+            world.get_block(BlockPosition::new(-1, 71, 17)),
+            BlockState::AIR
+        );
+        // Setting a block loads its chunk, which is generated as a flat world
+        assert!(world.chunks.contains_key(&(-1, 1)));
+        assert_eq!(
+            world.get_block(BlockPosition::new(-16, 3, 31)),
+            state("minecraft:grass_block")
+        );
+        assert_eq!(
+            world.get_block(BlockPosition::new(-9, 0, 20)),
+            state("minecraft:bedrock")
+        );
+        // Outside of the world nothing is set
+        world.set_block(BlockPosition::new(0, -1, 0), stone);
+        world.set_block(BlockPosition::new(0, WORLD_HEIGHT, 0), stone);
+        assert_eq!(
+            world.get_block(BlockPosition::new(0, -1, 0)),
+            BlockState::AIR
+        );
+        assert!(!world.chunks.contains_key(&(0, 0)));
+    }
+
+    #[test]
+    fn unloaded_chunks_are_air() {
+        let mut test = TestWorld::new("unloaded");
+        let world = &mut test.world;
+        let position = BlockPosition::new(100_000, 0, -100_000);
+        assert_eq!(world.get_block(position), BlockState::AIR);
+        assert!(!world.chunks.contains_key(&(100_000 >> 4, -100_000 >> 4)));
+
+        world.load_chunks_around(&Location::new(-0.5, 4.0, 15.5, 0.0, 0.0), 1);
+        for chunk_x in -2..=0 {
+            for chunk_z in -1..=1 {
+                assert!(world.chunks.contains_key(&(chunk_x, chunk_z)));
+            }
+        }
+        assert!(!world.chunks.contains_key(&(1, 0)));
+        assert!(!world.chunks.contains_key(&(-1, 2)));
+        assert_eq!(
+            world.get_block(BlockPosition::new(-20, 3, -5)),
+            state("minecraft:grass_block")
+        );
+    }
+
+    #[test]
+    fn change_tracking() {
+        let mut test = TestWorld::new("changes");
+        let world = &mut test.world;
+        let stone = state("minecraft:stone");
+        let dirt = state("minecraft:dirt");
+        world.set_block(BlockPosition::new(1, 10, 1), stone);
+        world.set_block(BlockPosition::new(2, 10, 1), stone);
+        // Only the latest change of a position is kept
+        world.set_block(BlockPosition::new(1, 10, 1), dirt);
+        world.set_block(BlockPosition::new(16, 10, 0), stone);
+
+        let mut changes = world.take_block_changes();
+        assert_eq!(changes.len(), 2);
+        let chunk: Vec<_> = changes
+            .remove(&(0, 0))
+            .unwrap()
+            .into_iter()
+            .map(|change| (change.position, change.state))
+            .collect();
+        assert_eq!(
+            chunk,
+            [
+                (BlockPosition::new(2, 10, 1), stone),
+                (BlockPosition::new(1, 10, 1), dirt),
+            ]
+        );
+        assert_eq!(changes[&(1, 0)].len(), 1);
+        assert!(world.take_block_changes().is_empty());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut test = TestWorld::new("save");
+        let stone = state("minecraft:stone");
+        let position = BlockPosition::new(5, 200, -30);
+        test.world.set_block(position, stone);
+        test.world
+            .load_chunks_around(&Location::new(100.0, 0.0, 100.0, 0.0, 0.0), 0);
+        test.world.time = 1234;
+        test.world.set_weather(Weather::Rain, 100);
+        test.world.save().unwrap();
+        // Only the changed chunk is written
+        let chunks = fs::read_dir(test.world.directory.join("chunks")).unwrap();
+        assert_eq!(chunks.count(), 1);
+
+        let loaded = World::load(test.world.directory.clone());
+        assert_eq!(loaded.time, 1234);
+        assert_eq!(loaded.weather, Weather::Rain);
+        assert_eq!(loaded.get_block(position), BlockState::AIR);
+        let mut loaded = loaded;
+        loaded.load_chunks_around(&Location::new(5.0, 0.0, -30.0, 0.0, 0.0), 0);
+        assert_eq!(loaded.get_block(position), stone);
+        assert_eq!(
+            loaded.get_block(BlockPosition::new(5, 3, -30)),
+            state("minecraft:grass_block")
+        );
+    }
+
+    #[test]
+    fn background_saves() {
+        let mut test = TestWorld::new("background-save");
+        let stone = state("minecraft:stone");
+        let dirt = state("minecraft:dirt");
+        let position = BlockPosition::new(0, 100, 0);
+        test.world.set_block(position, stone);
+        test.world.time = 1;
+        let background = test.world.background_save();
+
+        // A save before the background one is written still includes its chunks
+        test.world.set_block(position, dirt);
+        test.world.time = 2;
+        test.world.save().unwrap();
+        // The older background save doesn't overwrite it
+        background.write().unwrap();
+        test.world.finish_background_save();
+
+        let mut loaded = World::load(test.world.directory.clone());
+        assert_eq!(loaded.time, 2);
+        loaded.load_chunks_around(&Location::new(0.0, 0.0, 0.0, 0.0, 0.0), 0);
+        assert_eq!(loaded.get_block(position), dirt);
+
+        // Nothing changed since, so only the level data is written
+        assert_eq!(test.world.background_save().files.len(), 1);
+        test.world.finish_background_save();
+        test.world.set_block(position, stone);
+        assert_eq!(test.world.background_save().files.len(), 2);
+    }
+
+    #[test]
+    fn chunk_data() {
+        let mut chunk = Chunk::generate_flat();
+        chunk.set_block(3, 100, 4, state("minecraft:stone"));
+        let read = Chunk::read(&chunk.write()).unwrap();
+        assert_eq!(read.get_block(3, 100, 4), state("minecraft:stone"));
+        assert_eq!(read.get_block(3, 3, 4), state("minecraft:grass_block"));
+        assert_eq!(read.get_block(3, 50, 4), BlockState::AIR);
+        assert!(Chunk::read(&chunk.write()[..100]).is_err());
     }
 }